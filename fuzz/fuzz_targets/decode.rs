@@ -0,0 +1,11 @@
+#![no_main]
+// any bytes a peer could publish: decoding may fail, but must not panic
+use libfuzzer_sys::fuzz_target;
+use peer2peer::protocol::{decode, encode};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = decode(data) {
+        // whatever was accepted must survive a round trip
+        decode(&encode(&message)).expect("re-encoded message decodes");
+    }
+});
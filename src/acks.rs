@@ -0,0 +1,115 @@
+use crate::sealing;
+use crate::unix_time;
+use data_encoding::BASE64;
+use libp2p::{identity, PeerId};
+use peer2peer::protocol::Ack;
+use std::collections::HashMap;
+
+// a direct message is sent again this often until its recipient confirms it
+const RETRY_EVERY: u64 = 10;
+// and given up on after this long
+const EXPIRE_AFTER: u64 = 5 * 60;
+
+pub struct Unacked {
+    pub to: PeerId,
+    pub text: String,
+    pub first_sent: u64,
+    last_sent: u64,
+}
+
+// direct messages waiting for their ack, and the ones we acked ourselves so
+// a retry isn't shown twice
+#[derive(Default)]
+pub struct Acks {
+    pending: HashMap<u64, Unacked>,
+    seen: HashMap<(PeerId, u64), u64>,
+}
+
+impl Acks {
+    pub fn track(&mut self, id: u64, to: PeerId, text: &str) {
+        let now = unix_time();
+        self.pending.insert(
+            id,
+            Unacked {
+                to,
+                text: text.to_owned(),
+                first_sent: now,
+                last_sent: now,
+            },
+        );
+    }
+
+    // only the recipient can confirm a message
+    pub fn acked(&mut self, from: &PeerId, id: u64) -> Option<Unacked> {
+        match self.pending.get(&id) {
+            Some(unacked) if &unacked.to == from => self.pending.remove(&id),
+            _ => None,
+        }
+    }
+
+    // messages to send again, and those given up on
+    pub fn due(&mut self) -> (Vec<(u64, &Unacked)>, Vec<Unacked>) {
+        let now = unix_time();
+        let expired: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, u)| now.saturating_sub(u.first_sent) >= EXPIRE_AFTER)
+            .map(|(id, _)| *id)
+            .collect();
+        let expired = expired
+            .into_iter()
+            .filter_map(|id| self.pending.remove(&id))
+            .collect();
+        self.seen.retain(|_, at| now.saturating_sub(*at) < EXPIRE_AFTER);
+        let retries = self
+            .pending
+            .iter_mut()
+            .filter(|(_, u)| now.saturating_sub(u.last_sent) >= RETRY_EVERY)
+            .map(|(id, u)| {
+                u.last_sent = now;
+                (*id, &*u)
+            })
+            .collect();
+        (retries, expired)
+    }
+
+    // false for a message we already received and showed
+    pub fn first_time(&mut self, from: PeerId, id: u64) -> bool {
+        self.seen.insert((from, id), unix_time()).is_none()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Unacked> {
+        self.pending.values()
+    }
+}
+
+fn statement(from: &str, receiver: &str, id: u64) -> Vec<u8> {
+    format!("peer2peer ack\n{}\n{}\n{}", from, receiver, id).into_bytes()
+}
+
+pub fn sign(keys: &identity::Keypair, receiver: String, id: u64) -> Ack {
+    let from = PeerId::from(keys.public()).to_string();
+    let signature = keys
+        .sign(&statement(&from, &receiver, id))
+        .expect("ed25519 signing can't fail");
+    Ack {
+        receiver,
+        id,
+        signature: Some(BASE64.encode(&signature)),
+    }
+}
+
+// whether `from` itself sent the ack. older nodes don't sign theirs, those
+// count only when they came straight from `from`
+pub fn from(ack: &Ack, from: &PeerId, direct: bool) -> bool {
+    let signature = match ack.signature {
+        Some(ref signature) => signature,
+        None => return direct,
+    };
+    let signature = match BASE64.decode(signature.as_bytes()) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    let statement = statement(&from.to_string(), &ack.receiver, ack.id);
+    matches!(sealing::public_key(from), Some(key) if key.verify(&statement, &signature))
+}
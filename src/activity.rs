@@ -0,0 +1,132 @@
+use crate::mqtt;
+use crate::notify;
+use crate::unix_time;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+
+pub const ACTIVITY_PATH: &str = "./activity.log";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Activity {
+    PeerJoined { peer: String },
+    PeerLeft { peer: String },
+    CatalogReceived { peer: String, books: usize },
+    CatalogSent { peer: String, bytes: usize },
+    BookAdded { title: String },
+    BookShared { title: String, group: Option<String> },
+    BookUnshared { title: String },
+    BookRemoved { title: String },
+    BookRestored { title: String },
+    DirectMessage { peer: String },
+    KeyRotated { old: String, new: String },
+    // a peer offers a book on our to-read list
+    WishlistMatch { peer: String, title: String },
+    LoanOffered { peer: String, title: String },
+    // a book's file came from a peer and is kept in the downloads
+    DownloadFinished { peer: String, title: String, bytes: u64 },
+}
+
+impl fmt::Display for Activity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Activity::PeerJoined { peer } => write!(f, "{} joined", peer),
+            Activity::PeerLeft { peer } => write!(f, "{} left", peer),
+            Activity::CatalogReceived { peer, books } => {
+                write!(f, "received {} books from {}", books, peer)
+            }
+            Activity::CatalogSent { peer, bytes } => {
+                write!(f, "sent our catalog to {} ({} bytes)", peer, bytes)
+            }
+            Activity::BookAdded { title } => write!(f, "added {}", title),
+            Activity::BookShared { title, group: None } => write!(f, "shared {}", title),
+            Activity::BookShared {
+                title,
+                group: Some(group),
+            } => write!(f, "shared {} with @{}", title, group),
+            Activity::BookUnshared { title } => write!(f, "stopped sharing {}", title),
+            Activity::BookRemoved { title } => write!(f, "moved {} to the trash", title),
+            Activity::BookRestored { title } => write!(f, "restored {}", title),
+            Activity::DirectMessage { peer } => write!(f, "direct message from {}", peer),
+            Activity::KeyRotated { old, new } => write!(f, "{} moved to {}", old, new),
+            Activity::WishlistMatch { peer, title } => {
+                write!(f, "{} offers {} from the to-read list", peer, title)
+            }
+            Activity::LoanOffered { peer, title } => write!(f, "{} offers to lend {}", peer, title),
+            Activity::DownloadFinished { peer, title, bytes } => {
+                write!(f, "downloaded {} from {} ({} bytes)", title, peer, bytes)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub at: u64,
+    #[serde(flatten)]
+    pub activity: Activity,
+}
+
+// one json object per line, only ever appended to
+pub fn record(activity: Activity) {
+    let entry = Entry {
+        at: unix_time(),
+        activity,
+    };
+    mqtt::publish(&entry);
+    notify::publish(&entry);
+    let result = serde_json::to_string(&entry)
+        .map_err(|e| e.to_string())
+        .and_then(|line| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(ACTIVITY_PATH)
+                .and_then(|mut file| writeln!(file, "{}", line))
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        error!("unable to record activity: {}", e);
+    }
+}
+
+// entries at or after the given unix time, oldest first. lines this version
+// doesn't understand are skipped rather than failing the whole feed
+pub fn since(from: u64) -> Vec<Entry> {
+    let content = match std::fs::read_to_string(ACTIVITY_PATH) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+    content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Entry>(line).ok())
+        .filter(|entry| entry.at >= from)
+        .collect()
+}
+
+// "30m", "12h", "1d" or "2w" in seconds
+pub fn parse_duration(input: &str) -> Option<u64> {
+    let input = input.trim();
+    let unit = input.chars().last()?;
+    let amount: u64 = input[..input.len() - unit.len_utf8()].parse().ok()?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    amount.checked_mul(seconds)
+}
+
+pub fn ago(seconds: u64) -> String {
+    match seconds {
+        0..=59 => "just now".to_owned(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
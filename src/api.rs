@@ -0,0 +1,489 @@
+use crate::archive;
+use crate::audit::{self, Access};
+use crate::commands::{add_new_book, read_local_library, share_book};
+use crate::config::{Scope, CONFIG};
+use crate::links::Links;
+use crate::systemd;
+use crate::{unix_time, BookBehavior, Result};
+use libp2p::swarm::Swarm;
+use log::{error, info};
+use peer2peer::formats;
+use peer2peer::protocol::catalog_for;
+use peer2peer::respond::share_ended;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt::Write;
+use std::io::SeekFrom;
+use std::net::SocketAddr;
+use subtle::ConstantTimeEq;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+};
+
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+#[cfg(feature = "web-ui")]
+const INDEX_HTML: &str = include_str!("../web/index.html");
+
+// queries that need the swarm are forwarded to the event loop, which owns it
+#[derive(Debug)]
+pub enum ApiQuery {
+    Peers,
+    RemoteBooks,
+    Metrics,
+}
+
+pub struct ApiRequest {
+    pub query: ApiQuery,
+    pub reply: oneshot::Sender<Value>,
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // token from an "Authorization: Bearer" header, or ?token= so the web ui can be opened from a link
+    fn token(&self) -> Option<&str> {
+        self.header("authorization")
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .or_else(|| self.query_param("token"))
+    }
+}
+
+#[derive(Deserialize)]
+struct NewBook {
+    title: String,
+    author: String,
+    publisher: String,
+}
+
+#[derive(Deserialize)]
+struct ShareRequest {
+    title: String,
+}
+
+// a book's file is copied out as it's read rather than held in memory
+enum Body {
+    Bytes(Vec<u8>),
+    File(File, u64),
+}
+
+struct HttpResponse {
+    status: u16,
+    content_type: &'static str,
+    body: Body,
+    // saved under this name rather than shown
+    filename: Option<String>,
+}
+
+impl HttpResponse {
+    fn json(status: u16, value: &Value) -> Self {
+        HttpResponse {
+            status,
+            content_type: "application/json",
+            body: Body::Bytes(value.to_string().into_bytes()),
+            filename: None,
+        }
+    }
+
+    fn error(status: u16, msg: &str) -> Self {
+        Self::json(status, &json!({ "error": msg }))
+    }
+}
+
+// the socket systemd passed us, or else api.listen. None without either
+pub async fn listener() -> Result<Option<TcpListener>> {
+    if let Some(listener) = systemd::listener() {
+        listener.set_nonblocking(true)?;
+        return Ok(Some(TcpListener::from_std(listener)?));
+    }
+    match CONFIG.api.listen {
+        Some(ref addr) => Ok(Some(TcpListener::bind(addr).await?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn serve(listener: TcpListener, sender: mpsc::UnboundedSender<ApiRequest>) -> Result<()> {
+    info!("api listening on http://{}", listener.local_addr()?);
+    if CONFIG.api.tokens.iter().any(|t| t.token.is_empty()) {
+        error!("ignoring empty tokens in [[api.tokens]]");
+    }
+    loop {
+        let (stream, client) = listener.accept().await?;
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, client, sender).await {
+                error!("api connection error: {}", e);
+            }
+        });
+    }
+}
+
+// answer a forwarded query from inside the event loop
+pub fn answer(req: ApiRequest, swarm: &mut Swarm<BookBehavior>) {
+    let value = match req.query {
+        ApiQuery::Peers => {
+            let peers: Vec<String> = swarm
+                .behaviour()
+                .discovered_peers()
+                .iter()
+                .map(|p| p.to_string())
+                .collect();
+            json!(peers)
+        }
+        ApiQuery::RemoteBooks => json!(swarm.behaviour().remote_catalogs.all()),
+        ApiQuery::Metrics => json!(metrics(swarm)),
+    };
+    // the client may have hung up already
+    let _ = req.reply.send(value);
+}
+
+// prometheus text format
+fn metrics(swarm: &Swarm<BookBehavior>) -> String {
+    let behaviour = swarm.behaviour();
+    let mut out = String::new();
+    let _ = writeln!(out, "# TYPE peer2peer_connected_peers gauge");
+    let _ = writeln!(out, "peer2peer_connected_peers {}", swarm.network_info().num_peers());
+    let _ = writeln!(out, "# TYPE peer2peer_wire_bytes_total counter");
+    let _ = writeln!(
+        out,
+        "peer2peer_wire_bytes_total{{direction=\"in\"}} {}",
+        behaviour.wire.total_inbound()
+    );
+    let _ = writeln!(
+        out,
+        "peer2peer_wire_bytes_total{{direction=\"out\"}} {}",
+        behaviour.wire.total_outbound()
+    );
+    let _ = writeln!(out, "# TYPE peer2peer_peer_bytes_total counter");
+    let _ = writeln!(out, "# TYPE peer2peer_peer_messages_total counter");
+    for (peer, t) in behaviour.traffic.iter() {
+        for (direction, bytes, messages) in [
+            ("sent", t.bytes_sent, t.messages_sent),
+            ("received", t.bytes_received, t.messages_received),
+        ] {
+            let labels = format!("peer=\"{}\",direction=\"{}\"", peer, direction);
+            let _ = writeln!(out, "peer2peer_peer_bytes_total{{{}}} {}", labels, bytes);
+            let _ = writeln!(out, "peer2peer_peer_messages_total{{{}}} {}", labels, messages);
+        }
+    }
+    out
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    client: SocketAddr,
+    sender: mpsc::UnboundedSender<ApiRequest>,
+) -> Result<()> {
+    let res = match read_request(&mut stream).await? {
+        Some(req) => route(req, client, &sender).await,
+        None => HttpResponse::error(400, "bad request"),
+    };
+    let length = match res.body {
+        Body::Bytes(ref bytes) => bytes.len() as u64,
+        Body::File(_, length) => length,
+    };
+    let disposition = match res.filename {
+        Some(ref name) => format!("Content-Disposition: attachment; filename=\"{}\"\r\n", name),
+        None => String::new(),
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}\
+         Connection: close\r\n\r\n",
+        res.status,
+        reason(res.status),
+        res.content_type,
+        length,
+        disposition
+    );
+    stream.write_all(head.as_bytes()).await?;
+    match res.body {
+        Body::Bytes(bytes) => stream.write_all(&bytes).await?,
+        // no more than we announced, should the file have grown since
+        Body::File(file, length) => {
+            tokio::io::copy(&mut file.take(length), &mut stream).await?;
+        }
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Option<HttpRequest>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 || buf.len() + n > MAX_REQUEST_SIZE {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut parsed = httparse::Request::new(&mut headers);
+        let body_start = match parsed.parse(&buf)? {
+            httparse::Status::Complete(len) => len,
+            httparse::Status::Partial => continue,
+        };
+
+        let headers: Vec<(String, String)> = parsed
+            .headers
+            .iter()
+            .map(|h| (h.name.to_owned(), String::from_utf8_lossy(h.value).into_owned()))
+            .collect();
+        let content_length = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, v)| v.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        let method = parsed.method.unwrap_or_default().to_owned();
+        let target = parsed.path.unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let path = path.to_owned();
+        let query = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+
+        if body_start + content_length > MAX_REQUEST_SIZE {
+            return Ok(None);
+        }
+        while buf.len() < body_start + content_length {
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Ok(None);
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+
+        return Ok(Some(HttpRequest {
+            method,
+            path,
+            query,
+            headers,
+            body: buf[body_start..body_start + content_length].to_vec(),
+        }));
+    }
+}
+
+// scope granted to the request, or None if it must be rejected
+fn authorize(req: &HttpRequest) -> Option<Scope> {
+    let tokens = &CONFIG.api.tokens;
+    if tokens.is_empty() {
+        return Some(Scope::Admin);
+    }
+    // an empty token in the config would let in anyone sending "Bearer "
+    let presented = req.token().filter(|t| !t.is_empty())?;
+    // every token is compared in full, so the time taken doesn't tell an
+    // attacker how much of a guess was right
+    let mut scope = None;
+    for t in tokens {
+        if bool::from(t.token.as_bytes().ct_eq(presented.as_bytes())) {
+            scope = scope.or(Some(t.scope));
+        }
+    }
+    scope
+}
+
+async fn route(
+    req: HttpRequest,
+    client: SocketAddr,
+    sender: &mpsc::UnboundedSender<ApiRequest>,
+) -> HttpResponse {
+    // download links carry their own token, made with "link book <id>"
+    if let Some(token) = req.path.strip_prefix("/files/") {
+        if req.method != "GET" {
+            return HttpResponse::error(405, "method not allowed");
+        }
+        return download(token, client).await;
+    }
+
+    // the page itself holds no data, it calls the api with the token from its own url
+    #[cfg(feature = "web-ui")]
+    if req.method == "GET" && (req.path == "/" || req.path == "/index.html") {
+        return HttpResponse {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: Body::Bytes(INDEX_HTML.as_bytes().to_vec()),
+            filename: None,
+        };
+    }
+
+    let scope = match authorize(&req) {
+        Some(scope) => scope,
+        None => return HttpResponse::error(401, "missing or invalid token"),
+    };
+    let required = match req.method.as_str() {
+        "GET" => Scope::Read,
+        _ => Scope::Admin,
+    };
+    if scope < required {
+        return HttpResponse::error(403, "token does not allow this");
+    }
+    if required == Scope::Admin && archive::read_only() {
+        return HttpResponse::error(403, "this node is a read-only archive");
+    }
+
+    match (req.method.as_str(), req.path.as_str()) {
+        ("GET", "/api/books") => match read_local_library().await {
+            Ok(mut books) => {
+                books.retain(|b| b.trashed.is_none());
+                // a read token gets what any peer would, no private books,
+                // paths on our disk, locations or reviews
+                if scope < Scope::Admin {
+                    books.retain(|b| !share_ended(b, unix_time()));
+                    books = catalog_for(books, |_| false, false, &CONFIG.visibility);
+                    books.iter_mut().for_each(|b| b.review = None);
+                }
+                HttpResponse::json(200, &json!(books))
+            }
+            Err(e) => HttpResponse::error(500, &e.to_string()),
+        },
+        ("GET", "/api/peers") => forward(ApiQuery::Peers, sender).await,
+        ("GET", "/api/remote") => forward(ApiQuery::RemoteBooks, sender).await,
+        ("GET", "/metrics") => match ask(ApiQuery::Metrics, sender).await {
+            Ok(Value::String(text)) => HttpResponse {
+                status: 200,
+                content_type: "text/plain; version=0.0.4",
+                body: Body::Bytes(text.into_bytes()),
+                filename: None,
+            },
+            Ok(_) => HttpResponse::error(500, "unexpected answer from node"),
+            Err(res) => res,
+        },
+        ("POST", "/api/books") => match serde_json::from_slice::<NewBook>(&req.body) {
+            Ok(book) => match add_new_book(&book.title, &book.author, &book.publisher).await {
+                Ok(()) => HttpResponse::json(201, &json!({ "added": book.title })),
+                Err(e) => HttpResponse::error(500, &e.to_string()),
+            },
+            Err(e) => HttpResponse::error(400, &e.to_string()),
+        },
+        ("POST", "/api/share") => match serde_json::from_slice::<ShareRequest>(&req.body) {
+            Ok(share) => match share_book(&share.title).await {
+                Ok(()) => HttpResponse::json(200, &json!({ "shared": share.title })),
+                Err(e) => HttpResponse::error(500, &e.to_string()),
+            },
+            Err(e) => HttpResponse::error(400, &e.to_string()),
+        },
+        (_, "/api/books") | (_, "/api/peers") | (_, "/api/remote") | (_, "/api/share")
+        | (_, "/metrics") => {
+            HttpResponse::error(405, "method not allowed")
+        }
+        _ => HttpResponse::error(404, "not found"),
+    }
+}
+
+// the file of a book we still share. an unknown token, an expired one and a
+// book no longer shared all look the same
+async fn download(token: &str, client: SocketAddr) -> HttpResponse {
+    let not_found = || HttpResponse::error(404, "no such link, or it expired");
+    let id = match Links::load().book(token) {
+        Some(id) => id,
+        None => return not_found(),
+    };
+    let library = match read_local_library().await {
+        Ok(library) => library,
+        Err(e) => return HttpResponse::error(500, &e.to_string()),
+    };
+    let now = unix_time();
+    let path = library
+        .into_iter()
+        .find(|b| b.id == id && b.public && b.trashed.is_none() && !share_ended(b, now))
+        .and_then(|b| b.file);
+    let path = match path {
+        Some(path) => path,
+        None => return not_found(),
+    };
+    let (file, length, head) = match open(&path).await {
+        Ok(opened) => opened,
+        Err(e) => {
+            error!("unable to serve {}: {}", path, e);
+            return not_found();
+        }
+    };
+    audit::record(&client.ip().to_string(), Access::Download { id });
+    let path = std::path::Path::new(&path);
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    // what the file is rather than what it's named
+    let content_type = match (formats::detect(&head), extension.to_lowercase().as_str()) {
+        (Some(format), _) => format.mime(),
+        (None, "txt") => "text/plain; charset=utf-8",
+        (None, _) => "application/octet-stream",
+    };
+    let filename = path
+        .file_name()
+        .map(|n| n.to_string_lossy().replace(['"', '\r', '\n'], ""));
+    HttpResponse {
+        status: 200,
+        content_type,
+        body: Body::File(file, length),
+        filename,
+    }
+}
+
+// the file rewound after reading enough of it to tell its format, and its length
+async fn open(path: &str) -> std::io::Result<(File, u64, Vec<u8>)> {
+    let mut file = File::open(path).await?;
+    let length = file.metadata().await?.len();
+    let mut head = Vec::new();
+    (&mut file).take(68).read_to_end(&mut head).await?;
+    file.seek(SeekFrom::Start(0)).await?;
+    Ok((file, length, head))
+}
+
+async fn forward(query: ApiQuery, sender: &mpsc::UnboundedSender<ApiRequest>) -> HttpResponse {
+    match ask(query, sender).await {
+        Ok(value) => HttpResponse::json(200, &value),
+        Err(res) => res,
+    }
+}
+
+async fn ask(
+    query: ApiQuery,
+    sender: &mpsc::UnboundedSender<ApiRequest>,
+) -> std::result::Result<Value, HttpResponse> {
+    let (reply, receiver) = oneshot::channel();
+    if sender.send(ApiRequest { query, reply }).is_err() {
+        return Err(HttpResponse::error(503, "node is shutting down"));
+    }
+    receiver
+        .await
+        .map_err(|_| HttpResponse::error(503, "no answer from node"))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
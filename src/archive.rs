@@ -0,0 +1,80 @@
+use crate::config::CONFIG;
+
+// commands that leave the library, the lending ledger and the files the node
+// serves as they are, with whatever follows them. an archive node turns any
+// other command down before it does anything, one added later included
+const READ_ONLY: &[&str] = &[
+    "ls peers",
+    "ls bookmarks",
+    "ls books",
+    "ls loans",
+    "ls channels",
+    "ls clubs",
+    "ls groups",
+    "ls pins",
+    "peers score",
+    "peers reputation",
+    "forget me",
+    "telemetry",
+    "bandwidth",
+    "activity",
+    "audit",
+    "queue",
+    "requests",
+    "cancel",
+    "bookmark",
+    "devices",
+    "presence",
+    "rotate key",
+    "ping",
+    "invite",
+    "accept-invite",
+    "show book",
+    "link book",
+    "unlink book",
+    "recommend",
+    "loans",
+    "missing volumes",
+    "search",
+    "export",
+    "node export",
+    "say",
+    "msg",
+    "club",
+    "trust",
+    "status",
+    "history",
+    "trash list",
+    "snapshot list",
+    "snapshot create",
+    "debug",
+    "silent",
+    "power",
+    "cache",
+    "quota",
+    "policy",
+    "group",
+    "join",
+    "leave",
+];
+
+// commands that only list things on their own, what follows them changes them
+const LISTINGS: &[&str] = &["conflicts", "downloads"];
+
+// a community archive: it serves its catalog and files, but nothing changes
+// it while it runs, not from the prompt, the api or other peers
+pub fn read_only() -> bool {
+    CONFIG.archive.enabled
+}
+
+pub fn refuses(cmd: &str) -> bool {
+    read_only() && !allowed(cmd.trim())
+}
+
+fn allowed(cmd: &str) -> bool {
+    let follows = |c: &&str| match cmd.strip_prefix(*c) {
+        Some(rest) => rest.is_empty() || rest.starts_with(' '),
+        None => false,
+    };
+    cmd.is_empty() || LISTINGS.contains(&cmd) || READ_ONLY.iter().any(follows)
+}
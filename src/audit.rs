@@ -0,0 +1,82 @@
+use crate::unix_time;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+
+pub const AUDIT_PATH: &str = "./audit.log";
+
+// who asked for what, kept apart from the activity feed so it can be
+// handed over or checked on its own
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "access", rename_all = "snake_case")]
+pub enum Access {
+    // our catalog went out, with as many books as the requester may see
+    Catalog { books: usize },
+    // the details of one book went out, or that we have none to show
+    Book { id: usize, found: bool },
+    // a request for our catalog we didn't answer
+    Refused { reason: String },
+    // the peer asked to be forgotten, this is what we dropped
+    Forgotten { dropped: Vec<String> },
+    // a book's file went out through a download link, the peer is the address
+    Download { id: usize },
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Catalog { books } => write!(f, "got our catalog ({} books)", books),
+            Access::Book { id, found: true } => write!(f, "got the details of book {}", id),
+            Access::Book { id, found: false } => write!(f, "asked for book {}, not shown", id),
+            Access::Refused { reason } => write!(f, "was refused our catalog ({})", reason),
+            Access::Forgotten { dropped } => {
+                write!(f, "asked to be forgotten, dropped {}", dropped.join(", "))
+            }
+            Access::Download { id } => write!(f, "downloaded book {} through a link", id),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub at: u64,
+    pub peer: String,
+    #[serde(flatten)]
+    pub access: Access,
+}
+
+// one json object per line, only ever appended to
+pub fn record(peer: &str, access: Access) {
+    let entry = Entry {
+        at: unix_time(),
+        peer: peer.to_owned(),
+        access,
+    };
+    let result = serde_json::to_string(&entry)
+        .map_err(|e| e.to_string())
+        .and_then(|line| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(AUDIT_PATH)
+                .and_then(|mut file| writeln!(file, "{}", line))
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        error!("unable to write audit log: {}", e);
+    }
+}
+
+// entries at or after the given unix time, oldest first
+pub fn since(from: u64) -> Vec<Entry> {
+    let content = match std::fs::read_to_string(AUDIT_PATH) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+    content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Entry>(line).ok())
+        .filter(|entry| entry.at >= from)
+        .collect()
+}
@@ -0,0 +1,90 @@
+use crate::Result;
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::{net::UdpSocket, sync::mpsc, time};
+
+const INTERVAL: Duration = Duration::from_secs(30);
+
+// broadcast on the lan so peers can find each other where mdns is blocked.
+// the receiver dials the packet's source ip on the advertised tcp port
+#[derive(Debug, Serialize, Deserialize)]
+struct Beacon {
+    peer_id: String,
+    topic: String,
+    port: u16,
+}
+
+pub async fn run(
+    beacon_port: u16,
+    tcp_port: u16,
+    local_peer: PeerId,
+    topic: String,
+    found: mpsc::UnboundedSender<(PeerId, Vec<Multiaddr>)>,
+) {
+    if let Err(e) = beacon_loop(beacon_port, tcp_port, local_peer, topic, found).await {
+        error!("lan beacon stopped: {}", e);
+    }
+}
+
+async fn beacon_loop(
+    beacon_port: u16,
+    tcp_port: u16,
+    local_peer: PeerId,
+    topic: String,
+    found: mpsc::UnboundedSender<(PeerId, Vec<Multiaddr>)>,
+) -> Result<()> {
+    let socket = bind(beacon_port)?;
+    info!("lan beacon on udp port {}", beacon_port);
+    let beacon = serde_json::to_vec(&Beacon {
+        peer_id: local_peer.to_string(),
+        topic: topic.clone(),
+        port: tcp_port,
+    })?;
+    let broadcast = SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), beacon_port);
+
+    let mut ticker = time::interval(INTERVAL);
+    let mut buf = [0u8; 1024];
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if let Err(e) = socket.send_to(&beacon, broadcast).await {
+                    debug!("unable to send lan beacon: {}", e);
+                }
+            }
+            received = socket.recv_from(&mut buf) => {
+                let (n, from) = received?;
+                let beacon: Beacon = match serde_json::from_slice(&buf[..n]) {
+                    Ok(beacon) => beacon,
+                    Err(_) => continue,
+                };
+                let peer: PeerId = match beacon.peer_id.parse() {
+                    Ok(peer) => peer,
+                    Err(_) => continue,
+                };
+                if peer == local_peer || beacon.topic != topic {
+                    continue;
+                }
+                let addr = Multiaddr::empty()
+                    .with(from.ip().into())
+                    .with(Protocol::Tcp(beacon.port));
+                let _ = found.send((peer, vec![addr]));
+            }
+        }
+    }
+}
+
+// several nodes on one machine all need to hear the broadcasts
+fn bind(port: u16) -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port).into())?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
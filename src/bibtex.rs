@@ -0,0 +1,367 @@
+// books to and from BibTeX, the format citation managers like Zotero and
+// JabRef read and write. every book becomes a @book entry, and importing
+// takes any entry with a title and an author
+use crate::protocol::Book;
+use std::collections::{BTreeMap, HashSet};
+
+// a .bib entry, field names lowercase and values as written, braces and all
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub kind: String,
+    pub key: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Entry {
+    fn field(&self, name: &str) -> Option<String> {
+        self.fields.get(name).map(|v| plain(v)).filter(|v| !v.is_empty())
+    }
+
+    // a private book with id 0, None without a title or an author. articles
+    // and chapters keep their journal or collection as the publisher
+    pub fn book(&self) -> Option<Book> {
+        let title = self.field("title")?;
+        let author = self.field("author").or_else(|| self.field("editor"))?;
+        let publisher = ["publisher", "journal", "booktitle", "institution", "school"]
+            .iter()
+            .find_map(|name| self.field(name))
+            .unwrap_or_default();
+        let year = self
+            .field("year")
+            .or_else(|| self.field("date"))
+            .and_then(|date| date.get(..4).and_then(|y| y.parse().ok()));
+        Some(Book {
+            title,
+            author: names(&author),
+            publisher,
+            public: false,
+            series: self.field("series"),
+            volume: self.field("volume").and_then(|v| v.parse().ok()),
+            year,
+            isbn: self.field("isbn"),
+            ..Default::default()
+        })
+    }
+}
+
+// one @book entry per book, keys like "camus1956fall"
+pub fn export(books: &[Book]) -> String {
+    let mut keys = HashSet::new();
+    let mut out = String::new();
+    for book in books {
+        let base = cite_key(book);
+        let mut key = base.clone();
+        let mut n = 2;
+        while !keys.insert(key.clone()) {
+            key = format!("{}{}", base, n);
+            n += 1;
+        }
+        out.push_str(&format!("@book{{{},\n", key));
+        let mut field = |name: &str, value: &str| {
+            if !value.trim().is_empty() {
+                out.push_str(&format!("  {} = {{{}}},\n", name, escape(value.trim())));
+            }
+        };
+        field("title", &book.title);
+        field("author", &book.author);
+        field("publisher", &book.publisher);
+        field("year", &book.year.map(|y| y.to_string()).unwrap_or_default());
+        field("isbn", book.isbn.as_deref().unwrap_or_default());
+        field("series", book.series.as_deref().unwrap_or_default());
+        field("volume", &book.volume.map(|v| v.to_string()).unwrap_or_default());
+        out.push_str("}\n\n");
+    }
+    out
+}
+
+// the author's last name, the year and the first long word of the title
+fn cite_key(book: &Book) -> String {
+    let first_author = book.author.split(" and ").next().unwrap_or_default();
+    let last_name = match first_author.split_once(',') {
+        Some((last, _)) => last,
+        None => first_author.split_whitespace().last().unwrap_or_default(),
+    };
+    let word = book
+        .title
+        .split_whitespace()
+        .map(|w| w.chars().filter(|c| c.is_alphanumeric()).collect::<String>())
+        .find(|w| w.chars().count() > 3)
+        .unwrap_or_default();
+    let year = book.year.map(|y| y.to_string()).unwrap_or_default();
+    let key: String = format!("{}{}{}", last_name, year, word)
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    if key.is_empty() {
+        return "book".to_owned();
+    }
+    key
+}
+
+// LaTeX would take these for commands
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '&' | '%' | '$' | '#' | '_' | '{' | '}') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+// "Le Guin, Ursula K. and Tolkien, J. R. R." reads "Ursula K. Le Guin and
+// J. R. R. Tolkien"
+fn names(authors: &str) -> String {
+    authors
+        .split(" and ")
+        .map(|name| match name.split_once(',') {
+            Some((last, first)) => format!("{} {}", first.trim(), last.trim()),
+            None => name.trim().to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join(" and ")
+}
+
+// accented letters written the LaTeX way, e.g. {\'e}
+const ACCENTS: &[(char, &str, &str)] = &[
+    ('\'', "aeiouyAEIOUY", "áéíóúýÁÉÍÓÚÝ"),
+    ('`', "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+    ('^', "aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+    ('"', "aeiouyAEIOU", "äëïöüÿÄËÏÖÜ"),
+    ('~', "anoANO", "ãñõÃÑÕ"),
+    ('c', "cC", "çÇ"),
+];
+
+// a letter accent like \c has to be followed by its argument, otherwise it
+// starts a command like \cite
+fn accent_of(accent: char, next: Option<&char>) -> Option<(&'static str, &'static str)> {
+    let (_, from, to) = ACCENTS.iter().find(|(a, _, _)| *a == accent)?;
+    if accent.is_alphabetic() && !matches!(next, Some('{') | Some(' ')) {
+        return None;
+    }
+    Some((from, to))
+}
+
+// a value as text: braces dropped, escapes and accents resolved, spacing
+// collapsed
+fn plain(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' => (),
+            '\\' => {
+                let c = match chars.next() {
+                    Some(c) => c,
+                    None => break,
+                };
+                match accent_of(c, chars.peek()) {
+                    Some((from, to)) => {
+                        // the letter may be braced, as in \'{e}, or follow a space, as in \c c
+                        while chars.next_if(|c| *c == '{' || *c == ' ').is_some() {}
+                        let letter = chars.next();
+                        match letter.and_then(|l| from.chars().position(|f| f == l)) {
+                            Some(i) => out.extend(to.chars().nth(i)),
+                            None => out.extend(letter),
+                        }
+                    }
+                    // commands like \textit are dropped, their argument stays
+                    None if c.is_alphabetic() => {
+                        while chars.next_if(|c| c.is_alphabetic()).is_some() {}
+                    }
+                    None => out.push(c),
+                }
+            }
+            '~' => out.push(' '),
+            c => out.push(c),
+        }
+    }
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// the entries of a .bib file. @comment and @preamble are skipped, and
+// @string abbreviations are filled in
+pub fn parse(input: &str) -> Result<Vec<Entry>, String> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        strings: BTreeMap::new(),
+    };
+    let mut entries = Vec::new();
+    while parser.skip_to('@') {
+        let kind = parser.word().to_lowercase();
+        parser.spaces();
+        let close = match parser.next() {
+            Some('{') => '}',
+            Some('(') => ')',
+            _ => return Err(parser.error(&format!("expected {{ after @{}", kind))),
+        };
+        match kind.as_str() {
+            "comment" | "preamble" if close == '}' => {
+                parser.pos -= 1;
+                parser.balanced()?;
+            }
+            "comment" | "preamble" => {
+                parser.skip_to(close);
+            }
+            "string" => {
+                let (name, value) = parser.field()?;
+                parser.strings.insert(name, value);
+                parser.spaces();
+                parser.expect(close)?;
+            }
+            _ => entries.push(parser.entry(kind, close)?),
+        }
+    }
+    Ok(entries)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    strings: BTreeMap<String, String>,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).copied();
+        self.pos += 1;
+        c
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.chars[..self.pos.min(self.chars.len())]
+            .iter()
+            .filter(|c| **c == '\n')
+            .count();
+        format!("line {}: {}", line + 1, message)
+    }
+
+    // false at the end of the input
+    fn skip_to(&mut self, wanted: char) -> bool {
+        while let Some(c) = self.next() {
+            if c == wanted {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn spaces(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, wanted: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == wanted => Ok(()),
+            _ => Err(self.error(&format!("expected {}", wanted))),
+        }
+    }
+
+    // names, keys and bare values
+    fn word(&mut self) -> String {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if !c.is_whitespace() && !"{}()=,#\"".contains(c)) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    // a {...} group, returned without its outer braces
+    fn balanced(&mut self) -> Result<String, String> {
+        self.expect('{')?;
+        let start = self.pos;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some('{') => depth += 1,
+                Some('}') => depth -= 1,
+                Some('\\') => self.pos += 1,
+                Some(_) => (),
+                None => return Err(self.error("unclosed {")),
+            }
+        }
+        Ok(self.chars[start..self.pos - 1].iter().collect())
+    }
+
+    fn quoted(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let start = self.pos;
+        let mut depth = 0;
+        loop {
+            match self.next() {
+                Some('"') if depth == 0 => break,
+                Some('{') => depth += 1,
+                Some('}') => depth -= 1,
+                Some('\\') => self.pos += 1,
+                Some(_) => (),
+                None => return Err(self.error("unclosed \"")),
+            }
+        }
+        Ok(self.chars[start..self.pos - 1].iter().collect())
+    }
+
+    // name = part # part # ...
+    fn field(&mut self) -> Result<(String, String), String> {
+        self.spaces();
+        let name = self.word().to_lowercase();
+        if name.is_empty() {
+            return Err(self.error("expected a field name"));
+        }
+        self.spaces();
+        self.expect('=')?;
+        let mut value = String::new();
+        loop {
+            self.spaces();
+            match self.peek() {
+                Some('{') => value.push_str(&self.balanced()?),
+                Some('"') => value.push_str(&self.quoted()?),
+                _ => {
+                    let word = self.word();
+                    if word.is_empty() {
+                        return Err(self.error(&format!("{} has no value", name)));
+                    }
+                    let abbreviation = self.strings.get(&word.to_lowercase()).cloned();
+                    value.push_str(&abbreviation.unwrap_or(word));
+                }
+            }
+            self.spaces();
+            if self.peek() != Some('#') {
+                break;
+            }
+            self.pos += 1;
+        }
+        Ok((name, value))
+    }
+
+    fn entry(&mut self, kind: String, close: char) -> Result<Entry, String> {
+        self.spaces();
+        let key = self.word();
+        let mut fields = BTreeMap::new();
+        loop {
+            self.spaces();
+            match self.next() {
+                Some(c) if c == close => break,
+                Some(',') => {
+                    self.spaces();
+                    // a trailing comma before the end
+                    if self.peek() == Some(close) {
+                        continue;
+                    }
+                    let (name, value) = self.field()?;
+                    fields.insert(name, value);
+                }
+                _ => return Err(self.error(&format!("expected , or {} in {}", close, key))),
+            }
+        }
+        Ok(Entry { kind, key, fields })
+    }
+}
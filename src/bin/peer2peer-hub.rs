@@ -0,0 +1,190 @@
+// a library-less node for a small always-on server. it bridges friend groups
+// by forwarding their floodsub messages and serves as a rendezvous point,
+// using the same config.toml and identity.key as a regular node
+use libp2p::{
+    core::{either::EitherTransport, upgrade},
+    dns::TokioDnsConfig,
+    floodsub::{Floodsub, FloodsubEvent, Topic},
+    futures::StreamExt,
+    identity, mplex,
+    noise::{Keypair, NoiseConfig, X25519Spec},
+    pnet::{PnetConfig, PreSharedKey},
+    rendezvous,
+    swarm::{ConnectionLimits, NetworkBehaviourEventProcess, SwarmBuilder, SwarmEvent},
+    tcp::TokioTcpConfig,
+    NetworkBehaviour, PeerId, Transport,
+};
+use log::{debug, error, info};
+use once_cell::sync::Lazy;
+
+// the regular node uses more of these than the hub does
+#[allow(dead_code)]
+#[path = "../config.rs"]
+mod config;
+#[allow(dead_code)]
+#[path = "../datadir.rs"]
+mod datadir;
+#[path = "../keyring.rs"]
+mod keyring;
+#[allow(dead_code)]
+#[path = "../keys.rs"]
+mod keys;
+#[allow(dead_code)]
+#[path = "../store.rs"]
+mod store;
+
+use config::CONFIG;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+static KEYS: Lazy<identity::Keypair> = Lazy::new(keys::loaded);
+static PEER_ID: Lazy<PeerId> = Lazy::new(|| PeerId::from(KEYS.public()));
+static PSK: Lazy<Option<PreSharedKey>> = Lazy::new(|| {
+    CONFIG.psk_file.as_ref().map(|path| {
+        std::fs::read_to_string(path)
+            .expect("unable to read psk file")
+            .parse()
+            .expect("unable to parse psk file")
+    })
+});
+
+// must match the topic the nodes use, see topic_name in main.rs
+fn topic_name() -> String {
+    match (&CONFIG.network, PSK.as_ref()) {
+        (Some(network), _) => format!("library/{}", network),
+        (None, Some(psk)) => format!("library/{}", psk.fingerprint()),
+        (None, None) => "library".to_owned(),
+    }
+}
+
+#[derive(NetworkBehaviour)]
+#[behaviour(event_process = true)]
+struct HubBehavior {
+    // floodsub forwards what it receives to every other subscribed peer,
+    // which is all the bridging a hub has to do
+    floodsub: Floodsub,
+    rendezvous: rendezvous::server::Behaviour,
+}
+
+impl NetworkBehaviourEventProcess<FloodsubEvent> for HubBehavior {
+    fn inject_event(&mut self, event: FloodsubEvent) {
+        if let FloodsubEvent::Message(msg) = event {
+            debug!("forwarding {} bytes from {}", msg.data.len(), msg.source);
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<rendezvous::server::Event> for HubBehavior {
+    fn inject_event(&mut self, event: rendezvous::server::Event) {
+        match event {
+            rendezvous::server::Event::PeerRegistered { peer, registration } => {
+                info!("{} registered as {}", peer, registration.namespace)
+            }
+            event => debug!("rendezvous server: {:?}", event),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // the node's data directory, so both find the same config.toml and identity.key
+    let data_dir = datadir::enter().expect("unable to use the data directory");
+    pretty_env_logger::init();
+    info!("data directory: {}", data_dir.display());
+    if let Err(e) = keys::load() {
+        error!("unable to load the identity key: {}", e);
+        std::process::exit(1);
+    }
+
+    let topic = Topic::new(topic_name());
+    info!("Hub Peer Id: {}", PEER_ID.clone());
+    info!("Topic: {}", topic.id());
+
+    let auth_keys = Keypair::<X25519Spec>::new()
+        .into_authentic(&KEYS)
+        .expect("unable to create auth keys");
+
+    let tcp = TokioDnsConfig::system(TokioTcpConfig::new()).expect("unable to read system dns config");
+    let tcp = match *PSK {
+        Some(psk) => EitherTransport::Left(
+            tcp.and_then(move |socket, _| PnetConfig::new(psk).handshake(socket)),
+        ),
+        None => EitherTransport::Right(tcp),
+    };
+    let transport = tcp
+        .upgrade(upgrade::Version::V1)
+        .authenticate(NoiseConfig::xx(auth_keys).into_authenticated())
+        .multiplex(mplex::MplexConfig::new())
+        .boxed();
+
+    let mut behavior = HubBehavior {
+        floodsub: Floodsub::new(*PEER_ID),
+        rendezvous: rendezvous::server::Behaviour::new(rendezvous::server::Config::default()),
+    };
+    behavior.floodsub.subscribe(topic.clone());
+    for channel in &CONFIG.channels {
+        behavior
+            .floodsub
+            .subscribe(Topic::new(format!("{}/{}", topic.id(), channel)));
+    }
+
+    let limits = ConnectionLimits::default()
+        .with_max_established(CONFIG.connections.max)
+        .with_max_established_per_peer(CONFIG.connections.max_per_peer);
+    let mut swarm = SwarmBuilder::new(transport, behavior, *PEER_ID)
+        .executor(Box::new(|future| {
+            tokio::spawn(future);
+        }))
+        .connection_limits(limits)
+        .build();
+
+    let mut listening = false;
+    for addr in CONFIG.listen_addrs() {
+        match swarm.listen_on(addr.clone()) {
+            Ok(_) => listening = true,
+            Err(e) => error!("unable to listen on {}: {}", addr, e),
+        }
+    }
+    if !listening {
+        panic!("swarm unable to start");
+    }
+
+    loop {
+        match swarm.select_next_some().await {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("listening on {}/p2p/{}", address, PEER_ID.clone())
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                num_established,
+                ..
+            } => {
+                if num_established.get() == 1 {
+                    info!(
+                        "{} joined ({} peers online)",
+                        peer_id,
+                        swarm.network_info().num_peers()
+                    );
+                }
+                swarm.behaviour_mut().floodsub.add_node_to_partial_view(peer_id);
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => {
+                info!(
+                    "{} left ({} peers online)",
+                    peer_id,
+                    swarm.network_info().num_peers()
+                );
+                // peers come to the hub, it never dials them back
+                swarm
+                    .behaviour_mut()
+                    .floodsub
+                    .remove_node_from_partial_view(&peer_id);
+            }
+            event => debug!("Unhandled swarm event: {:?}", event),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::store;
+use crate::unix_time;
+use log::error;
+use peer2peer::protocol::Book;
+use serde::{Deserialize, Serialize};
+
+pub const BOOKMARKS_PATH: &str = "./bookmarks.json";
+
+// a book on someone else's shelf. title and author are remembered once seen,
+// so the list still reads well while the peer is away
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub peer: String,
+    pub id: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    pub added: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Bookmarks {
+    bookmarks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    pub fn load() -> Self {
+        store::load(BOOKMARKS_PATH)
+    }
+
+    fn save(&self) {
+        if let Err(e) = store::save(BOOKMARKS_PATH, self) {
+            error!("unable to save bookmarks: {}", e);
+        }
+    }
+
+    // false when it was bookmarked already
+    pub fn add(&mut self, peer: &str, id: usize, book: Option<&Book>) -> bool {
+        if self.bookmarks.iter().any(|b| b.peer == peer && b.id == id) {
+            return false;
+        }
+        self.bookmarks.push(Bookmark {
+            peer: peer.to_owned(),
+            id,
+            title: book.map(|b| b.title.clone()),
+            author: book.map(|b| b.author.clone()),
+            added: unix_time(),
+        });
+        self.save();
+        true
+    }
+
+    pub fn remove(&mut self, peer: &str, id: usize) -> bool {
+        let before = self.bookmarks.len();
+        self.bookmarks.retain(|b| !(b.peer == peer && b.id == id));
+        if self.bookmarks.len() == before {
+            return false;
+        }
+        self.save();
+        true
+    }
+
+    // keeps titles current with what the peer offers now
+    pub fn fill_in(&mut self, peer: &str, books: &[Book]) {
+        let mut changed = false;
+        for bookmark in self.bookmarks.iter_mut().filter(|b| b.peer == peer) {
+            if let Some(book) = books.iter().find(|b| b.id == bookmark.id) {
+                if bookmark.title.as_ref() != Some(&book.title)
+                    || bookmark.author.as_ref() != Some(&book.author)
+                {
+                    bookmark.title = Some(book.title.clone());
+                    bookmark.author = Some(book.author.clone());
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.save();
+        }
+    }
+
+    // the bookmarks stay, what they remembered of the peer's books goes
+    pub fn forget_titles(&mut self, peer: &str) -> usize {
+        let mut forgotten = 0;
+        for bookmark in self.bookmarks.iter_mut().filter(|b| b.peer == peer) {
+            if bookmark.title.take().is_some() | bookmark.author.take().is_some() {
+                forgotten += 1;
+            }
+        }
+        if forgotten > 0 {
+            self.save();
+        }
+        forgotten
+    }
+
+    // follows a peer that rotated its key
+    pub fn replace_peer(&mut self, old: &str, new: &str) -> bool {
+        let mut replaced = false;
+        for bookmark in self.bookmarks.iter_mut().filter(|b| b.peer == old) {
+            bookmark.peer = new.to_owned();
+            replaced = true;
+        }
+        if replaced {
+            self.save();
+        }
+        replaced
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Bookmark> {
+        self.bookmarks.iter()
+    }
+}
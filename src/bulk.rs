@@ -0,0 +1,63 @@
+use peer2peer::protocol::Book;
+
+// selects books for bulk commands, e.g. "--author tolkien --publisher allen".
+// values match case-insensitively anywhere in the field
+#[derive(Debug, Default)]
+pub struct Filter {
+    title: Option<String>,
+    author: Option<String>,
+    publisher: Option<String>,
+    // a whole tag, not part of one, so --tag sf leaves out sf-classics
+    tag: Option<String>,
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> Result<Filter, String> {
+        let mut filter = Filter::default();
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(filter);
+        }
+        let rest = input.strip_prefix("--").ok_or_else(|| {
+            format!("expected --author, --publisher, --tag or --title, got {}", input)
+        })?;
+        for option in rest.split(" --") {
+            let (name, value) = option.split_once(' ').unwrap_or((option, ""));
+            let value = value.trim();
+            if value.is_empty() {
+                return Err(format!("--{} needs a value", name));
+            }
+            let value = Some(value.to_lowercase());
+            match name {
+                "title" => filter.title = value,
+                "author" => filter.author = value,
+                "publisher" => filter.publisher = value,
+                "tag" => filter.tag = value,
+                _ => return Err(format!("unknown option --{}", name)),
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.author.is_none()
+            && self.publisher.is_none()
+            && self.tag.is_none()
+    }
+
+    pub fn matches(&self, book: &Book) -> bool {
+        let field = |wanted: &Option<String>, value: &str| match wanted {
+            Some(wanted) => value.to_lowercase().contains(wanted),
+            None => true,
+        };
+        book.trashed.is_none()
+            && field(&self.title, &book.title)
+            && field(&self.author, &book.author)
+            && field(&self.publisher, &book.publisher)
+            && match self.tag {
+                Some(ref tag) => book.tags.iter().any(|t| t.to_lowercase() == *tag),
+                None => true,
+            }
+    }
+}
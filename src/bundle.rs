@@ -0,0 +1,197 @@
+use crate::commands::CATALOG_VERSION_PATH;
+use crate::config::{KeyStore, CONFIG, CONFIG_PATH};
+use crate::downloads::{DOWNLOADS_DIR, DOWNLOADS_PATH};
+use crate::{
+    activity, audit, bookmarks, checksums, clubs, conflicts, deletions, groups, history, invite,
+    keys, ledger, links, mailbox, outbox, peers, pins, reputation, rotation, snapshots, supernode,
+    sync, traffic,
+};
+use crate::{unix_time, Result, PEER_ID, STORAGE_PATH};
+use data_encoding::BASE64;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+const FORMAT: &str = "peer2peer node";
+const VERSION: u32 = 1;
+// the node as it was before an import, in case the wrong archive was picked
+pub const BEFORE_IMPORT_PATH: &str = "./node-before-import.json";
+
+// every file a node keeps in its data directory, by the paths the modules
+// keep them at. logs of earlier runs, migration backups and half-written
+// files stay behind
+const NODE_FILES: &[&str] = &[
+    keys::KEY_PATH,
+    CONFIG_PATH,
+    STORAGE_PATH,
+    peers::PEERS_PATH,
+    groups::GROUPS_PATH,
+    invite::INVITES_PATH,
+    rotation::ROTATIONS_PATH,
+    pins::PINS_PATH,
+    bookmarks::BOOKMARKS_PATH,
+    clubs::CLUBS_PATH,
+    ledger::LEDGER_PATH,
+    links::LINKS_PATH,
+    sync::SYNC_STATE_PATH,
+    conflicts::CONFLICTS_PATH,
+    deletions::DELETIONS_PATH,
+    outbox::OUTBOX_PATH,
+    mailbox::MAILBOX_PATH,
+    traffic::TRAFFIC_PATH,
+    reputation::REPUTATION_PATH,
+    checksums::CHECKSUMS_PATH,
+    CATALOG_VERSION_PATH,
+    DOWNLOADS_PATH,
+    supernode::AGGREGATE_PATH,
+    activity::ACTIVITY_PATH,
+    audit::AUDIT_PATH,
+    history::HISTORY_PATH,
+];
+
+// directories whose files the node keeps, one level deep
+const NODE_DIRS: &[&str] = &[DOWNLOADS_DIR, snapshots::SNAPSHOTS_DIR];
+
+// a whole node in one json file, to move it to another machine
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Bundle {
+    format: String,
+    version: u32,
+    pub peer: String,
+    pub exported_at: u64,
+    // file name to base64 content
+    pub files: BTreeMap<String, String>,
+}
+
+fn name(path: &str) -> &str {
+    path.trim_start_matches("./")
+}
+
+// names the bundle carries: the node's files, those in its directories as
+// e.g. "downloads/dune.epub", and a pre-shared key kept next to them. a key
+// elsewhere on the disk has to be copied by hand
+fn names() -> Result<Vec<String>> {
+    let mut names: Vec<String> = NODE_FILES.iter().map(|path| name(path).to_owned()).collect();
+    if let Some(ref psk) = CONFIG.psk_file {
+        if is_plain_name(psk) {
+            names.push(psk.clone());
+        }
+    }
+    for dir in NODE_DIRS {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("unable to read {}: {}", dir, e).into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let file = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_file() && is_plain_name(&file) && !file.ends_with(".tmp") {
+                names.push(format!("{}/{}", name(dir), file));
+            }
+        }
+    }
+    Ok(names)
+}
+
+// only what export would write, so an archive can't put other files in the
+// data directory or write elsewhere
+fn is_node_file(entry: &str) -> bool {
+    let psk = CONFIG.psk_file.as_deref().filter(|psk| is_plain_name(psk));
+    match entry.split_once('/') {
+        Some((dir, file)) => NODE_DIRS.iter().any(|d| name(d) == dir) && is_plain_name(file),
+        None => NODE_FILES.iter().any(|path| name(path) == entry) || psk == Some(entry),
+    }
+}
+
+// no directories and nothing hidden
+fn is_plain_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+pub fn export(path: &Path) -> Result<Bundle> {
+    let mut files = BTreeMap::new();
+    for name in names()? {
+        match std::fs::read(&name) {
+            Ok(content) => {
+                files.insert(name, BASE64.encode(&content));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(format!("unable to read {}: {}", name, e).into()),
+        }
+    }
+    let bundle = Bundle {
+        format: FORMAT.to_owned(),
+        version: VERSION,
+        peer: PEER_ID.to_string(),
+        exported_at: unix_time(),
+        files,
+    };
+    // it holds the private key, so only we may read it, an archive written
+    // over included
+    let mut file = std::fs::File::create(path)?;
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(&serde_json::to_vec(&bundle)?)?;
+    Ok(bundle)
+}
+
+// replaces this node's files with the bundle's. files the bundle doesn't
+// have are removed, so nothing of this node mixes with the imported one
+pub fn import(path: &Path) -> Result<Bundle> {
+    let bundle: Bundle = serde_json::from_slice(&std::fs::read(path)?)?;
+    if bundle.format != FORMAT {
+        return Err("not a node archive".into());
+    }
+    if bundle.version > VERSION {
+        return Err(format!("archive version {} is newer than this node", bundle.version).into());
+    }
+    // decoded and checked before anything is touched
+    let mut files = Vec::new();
+    for (name, content) in &bundle.files {
+        if !is_node_file(name) {
+            return Err(format!("archive holds {}, which isn't a node file", name).into());
+        }
+        let content = BASE64
+            .decode(content.as_bytes())
+            .map_err(|e| format!("{} is damaged: {}", name, e))?;
+        files.push((name, content));
+    }
+    export(Path::new(BEFORE_IMPORT_PATH))?;
+    for name in names()? {
+        if !bundle.files.contains_key(&name) {
+            let _ = std::fs::remove_file(&name);
+        }
+    }
+    for dir in NODE_DIRS {
+        std::fs::create_dir_all(dir)?;
+    }
+    for (name, content) in files {
+        let tmp = format!("{}.tmp", name);
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, name)?;
+    }
+    Ok(bundle)
+}
+
+// the library.json a node archive holds, None when the content isn't one
+pub fn library_in(content: &[u8]) -> Option<Result<Vec<u8>>> {
+    let bundle: Bundle = serde_json::from_slice(content).ok()?;
+    if bundle.format != FORMAT {
+        return None;
+    }
+    let library = match bundle.files.get("library.json") {
+        Some(library) => library,
+        None => return Some(Err("the archive holds no library".into())),
+    };
+    let library = BASE64
+        .decode(library.as_bytes())
+        .map_err(|e| format!("library.json is damaged: {}", e).into());
+    Some(library)
+}
+
+// a key held by the system keyring isn't a file, it stays on this machine
+pub fn identity_in_keyring() -> bool {
+    matches!(CONFIG.identity.store, KeyStore::Keyring)
+}
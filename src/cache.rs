@@ -0,0 +1,174 @@
+use log::info;
+use peer2peer::protocol::{Book, Library};
+use std::collections::HashMap;
+
+struct Entry {
+    bytes: usize,
+    // the use counter at the last lookup, the lowest goes first
+    used: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    pub peers: usize,
+    pub bytes: usize,
+    pub budget: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evicted: u64,
+}
+
+// the last catalog of each peer, kept under a size budget. the catalog looked
+// at least recently is dropped first, an `ls books` fetches it again
+pub struct CatalogCache {
+    budget: usize,
+    catalogs: HashMap<String, Library>,
+    entries: HashMap<String, Entry>,
+    // the newest catalog version each peer sent, kept when its catalog is
+    // dropped to stay under the budget
+    versions: HashMap<String, u64>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evicted: u64,
+}
+
+impl CatalogCache {
+    pub fn new(budget: usize) -> Self {
+        CatalogCache {
+            budget,
+            catalogs: HashMap::new(),
+            entries: HashMap::new(),
+            versions: HashMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evicted: 0,
+        }
+    }
+
+    // every catalog, for what looks across peers. doesn't count as a use
+    pub fn all(&self) -> &HashMap<String, Library> {
+        &self.catalogs
+    }
+
+    // without counting it as a use
+    pub fn peek(&self, peer: &str) -> Option<&Library> {
+        self.catalogs.get(peer)
+    }
+
+    pub fn get(&mut self, peer: &str) -> Option<&Library> {
+        match self.entries.get_mut(peer) {
+            Some(entry) => {
+                self.clock += 1;
+                entry.used = self.clock;
+                self.hits += 1;
+            }
+            None => self.misses += 1,
+        }
+        self.catalogs.get(peer)
+    }
+
+    // whether a catalog is older than one the peer already sent, e.g. the
+    // answer to an earlier request that took the long way
+    pub fn stale(&self, peer: &str, version: Option<u64>) -> bool {
+        match (version, self.versions.get(peer)) {
+            (Some(version), Some(&newest)) => version < newest,
+            _ => false,
+        }
+    }
+
+    // versions older peers don't send
+    pub fn insert(&mut self, peer: String, catalog: Library, version: Option<u64>) {
+        if let Some(version) = version {
+            self.versions.insert(peer.clone(), version);
+        }
+        self.clock += 1;
+        let entry = Entry {
+            bytes: size_of(&catalog),
+            used: self.clock,
+        };
+        self.entries.insert(peer.clone(), entry);
+        self.catalogs.insert(peer.clone(), catalog);
+        self.evict(&peer);
+    }
+
+    // drops the peer's books that don't pass, returns how many went
+    pub fn retain(&mut self, peer: &str, keep: impl Fn(&Book) -> bool) -> usize {
+        let catalog = match self.catalogs.get_mut(peer) {
+            Some(catalog) => catalog,
+            None => return 0,
+        };
+        let before = catalog.len();
+        catalog.retain(|b| keep(b));
+        let removed = before - catalog.len();
+        if removed > 0 {
+            let bytes = size_of(catalog);
+            if let Some(entry) = self.entries.get_mut(peer) {
+                entry.bytes = bytes;
+            }
+        }
+        removed
+    }
+
+    pub fn remove(&mut self, peer: &str) -> bool {
+        self.versions.remove(peer);
+        self.entries.remove(peer);
+        self.catalogs.remove(peer).is_some()
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict("");
+    }
+
+    // the versions go too, or a peer's next catalog could still be refused
+    // as older than one we no longer have
+    pub fn clear(&mut self) -> usize {
+        let peers = self.catalogs.len();
+        self.catalogs.clear();
+        self.entries.clear();
+        self.versions.clear();
+        peers
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            peers: self.catalogs.len(),
+            bytes: self.bytes(),
+            budget: self.budget,
+            hits: self.hits,
+            misses: self.misses,
+            evicted: self.evicted,
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        self.entries.values().map(|e| e.bytes).sum()
+    }
+
+    // the catalog that just came in stays even when it alone is over budget,
+    // it's what was asked for
+    fn evict(&mut self, keep: &str) {
+        while self.bytes() > self.budget {
+            let oldest = self
+                .entries
+                .iter()
+                .filter(|(peer, _)| peer.as_str() != keep)
+                .min_by_key(|(_, e)| e.used)
+                .map(|(peer, _)| peer.clone());
+            let peer = match oldest {
+                Some(peer) => peer,
+                None => return,
+            };
+            self.entries.remove(&peer);
+            self.catalogs.remove(&peer);
+            self.evicted += 1;
+            info!("dropped {}'s catalog from the cache", peer);
+        }
+    }
+}
+
+fn size_of(catalog: &Library) -> usize {
+    serde_json::to_vec(catalog).map(|json| json.len()).unwrap_or(0)
+}
@@ -0,0 +1,176 @@
+use crate::store;
+use data_encoding::HEXLOWER;
+use log::error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const CHECKSUMS_PATH: &str = "./checksums.json";
+
+// how long after a change a file's time can stay the same
+const STALE: u64 = 2_000_000_000;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Checksum {
+    size: u64,
+    // unix times in nanoseconds
+    modified: u64,
+    #[serde(default)]
+    hashed: u64,
+    sha256: String,
+}
+
+// sha256 of attached files, hashed again only when one's size or time
+// changes. the files are the user's, the node only reads them, except for
+// dedup files --remove --yes
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Checksums {
+    files: BTreeMap<String, Checksum>,
+}
+
+impl Checksums {
+    pub fn load() -> Self {
+        store::load(CHECKSUMS_PATH)
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = store::save(CHECKSUMS_PATH, self) {
+            error!("unable to save checksums: {}", e);
+        }
+    }
+
+    pub fn of(&mut self, path: &str) -> io::Result<String> {
+        let metadata = std::fs::metadata(path)?;
+        let modified = nanos(metadata.modified()?);
+        match self.files.get(path) {
+            // a file changed within a tick of its hashing may have changed
+            // again without its time moving, on coarse clocks a whole second
+            Some(known)
+                if known.size == metadata.len()
+                    && known.modified == modified
+                    && known.hashed > modified.saturating_add(STALE) =>
+            {
+                return Ok(known.sha256.clone());
+            }
+            _ => {}
+        }
+        let hashed = nanos(SystemTime::now());
+        let sha256 = hash(path)?;
+        let checksum = Checksum {
+            size: metadata.len(),
+            modified,
+            hashed,
+            sha256: sha256.clone(),
+        };
+        self.files.insert(path.to_owned(), checksum);
+        Ok(sha256)
+    }
+
+    // another of the paths with the same content as this one. only files
+    // of the same size are hashed
+    pub fn copy_of<'a>(
+        &mut self,
+        path: &str,
+        others: impl IntoIterator<Item = &'a str>,
+    ) -> io::Result<Option<&'a str>> {
+        let size = std::fs::metadata(path)?.len();
+        let mut sha256 = None;
+        for other in others {
+            if other == path || !matches!(std::fs::metadata(other), Ok(m) if m.len() == size) {
+                continue;
+            }
+            if same_file(path, other)? {
+                return Ok(Some(other));
+            }
+            let ours = match sha256 {
+                Some(ref ours) => ours,
+                None => sha256.insert(self.of(path)?),
+            };
+            if self.of(other).ok().as_ref() == Some(ours) {
+                return Ok(Some(other));
+            }
+        }
+        Ok(None)
+    }
+
+    // the paths grouped by content, only groups of more than one file. paths
+    // to the same file are in one group but don't count as copies. files
+    // that can't be read are left out
+    pub fn identical<'a>(&mut self, paths: &HashSet<&'a str>) -> Vec<Vec<&'a str>> {
+        let mut by_size: HashMap<u64, Vec<&str>> = HashMap::new();
+        for path in paths {
+            if let Ok(metadata) = std::fs::metadata(path) {
+                by_size.entry(metadata.len()).or_default().push(path);
+            }
+        }
+        let mut by_hash: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for same_size in by_size.into_values().filter(|paths| paths.len() > 1) {
+            for path in same_size {
+                if let Ok(sha256) = self.of(path) {
+                    by_hash.entry(sha256).or_default().push(path);
+                }
+            }
+        }
+        by_hash
+            .into_values()
+            .filter(|paths| paths.iter().any(|p| !matches!(same_file(paths[0], p), Ok(true))))
+            .collect()
+    }
+
+    // forgets files no book points at anymore
+    pub fn retain(&mut self, paths: &HashSet<&str>) {
+        self.files.retain(|path, _| paths.contains(path.as_str()));
+    }
+}
+
+fn hash(path: &str) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            n => hasher.update(&buffer[..n]),
+        }
+    }
+    Ok(HEXLOWER.encode(&hasher.finalize()))
+}
+
+fn nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default()
+}
+
+// whether both paths lead to one file, through links or spelled differently
+#[cfg(unix)]
+pub fn same_file(a: &str, b: &str) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (a, b) = (std::fs::metadata(a)?, std::fs::metadata(b)?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+#[cfg(not(unix))]
+pub fn same_file(a: &str, b: &str) -> io::Result<bool> {
+    Ok(std::fs::canonicalize(a)? == std::fs::canonicalize(b)?)
+}
+
+// compares the two files byte by byte
+pub fn same_bytes(a: &str, b: &str) -> io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+    let (mut ours, mut theirs) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    loop {
+        let n = a.read(&mut ours)?;
+        if n == 0 {
+            return Ok(b.read(&mut theirs)? == 0);
+        }
+        b.read_exact(&mut theirs[..n])?;
+        if ours[..n] != theirs[..n] {
+            return Ok(false);
+        }
+    }
+}
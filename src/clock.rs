@@ -0,0 +1,46 @@
+use crate::unix_time;
+use log::error;
+use peer2peer::protocol::{Book, Library};
+
+// how far ahead of ours another node's clock may be. a timestamp beyond it
+// is a broken clock or a forgery, and isn't taken at its word
+pub const MAX_SKEW: u64 = 15 * 60;
+
+// a lamport counter: an edit gets a revision above any the library holds,
+// merged edits of other devices included, so edits are ordered by what each
+// side had seen rather than by whose wall clock is right
+pub fn next_revision(library: &Library) -> u64 {
+    let latest = library.iter().filter_map(|b| b.revision).max().unwrap_or(0);
+    latest.saturating_add(1)
+}
+
+// marks a local edit. the time is for people, the revision for ordering
+pub fn stamp(book: &mut Book, now: u64, revision: u64) {
+    book.modified = Some(now);
+    book.revision = Some(revision);
+}
+
+// an edit time no later than the skew allows, for comparing edits of
+// devices that don't count revisions. the book keeps its own
+pub fn bounded(at: u64) -> u64 {
+    at.min(unix_time() + MAX_SKEW)
+}
+
+// how many books are dated too far in the future
+pub fn ahead(library: &Library) -> usize {
+    let bound = unix_time() + MAX_SKEW;
+    library
+        .iter()
+        .filter(|b| matches!(b.modified, Some(at) if at > bound))
+        .count()
+}
+
+// false for a time too far in the future, told once per message
+pub fn plausible(at: u64, what: &str, from: &str) -> bool {
+    let now = unix_time();
+    if at <= now + MAX_SKEW {
+        return true;
+    }
+    error!("{} from {} is dated {}s ahead, is its clock wrong?", what, from, at - now);
+    false
+}
@@ -0,0 +1,176 @@
+use crate::store;
+use crate::unix_time;
+use log::error;
+use peer2peer::protocol::{ClubBook, ClubState, Milestone};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const CLUBS_PATH: &str = "./clubs.json";
+
+// a long schedule is a plan nobody follows, and it has to fit in one message
+pub const MAX_MILESTONES: usize = 12;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Club {
+    // peers seen on the club's topic, remembered so the list reads well while
+    // they're away
+    pub members: BTreeSet<String>,
+    pub state: ClubState,
+}
+
+// what came of a state another member sent
+pub enum Merge {
+    // theirs was newer and is ours now
+    Adopted,
+    // ours is newer, they should get it
+    Behind(ClubState),
+    Same,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Clubs {
+    clubs: BTreeMap<String, Club>,
+}
+
+impl Clubs {
+    pub fn load() -> Self {
+        store::load(CLUBS_PATH)
+    }
+
+    fn save(&self) {
+        if let Err(e) = store::save(CLUBS_PATH, self) {
+            error!("unable to save clubs: {}", e);
+        }
+    }
+
+    // false when we're in it already
+    pub fn join(&mut self, name: &str) -> bool {
+        if self.clubs.contains_key(name) {
+            return false;
+        }
+        let club = Club {
+            members: BTreeSet::new(),
+            state: ClubState {
+                club: name.to_owned(),
+                ..ClubState::default()
+            },
+        };
+        self.clubs.insert(name.to_owned(), club);
+        self.save();
+        true
+    }
+
+    pub fn leave(&mut self, name: &str) -> bool {
+        if self.clubs.remove(name).is_none() {
+            return false;
+        }
+        self.save();
+        true
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Club> {
+        self.clubs.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.clubs.keys()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Club)> {
+        self.clubs.iter()
+    }
+
+    // true for someone we hadn't seen in the club before
+    pub fn add_member(&mut self, name: &str, peer: &str) -> bool {
+        let added = match self.clubs.get_mut(name) {
+            Some(club) => club.members.insert(peer.to_owned()),
+            None => false,
+        };
+        if added {
+            self.save();
+        }
+        added
+    }
+
+    pub fn remove_member(&mut self, name: &str, peer: &str) -> bool {
+        let removed = match self.clubs.get_mut(name) {
+            Some(club) => club.members.remove(peer),
+            None => false,
+        };
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    // the new state to announce, None when we're not in the club
+    pub fn set_book(&mut self, name: &str, book: Option<ClubBook>) -> Option<ClubState> {
+        self.change(name, |state| state.book = book)
+    }
+
+    // adds a milestone, keeping them in date order
+    pub fn schedule(&mut self, name: &str, milestone: Milestone) -> Option<ClubState> {
+        self.change(name, |state| {
+            state.schedule.push(milestone);
+            state.schedule.sort_by_key(|m| m.by);
+        })
+    }
+
+    pub fn clear_schedule(&mut self, name: &str) -> Option<ClubState> {
+        self.change(name, |state| state.schedule.clear())
+    }
+
+    fn change(&mut self, name: &str, edit: impl FnOnce(&mut ClubState)) -> Option<ClubState> {
+        let club = self.clubs.get_mut(name)?;
+        edit(&mut club.state);
+        // strictly newer, so two changes in the same second still replace each other
+        club.state.updated = unix_time().max(club.state.updated + 1);
+        let state = club.state.clone();
+        self.save();
+        Some(state)
+    }
+
+    // the newest state wins. two different changes made in the same second are
+    // settled by their encoding, so every member ends up with the same one
+    pub fn merge(&mut self, theirs: ClubState) -> Merge {
+        let club = match self.clubs.get_mut(&theirs.club) {
+            Some(club) => club,
+            None => return Merge::Same,
+        };
+        let newer = match theirs.updated.cmp(&club.state.updated) {
+            Ordering::Equal if theirs != club.state => {
+                let encoded = |state: &ClubState| serde_json::to_string(state).unwrap_or_default();
+                encoded(&theirs) > encoded(&club.state)
+            }
+            ordering => ordering == Ordering::Greater,
+        };
+        if newer {
+            club.state = theirs;
+            self.save();
+            Merge::Adopted
+        } else if theirs != club.state {
+            Merge::Behind(club.state.clone())
+        } else {
+            Merge::Same
+        }
+    }
+}
+
+// "due in 3d" or "was due 2h ago". rounded up, so a week from now doesn't
+// read as 6d a second later, and a moment ago isn't 0m
+pub fn due(by: u64, now: u64) -> String {
+    let span = |seconds: u64| {
+        let (unit, name) = match seconds {
+            0..=3599 => (60, "m"),
+            3600..=86399 => (3600, "h"),
+            _ => (86400, "d"),
+        };
+        format!("{}{}", (seconds as f64 / unit as f64).ceil(), name)
+    };
+    if by >= now {
+        format!("due in {}", span(by - now))
+    } else {
+        format!("was due {} ago", span(now - by))
+    }
+}
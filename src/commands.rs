@@ -1,33 +1,432 @@
-use crate::ListResponse;
+use crate::activity::{self, Activity};
+use crate::archive;
+use crate::audit::{self, Access};
+use crate::conflicts::{self, Base, Conflicts};
+use crate::bookmarks::Bookmarks;
+use crate::bundle;
+use crate::clubs::{self, MAX_MILESTONES};
+use crate::bulk::Filter;
+use crate::checksums::{self, Checksums};
+use crate::clock;
+use crate::config::CONFIG;
+use crate::datadir;
+use crate::debug;
+use crate::deletions::Deletions;
+use crate::downloads::{self, Download, Downloads, DOWNLOADS_DIR};
+use crate::forget;
+use crate::fsck;
+use crate::groups::Groups;
+use crate::history::{self, Change};
+use crate::inflight::Kind;
+use crate::invite::{Invite, Invites};
+use crate::ipfs;
+use crate::keys;
+use crate::ledger::{self, Ledger};
+use crate::links::Links;
+use crate::operations::{self, Operation};
+use crate::liveness::State;
+use crate::pins::Pins;
+use crate::presence;
+use crate::progress::Progress;
+use crate::recommend;
+use crate::merge::{self, Policy};
+use crate::rotation::{self, Rotations};
+use crate::sealing;
+use crate::series;
+use crate::snapshots;
+use crate::store;
+use crate::sync::{self, Replica};
+use crate::traces::Span;
+use peer2peer::protocol::{
+    valid_name, Advert, Availability, BookDetail, BookRequest, ClubBook, ClubState, Condition,
+    Deposit, FileFormat, LoanEvent, LoanRecord, Message, Milestone, Nack, NackReason,
+    ReadingStatus, Relayed, Summary, SummaryMode, HIDEABLE, MAX_REVIEW,
+};
+use peer2peer::bibtex;
+use peer2peer::eviction::Eviction;
+use peer2peer::formats;
+use peer2peer::goodreads;
+use peer2peer::query::Query;
+use peer2peer::respond::{self, share_ended};
+use peer2peer::schema;
 
-use super::{Book, BookBehavior, Library, ListMode, ListRequest, STORAGE_PATH, TOPIC};
-use libp2p::swarm::Swarm;
+use super::{
+    channel_topic, club_topic, publish, show_club, unix_time, Book, BookBehavior, ChatMessage,
+    Library, ListMode, ListRequest, Reply, INVITES, KEYS, PEER_ID, PSK, RELAYS, STORAGE_PATH,
+    TOPIC,
+};
+use libp2p::{
+    core::ConnectedPoint,
+    floodsub::Topic,
+    identity,
+    multiaddr::Protocol,
+    request_response::ResponseChannel,
+    swarm::{dial_opts::DialOpts, Swarm},
+    Multiaddr, PeerId,
+};
 use log::{error, info};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::{fs, sync::mpsc};
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
 
-async fn read_local_library() -> Result<Library> {
+pub async fn read_local_library() -> Result<Library> {
     let content = fs::read(STORAGE_PATH).await?;
-    let result = serde_json::from_slice(&content)?;
-    Ok(result)
+    schema::parse(&content)
 }
 
-async fn write_local_library(library: &Library) -> Result<()> {
-    let json = serde_json::to_string(&library)?;
-    fs::write(STORAGE_PATH, &json).await?;
+pub async fn write_local_library(library: &Library) -> Result<()> {
+    write_library(library, None).await
+}
+
+// what changed goes into the history first, the library file is a snapshot of
+// where the history got to. it's written next to the library and renamed over
+// it, so a failure mid-write leaves the previous one intact, and the next
+// start rebuilds it from the history
+async fn write_library(library: &Library, undoes: Option<u64>) -> Result<()> {
+    if archive::read_only() {
+        return Err("this node is a read-only archive".into());
+    }
+    let before = read_local_library().await.unwrap_or_default();
+    let last = history::record(&before, library, undoes)?;
+    let json = schema::to_snapshot(library, Some(last))?;
+    let tmp = format!("{}.tmp", STORAGE_PATH);
+    fs::write(&tmp, &json).await?;
+    fs::rename(&tmp, STORAGE_PATH).await?;
+    let mut deletions = Deletions::load();
+    if deletions.record(&before, library) {
+        deletions.save();
+    }
     Ok(())
 }
 
-pub async fn handle_list_peers(swarm: &mut Swarm<BookBehavior>) {
-    info!("Peers discovered: ");
-    let nodes = swarm.behaviour().mdns.discovered_nodes();
-    let mut unique_peers = std::collections::HashSet::new();
+// "ls peers --verbose" adds each connected peer's connections and protocols
+pub async fn handle_list_peers(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let verbose = match cmd.strip_prefix("ls peers").map(str::trim) {
+        Some("") => false,
+        Some("--verbose") | Some("-v") => true,
+        _ => {
+            error!("format should be: ls peers [--verbose]");
+            return;
+        }
+    };
+    info!("Peers: ");
+    let behaviour = swarm.behaviour();
+    // discovered on the lan, connected, or heard from in this or an earlier run
+    let mut peers = behaviour.discovered_peers();
+    peers.extend(swarm.connected_peers());
+    peers.extend(behaviour.liveness.peers());
+    for peer in peers {
+        let liveness = describe_state(behaviour.liveness.state(&peer, swarm.is_connected(&peer)));
+        let name = match behaviour.names.get(&peer) {
+            Some(name) if behaviour.impostors.contains(&peer) => format!(" {}, not trusted", name),
+            Some(name) => format!(" {}", name),
+            None => String::new(),
+        };
+        let presence = match behaviour.presence.of(&peer) {
+            Some(presence) => format!(", {}", presence::describe(presence)),
+            None => String::new(),
+        };
+        let advert = behaviour.adverts.get(&peer).map(describe_advert).unwrap_or_default();
+        match behaviour.capabilities.get(&peer) {
+            Some(caps) => {
+                let caps: Vec<&str> = caps.iter().map(String::as_str).collect();
+                let caps = caps.join(", ");
+                info!("{}{}{} ({}) - {}{}", peer, name, advert, caps, liveness, presence)
+            }
+            None => info!("{}{}{} - {}{}", peer, name, advert, liveness, presence),
+        }
+        if verbose {
+            list_connection_details(behaviour, &peer);
+        }
+    }
+}
+
+fn describe_state(state: Option<State>) -> String {
+    match state {
+        Some(State::Connected) => "connected".to_owned(),
+        Some(State::RecentlySeen(ago)) => format!("seen {}", activity::ago(ago)),
+        Some(State::Gone(ago)) => format!("gone, last heard {}", activity::ago(ago)),
+        None => "discovered".to_owned(),
+    }
+}
+
+// "Alice's SciFi Shelf" (412 books)
+fn describe_advert(advert: &Advert) -> String {
+    match (&advert.title, advert.books) {
+        (Some(title), Some(books)) => format!(" {:?} ({} books)", title, books),
+        (Some(title), None) => format!(" {:?}", title),
+        (None, Some(books)) => format!(" ({} books)", books),
+        (None, None) => String::new(),
+    }
+}
+
+fn list_connection_details(behaviour: &BookBehavior, peer: &PeerId) {
+    let details = match behaviour.connections.get(peer) {
+        Some(details) => details,
+        None => return,
+    };
+    for endpoint in &details.endpoints {
+        match endpoint {
+            ConnectedPoint::Dialer { address, .. } => info!("    {} (outbound)", address),
+            ConnectedPoint::Listener { send_back_addr, .. } => {
+                info!("    {} (inbound)", send_back_addr)
+            }
+        }
+    }
+    if !details.listen_addrs.is_empty() {
+        let addrs: Vec<String> = details.listen_addrs.iter().map(|a| a.to_string()).collect();
+        info!("    listening on {}", addrs.join(", "));
+    }
+    if let Some(agent) = &details.agent_version {
+        info!("    agent {}", agent);
+    }
+    if !details.protocols.is_empty() {
+        info!("    protocols {}", details.protocols.join(", "));
+    }
+    match details.rtt {
+        Some(rtt) => info!("    latency {}ms", rtt.as_millis()),
+        None => info!("    latency not measured yet"),
+    }
+}
+
+// the result is reported when the next ping on its connection comes back,
+// which is right away for a connection opened for it
+pub fn handle_ping(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let peer: PeerId = match cmd.strip_prefix("ping ").map(str::trim).map(str::parse) {
+        Some(Ok(peer)) => peer,
+        _ => {
+            error!("format should be: ping <peer id>");
+            return;
+        }
+    };
+    if peer == *PEER_ID {
+        error!("that's us");
+        return;
+    }
+    if swarm.is_connected(&peer) {
+        info!("pinging {}", peer);
+    } else {
+        // known addresses, plus whatever mdns or the hubs found
+        let addrs = swarm.behaviour().peer_store.addrs_of(&peer);
+        let opts = DialOpts::peer_id(peer)
+            .addresses(addrs)
+            .extend_addresses_through_behaviour()
+            .build();
+        if let Err(e) = swarm.dial(opts) {
+            error!("ping to {} failed, unable to dial: {}", peer, e);
+            return;
+        }
+        info!("dialing {} to ping it", peer);
+    }
+    swarm.behaviour_mut().connections.probe(peer);
+}
+
+// "presence away back at 6" or "presence dnd", just "presence" shows ours
+pub fn handle_presence(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let presences = &mut swarm.behaviour_mut().presence;
+    match cmd.strip_prefix("presence").map(str::trim) {
+        Some("") => (),
+        Some(input) => match presence::parse(input) {
+            Some(presence) => presences.set(presence),
+            None => {
+                error!("format should be: presence online|away|dnd [status]");
+                return;
+            }
+        },
+        None => return,
+    }
+    info!("you are {}", presence::describe(&presences.own));
+}
+
+pub fn handle_list_pins() {
+    let pins = Pins::load();
+    let mut empty = true;
+    for (name, peer) in pins.iter() {
+        info!("{} = {}", name, peer);
+        empty = false;
+    }
+    if empty {
+        info!("no names pinned yet");
+    }
+}
+
+// "bookmark <peer id> <book id>" remembers a book on someone else's shelf,
+// "bookmark rm <peer id> <book id>" forgets it
+pub fn handle_bookmark(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    let (remove, peer, id) = match args.as_slice() {
+        ["rm", peer, id] => (true, peer, id),
+        [peer, id] => (false, peer, id),
+        _ => {
+            error!("format should be: bookmark [rm] <peer id> <book id>");
+            return;
+        }
+    };
+    let (peer, id) = match (peer.parse::<PeerId>(), id.parse::<usize>()) {
+        (Ok(peer), Ok(id)) => (peer.to_string(), id),
+        _ => {
+            error!("invalid peer id or book id");
+            return;
+        }
+    };
+    let mut bookmarks = Bookmarks::load();
+    if remove {
+        if !bookmarks.remove(&peer, id) {
+            error!("book {} of {} isn't bookmarked", id, peer);
+        }
+        return;
+    }
+    let book = swarm
+        .behaviour_mut()
+        .remote_catalogs
+        .get(&peer)
+        .and_then(|catalog| catalog.iter().find(|b| b.id == id));
+    if book.is_none() {
+        info!("book {} isn't in {}'s catalog as far as we know, bookmarking it anyway", id, peer);
+    }
+    if bookmarks.add(&peer, id, book) {
+        info!("bookmarked book {} of {}", id, peer);
+    } else {
+        info!("book {} of {} is bookmarked already", id, peer);
+    }
+}
+
+// whether each bookmarked peer is around and, going by the last catalog we
+// got from it, still offers the book
+pub fn handle_list_bookmarks(swarm: &mut Swarm<BookBehavior>) {
+    let bookmarks = Bookmarks::load();
+    let mut empty = true;
+    for bookmark in bookmarks.iter() {
+        empty = false;
+        let title = match (&bookmark.title, &bookmark.author) {
+            (Some(title), Some(author)) => format!("{} by {}", title, author),
+            _ => format!("book {}", bookmark.id),
+        };
+        let peer = bookmark.peer.parse::<PeerId>().ok();
+        let online = match peer {
+            Some(peer) if swarm.is_connected(&peer) => match swarm.behaviour().presence.of(&peer) {
+                Some(presence) => presence::describe(presence),
+                None => "online".to_owned(),
+            },
+            _ => "offline".to_owned(),
+        };
+        let offered = match swarm.behaviour_mut().remote_catalogs.get(&bookmark.peer) {
+            Some(catalog) if catalog.iter().any(|b| b.id == bookmark.id) => "offered",
+            Some(_) => "not offered",
+            None => "not checked yet",
+        };
+        info!("{} (#{} of {}): {}, {}", title, bookmark.id, bookmark.peer, online, offered);
+    }
+    if empty {
+        info!("no bookmarks yet");
+    } else {
+        info!("ls books <peer id> brings a peer's catalog up to date");
+    }
+}
+
+// takes a peer's word for its nickname, e.g. after it lost its key
+pub fn handle_trust(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let peer: PeerId = match cmd.strip_prefix("trust ").map(str::trim).map(str::parse) {
+        Some(Ok(peer)) => peer,
+        _ => {
+            error!("format should be: trust <peer id>");
+            return;
+        }
+    };
+    let behaviour = swarm.behaviour_mut();
+    let name = match behaviour.names.get(&peer) {
+        Some(name) => name.clone(),
+        None => {
+            error!("{} hasn't announced a name", peer);
+            return;
+        }
+    };
+    behaviour.impostors.remove(&peer);
+    if Pins::load().pin(&name, &peer.to_string()) {
+        info!("{} is now pinned to {}", name, peer);
+    } else {
+        info!("{} was already pinned to {}", name, peer);
+    }
+}
+
+// prints a string a friend can paste into accept-invite to reach us
+pub fn handle_invite(swarm: &mut Swarm<BookBehavior>) {
+    let mut addrs: Vec<Multiaddr> = swarm.external_addresses().map(|a| a.addr.clone()).collect();
+    for addr in swarm.listeners().filter(|a| reachable_by_others(a)) {
+        if !addrs.contains(addr) {
+            addrs.push(addr.clone());
+        }
+    }
+    if addrs.is_empty() {
+        error!("no address others could reach us on yet");
+        return;
+    }
+    let mut invite = Invite::new(&PEER_ID, &addrs);
+    invite.name = CONFIG.name.clone().filter(|name| valid_name(name));
+    invite.network = CONFIG.network.clone().or_else(|| INVITES.network().cloned());
+    if let Some(psk) = PSK.as_ref() {
+        invite = invite.with_psk(psk);
+        info!("this invite holds the key to our private network, only give it to people you'd give swarm.key");
+    }
+    info!("{}", invite.encode());
+}
 
-    for peer in nodes {
-        unique_peers.insert(peer);
+// loopback and link local addresses are no use to anyone else
+fn reachable_by_others(addr: &Multiaddr) -> bool {
+    match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) => !ip.is_loopback() && !ip.is_unspecified() && !ip.is_link_local(),
+        Some(Protocol::Ip6(ip)) => {
+            !ip.is_loopback() && !ip.is_unspecified() && (ip.segments()[0] & 0xffc0) != 0xfe80
+        }
+        _ => true,
     }
+}
 
-    unique_peers.iter().for_each(|p| info!("{}", p))
+// adds the inviting peer as a bootstrap peer and pins the name it goes by
+pub fn handle_accept_invite(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let invite = match cmd.strip_prefix("accept-invite").map(Invite::decode) {
+        Some(Ok(invite)) => invite,
+        Some(Err(e)) => {
+            error!("invalid invite: {}", e);
+            return;
+        }
+        None => return,
+    };
+    let peer = invite.peer_id().expect("checked when decoding");
+    if peer == *PEER_ID {
+        error!("that's our own invite");
+        return;
+    }
+    let psk = invite.psk().expect("checked when decoding");
+    // the transport and topic are set up at startup, so joining another
+    // network waits for a restart. the config wins over invites
+    let new_psk = CONFIG.psk_file.is_none() && psk.is_some() && psk != *PSK;
+    let new_network = CONFIG.network.is_none()
+        && invite.network.is_some()
+        && invite.network.as_ref() != INVITES.network();
+    let bootstrap = invite.bootstrap();
+    let name = invite.name.clone().filter(|name| valid_name(name));
+    if let Err(e) = Invites::load().accept(invite) {
+        error!("unable to save invite: {}", e);
+        return;
+    }
+    info!("accepted invite from {}", peer);
+    if let Some(name) = name {
+        swarm.behaviour_mut().impostors.remove(&peer);
+        Pins::load().pin(&name, &peer.to_string());
+        info!("{} is now pinned to {}", name, peer);
+    }
+    if new_psk || new_network {
+        info!("restart to join their network");
+        return;
+    }
+    let addrs = bootstrap.into_iter().map(|(_, addr)| addr).collect();
+    if let Err(e) = swarm.dial(DialOpts::peer_id(peer).addresses(addrs).build()) {
+        error!("unable to dial {}: {}", peer, e);
+    }
 }
 
 pub async fn handle_add_book(cmd: &str) {
@@ -46,101 +445,3338 @@ pub async fn handle_add_book(cmd: &str) {
     }
 }
 
-async fn add_new_book(title: &str, author: &str, publisher: &str) -> Result<()> {
+pub async fn add_new_book(title: &str, author: &str, publisher: &str) -> Result<()> {
     let mut local_library = read_local_library().await?;
     let next_id = match local_library.iter().max_by_key(|book| book.id) {
         Some(val) => val.id + 1,
         None => 0,
     };
+    let revision = clock::next_revision(&local_library);
     local_library.push(Book {
         id: next_id,
+        uid: schema::new_uid(),
         title: title.to_owned(),
         author: author.to_owned(),
         publisher: publisher.to_owned(),
         public: false,
+        modified: Some(unix_time()),
+        revision: Some(revision),
+        ..Default::default()
     });
     write_local_library(&local_library).await?;
     info!(
         "added book: {} by {} - published by {}",
         title, author, publisher
     );
+    activity::record(Activity::BookAdded {
+        title: title.to_owned(),
+    });
 
     Ok(())
 }
 
 pub async fn handle_share_book(cmd: &str) {
     if let Some(input) = cmd.strip_prefix("share book") {
-        match input.trim() {
-            title => {
-                if let Err(e) = share_book(title).await {
-                    info!("error sharing book {}: {}", title, e);
-                } else {
-                    info!("now sharing book: {}", title);
-                }
+        // "share book <title> --for 7d" goes back to private after a week
+        let (input, window) = match input.trim().rsplit_once("--for") {
+            Some((rest, window)) => (rest, Some(window.trim())),
+            None => (input, None),
+        };
+        let until = match window.map(activity::parse_duration) {
+            Some(Some(window)) => Some(unix_time() + window),
+            Some(None) => {
+                error!("usage: share book <title or id> [@group] [--for 7d], with m, h, d or w");
+                return;
+            }
+            None => None,
+        };
+        // "share book <title> @family" only shares with members of that group
+        let (title, group) = match input.trim().rsplit_once(" @") {
+            Some((title, group)) => (title.trim(), Some(group.trim().to_owned())),
+            None => (input.trim(), None),
+        };
+        if title.is_empty() {
+            error!("invalid title: {}", title);
+            return;
+        }
+        if let Some(ref group) = group {
+            if Groups::load().members(group).is_none() {
+                error!("unknown group: {}", group);
+                return;
+            }
+        }
+        let title = match share_book_with(title, group.clone(), until).await {
+            Ok(title) => title,
+            Err(e) => {
+                info!("error sharing book {}: {}", title, e);
+                return;
             }
-            _ => error!("invalid title: {}", input.trim()),
         };
+        let window = window.map(|w| format!(" for {}", w)).unwrap_or_default();
+        match group {
+            Some(group) => info!("now sharing book: {} with @{}{}", title, group, window),
+            None => info!("now sharing book: {}{}", title, window),
+        }
     }
 }
 
-async fn share_book(title: &str) -> Result<()> {
-    let mut local_library = read_local_library().await?;
-    local_library
-        .iter_mut()
-        .filter(|book| book.title == title)
-        .for_each(|b| b.public = true);
-    write_local_library(&local_library).await?;
-    Ok(())
+pub async fn share_book(title: &str) -> Result<()> {
+    share_book_with(title, None, None).await.map(|_| ())
 }
 
-pub async fn handle_list_books(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
-    let input = cmd.strip_prefix("ls books ");
+// a book by title, or by id when no title matches
+fn select<'a>(library: &'a mut Library, selector: &str) -> impl Iterator<Item = &'a mut Book> {
+    let by_title = library
+        .iter()
+        .any(|b| b.title == selector && b.trashed.is_none());
+    let id = if by_title { None } else { selector.parse().ok() };
+    let selector = selector.to_owned();
+    library.iter_mut().filter(move |b| {
+        b.trashed.is_none() && (b.title == selector || Some(b.id) == id)
+    })
+}
 
-    match input {
-        Some("all") => {
-            let req = ListRequest {
-                mode: ListMode::ALL,
+// "series <book title or id>|<series>|<volume>" places a book in a series,
+// "series <book title or id>|off" takes it out again
+pub async fn handle_series(cmd: &str) {
+    let input = cmd.strip_prefix("series").unwrap_or_default().trim();
+    let elem: Vec<&str> = input.split('|').map(str::trim).collect();
+    let placement = match elem.as_slice() {
+        [_, "off"] => None,
+        [_, series, volume] if !series.is_empty() => match volume.trim_start_matches('#').parse() {
+            Ok(volume) if volume > 0 => Some((series.to_string(), volume)),
+            _ => {
+                error!("volume should be a number from 1");
+                return;
+            }
+        },
+        _ => {
+            error!("format should be: series <book>|<series>|<volume> or series <book>|off");
+            return;
+        }
+    };
+    match set_series(elem[0], placement).await {
+        Ok(title) => info!("updated {}", title),
+        Err(e) => error!("error updating {}: {}", elem[0], e),
+    }
+}
+
+async fn set_series(selector: &str, placement: Option<(String, u32)>) -> Result<String> {
+    edit_book(selector, |b| {
+        b.series = placement.as_ref().map(|(series, _)| series.clone());
+        b.volume = placement.as_ref().map(|(_, volume)| *volume);
+    })
+    .await
+}
+
+// "rate <book title or id>|<1 to 5>" scores one of our books, "rate <book>|off"
+// clears it. the rating goes out with the book in our catalog
+pub async fn handle_rate(cmd: &str) {
+    let input = cmd.strip_prefix("rate").unwrap_or_default().trim();
+    let (selector, rating) = match input.rsplit_once('|') {
+        Some((selector, "off")) => (selector.trim(), None),
+        Some((selector, rating)) => match rating.trim().parse::<u8>() {
+            Ok(rating @ 1..=5) => (selector.trim(), Some(rating)),
+            _ => {
+                error!("rating should be a number from 1 to 5");
+                return;
+            }
+        },
+        None => {
+            error!("format should be: rate <book>|<1 to 5> or rate <book>|off");
+            return;
+        }
+    };
+    match edit_book(selector, |b| b.rating = rating).await {
+        Ok(title) => info!("updated {}", title),
+        Err(e) => error!("error rating {}: {}", selector, e),
+    }
+}
+
+// "review <book title or id>|<text>" keeps our words on one of our books,
+// "review <book>|off" drops them. peers read it with the book's details
+pub async fn handle_review(cmd: &str) {
+    let input = cmd.strip_prefix("review").unwrap_or_default().trim();
+    let (selector, text) = match input.split_once('|') {
+        Some((selector, text)) if !text.trim().is_empty() => (selector.trim(), text.trim()),
+        _ => {
+            error!("format should be: review <book>|<text> or review <book>|off");
+            return;
+        }
+    };
+    let review = match text {
+        "off" => None,
+        text if text.chars().count() > MAX_REVIEW => {
+            error!("a review takes up to {} characters", MAX_REVIEW);
+            return;
+        }
+        // a typed \n starts a new paragraph, the prompt reads one line
+        text => Some(text.replace("\\n", "\n")),
+    };
+    match edit_book(selector, |b| b.review = review.clone()).await {
+        Ok(title) => info!("updated {}", title),
+        Err(e) => error!("error reviewing {}: {}", selector, e),
+    }
+}
+
+// "lend <book title or id>|<peer id>" proposes a loan, "|<2w>" after it sets
+// when the book is due back. it goes in the ledger once the borrower signs
+pub async fn handle_lend(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let input = cmd.strip_prefix("lend").unwrap_or_default().trim();
+    let elem: Vec<&str> = input.split('|').map(str::trim).collect();
+    let (selector, peer, within) = match elem.as_slice() {
+        [selector, peer] => (*selector, *peer, None),
+        [selector, peer, within] => match activity::parse_duration(within) {
+            Some(within) => (*selector, *peer, Some(within)),
+            None => {
+                error!("invalid time span {}, e.g. 3d or 2w", within);
+                return;
+            }
+        },
+        _ => {
+            error!("format should be: lend <book>|<peer id> or lend <book>|<peer id>|<2w>");
+            return;
+        }
+    };
+    let peer: PeerId = match peer.parse() {
+        Ok(peer) => peer,
+        Err(_) => {
+            error!("invalid peer id: {}", peer);
+            return;
+        }
+    };
+    if !loan_reachable(swarm, &peer) {
+        return;
+    }
+    let mut local_library = match read_local_library().await {
+        Ok(library) => library,
+        Err(e) => {
+            error!("error fetching local library: {}", e);
+            return;
+        }
+    };
+    let books: Vec<&mut Book> = select(&mut local_library, selector).collect();
+    let book = match books.as_slice() {
+        [book] => book,
+        [] => {
+            error!("no such book: {}", selector);
+            return;
+        }
+        _ => {
+            error!("{} matches several books, use its id", selector);
+            return;
+        }
+    };
+    let ledger = Ledger::load();
+    let owned = book.copies.unwrap_or(1);
+    if ledger.lent_out(&PEER_ID.to_string(), book.id) >= owned {
+        error!("every copy of {} is lent out ({} owned)", book.title, owned);
+        return;
+    }
+    let now = unix_time();
+    let record = LoanRecord {
+        id: ledger.new_id(),
+        event: LoanEvent::Lent,
+        lender: PEER_ID.to_string(),
+        borrower: peer.to_string(),
+        book: book.id,
+        title: book.title.clone(),
+        at: now,
+        due: within.map(|within| now + within),
+        lender_signature: None,
+        borrower_signature: None,
+    };
+    info!("asked {} to sign for {} (loan {})", peer, record.title, record.id);
+    propose_loan(swarm, ledger, record);
+}
+
+// both sides have to be around to sign
+fn loan_reachable(swarm: &mut Swarm<BookBehavior>, peer: &PeerId) -> bool {
+    if !swarm.is_connected(peer) {
+        error!("not connected to {}", peer);
+        return false;
+    }
+    if !swarm.behaviour().supports(peer, "loans") {
+        error!("{} doesn't keep a lending ledger", peer);
+        return false;
+    }
+    true
+}
+
+fn propose_loan(swarm: &mut Swarm<BookBehavior>, mut ledger: Ledger, mut record: LoanRecord) {
+    ledger::sign(&KEYS, &mut record);
+    ledger.propose(record.clone());
+    publish(swarm, TOPIC.clone(), &Message::Loan(record));
+}
+
+const LOAN_FORMAT: &str =
+    "format should be: loan accept|reject|returned|remind <loan id> or loan extend <loan id> <1w>";
+
+// "loan accept <id>" signs what the other side proposed, "loan reject <id>"
+// drops it, "loan returned <id>" proposes that a book came back. lenders can
+// also "loan remind <id>" the borrower and "loan extend <id> <1w>" the loan
+pub fn handle_loan(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    let (action, id, more) = match args.as_slice() {
+        [action, id, more @ ..] => match id.parse::<u32>() {
+            Ok(id) => (*action, id, more),
+            Err(_) => {
+                error!("invalid loan id: {}", id);
+                return;
+            }
+        },
+        _ => {
+            error!("{}", LOAN_FORMAT);
+            return;
+        }
+    };
+    if more.len() != usize::from(action == "extend") {
+        error!("{}", LOAN_FORMAT);
+        return;
+    }
+    let mut ledger = Ledger::load();
+    let us = PEER_ID.to_string();
+    let other = |record: &LoanRecord| match record.lender == us {
+        true => record.borrower.clone(),
+        false => record.lender.clone(),
+    };
+    match action {
+        "accept" | "reject" => {
+            let ours = |r: &LoanRecord| match r.lender == us {
+                true => r.lender_signature.is_some(),
+                false => r.borrower_signature.is_some(),
+            };
+            let mut record = match ledger.pending().find(|r| r.id == id && !ours(r)).cloned() {
+                Some(record) => record,
+                None => {
+                    error!("nobody asked you to sign loan {}", id);
+                    return;
+                }
+            };
+            ledger.take_pending(&record);
+            if action == "reject" {
+                info!("rejected loan {} from {}", id, other(&record));
+                return;
+            }
+            let peer = match other(&record).parse::<PeerId>() {
+                Ok(peer) if loan_reachable(swarm, &peer) => peer,
+                _ => {
+                    ledger.propose(record);
+                    return;
+                }
             };
-            let json = serde_json::to_string(&req).expect("unable to jsonify request for all");
-            swarm.behaviour_mut().floodsub.publish(TOPIC.clone(), json.as_bytes());
+            ledger::sign(&KEYS, &mut record);
+            if record.event == LoanEvent::Returned {
+                swarm.behaviour_mut().reputation.loan(&peer.to_string());
+            }
+            ledger.append(record.clone());
+            info!("signed loan {} with {}, it's in the ledger", id, peer);
+            publish(swarm, TOPIC.clone(), &Message::Loan(record));
         }
-        Some(library_peer_id) => {
-            let req = ListRequest {
-                mode: ListMode::One(library_peer_id.to_owned()),
+        "returned" => {
+            let lent = match ledger.outstanding(id) {
+                Some(lent) => lent.clone(),
+                None => {
+                    error!("no loan {} that's still out", id);
+                    return;
+                }
+            };
+            let peer = match other(&lent).parse::<PeerId>() {
+                Ok(peer) if loan_reachable(swarm, &peer) => peer,
+                _ => return,
+            };
+            let record = LoanRecord {
+                event: LoanEvent::Returned,
+                at: unix_time(),
+                due: None,
+                lender_signature: None,
+                borrower_signature: None,
+                ..lent
             };
-            let json =
-                serde_json::to_string(&req).expect("unable to jsonify request for library peer id");
-            swarm.behaviour_mut().floodsub.publish(TOPIC.clone(), json.as_bytes());
+            info!("asked {} to agree {} came back (loan {})", peer, record.title, id);
+            propose_loan(swarm, ledger, record);
         }
-        None => {
-            match read_local_library().await {
-                Ok(val) => {
-                    info!("Local books ({})", val.len());
-                    val.iter().for_each(|book| info!("{:?}", book));
+        "remind" | "extend" => {
+            let lent = match ledger.outstanding(id) {
+                Some(lent) if lent.lender == us => lent.clone(),
+                Some(_) => {
+                    error!("only the lender can {} for loan {}", action, id);
+                    return;
                 }
-                Err(e) => error!("error retrieving local library: {}", e),
+                None => {
+                    error!("no loan {} that's still out", id);
+                    return;
+                }
+            };
+            let due = ledger.due(&lent);
+            if action == "remind" {
+                let due = due
+                    .map(|due| format!(", {}", clubs::due(due, unix_time())))
+                    .unwrap_or_default();
+                let text = format!("a reminder that you have {} (loan {}){}", lent.title, id, due);
+                handle_msg(&format!("msg {} {}", lent.borrower, text), swarm);
+                return;
+            }
+            let within = match activity::parse_duration(more[0]) {
+                Some(within) => within,
+                None => {
+                    error!("invalid time span {}, e.g. 3d or 2w", more[0]);
+                    return;
+                }
+            };
+            let peer = match lent.borrower.parse::<PeerId>() {
+                Ok(peer) if loan_reachable(swarm, &peer) => peer,
+                _ => return,
             };
+            // from the old due date, or from now when it has passed
+            let now = unix_time();
+            let record = LoanRecord {
+                event: LoanEvent::Extended,
+                at: now,
+                due: Some(due.unwrap_or(now).max(now) + within),
+                lender_signature: None,
+                borrower_signature: None,
+                ..lent
+            };
+            info!("asked {} to agree to more time with {} (loan {})", peer, record.title, id);
+            propose_loan(swarm, ledger, record);
         }
+        _ => error!("{}", LOAN_FORMAT),
     }
 }
 
-pub async fn respond_with_public_books(
-    sender: mpsc::UnboundedSender<ListResponse>,
-    receiver: String,
-) {
-    tokio::spawn(async move {
-        match read_local_library().await {
-            Ok(books) => {
-                let res = ListResponse {
-                    mode: ListMode::ALL,
-                    receiver,
-                    data: books.into_iter().filter(|b| b.public).collect(),
-                };
-                if let Err(e) = sender.send(res) {
-                    error!("error responding: {}", e);
+// what's out on loan, ours and theirs, by the peer on the other side
+pub fn handle_loans(swarm: &mut Swarm<BookBehavior>) {
+    let ledger = Ledger::load();
+    let us = PEER_ID.to_string();
+    let now = unix_time();
+    let mut by_peer: BTreeMap<&String, Vec<(&LoanRecord, Option<u64>)>> = BTreeMap::new();
+    let out = ledger.out();
+    for (lent, due) in &out {
+        let other = if lent.lender == us { &lent.borrower } else { &lent.lender };
+        by_peer.entry(other).or_default().push((lent, *due));
+    }
+    if by_peer.is_empty() {
+        info!("nothing lent or borrowed, ls loans shows the history");
+        return;
+    }
+    let mut overdue = 0;
+    for (peer, loans) in by_peer {
+        let parsed = peer.parse::<PeerId>().ok();
+        let online = match parsed {
+            Some(p) if swarm.is_connected(&p) => "online",
+            _ => "offline",
+        };
+        match parsed.and_then(|p| swarm.behaviour().names.get(&p)) {
+            Some(name) => info!("{} ({}, {}):", name, peer, online),
+            None => info!("{} ({}):", peer, online),
+        }
+        for (lent, due) in loans {
+            let verb = if lent.lender == us { "lent" } else { "borrowed" };
+            let line = format!("  {} {} (loan {})", verb, lent.title, lent.id);
+            match due {
+                Some(due) if due < now => {
+                    overdue += 1;
+                    error!("{}, overdue, {}", line, clubs::due(due, now));
                 }
+                Some(due) => info!("{}, {}", line, clubs::due(due, now)),
+                None => info!("{}, no due date", line),
             }
-            Err(e) => error!("error retrieving local library: {}", e),
         }
-    });
+    }
+    let lent = out.iter().filter(|(l, _)| l.lender == us).count();
+    info!("{} lent, {} borrowed, {} overdue", lent, out.len() - lent, overdue);
+    info!("loan remind|returned <loan id> or loan extend <loan id> <1w> act on one");
+}
+
+// the agreed history, oldest first, then what still waits for a signature.
+// a record whose signatures don't check out was changed after signing
+pub fn handle_list_loans() {
+    let ledger = Ledger::load();
+    let us = PEER_ID.to_string();
+    let describe = |record: &LoanRecord| {
+        let (verb, other) = match (record.event, record.lender == us) {
+            (LoanEvent::Lent, true) => ("lent", format!("to {}", record.borrower)),
+            (LoanEvent::Lent, false) => ("borrowed", format!("from {}", record.lender)),
+            (LoanEvent::Returned, true) => ("got back", format!("from {}", record.borrower)),
+            (LoanEvent::Returned, false) => ("returned", format!("to {}", record.lender)),
+            (LoanEvent::Extended, true) => ("extended", format!("for {}", record.borrower)),
+            (LoanEvent::Extended, false) => ("more time for", format!("from {}", record.lender)),
+        };
+        format!("loan {}: {} {} {}", record.id, verb, record.title, other)
+    };
+    let mut empty = true;
+    for record in ledger.entries() {
+        empty = false;
+        let when = activity::ago(unix_time().saturating_sub(record.at));
+        if ledger::lender_signed(record) && ledger::borrower_signed(record) {
+            info!("{}, {}", describe(record), when);
+        } else {
+            error!("{}, {}, signatures don't match, tampered with", describe(record), when);
+        }
+    }
+    for record in ledger.pending() {
+        empty = false;
+        let waiting = match (record.lender == us, record.lender_signature.is_some()) {
+            (true, true) | (false, false) => "waiting for the other side to sign",
+            _ => "waiting for you, loan accept or loan reject",
+        };
+        info!("{} ({})", describe(record), waiting);
+    }
+    if empty {
+        info!("no loans yet, lend <book>|<peer id> lends one");
+    }
+}
+
+// "shelve <book title or id>|<location>" notes where a paper copy sits,
+// "shelve <book>|off" forgets it. locations are never shared
+pub async fn handle_shelve(cmd: &str) {
+    let input = cmd.strip_prefix("shelve").unwrap_or_default().trim();
+    let (selector, location) = match input.rsplit_once('|') {
+        Some((selector, "off")) => (selector.trim(), None),
+        Some((selector, location)) if !location.trim().is_empty() => {
+            (selector.trim(), Some(location.trim().to_owned()))
+        }
+        _ => {
+            error!("format should be: shelve <book>|<location> or shelve <book>|off");
+            return;
+        }
+    };
+    match edit_book(selector, |b| b.location = location.clone()).await {
+        Ok(title) => info!("updated {}", title),
+        Err(e) => error!("error shelving {}: {}", selector, e),
+    }
+}
+
+// "condition <book title or id>|new|good|fair|poor", or "|off"
+pub async fn handle_condition(cmd: &str) {
+    let input = cmd.strip_prefix("condition").unwrap_or_default().trim();
+    let (selector, condition) = match input.rsplit_once('|') {
+        Some((selector, condition)) => match condition.trim() {
+            "new" => (selector.trim(), Some(Condition::New)),
+            "good" => (selector.trim(), Some(Condition::Good)),
+            "fair" => (selector.trim(), Some(Condition::Fair)),
+            "poor" => (selector.trim(), Some(Condition::Poor)),
+            "off" => (selector.trim(), None),
+            other => {
+                error!("unknown condition {}, expected new, good, fair, poor or off", other);
+                return;
+            }
+        },
+        None => {
+            error!("format should be: condition <book>|<new|good|fair|poor|off>");
+            return;
+        }
+    };
+    match edit_book(selector, |b| b.condition = condition).await {
+        Ok(title) => info!("updated {}", title),
+        Err(e) => error!("error updating {}: {}", selector, e),
+    }
+}
+
+const HIDE_USAGE: &str = "hide <book>|<public|friends>|<fields> or hide <book>|off";
+
+// "hide <book title or id>|public|<field,field>" or "|friends|...", "|none" for
+// no fields and "hide <book>|off" to go by the config again. "hide" alone shows
+// the configured fields
+pub async fn handle_hide(cmd: &str) {
+    let input = cmd.strip_prefix("hide").unwrap_or_default().trim();
+    if input.is_empty() {
+        let listed = |fields: &[String]| match fields.join(", ") {
+            fields if fields.is_empty() => "nothing".to_owned(),
+            fields => fields,
+        };
+        info!("hidden from everyone: {}", listed(&CONFIG.visibility.public));
+        info!("hidden from friends: {}", listed(&CONFIG.visibility.friends));
+        info!("fields that can be hidden: {}", HIDEABLE.join(", "));
+        return;
+    }
+    let (selector, hidden) = match input.rsplit_once('|') {
+        Some((selector, "off")) => (selector.trim(), None),
+        Some((rest, fields)) => match rest.rsplit_once('|') {
+            Some((selector, audience)) => match (audience.trim(), hidden_fields(fields)) {
+                ("public" | "friends", Err(unknown)) => {
+                    error!("{} can't be hidden, pick from {}", unknown, HIDEABLE.join(", "));
+                    return;
+                }
+                ("public", Ok(fields)) => (selector.trim(), Some((false, fields))),
+                ("friends", Ok(fields)) => (selector.trim(), Some((true, fields))),
+                (other, _) => {
+                    error!("unknown audience {}, expected public or friends", other);
+                    return;
+                }
+            },
+            None => {
+                error!("format should be: {}", HIDE_USAGE);
+                return;
+            }
+        },
+        None => {
+            error!("format should be: {}", HIDE_USAGE);
+            return;
+        }
+    };
+    let edit = |b: &mut Book| match hidden {
+        // the book's own lists start as a copy of the config's
+        Some((friends, ref fields)) => {
+            let visibility = b.hidden.get_or_insert_with(|| CONFIG.visibility.clone());
+            if friends {
+                visibility.friends = fields.clone();
+            } else {
+                visibility.public = fields.clone();
+            }
+        }
+        None => b.hidden = None,
+    };
+    match edit_book(selector, edit).await {
+        Ok(title) => info!("updated {}", title),
+        Err(e) => error!("error updating {}: {}", selector, e),
+    }
+}
+
+// "publisher, tags", or the first name that isn't a field
+fn hidden_fields(list: &str) -> std::result::Result<Vec<String>, String> {
+    if list.trim() == "none" {
+        return Ok(Vec::new());
+    }
+    list.split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(|f| if HIDEABLE.contains(&f) { Ok(f.to_owned()) } else { Err(f.to_owned()) })
+        .collect()
+}
+
+// "copies <book title or id>|<how many>" for books owned more than once
+pub async fn handle_copies(cmd: &str) {
+    let input = cmd.strip_prefix("copies").unwrap_or_default().trim();
+    let (selector, copies) = match input.rsplit_once('|') {
+        Some((selector, copies)) => match copies.trim().parse::<u32>() {
+            Ok(copies) if copies > 0 => (selector.trim(), copies),
+            _ => {
+                error!("copies should be a number from 1");
+                return;
+            }
+        },
+        None => {
+            error!("format should be: copies <book>|<how many>");
+            return;
+        }
+    };
+    // one is what a book without a count means
+    let count = if copies > 1 { Some(copies) } else { None };
+    match edit_book(selector, |b| b.copies = count).await {
+        Ok(title) => info!("updated {}", title),
+        Err(e) => error!("error updating {}: {}", selector, e),
+    }
+}
+
+// "attach <book title or id>|<file>" remembers where the book's file is, for
+// download links, and publishes it through our ipfs daemon if we run one.
+// "attach <book>|<cid>" shares a file published elsewhere, "attach <book>|off"
+// forgets both
+pub async fn handle_attach(cmd: &str) {
+    let input = cmd.strip_prefix("attach").unwrap_or_default().trim();
+    let (selector, target) = match input.rsplit_once('|') {
+        Some((selector, target)) if !target.trim().is_empty() => (selector.trim(), target.trim()),
+        _ => {
+            error!("format should be: attach <book>|<file or cid> or attach <book>|off");
+            return;
+        }
+    };
+    // the file is left alone when only a cid is given, though what it is
+    // isn't known then
+    let (cid, file, format) = match target {
+        "off" => (None, Some(None), None),
+        cid if ipfs::is_cid(cid) => (Some(cid.to_owned()), None, None),
+        path => {
+            let checked = match fs::canonicalize(datadir::user_path(path)).await {
+                Ok(path) => {
+                    let path = path.to_string_lossy().into_owned();
+                    file_format(&path).await.map(|format| (path, format))
+                }
+                Err(e) => Err(e.into()),
+            };
+            let (path, format) = match checked {
+                Ok(checked) => checked,
+                Err(e) => {
+                    error!("unable to attach {}: {}", path, e);
+                    return;
+                }
+            };
+            let path = stored_once(path).await;
+            if let Some(ref api) = CONFIG.ipfs.api {
+                // a large file takes a while, `cancel <id>` stops it
+                let op = Operation::start(cmd);
+                info!("publishing {} to ipfs as #{}, cancel {} stops it", path, op.id, op.id);
+                let selector = selector.to_owned();
+                tokio::spawn(publish_attachment(api, selector, path, format, op));
+                return;
+            }
+            (None, Some(Some(path)), format)
+        }
+    };
+    attach(selector, target, cid, file, format).await
+}
+
+// a file with the same content as one attached already is attached as that
+// one, so the same book is kept on the disk once
+async fn stored_once(path: String) -> String {
+    let library = read_local_library().await.unwrap_or_default();
+    let others: Vec<String> = library.into_iter().filter_map(|b| b.file).collect();
+    let file = path.clone();
+    let found = tokio::task::spawn_blocking(move || {
+        let mut checksums = Checksums::load();
+        let copy = checksums.copy_of(&file, others.iter().map(String::as_str));
+        checksums.save();
+        copy.map(|copy| copy.map(str::to_owned))
+    })
+    .await;
+    match found {
+        Ok(Ok(Some(copy))) => {
+            info!("{} is the same as {}, attaching that one", path, copy);
+            copy
+        }
+        Ok(Ok(None)) => path,
+        Ok(Err(e)) => {
+            error!("unable to compare {} with the attached files: {}", path, e);
+            path
+        }
+        Err(e) => {
+            error!("unable to compare {} with the attached files: {}", path, e);
+            path
+        }
+    }
+}
+
+// dedup files [--remove [--yes]] reports attached files with the same content,
+// --remove lists the copies it would delete and with --yes points their books
+// at one copy and deletes the others
+pub fn handle_dedup(cmd: &str) {
+    let (list, remove) = match cmd.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["dedup", "files"] => (false, false),
+        ["dedup", "files", "--remove"] => (true, false),
+        ["dedup", "files", "--remove", "--yes"] => (true, true),
+        _ => return error!("format should be: dedup files [--remove [--yes]]"),
+    };
+    let op = Operation::start(cmd);
+    info!("looking for identical files as #{}, cancel {} stops it", op.id, op.id);
+    tokio::spawn(async move {
+        let dedup = match dedup_files(remove, &op).await {
+            Ok(Some(dedup)) => dedup,
+            Ok(None) => return info!("cancelled looking for identical files, nothing changed"),
+            Err(e) => return error!("unable to deduplicate files: {}", e),
+        };
+        for (path, e) in &dedup.failed {
+            error!("kept {}: {}", path, e);
+        }
+        if dedup.copies.is_empty() && dedup.failed.is_empty() {
+            return info!("no two attached files are the same");
+        }
+        let mb = dedup.bytes as f64 / 1_000_000.0;
+        if !remove {
+            for (path, of) in dedup.copies.iter().filter(|_| list) {
+                info!("would remove {}, the same as {}", path, of);
+            }
+            info!(
+                "{} files are attached more than once, {} other copies take {:.1} MB, {}",
+                dedup.groups,
+                dedup.copies.len(),
+                mb,
+                if list {
+                    "dedup files --remove --yes keeps one of each"
+                } else {
+                    "dedup files --remove lists the copies it would remove"
+                }
+            );
+            return;
+        }
+        info!(
+            "{} books now point at one copy, removed {} copies, {:.1} MB",
+            dedup.repointed,
+            dedup.copies.len(),
+            mb
+        );
+    });
+}
+
+struct Dedup {
+    groups: usize,
+    repointed: usize,
+    // the files no book points at once they're removed, and the file kept
+    // in their place
+    copies: Vec<(String, String)>,
+    bytes: u64,
+    failed: Vec<(String, std::io::Error)>,
+}
+
+async fn dedup_files(remove: bool, op: &Operation) -> Result<Option<Dedup>> {
+    let library = read_local_library().await?;
+    let paths: HashSet<String> = library.into_iter().filter_map(|b| b.file).collect();
+    let groups = tokio::task::spawn_blocking(move || {
+        let paths: HashSet<&str> = paths.iter().map(String::as_str).collect();
+        let mut checksums = Checksums::load();
+        let groups: Vec<Vec<String>> = checksums
+            .identical(&paths)
+            .into_iter()
+            .map(|group| group.into_iter().map(str::to_owned).collect())
+            .collect();
+        checksums.retain(&paths);
+        checksums.save();
+        groups
+    })
+    .await?;
+    if op.cancelled() {
+        return Ok(None);
+    }
+    // read again, it may have changed while the files were hashed
+    let mut library = read_local_library().await?;
+    let mut candidates = Vec::new();
+    for group in &groups {
+        // the copy of the book that was attached first
+        let first = library
+            .iter()
+            .filter(|b| matches!(b.file, Some(ref f) if group.contains(f)))
+            .min_by_key(|b| b.id);
+        if let Some(keep) = first.and_then(|b| b.file.clone()) {
+            let others = group.iter().filter(|path| **path != keep);
+            candidates.extend(others.map(|path| (path.clone(), keep.clone())));
+        }
+    }
+    // a link to the kept file or another spelling of its path is no copy,
+    // its books can point at the kept path but nothing is removed. the rest
+    // is compared byte for byte, the hashes may be of older contents
+    let (aliases, copies, failed) = tokio::task::spawn_blocking(move || {
+        let (mut aliases, mut copies, mut failed) = (Vec::new(), Vec::new(), Vec::new());
+        for (path, keep) in candidates {
+            match checksums::same_file(&path, &keep) {
+                Ok(true) => aliases.push((path, keep)),
+                Ok(false) if !remove => copies.push((path, keep)),
+                Ok(false) => match checksums::same_bytes(&path, &keep) {
+                    Ok(true) => copies.push((path, keep)),
+                    Ok(false) => failed.push((path, changed())),
+                    Err(e) => failed.push((path, e)),
+                },
+                Err(e) => failed.push((path, e)),
+            }
+        }
+        (aliases, copies, failed)
+    })
+    .await?;
+    let mut dedup = Dedup {
+        groups: groups.len(),
+        repointed: 0,
+        copies,
+        bytes: 0,
+        failed,
+    };
+    if !remove {
+        for (path, _) in &dedup.copies {
+            dedup.bytes += fs::metadata(path).await.map(|m| m.len()).unwrap_or_default();
+        }
+        return Ok(Some(dedup));
+    }
+    let now = unix_time();
+    let revision = clock::next_revision(&library);
+    // the books moved off each path, to move back if its file can't go
+    let mut moved: Vec<(usize, String)> = Vec::new();
+    for (path, keep) in aliases.iter().chain(&dedup.copies) {
+        for book in library.iter_mut() {
+            if book.file.as_ref() == Some(path) {
+                book.file = Some(keep.clone());
+                clock::stamp(book, now, revision);
+                moved.push((book.id, path.clone()));
+            }
+        }
+    }
+    dedup.repointed = moved.len();
+    // the library first, so a copy is never gone while a book points at it
+    if dedup.repointed > 0 {
+        write_local_library(&library).await?;
+    }
+    let copies = std::mem::take(&mut dedup.copies);
+    let (removed, kept, bytes) = tokio::task::spawn_blocking(move || {
+        let (mut removed, mut kept, mut bytes) = (Vec::new(), Vec::new(), 0);
+        for (path, keep) in copies {
+            // compared again, it may have been written to since
+            let len = std::fs::metadata(&path).map(|m| m.len()).unwrap_or_default();
+            let result = match checksums::same_bytes(&path, &keep) {
+                Ok(true) => std::fs::remove_file(&path),
+                Ok(false) => Err(changed()),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {
+                    removed.push((path, keep));
+                    bytes += len;
+                }
+                Err(e) => kept.push((path, e)),
+            }
+        }
+        (removed, kept, bytes)
+    })
+    .await?;
+    if !kept.is_empty() {
+        for book in library.iter_mut() {
+            let from = moved.iter().find(|(id, _)| *id == book.id).map(|(_, from)| from);
+            if let Some(from) = from.filter(|from| kept.iter().any(|(path, _)| path == *from)) {
+                book.file = Some(from.clone());
+                clock::stamp(book, now, revision);
+                dedup.repointed -= 1;
+            }
+        }
+        write_local_library(&library).await?;
+    }
+    dedup.bytes = bytes;
+    dedup.copies = removed;
+    dedup.failed.extend(kept);
+    Ok(Some(dedup))
+}
+
+fn changed() -> std::io::Error {
+    std::io::Error::other("its content changed since it was hashed")
+}
+
+// "download <peer id> <book id>" fetches the file of a book in a peer's
+// catalog through our ipfs daemon, into ./downloads
+pub fn handle_download(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    let (peer, id) = match args.as_slice() {
+        [peer, id] => match (peer.parse::<PeerId>(), id.parse::<usize>()) {
+            (Ok(peer), Ok(id)) => (peer.to_string(), id),
+            _ => return error!("invalid peer id or book id"),
+        },
+        _ => return error!("format should be: download <peer id> <book id>"),
+    };
+    let api = match CONFIG.ipfs.api {
+        Some(ref api) => api.as_str(),
+        None => return error!("downloading needs the api of an ipfs daemon under [ipfs]"),
+    };
+    let book = swarm
+        .behaviour_mut()
+        .remote_catalogs
+        .get(&peer)
+        .and_then(|catalog| catalog.iter().find(|b| b.id == id));
+    let book = match book {
+        Some(book) => book.clone(),
+        None => {
+            error!("book {} isn't in {}'s catalog as far as we know", id, peer);
+            info!("ls books {} brings its catalog up to date", peer);
+            return;
+        }
+    };
+    // it names the file on our disk and goes into a request to the daemon
+    let cid = match book.cid {
+        Some(ref cid) if ipfs::is_cid(cid) => cid.clone(),
+        Some(ref cid) => {
+            return error!("{} shares {} under an invalid cid {:?}", peer, book.title, cid)
+        }
+        None => return error!("{} shares no file of {}", peer, book.title),
+    };
+    let cmd = cmd.to_owned();
+    tokio::spawn(async move {
+        let lock = downloads::lock().await;
+        let mut downloads = Downloads::load();
+        if let Some(download) = downloads.by_cid(&cid) {
+            download.used = unix_time();
+            info!("{} is downloaded already, to {}", book.title, download.path);
+            downloads.save();
+            return;
+        }
+        drop(lock);
+        if !downloads::fetching(&cid) {
+            return info!("{} is being downloaded already", book.title);
+        }
+        let op = Operation::start(&cmd);
+        info!("downloading {} as #{}, cancel {} stops it", book.title, op.id, op.id);
+        download(api, peer, book, &cid, op).await;
+        downloads::fetched(&cid);
+    });
+}
+
+async fn download(api: &str, peer: String, book: Book, cid: &str, mut op: Operation) {
+    // the file goes to the disk as it comes, and under its name once it's whole
+    let part = format!("{}/{}.part", DOWNLOADS_DIR, cid);
+    let fetched = tokio::select! {
+        fetched = fetch(api, cid, &part) => Some(fetched),
+        _ = op.until_cancelled() => None,
+    };
+    let bytes = match fetched {
+        Some(Ok(bytes)) if !op.cancelled() => bytes,
+        Some(Err(e)) => {
+            let _ = fs::remove_file(&part).await;
+            return error!("unable to download {}: {}", book.title, e);
+        }
+        _ => {
+            let _ = fs::remove_file(&part).await;
+            return info!("cancelled downloading {}", book.title);
+        }
+    };
+    match keep_download(&peer, &book, cid, &part, bytes).await {
+        Ok(path) => {
+            info!("downloaded {} to {}", book.title, path);
+            activity::record(Activity::DownloadFinished {
+                peer,
+                title: book.title,
+                bytes,
+            });
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&part).await;
+            error!("not keeping {}: {}", book.title, e);
+        }
+    }
+}
+
+// a file larger than the cap is never read to the end
+async fn fetch(api: &str, cid: &str, part: &str) -> Result<u64> {
+    fs::create_dir_all(DOWNLOADS_DIR).await?;
+    let mut file = fs::File::create(part).await?;
+    // the size is only known once the daemon's done, so it counts bytes without an eta
+    let mut progress = Progress::new(&format!("downloading {}", cid), None);
+    let max = CONFIG.downloads.max_bytes;
+    let bytes = ipfs::cat(api.parse()?, cid, &mut file, max, &mut progress).await?;
+    file.flush().await?;
+    Ok(bytes)
+}
+
+async fn keep_download(
+    peer: &str,
+    book: &Book,
+    cid: &str,
+    part: &str,
+    bytes: u64,
+) -> Result<String> {
+    // the format the peer's catalog claims has to be what came
+    let format = file_format(part).await?;
+    if let Some(claimed) = book.format.filter(|claimed| format != Some(*claimed)) {
+        return Err(format!("it isn't the {} {}'s catalog says", claimed.name(), peer).into());
+    }
+    let _lock = downloads::lock().await;
+    let mut downloads = Downloads::load();
+    if let Some(max) = CONFIG.downloads.max_bytes {
+        let evicted = downloads
+            .to_evict(bytes, max, CONFIG.downloads.evict)
+            .ok_or_else(|| {
+                format!(
+                    "no room for {:.1} MB under the downloads cap of {:.1} MB",
+                    bytes as f64 / 1_000_000.0,
+                    max as f64 / 1_000_000.0
+                )
+            })?;
+        for id in evicted {
+            if let Some(old) = downloads.remove(id) {
+                if let Err(e) = fs::remove_file(&old.path).await {
+                    error!("unable to remove {}: {}", old.path, e);
+                }
+                info!("removed the download of {} to make room", old.title);
+            }
+        }
+        // the evicted ones are gone whatever happens next
+        downloads.save();
+    }
+    let extension = format.map(|f| f.name().to_lowercase()).unwrap_or_else(|| "bin".to_owned());
+    let path = format!("{}/{}.{}", DOWNLOADS_DIR, cid, extension);
+    fs::rename(part, &path).await?;
+    let now = unix_time();
+    let id = downloads.next_id();
+    downloads.add(Download {
+        id,
+        cid: cid.to_owned(),
+        peer: peer.to_owned(),
+        book: book.id,
+        title: book.title.clone(),
+        author: book.author.clone(),
+        path: path.clone(),
+        bytes,
+        fetched: now,
+        used: now,
+        favorite: false,
+    });
+    downloads.save();
+    Ok(path)
+}
+
+// "downloads" shows what the downloaded files take, "downloads open|favorite|
+// unfavorite|rm <id>" acts on one
+pub async fn handle_downloads(cmd: &str) {
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    let _lock = downloads::lock().await;
+    let mut downloads = Downloads::load();
+    let (action, id) = match args.as_slice() {
+        [] => return show_downloads(&downloads),
+        [action, id] => match id.trim_start_matches('#').parse::<u64>() {
+            Ok(id) => (*action, id),
+            Err(_) => return error!("invalid download id {}", id),
+        },
+        _ => return error!("format should be: downloads [open|favorite|unfavorite|rm <id>]"),
+    };
+    let download = match downloads.get_mut(id) {
+        Some(download) => download,
+        None => return error!("no download #{}", id),
+    };
+    match action {
+        "open" => {
+            download.used = unix_time();
+            info!("{} by {}: {}", download.title, download.author, download.path);
+        }
+        "favorite" | "unfavorite" => {
+            download.favorite = action == "favorite";
+            if download.favorite && CONFIG.downloads.evict != Eviction::KeepFavorites {
+                info!("favorites are only kept with evict = \"keep_favorites\" under [downloads]");
+            }
+        }
+        "rm" => {
+            let path = download.path.clone();
+            if let Err(e) = fs::remove_file(&path).await {
+                error!("unable to remove {}: {}", path, e);
+            }
+            downloads.remove(id);
+            info!("removed download #{}", id);
+        }
+        _ => return error!("format should be: downloads [open|favorite|unfavorite|rm <id>]"),
+    }
+    downloads.save();
+}
+
+fn show_downloads(downloads: &Downloads) {
+    let used = downloads.bytes() as f64 / 1_000_000.0;
+    match CONFIG.downloads.max_bytes {
+        Some(max) => info!("downloads take {:.1} of {:.1} MB", used, max as f64 / 1_000_000.0),
+        None => info!("downloads take {:.1} MB, with no cap", used),
+    }
+    let now = unix_time();
+    for download in downloads.iter() {
+        let favorite = if download.favorite { ", favorite" } else { "" };
+        info!(
+            "#{} {} by {} - {:.1} MB, used {}{}: {}",
+            download.id,
+            download.title,
+            download.author,
+            download.bytes as f64 / 1_000_000.0,
+            activity::ago(now.saturating_sub(download.used)),
+            favorite,
+            download.path
+        );
+    }
+}
+
+// what the file's first bytes say it is, refused when its name says otherwise
+async fn file_format(path: &str) -> Result<Option<FileFormat>> {
+    let mut head = Vec::with_capacity(formats::HEAD);
+    fs::File::open(path)
+        .await?
+        .take(formats::HEAD as u64)
+        .read_to_end(&mut head)
+        .await?;
+    Ok(formats::check(path, &head)?)
+}
+
+async fn publish_attachment(
+    api: &'static str,
+    selector: String,
+    path: String,
+    format: Option<FileFormat>,
+    mut op: Operation,
+) {
+    // the daemon drops an upload that stops halfway
+    let cid = tokio::select! {
+        added = add_to_ipfs(api, &path) => match added {
+            Ok(cid) => cid,
+            Err(e) => return error!("unable to publish {} to ipfs: {}", path, e),
+        },
+        _ = op.until_cancelled() => return info!("cancelled publishing {}", path),
+    };
+    // done uploading just as it was cancelled
+    if op.cancelled() {
+        match unpin(api, &cid).await {
+            Ok(()) => info!("cancelled publishing {}, ipfs let go of it", path),
+            Err(e) => error!("cancelled publishing {}, but ipfs still keeps {}: {}", path, cid, e),
+        }
+        return;
+    }
+    attach(&selector, &path, Some(cid), Some(Some(path.clone())), format).await
+}
+
+async fn unpin(api: &str, cid: &str) -> Result<()> {
+    ipfs::unpin(api.parse()?, cid).await
+}
+
+async fn attach(
+    selector: &str,
+    target: &str,
+    cid: Option<String>,
+    file: Option<Option<String>>,
+    format: Option<FileFormat>,
+) {
+    let edit = |b: &mut Book| {
+        b.cid = cid.clone();
+        b.format = format;
+        if let Some(ref file) = file {
+            b.file = file.clone();
+        }
+    };
+    match edit_book(selector, edit).await {
+        Ok(title) => match cid {
+            Some(ref cid) => info!(
+                "{} can be fetched at {}",
+                title,
+                ipfs::gateway_url(&CONFIG.ipfs.gateway, cid)
+            ),
+            None if target == "off" => info!("{} has no file anymore", title),
+            None => {
+                let target = match format {
+                    Some(format) => format!("{} as {}", target, format.name()),
+                    None => target.to_owned(),
+                };
+                info!("attached {} to {}, link book <id> makes a download link", target, title)
+            }
+        },
+        Err(e) => error!("error attaching to {}: {}", selector, e),
+    }
+}
+
+async fn add_to_ipfs(api: &str, path: &str) -> Result<String> {
+    let content = fs::read(path).await?;
+    let name = std::path::Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("book");
+    ipfs::add(api.parse()?, name, &content).await
+}
+
+// "link book <id>" makes a download link for the file of a shared book, good
+// for a week unless given e.g. "|2d". "unlink book <id>" stops its links
+pub async fn handle_link(cmd: &str) {
+    let input = cmd.strip_prefix("link book").unwrap_or_default().trim();
+    let (id, span) = match input.split_once('|') {
+        Some((id, span)) => (id.trim(), span.trim()),
+        None => (input, "7d"),
+    };
+    let (id, valid_for) = match (id.parse::<usize>(), activity::parse_duration(span)) {
+        (Ok(id), Some(valid_for)) if valid_for > 0 => (id, valid_for),
+        _ => {
+            error!("format should be: link book <id> or link book <id>|<7d>");
+            return;
+        }
+    };
+    let base = match (&CONFIG.api.url, &CONFIG.api.listen) {
+        (Some(url), _) => url.trim_end_matches('/').to_owned(),
+        (None, Some(listen)) => format!("http://{}", listen),
+        (None, None) => {
+            error!("links are served by the http api, set listen under [api] in config.toml");
+            return;
+        }
+    };
+    let library = match read_local_library().await {
+        Ok(library) => library,
+        Err(e) => {
+            error!("error fetching local library: {}", e);
+            return;
+        }
+    };
+    let book = match library.iter().find(|b| b.id == id && b.trashed.is_none()) {
+        Some(book) => book,
+        None => {
+            error!("no book with id {}", id);
+            return;
+        }
+    };
+    if !book.public {
+        error!("{} isn't shared, links only serve shared books", book.title);
+        return;
+    }
+    let file = match book.file {
+        Some(ref file) => file,
+        None => {
+            error!("{} has no file, attach one first", book.title);
+            return;
+        }
+    };
+    // the file may have been replaced since it was attached
+    match file_format(file).await {
+        Ok(format) if format == book.format => {}
+        Ok(_) => {
+            error!("{} is no longer the file attached to {}, attach it again", file, book.title);
+            return;
+        }
+        Err(e) => {
+            error!("unable to link {}: {}", file, e);
+            return;
+        }
+    }
+    let token = Links::load().create(id, valid_for);
+    info!("anyone with this link can download {} for {}:", book.title, span);
+    info!("{}/files/{}", base, token);
+}
+
+pub fn handle_unlink(cmd: &str) {
+    let input = cmd.strip_prefix("unlink book").unwrap_or_default().trim();
+    match input.parse::<usize>() {
+        Ok(id) => info!("revoked {} links to book {}", Links::load().revoke(id), id),
+        Err(_) => error!("format should be: unlink book <id>"),
+    }
+}
+
+// books a peer just started offering that are on our to-read list
+pub async fn match_wishlist(peer: String, offered: Library) {
+    if offered.is_empty() {
+        return;
+    }
+    let local_library = match read_local_library().await {
+        Ok(library) => library,
+        Err(e) => {
+            error!("error fetching local library: {}", e);
+            return;
+        }
+    };
+    let wanted: HashSet<_> = local_library
+        .iter()
+        .filter(|b| b.status == Some(ReadingStatus::ToRead) && b.trashed.is_none())
+        .map(Book::key)
+        .collect();
+    for book in offered.iter().filter(|b| wanted.contains(&b.key())) {
+        info!("{} offers {} from your to-read list", peer, book.title);
+        activity::record(Activity::WishlistMatch {
+            peer: peer.clone(),
+            title: book.title.clone(),
+        });
+    }
+}
+
+// "export --format bibtex <file>" and "import --format bibtex <file>", e.g.
+// for moving references to and from a citation manager
+fn format_and_path<'a>(cmd: &'a str, command: &str) -> Option<(&'a str, &'a str)> {
+    let rest = cmd.strip_prefix(command)?.trim().strip_prefix("--format")?;
+    let (format, path) = rest.trim().split_once(' ')?;
+    let path = path.trim();
+    if path.is_empty() {
+        return None;
+    }
+    Some((format, path))
+}
+
+// every book we have outside the trash, shared or not
+pub async fn handle_export(cmd: &str) {
+    let path = match format_and_path(cmd, "export") {
+        Some(("bibtex", path)) => path,
+        Some((format, _)) => {
+            error!("unknown format {}, only bibtex is supported", format);
+            return;
+        }
+        None => {
+            error!("format should be: export --format bibtex <file>");
+            return;
+        }
+    };
+    let library = match read_local_library().await {
+        Ok(library) => library,
+        Err(e) => {
+            error!("error fetching local library: {}", e);
+            return;
+        }
+    };
+    let books: Vec<Book> = library.into_iter().filter(|b| b.trashed.is_none()).collect();
+    match fs::write(datadir::user_path(path), bibtex::export(&books)).await {
+        Ok(()) => info!("exported {} books to {}", books.len(), path),
+        Err(e) => error!("unable to write {}: {}", path, e),
+    }
+}
+
+// node export <archive> and node import <archive>. an import ends the node,
+// it starts again as the imported one
+pub fn handle_node(cmd: &str) {
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    match args.as_slice() {
+        ["export", path] => match bundle::export(&datadir::user_path(path)) {
+            Ok(bundle) => {
+                info!("exported {} files of {} to {}", bundle.files.len(), bundle.peer, path);
+                if bundle::identity_in_keyring() {
+                    info!("the identity is in the system keyring and isn't in the archive");
+                } else {
+                    error!("{} holds this node's private key, keep it to yourself", path);
+                }
+            }
+            Err(e) => error!("unable to export the node to {}: {}", path, e),
+        },
+        ["import", path] => match bundle::import(&datadir::user_path(path)) {
+            Ok(bundle) => {
+                info!(
+                    "imported {} files of {}, exported {}",
+                    bundle.files.len(),
+                    bundle.peer,
+                    activity::ago(unix_time().saturating_sub(bundle.exported_at))
+                );
+                info!("this node's files are in {}", bundle::BEFORE_IMPORT_PATH);
+                info!("stopping, start the node again to run as {}", bundle.peer);
+                std::process::exit(0);
+            }
+            Err(e) => error!("unable to import {}: {}", path, e),
+        },
+        _ => error!("format should be: node export|import <archive>"),
+    }
+}
+
+// new private books for the entries we don't have yet, going by title and author
+// runs in the background, `cancel <id>` stops it before anything is added
+pub fn handle_import(cmd: &str) {
+    let (format, path) = match format_and_path(cmd, "import") {
+        Some((format, path)) => (format.to_owned(), path.to_owned()),
+        None => {
+            error!("format should be: import --format bibtex|goodreads|storygraph <file>");
+            return;
+        }
+    };
+    if !matches!(format.as_str(), "bibtex" | "goodreads" | "storygraph") {
+        error!("unknown format {}, use bibtex, goodreads or storygraph", format);
+        return;
+    }
+    let mut op = Operation::start(cmd);
+    info!("importing {} as #{}, cancel {} stops it", path, op.id, op.id);
+    tokio::spawn(async move {
+        let read = tokio::select! {
+            read = fs::read_to_string(datadir::user_path(&path)) => read,
+            _ = op.until_cancelled() => return info!("cancelled importing {}", path),
+        };
+        let content = match read {
+            Ok(content) => content,
+            Err(e) => return error!("unable to import {}: {}", path, e),
+        };
+        // both reading sites export the same kind of CSV, told apart by its header
+        let bibtex = format == "bibtex";
+        let parse = tokio::task::spawn_blocking(move || match bibtex {
+            true => bibtex::parse(&content).map(|entries| {
+                let books: Vec<Book> = entries.iter().filter_map(|entry| entry.book()).collect();
+                (entries.len() - books.len(), books)
+            }),
+            false => goodreads::parse(&content).map(|books| (0, books)),
+        });
+        let parsed = tokio::select! {
+            parsed = parse => parsed.map_err(|e| e.to_string()).and_then(|p| p),
+            _ = op.until_cancelled() => return info!("cancelled importing {}", path),
+        };
+        let (incomplete, books) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => return error!("unable to import {}: {}", path, e),
+        };
+        match import_books(books, &op).await {
+            Ok(None) => info!("cancelled importing {}, nothing was added", path),
+            Ok(Some((added, known))) => {
+                let incomplete = match bibtex {
+                    true => format!(", {} had no title or author", incomplete),
+                    false => String::new(),
+                };
+                info!(
+                    "imported {} books from {}, {} were in the library already{}",
+                    added, path, known, incomplete
+                )
+            }
+            Err(e) => error!("error importing {}: {}", path, e),
+        }
+    });
+}
+
+// adds the books we don't have, numbered after our own, and says how many
+// were added and how many we had. None when cancelled before the library was
+// written, the ids handed out go unused then
+async fn import_books(books: Vec<Book>, op: &Operation) -> Result<Option<(usize, usize)>> {
+    let mut local_library = read_local_library().await?;
+    let mut known: HashSet<(String, String)> = local_library
+        .iter()
+        .filter(|b| b.trashed.is_none())
+        .map(Book::key)
+        .collect();
+    let mut next_id = local_library.iter().map(|b| b.id + 1).max().unwrap_or(0);
+    let now = unix_time();
+    let revision = clock::next_revision(&local_library);
+    let (mut added, mut skipped) = (0, 0);
+    let mut progress = Progress::new("importing", Some(books.len() as u64));
+    for book in books {
+        progress.inc(1);
+        if !known.insert(book.key()) {
+            skipped += 1;
+            continue;
+        }
+        local_library.push(Book {
+            id: next_id,
+            uid: schema::new_uid(),
+            modified: Some(now),
+            revision: Some(revision),
+            ..book
+        });
+        next_id += 1;
+        added += 1;
+    }
+    progress.finish();
+    if op.cancelled() {
+        return Ok(None);
+    }
+    if added > 0 {
+        write_local_library(&local_library).await?;
+    }
+    Ok(Some((added, skipped)))
+}
+
+// applies `edit` to the selected book and returns its title
+async fn edit_book(selector: &str, edit: impl Fn(&mut Book)) -> Result<String> {
+    let mut local_library = read_local_library().await?;
+    let now = unix_time();
+    let revision = clock::next_revision(&local_library);
+    let mut title = None;
+    for b in select(&mut local_library, selector) {
+        edit(b);
+        clock::stamp(b, now, revision);
+        title = Some(b.title.clone());
+    }
+    let title = title.ok_or("no such book")?;
+    write_local_library(&local_library).await?;
+    Ok(title)
+}
+
+// books popular with peers whose shelves look like ours, and why
+pub async fn handle_recommend(swarm: &mut Swarm<BookBehavior>) {
+    let local_library = match read_local_library().await {
+        Ok(library) => library,
+        Err(e) => {
+            error!("error retrieving local library: {}", e);
+            return;
+        }
+    };
+    let behaviour = swarm.behaviour();
+    if behaviour.remote_catalogs.all().is_empty() {
+        info!("no peer catalogs yet, ls books all first");
+        return;
+    }
+    let recommendations = recommend::recommend(&local_library, behaviour.remote_catalogs.all());
+    if recommendations.is_empty() {
+        info!("nothing to recommend, no peer with books like yours has any you don't");
+        return;
+    }
+    for (i, r) in recommendations.iter().enumerate() {
+        info!("{}. {} by {}", i + 1, r.title, r.author);
+        for (peer, alike, in_common) in &r.because {
+            let name = peer
+                .parse::<PeerId>()
+                .ok()
+                .and_then(|p| behaviour.names.get(&p))
+                .map(|name| format!(" ({})", name))
+                .unwrap_or_default();
+            info!(
+                "   offered by {}{}, {:.0}% alike, you both have {}",
+                peer,
+                name,
+                alike * 100.0,
+                in_common.join(", ")
+            );
+        }
+    }
+}
+
+// the volumes missing from our series, and which peers' last catalogs offer them
+pub async fn handle_missing_volumes(swarm: &mut Swarm<BookBehavior>) {
+    let local_library = match read_local_library().await {
+        Ok(library) => library,
+        Err(e) => {
+            error!("error retrieving local library: {}", e);
+            return;
+        }
+    };
+    let catalogs = swarm.behaviour().remote_catalogs.all();
+    let missing = series::missing_volumes(&local_library, catalogs);
+    if missing.is_empty() {
+        info!("no volumes missing from your series");
+    }
+    for gap in missing {
+        if gap.offered_by.is_empty() {
+            info!("{} #{}: nobody you heard from offers it", gap.series, gap.volume);
+            continue;
+        }
+        info!("{} #{}:", gap.series, gap.volume);
+        for (peer, title) in gap.offered_by {
+            info!("  {} from {}", title, peer);
+        }
+    }
+    if catalogs.is_empty() {
+        info!("no peer catalogs yet, ls books all first");
+    }
+}
+
+// returns the title that was shared
+async fn share_book_with(
+    selector: &str,
+    group: Option<String>,
+    until: Option<u64>,
+) -> Result<String> {
+    let mut local_library = read_local_library().await?;
+    let now = unix_time();
+    let revision = clock::next_revision(&local_library);
+    let mut title = None;
+    for b in select(&mut local_library, selector) {
+        b.public = true;
+        b.visible_to = group.clone();
+        b.shared_until = until;
+        clock::stamp(b, now, revision);
+        title = Some(b.title.clone());
+    }
+    let title = title.ok_or("no such book")?;
+    write_local_library(&local_library).await?;
+    activity::record(Activity::BookShared {
+        title: title.clone(),
+        group,
+    });
+    Ok(title)
+}
+
+// takes a book out of every catalog we send from now on
+pub async fn handle_revoke(cmd: &str, tombstones: &mpsc::UnboundedSender<Vec<usize>>) {
+    let selector = cmd.strip_prefix("revoke").unwrap_or_default().trim();
+    if selector.is_empty() {
+        error!("format should be: revoke <title or id>");
+        return;
+    }
+    match unshare(selector).await {
+        Ok(books) => {
+            info!("stopped sharing {}", books[0].title);
+            announce_removed(tombstones, &books);
+        }
+        Err(e) => error!("error revoking {}: {}", selector, e),
+    }
+}
+
+// returns the books as they were before, at least one
+async fn unshare(selector: &str) -> Result<Vec<Book>> {
+    let mut local_library = read_local_library().await?;
+    let now = unix_time();
+    let revision = clock::next_revision(&local_library);
+    let mut unshared = Vec::new();
+    for b in select(&mut local_library, selector).filter(|b| b.public) {
+        unshared.push(b.clone());
+        b.public = false;
+        b.visible_to = None;
+        b.shared_until = None;
+        clock::stamp(b, now, revision);
+    }
+    if unshared.is_empty() {
+        return Err("no such shared book".into());
+    }
+    write_local_library(&local_library).await?;
+    activity::record(Activity::BookUnshared {
+        title: unshared[0].title.clone(),
+    });
+    Ok(unshared)
+}
+
+// shares whose time is up go back to private. they are already left out of
+// responses, this makes it stick and shows up in `ls books`
+pub async fn expire_shares(tombstones: &mpsc::UnboundedSender<Vec<usize>>) -> Result<()> {
+    let mut local_library = read_local_library().await?;
+    let now = unix_time();
+    let revision = clock::next_revision(&local_library);
+    let mut expired = Vec::new();
+    for b in local_library.iter_mut().filter(|b| share_ended(b, now)) {
+        expired.push(b.clone());
+        b.public = false;
+        b.visible_to = None;
+        b.shared_until = None;
+        clock::stamp(b, now, revision);
+    }
+    if expired.is_empty() {
+        return Ok(());
+    }
+    write_local_library(&local_library).await?;
+    announce_removed(tombstones, &expired);
+    for book in expired {
+        info!("share of {} ended", book.title);
+        activity::record(Activity::BookUnshared { title: book.title });
+    }
+    Ok(())
+}
+
+pub async fn handle_list_books(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let what = cmd.to_owned();
+    // a trailing "#channel" sends the request on that channel instead of the main topic
+    let (cmd, topic) = match cmd.rsplit_once(" #") {
+        Some((rest, channel)) => {
+            if !swarm.behaviour().channels.contains(channel) {
+                error!("not in channel {}, join it first", channel);
+                return;
+            }
+            (rest, channel_topic(channel))
+        }
+        None => (cmd, TOPIC.clone()),
+    };
+    let (cmd, summary) = match summary_flag(cmd) {
+        Ok(split) => split,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let input = cmd.strip_prefix("ls books ");
+
+    match input {
+        Some("all") => {
+            let req = ListRequest {
+                mode: ListMode::ALL,
+                query: None,
+                summary,
+                trace: swarm.behaviour_mut().traces.start("ls books all"),
+            };
+            let expected = library_peers(swarm);
+            swarm.behaviour_mut().inflight.start(what, Kind::Catalog, expected, true);
+            publish(swarm, topic, &Message::ListRequest(req));
+        }
+        Some(group) if group.starts_with('@') => {
+            let groups = Groups::load();
+            let members = match groups.members(&group[1..]) {
+                Some(members) => members,
+                None => {
+                    error!("unknown group: {}", group);
+                    return;
+                }
+            };
+            for member in members {
+                let req = ListRequest {
+                    mode: ListMode::One(member.to_owned()),
+                    query: None,
+                    summary,
+                    trace: swarm.behaviour_mut().traces.start(&format!("ls books {}", member)),
+                };
+                publish(swarm, topic.clone(), &Message::ListRequest(req));
+            }
+            let expected = members.iter().cloned().collect();
+            swarm.behaviour_mut().inflight.start(what, Kind::Catalog, expected, false);
+        }
+        Some(library_peer_id) => {
+            let trace = swarm
+                .behaviour_mut()
+                .traces
+                .start(&format!("ls books {}", library_peer_id));
+            let req = ListRequest {
+                mode: ListMode::One(library_peer_id.to_owned()),
+                query: None,
+                summary,
+                trace,
+            };
+            let expected = BTreeSet::from([library_peer_id.to_owned()]);
+            swarm.behaviour_mut().inflight.start(what, Kind::Catalog, expected, false);
+            publish(swarm, topic, &Message::ListRequest(req));
+        }
+        None => {
+            match read_local_library().await {
+                Ok(mut val) => {
+                    val.retain(|b| b.trashed.is_none());
+                    if let Some(mode) = summary {
+                        show_summary("Local books", &Summary::of(&val, mode));
+                        return;
+                    }
+                    info!("Local books ({})", val.len());
+                    val.iter().for_each(|book| info!("{:?}", book));
+                }
+                Err(e) => error!("error retrieving local library: {}", e),
+            };
+        }
+    }
+}
+
+// a trailing "--count", "--by author" or "--by publisher" asks for numbers
+// instead of the books themselves
+fn summary_flag(cmd: &str) -> std::result::Result<(&str, Option<SummaryMode>), String> {
+    if let Some(rest) = cmd.strip_suffix(" --count") {
+        return Ok((rest, Some(SummaryMode::Count)));
+    }
+    match cmd.rsplit_once(" --by ") {
+        Some((rest, "author")) => Ok((rest, Some(SummaryMode::ByAuthor))),
+        Some((rest, "publisher")) => Ok((rest, Some(SummaryMode::ByPublisher))),
+        Some((_, by)) => Err(format!("can't count by {}, only by author or publisher", by)),
+        None => Ok((cmd, None)),
+    }
+}
+
+pub fn show_summary(source: &str, summary: &Summary) {
+    info!("{}: {} books", source, summary.total);
+    for (name, count) in &summary.counts {
+        info!("  {}: {}", name, count);
+    }
+    let listed: usize = summary.counts.values().sum();
+    if summary.mode != SummaryMode::Count && listed < summary.total {
+        info!("  others: {}", summary.total - listed);
+    }
+}
+
+// "show book <peer id> <book id>" fetches one book's record from that peer
+pub fn handle_show_book(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let args: Vec<&str> = cmd.split_whitespace().skip(2).collect();
+    let (peer, id) = match args.as_slice() {
+        [peer, id] => match (peer.parse::<PeerId>(), id.parse::<usize>()) {
+            (Ok(peer), Ok(id)) => (peer, id),
+            _ => {
+                error!("invalid peer id or book id");
+                return;
+            }
+        },
+        _ => {
+            error!("format should be: show book <peer id> <book id>");
+            return;
+        }
+    };
+    if !swarm.is_connected(&peer) {
+        error!("not connected to {}", peer);
+        return;
+    }
+    // older nodes would take the request for garbage
+    if !swarm.behaviour().supports(&peer, "details") {
+        error!("{} doesn't support book details, try ls books {}", peer, peer);
+        return;
+    }
+    let req = BookRequest {
+        peer: peer.to_string(),
+        id,
+        trace: swarm.behaviour_mut().traces.start(&format!("show book {} {}", peer, id)),
+    };
+    let expected = BTreeSet::from([peer.to_string()]);
+    let behaviour = swarm.behaviour_mut();
+    behaviour.inflight.start(cmd.to_owned(), Kind::Book(id), expected, false);
+    // straight to the peer, the answer may hold what only it should read
+    let request = behaviour.details.send_request(&peer, req);
+    behaviour.book_requests.insert(request, id);
+}
+
+// "search author:le_guin title:/disposs.*/" asks every peer for its matching books
+pub fn handle_search(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let line = cmd;
+    let (cmd, summary) = match summary_flag(cmd) {
+        Ok(split) => split,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let input = cmd.strip_prefix("search").unwrap_or_default();
+    // checked here too, so a typo isn't sent to everyone
+    let query = match Query::parse(input) {
+        Ok(query) => query,
+        Err(e) => {
+            error!("invalid query: {}", e);
+            return;
+        }
+    };
+    let req = ListRequest {
+        mode: ListMode::ALL,
+        query: Some(query.as_str().to_owned()),
+        summary,
+        trace: swarm.behaviour_mut().traces.start("search"),
+    };
+    let kind = Kind::Search(query.as_str().to_owned());
+    let expected = library_peers(swarm);
+    swarm.behaviour_mut().inflight.start(line.to_owned(), kind, expected, true);
+    publish(swarm, TOPIC.clone(), &Message::ListRequest(req));
+}
+
+// "share all --author tolkien @family" shares every matching book in one write
+pub async fn handle_share_all(cmd: &str) {
+    let input = cmd.strip_prefix("share all").unwrap_or_default();
+    let (input, group) = match input.rsplit_once(" @") {
+        Some((rest, group)) => (rest, Some(group.trim().to_owned())),
+        None => (input, None),
+    };
+    if let Some(ref group) = group {
+        if Groups::load().members(group).is_none() {
+            error!("unknown group: {}", group);
+            return;
+        }
+    }
+    let filter = match Filter::parse(input) {
+        Ok(filter) => filter,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let result = bulk_edit(&filter, |book, _| {
+        book.public = true;
+        book.visible_to = group.clone();
+        book.shared_until = None;
+    })
+    .await;
+    match result {
+        Ok(books) => {
+            info!("now sharing {} books", books.len());
+            for book in books {
+                let group = group.clone();
+                activity::record(Activity::BookShared {
+                    title: book.title,
+                    group,
+                });
+            }
+        }
+        Err(e) => error!("error sharing books, nothing was changed: {}", e),
+    }
+}
+
+pub async fn handle_rm_books(cmd: &str, tombstones: &mpsc::UnboundedSender<Vec<usize>>) {
+    let filter = match Filter::parse(cmd.strip_prefix("rm books").unwrap_or_default()) {
+        Ok(filter) if filter.is_empty() => {
+            error!(
+                "refusing to remove every book, narrow it down with --author, --publisher, \
+                 --tag or --title"
+            );
+            return;
+        }
+        Ok(filter) => filter,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let result = bulk_edit(&filter, |book, now| {
+        book.trashed = Some(now);
+    })
+    .await;
+    match result {
+        Ok(books) => {
+            info!("moved {} books to the trash", books.len());
+            announce_removed(tombstones, &books);
+            for book in books {
+                activity::record(Activity::BookRemoved { title: book.title });
+            }
+        }
+        Err(e) => error!("error removing books, nothing was changed: {}", e),
+    }
+}
+
+// applies the edit to every matching book and saves once, so either all of
+// them change or none do. returns the changed books as they were before
+async fn bulk_edit(filter: &Filter, edit: impl Fn(&mut Book, u64)) -> Result<Vec<Book>> {
+    let mut local_library = read_local_library().await?;
+    let now = unix_time();
+    let revision = clock::next_revision(&local_library);
+    let matching = local_library.iter().filter(|b| filter.matches(b)).count();
+    let mut progress = Progress::new("editing", Some(matching as u64));
+    let mut changed = Vec::new();
+    for book in local_library.iter_mut().filter(|b| filter.matches(b)) {
+        changed.push(book.clone());
+        edit(book, now);
+        clock::stamp(book, now, revision);
+        progress.inc(1);
+    }
+    progress.finish();
+    if !changed.is_empty() {
+        write_local_library(&local_library).await?;
+    }
+    Ok(changed)
+}
+
+// peers caching our catalog drop the books that were shared
+fn announce_removed(tombstones: &mpsc::UnboundedSender<Vec<usize>>, books: &[Book]) {
+    let ids: Vec<usize> = books.iter().filter(|b| b.public).map(|b| b.id).collect();
+    if !ids.is_empty() {
+        let _ = tombstones.send(ids);
+    }
+}
+
+pub async fn handle_rm_book(cmd: &str, tombstones: &mpsc::UnboundedSender<Vec<usize>>) {
+    match cmd.strip_prefix("rm book").map(str::trim).map(str::parse) {
+        Some(Ok(id)) => match set_trashed(id, true).await {
+            Ok(book) => announce_removed(tombstones, &[book]),
+            Err(e) => error!("error removing book {}: {}", id, e),
+        },
+        _ => error!("format should be: rm book <id>"),
+    }
+}
+
+pub async fn handle_restore(cmd: &str) {
+    match cmd.strip_prefix("restore").map(str::trim).map(str::parse) {
+        Some(Ok(id)) => {
+            if let Err(e) = set_trashed(id, false).await {
+                error!("error restoring book {}: {}", id, e);
+            }
+        }
+        _ => error!("format should be: restore <id>"),
+    }
+}
+
+// returns the book as it was before
+async fn set_trashed(id: usize, trashed: bool) -> Result<Book> {
+    let mut local_library = read_local_library().await?;
+    let revision = clock::next_revision(&local_library);
+    let book = local_library
+        .iter_mut()
+        .find(|b| b.id == id && b.trashed.is_some() != trashed)
+        .ok_or(if trashed { "no such book" } else { "no such book in the trash" })?;
+    let before = book.clone();
+    let now = unix_time();
+    book.trashed = if trashed { Some(now) } else { None };
+    clock::stamp(book, now, revision);
+    let title = book.title.clone();
+    write_local_library(&local_library).await?;
+    if trashed {
+        info!("moved {} to the trash, restore {} brings it back", title, id);
+        activity::record(Activity::BookRemoved { title });
+    } else {
+        info!("restored {}", title);
+        activity::record(Activity::BookRestored { title });
+    }
+    Ok(before)
+}
+
+const HISTORY_SHOWN: usize = 20;
+
+// "history" lists the latest changes, "history <id>" those of one book and
+// "history --at <time>" the library as it was then
+pub fn handle_history(cmd: &str) {
+    let batches = history::load();
+    let now = unix_time();
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    match args.as_slice() {
+        [] => {
+            if batches.is_empty() {
+                info!("no changes recorded yet");
+            }
+            for batch in batches.iter().rev().take(HISTORY_SHOWN).rev() {
+                let described: Vec<String> = batch.changes.iter().map(Change::describe).collect();
+                let note = match (batch.undoes, batch.outside) {
+                    (Some(undone), _) => format!(", undoing #{}", undone),
+                    (None, true) => ", found at startup".to_owned(),
+                    (None, false) => String::new(),
+                };
+                let ago = activity::ago(now.saturating_sub(batch.at));
+                info!("#{} {}{}: {}", batch.id, ago, note, described.join("; "));
+            }
+        }
+        ["--at", when] => {
+            let at = match (when.parse::<u64>(), activity::parse_duration(when)) {
+                (Ok(at), _) => at,
+                (_, Some(ago)) => now.saturating_sub(ago),
+                _ => {
+                    error!("the time should be unix time or how long ago, e.g. 2d");
+                    return;
+                }
+            };
+            let mut library = history::replay(&batches, Some(at));
+            library.retain(|b| b.trashed.is_none());
+            info!("Local books {} ({})", activity::ago(now.saturating_sub(at)), library.len());
+            for book in library {
+                info!("{}: {} by {}", book.id, book.title, book.author);
+            }
+        }
+        [id] => {
+            let id: usize = match id.parse() {
+                Ok(id) => id,
+                Err(_) => {
+                    error!("format should be: history [<id> | --at <time>]");
+                    return;
+                }
+            };
+            let mut found = false;
+            for batch in &batches {
+                for change in batch.changes.iter().filter(|c| c.id() == id) {
+                    found = true;
+                    let ago = activity::ago(now.saturating_sub(batch.at));
+                    info!("#{} {}: {}", batch.id, ago, change.describe());
+                }
+            }
+            if !found {
+                info!("no changes recorded for book {}", id);
+            }
+        }
+        _ => error!("format should be: history [<id> | --at <time>]"),
+    }
+}
+
+// reverts the latest change made here that wasn't undone yet
+pub async fn handle_undo() {
+    let batches = history::load();
+    let batch = match history::last_undoable(&batches) {
+        Some(batch) => batch,
+        None => {
+            info!("nothing to undo");
+            return;
+        }
+    };
+    match undo(batch).await {
+        Ok(skipped) => {
+            let described: Vec<String> = batch.changes.iter().map(Change::describe).collect();
+            info!("undid #{}: {}", batch.id, described.join("; "));
+            for book in skipped {
+                info!("  left {} alone, it changed since", book);
+            }
+        }
+        Err(e) => error!("unable to undo #{}: {}", batch.id, e),
+    }
+}
+
+// the books left alone
+async fn undo(batch: &history::Batch) -> Result<Vec<String>> {
+    let mut library = read_local_library().await?;
+    let revision = clock::next_revision(&library);
+    let skipped = history::revert(batch, &mut library, unix_time(), revision);
+    write_library(&library, Some(batch.id)).await?;
+    Ok(skipped)
+}
+
+// snapshot create <name>, snapshot list and snapshot rollback <name>
+pub async fn handle_snapshot(cmd: &str) {
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    match args.as_slice() {
+        ["create", name] => {
+            let created = match read_local_library().await {
+                Ok(library) => snapshots::create(name, &library).map(|()| library.len()),
+                Err(e) => Err(e),
+            };
+            match created {
+                Ok(books) => info!("saved {} books as snapshot {}", books, name),
+                Err(e) => error!("unable to create snapshot {}: {}", name, e),
+            }
+        }
+        ["list"] => {
+            let snapshots = snapshots::list();
+            if snapshots.is_empty() {
+                info!("no snapshots, snapshot create <name> makes one");
+            }
+            let now = unix_time();
+            for snapshot in snapshots {
+                let ago = activity::ago(now.saturating_sub(snapshot.created));
+                info!("{}: {} books, {}", snapshot.name, snapshot.books, ago);
+            }
+        }
+        ["rollback", name] => match rollback(name).await {
+            Ok(changed) => {
+                info!("rolled back to snapshot {}, {} books changed", name, changed);
+                if changed > 0 {
+                    info!("undo brings back the library as it was before");
+                }
+            }
+            Err(e) => error!("unable to roll back to snapshot {}: {}", name, e),
+        },
+        _ => error!("format should be: snapshot create <name>|list|rollback <name>"),
+    }
+}
+
+// how many books were changed, added or removed
+async fn rollback(name: &str) -> Result<usize> {
+    let mut snapshot = snapshots::load(name)?;
+    let library = read_local_library().await?;
+    let now = unix_time();
+    let revision = clock::next_revision(&library);
+    let mut changed = library.iter().filter(|b| !snapshot.iter().any(|s| s.id == b.id)).count();
+    for book in snapshot.iter_mut() {
+        if !matches!(library.iter().find(|b| b.id == book.id), Some(b) if history::same(b, book)) {
+            // newer than what other devices hold, so syncing keeps the rollback
+            clock::stamp(book, now, revision);
+            changed += 1;
+        }
+    }
+    if changed > 0 {
+        write_local_library(&snapshot).await?;
+    }
+    Ok(changed)
+}
+
+pub async fn handle_trash(cmd: &str) {
+    if cmd.trim() != "trash list" {
+        error!("format should be: trash list");
+        return;
+    }
+    let library = match read_local_library().await {
+        Ok(library) => library,
+        Err(e) => {
+            error!("error retrieving local library: {}", e);
+            return;
+        }
+    };
+    let now = unix_time();
+    let keep = CONFIG.trash.keep_days * 24 * 60 * 60;
+    let mut empty = true;
+    for book in &library {
+        if let Some(trashed) = book.trashed {
+            empty = false;
+            let left = (trashed + keep).saturating_sub(now) / (24 * 60 * 60);
+            info!(
+                "{}: {} by {}, removed {}, purged in {} days",
+                book.id,
+                book.title,
+                book.author,
+                activity::ago(now.saturating_sub(trashed)),
+                left
+            );
+        }
+    }
+    if empty {
+        info!("the trash is empty");
+    }
+}
+
+pub async fn handle_fsck() {
+    match fsck::check().await {
+        Ok(report) => report.log(),
+        Err(e) => error!("unable to check {}: {}", STORAGE_PATH, e),
+    }
+}
+
+// books that sat in the trash longer than keep_days are gone for good
+pub async fn purge_trash() -> Result<()> {
+    let mut local_library = read_local_library().await?;
+    let cutoff = unix_time().saturating_sub(CONFIG.trash.keep_days * 24 * 60 * 60);
+    let before = local_library.len();
+    local_library.retain(|b| !matches!(b.trashed, Some(at) if at < cutoff));
+    if local_library.len() != before {
+        write_local_library(&local_library).await?;
+        info!("purged {} books from the trash", before - local_library.len());
+    }
+    Ok(())
+}
+
+// who asked for a catalog, and whether one only it may see can be sealed to it
+pub struct Requester {
+    pub peer: String,
+    pub sealable: bool,
+}
+
+pub fn respond_with_public_books(
+    sender: mpsc::UnboundedSender<(Topic, Reply)>,
+    requester: Requester,
+    topic: Topic,
+    query: Option<Query>,
+    summary: Option<SummaryMode>,
+    span: Option<Span>,
+    relayed: Vec<Relayed>,
+) {
+    let Requester { peer: receiver, sealable } = requester;
+    tokio::spawn(async move {
+        // before reading the library, a response built from an older copy of
+        // it must not get a newer version
+        let version = next_catalog_version();
+        match read_local_library().await {
+            Ok(books) => {
+                let groups = Groups::load();
+                let ledger = Ledger::load();
+                let us = PEER_ID.to_string();
+                let shelf = respond::Shelf {
+                    books,
+                    visibility: &CONFIG.visibility,
+                    now: unix_time(),
+                    rehost: CONFIG.supernode.allow,
+                    lent_out: &|id| ledger.lent_out(&us, id),
+                };
+                let requester = respond::Requester {
+                    peer: receiver.clone(),
+                    sealable,
+                    friend: groups.iter().any(|(_, members)| members.contains(&receiver)),
+                    in_group: &|group| groups.contains(group, &receiver),
+                };
+                let mut catalog = respond::catalog(shelf, &requester, query.as_ref(), summary);
+                catalog.response.trace = span.as_ref().map(Span::traceparent);
+                catalog.response.relayed = relayed;
+                catalog.response.version = Some(version);
+                if let Some(span) = span {
+                    span.end();
+                }
+                if let Err(e) = sender.send((topic, Ok(catalog))) {
+                    error!("error responding: {}", e);
+                }
+            }
+            Err(e) => {
+                error!("error retrieving local library: {}", e);
+                let nack = Nack {
+                    receiver,
+                    reason: NackReason::Unavailable,
+                    retry_after: None,
+                };
+                let _ = sender.send((topic, Err(nack)));
+            }
+        }
+    });
+}
+
+pub const CATALOG_VERSION_PATH: &str = "./catalog_version.json";
+
+// each catalog we send is numbered above the last, the time in ms unless the
+// clock went back. the last number is kept on disk, so one set back across a
+// restart doesn't make new catalogs look older than ones peers already have
+fn next_catalog_version() -> u64 {
+    static LAST: Mutex<Option<u64>> = Mutex::new(None);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    let mut last = LAST.lock().unwrap_or_else(|e| e.into_inner());
+    let next = now.max(last.unwrap_or_else(|| store::load(CATALOG_VERSION_PATH)) + 1);
+    *last = Some(next);
+    if let Err(e) = store::save(CATALOG_VERSION_PATH, &next) {
+        error!("unable to save {}: {}", CATALOG_VERSION_PATH, e);
+    }
+    next
+}
+
+// the one book, if it's in the catalog the requester would get, and what we
+// have of its file. the answer goes back on the request's own stream
+pub fn respond_with_book(
+    sender: mpsc::UnboundedSender<(ResponseChannel<BookDetail>, BookDetail)>,
+    receiver: String,
+    channel: ResponseChannel<BookDetail>,
+    id: usize,
+    span: Option<Span>,
+) {
+    tokio::spawn(async move {
+        match read_local_library().await {
+            Ok(books) => {
+                let ours = books.iter().find(|b| b.id == id).cloned();
+                let bytes = match ours.as_ref().and_then(|b| b.file.as_ref()) {
+                    Some(path) => fs::metadata(path).await.ok().map(|m| m.len()),
+                    None => None,
+                };
+                let groups = Groups::load();
+                let ledger = Ledger::load();
+                let us = PEER_ID.to_string();
+                let shelf = respond::Shelf {
+                    books,
+                    visibility: &CONFIG.visibility,
+                    now: unix_time(),
+                    rehost: false,
+                    lent_out: &|id| ledger.lent_out(&us, id),
+                };
+                let requester = respond::Requester {
+                    peer: receiver.clone(),
+                    sealable: false,
+                    friend: groups.iter().any(|(_, members)| members.contains(&receiver)),
+                    in_group: &|group| groups.contains(group, &receiver),
+                };
+                let book = respond::book(shelf, &requester, id);
+                let file = match (&book, ours) {
+                    (Some(_), Some(ours)) => {
+                        respond::file(&ours, &requester, &CONFIG.visibility, bytes)
+                    }
+                    _ => None,
+                };
+                audit::record(
+                    &receiver,
+                    Access::Book {
+                        id,
+                        found: book.is_some(),
+                    },
+                );
+                let detail = BookDetail {
+                    receiver,
+                    id,
+                    book,
+                    file,
+                    trace: span.as_ref().map(Span::traceparent),
+                };
+                if let Some(span) = span {
+                    span.end();
+                }
+                let _ = sender.send((channel, detail));
+            }
+            Err(e) => error!("error retrieving local library: {}", e),
+        }
+    });
+}
+
+// our current library, to go out to our other devices
+pub fn send_library_to_devices(sender: mpsc::UnboundedSender<(Replica, bool)>) {
+    tokio::spawn(async move {
+        match read_local_library().await {
+            Ok(books) => {
+                let deleted = Deletions::load();
+                let _ = sender.send((Replica { books, deleted }, false));
+            }
+            Err(e) => error!("error retrieving local library: {}", e),
+        }
+    });
+}
+
+pub fn merge_from_device(
+    device: String,
+    remote: Replica,
+    base: Base,
+    sender: mpsc::UnboundedSender<(Replica, bool)>,
+) {
+    tokio::spawn(async move {
+        // a fresh device has no library file yet
+        let mut library = read_local_library().await.unwrap_or_default();
+        let ahead = clock::ahead(&remote.books);
+        if ahead > 0 {
+            error!("{} books from {} are dated in the future, is its clock wrong?", ahead, device);
+        }
+        let theirs = sync::fingerprint(&remote);
+        let mut deleted = Deletions::load();
+        let (changed, found) = conflicts::merge(&mut library, &mut deleted, remote, base);
+        // before the library, whose write would date the deletions now
+        deleted.save();
+        let mut conflicts = Conflicts::load();
+        conflicts.settle(&library);
+        if !found.is_empty() {
+            for (kept, other) in found {
+                info!("book {} was edited on {} too, kept the newer edit", kept.id, device);
+                conflicts.add(&device, kept, other);
+            }
+            info!("see conflicts to review them");
+        }
+        if changed {
+            if let Err(e) = write_local_library(&library).await {
+                error!("error saving synced library: {}", e);
+                return;
+            }
+            info!("library updated from another device, {} books", library.len());
+        }
+        // the other device is missing something we have, send it back
+        let ours = Replica {
+            books: library,
+            deleted,
+        };
+        if sync::fingerprint(&ours) != theirs {
+            let _ = sender.send((ours, true));
+        }
+    });
+}
+
+// "conflicts" lists them, "conflicts pick <n> kept|other" resolves one
+pub async fn handle_conflicts(cmd: &str) {
+    let mut conflicts = Conflicts::load();
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    match args.as_slice() {
+        [] => {
+            let mut empty = true;
+            for (n, c) in conflicts.iter().enumerate() {
+                empty = false;
+                let when = activity::ago(unix_time().saturating_sub(c.at));
+                info!("#{} book {}, {} from {}:", n + 1, c.id, when, c.source);
+                info!("  kept:  {:?}", c.kept);
+                info!("  other: {:?}", c.other);
+            }
+            if empty {
+                info!("no conflicts");
+            }
+        }
+        ["pick", n, choice @ ("kept" | "other")] => {
+            let conflict = match n.parse::<usize>().ok().and_then(|n| n.checked_sub(1)) {
+                Some(index) => conflicts.take(index),
+                None => None,
+            };
+            let conflict = match conflict {
+                Some(conflict) => conflict,
+                None => {
+                    error!("no conflict #{}", n);
+                    return;
+                }
+            };
+            if *choice == "kept" {
+                info!("keeping book {} as it is", conflict.id);
+                return;
+            }
+            if let Err(e) = replace_book(conflict.other).await {
+                error!("error restoring the other version: {}", e);
+            }
+        }
+        _ => error!("format should be: conflicts or conflicts pick <n> kept|other"),
+    }
+}
+
+const MERGE_USAGE: &str = "format should be: merge <file> [--prefer ask|ours|theirs|newer]";
+
+// merge <file>, another library.json of any version or a node archive. the
+// merge is one history entry, undo takes it back
+pub async fn handle_merge(cmd: &str) {
+    let (path, policy) = match cmd.split_whitespace().skip(1).collect::<Vec<_>>().as_slice() {
+        [path] => (path.to_string(), Policy::Ask),
+        [path, "--prefer", policy] => match Policy::parse(policy) {
+            Some(policy) => (path.to_string(), policy),
+            None => return error!("{}", MERGE_USAGE),
+        },
+        _ => return error!("{}", MERGE_USAGE),
+    };
+    match merge_library(&path, policy).await {
+        Ok(outcome) => {
+            info!(
+                "merged {}: {} added, {} duplicates, {} replaced by theirs, {} kept as ours",
+                path, outcome.added, outcome.duplicates, outcome.replaced, outcome.kept
+            );
+            if !outcome.conflicts.is_empty() {
+                info!(
+                    "{} books differ on both sides, `conflicts` lists them to pick from",
+                    outcome.conflicts.len()
+                );
+            }
+        }
+        Err(e) => error!("unable to merge {}: {}", path, e),
+    }
+}
+
+async fn merge_library(path: &str, policy: Policy) -> Result<merge::Outcome> {
+    let content = fs::read(datadir::user_path(path)).await?;
+    let theirs = match bundle::library_in(&content) {
+        Some(library) => schema::parse(&library?)?,
+        None => schema::parse(&content)?,
+    };
+    let mut library = read_local_library().await?;
+    let outcome = merge::merge(&mut library, theirs, policy, unix_time());
+    if outcome.added + outcome.replaced > 0 {
+        write_local_library(&library).await?;
+    }
+    let mut conflicts = Conflicts::load();
+    for (kept, other) in outcome.conflicts.iter().cloned() {
+        conflicts.add(&format!("merge {}", path), kept, other);
+    }
+    Ok(outcome)
+}
+
+// the chosen version counts as a new edit, so it wins on the other devices too
+async fn replace_book(mut book: Book) -> Result<()> {
+    let mut local_library = read_local_library().await?;
+    clock::stamp(&mut book, unix_time(), clock::next_revision(&local_library));
+    match local_library.iter_mut().find(|b| b.id == book.id) {
+        Some(existing) => *existing = book.clone(),
+        None => local_library.push(book.clone()),
+    }
+    write_local_library(&local_library).await?;
+    info!("book {} is now {} by {}", book.id, book.title, book.author);
+    Ok(())
+}
+
+pub fn handle_devices(swarm: &mut Swarm<BookBehavior>) {
+    let sync = match swarm.behaviour().sync.as_ref() {
+        Some(sync) => sync,
+        None => {
+            info!("device sync is off, set a secret under [sync] in config.toml");
+            return;
+        }
+    };
+    if sync.devices.is_empty() {
+        info!("none of your other devices has been seen yet");
+    }
+    for device in &sync.devices {
+        let state = if swarm.is_connected(device) { "online" } else { "offline" };
+        info!("{} ({})", device, state);
+    }
+}
+
+// the running node keeps its current key, the new one is used from the next start
+pub fn handle_rotate_key(swarm: &mut Swarm<BookBehavior>) {
+    let new = identity::Keypair::generate_ed25519();
+    let rotation = rotation::sign(&KEYS, &new);
+    // saved first, a key nobody was told about would strand our friends
+    let mut rotations = Rotations::load();
+    rotations.push(rotation.clone());
+    if let Err(e) = rotations.save() {
+        error!("error saving rotations: {}", e);
+        return;
+    }
+    if let Err(e) = keys::replace(&new) {
+        error!("unable to store the new key, keeping the old one: {}", e);
+        return;
+    }
+    info!("new peer id {}, restart to use it", rotation.new);
+    publish(swarm, TOPIC.clone(), &Message::Rotation(rotation));
+}
+
+// asks every peer to drop what it keeps about us. peers that are away get it
+// when they're back, for a month
+pub fn handle_forget_me(swarm: &mut Swarm<BookBehavior>) {
+    let forget = forget::sign(&KEYS);
+    let away: Vec<String> = swarm
+        .behaviour()
+        .peer_store
+        .last_seen()
+        .map(|(peer, _)| peer)
+        .filter(|peer| !swarm.is_connected(peer))
+        .map(|peer| peer.to_string())
+        .collect();
+    let connected = swarm.connected_peers().count();
+    publish(swarm, TOPIC.clone(), &Message::Forget(forget.clone()));
+    for peer in &away {
+        let message = Message::Forget(forget.clone());
+        swarm.behaviour_mut().outbox.push_expiring(peer, message, forget::KEEP_ASKING);
+    }
+    info!(
+        "asked {} connected peers to forget you, {} more will be asked when they're back",
+        connected,
+        away.len()
+    );
+}
+
+pub fn handle_join_channel(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    if let Some(channel) = cmd.strip_prefix("join ") {
+        let channel = channel.trim();
+        if channel.is_empty() || channel.contains(char::is_whitespace) {
+            error!("invalid channel name: {}", channel);
+            return;
+        }
+        let behaviour = swarm.behaviour_mut();
+        if behaviour.floodsub.subscribe(channel_topic(channel)) {
+            behaviour.channels.insert(channel.to_owned());
+            info!("joined channel: {}", channel);
+        } else {
+            info!("already in channel: {}", channel);
+        }
+    }
+}
+
+pub fn handle_leave_channel(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    if let Some(channel) = cmd.strip_prefix("leave ") {
+        let channel = channel.trim();
+        let behaviour = swarm.behaviour_mut();
+        if behaviour.channels.remove(channel) {
+            behaviour.floodsub.unsubscribe(channel_topic(channel));
+            info!("left channel: {}", channel);
+        } else {
+            error!("not in channel: {}", channel);
+        }
+    }
+}
+
+pub fn handle_list_channels(swarm: &mut Swarm<BookBehavior>) {
+    let channels = &swarm.behaviour().channels;
+    info!("Channels ({})", channels.len());
+    channels.iter().for_each(|c| info!("#{}", c));
+}
+
+// "club create|join|leave <name>", "club book <name> <title>|<author>" (or
+// "off"), "club schedule <name> <7d>|<what to read by then>" (or "clear"),
+// "club say <name> <text>" and "club show <name>"
+pub fn handle_club(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let rest = cmd.strip_prefix("club ").unwrap_or_default().trim();
+    let (action, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    let (name, arg) = rest.trim().split_once(' ').unwrap_or((rest.trim(), ""));
+    let arg = arg.trim();
+    if !valid_name(name) {
+        error!("format should be: club create|join|leave|book|schedule|say|show <name> ...");
+        return;
+    }
+    match action {
+        "create" | "join" => {
+            let behaviour = swarm.behaviour_mut();
+            if !behaviour.clubs.join(name) {
+                info!("already in club {}", name);
+                return;
+            }
+            behaviour.floodsub.subscribe(club_topic(name));
+            if action == "create" {
+                info!("created club {}, others can join it with club join {}", name, name);
+            } else {
+                info!("joined club {}, members will send what it reads", name);
+            }
+        }
+        "leave" => {
+            let behaviour = swarm.behaviour_mut();
+            if behaviour.clubs.leave(name) {
+                behaviour.floodsub.unsubscribe(club_topic(name));
+                info!("left club {}", name);
+            } else {
+                error!("not in club {}", name);
+            }
+        }
+        "say" => {
+            if swarm.behaviour().clubs.get(name).is_none() {
+                error!("not in club {}, join it first", name);
+                return;
+            }
+            publish_chat(swarm, club_topic(name), arg, None, None);
+        }
+        "show" => show_club_members(name, swarm),
+        "book" => {
+            let book = match arg.split_once('|') {
+                _ if arg == "off" => None,
+                Some((title, author)) if !title.trim().is_empty() => Some(ClubBook {
+                    title: title.trim().to_owned(),
+                    author: author.trim().to_owned(),
+                }),
+                _ => {
+                    error!("format should be: club book <name> <title>|<author> or off");
+                    return;
+                }
+            };
+            let state = swarm.behaviour_mut().clubs.set_book(name, book);
+            announce_club(swarm, name, state);
+        }
+        "schedule" => {
+            let state = match arg.split_once('|') {
+                _ if arg == "clear" => swarm.behaviour_mut().clubs.clear_schedule(name),
+                Some((within, reading)) => {
+                    let within = match activity::parse_duration(within) {
+                        Some(within) => within,
+                        None => {
+                            error!("invalid time span {}, e.g. 3d or 2w", within.trim());
+                            return;
+                        }
+                    };
+                    let reading = reading.trim();
+                    if reading.is_empty() || reading.len() > 80 {
+                        error!("say what to read in at most 80 bytes");
+                        return;
+                    }
+                    let clubs = &mut swarm.behaviour_mut().clubs;
+                    if clubs.get(name).map_or(0, |c| c.state.schedule.len()) >= MAX_MILESTONES {
+                        error!("the schedule is full, clear it first");
+                        return;
+                    }
+                    let milestone = Milestone {
+                        by: unix_time() + within,
+                        reading: reading.to_owned(),
+                    };
+                    clubs.schedule(name, milestone)
+                }
+                None => {
+                    error!("format should be: club schedule <name> <3d>|<reading> or clear");
+                    return;
+                }
+            };
+            announce_club(swarm, name, state);
+        }
+        _ => error!("unknown club command {}", action),
+    }
+}
+
+// the change goes out on the club's topic, members keep the newest
+fn announce_club(swarm: &mut Swarm<BookBehavior>, name: &str, state: Option<ClubState>) {
+    match state {
+        Some(state) => {
+            info!("club {} updated:", name);
+            show_club(&state, swarm.behaviour().remote_catalogs.all());
+            publish(swarm, club_topic(name), &Message::Club(state));
+        }
+        None => error!("not in club {}, join it first", name),
+    }
+}
+
+fn show_club_members(name: &str, swarm: &mut Swarm<BookBehavior>) {
+    let behaviour = swarm.behaviour();
+    let club = match behaviour.clubs.get(name) {
+        Some(club) => club,
+        None => {
+            error!("not in club {}", name);
+            return;
+        }
+    };
+    info!("club {}:", name);
+    show_club(&club.state, behaviour.remote_catalogs.all());
+    info!("  members ({}):", club.members.len());
+    for member in &club.members {
+        let peer = member.parse::<PeerId>().ok();
+        let online = match peer {
+            Some(peer) if swarm.is_connected(&peer) => match behaviour.presence.of(&peer) {
+                Some(presence) => presence::describe(presence),
+                None => "online".to_owned(),
+            },
+            _ => "offline".to_owned(),
+        };
+        let name = peer.and_then(|p| behaviour.names.get(&p));
+        match name {
+            Some(name) => info!("    {} ({}): {}", name, member, online),
+            None => info!("    {}: {}", member, online),
+        }
+    }
+}
+
+pub fn handle_list_clubs(swarm: &mut Swarm<BookBehavior>) {
+    let clubs = &swarm.behaviour().clubs;
+    let mut empty = true;
+    for (name, club) in clubs.iter() {
+        empty = false;
+        let reading = match club.state.book {
+            Some(ref book) => format!("reading {} by {}", book.title, book.author),
+            None => "no book picked yet".to_owned(),
+        };
+        info!("{} ({} members): {}", name, club.members.len(), reading);
+    }
+    if empty {
+        info!("not in any club, club create <name> starts one");
+    }
+}
+
+pub fn handle_say(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    if let Some(rest) = cmd.strip_prefix("say ") {
+        // "say #scifi hello" talks on a channel, plain "say hello" on the main topic
+        let (topic, text) = match rest.strip_prefix('#').and_then(|r| r.split_once(' ')) {
+            Some((channel, text)) => {
+                if !swarm.behaviour().channels.contains(channel) {
+                    error!("not in channel {}, join it first", channel);
+                    return;
+                }
+                (channel_topic(channel), text)
+            }
+            None => (TOPIC.clone(), rest),
+        };
+        publish_chat(swarm, topic, text.trim(), None, None);
+    }
+}
+
+pub fn handle_msg(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    match cmd.strip_prefix("msg ").and_then(|rest| rest.split_once(' ')) {
+        Some((peer_id, text)) => {
+            let peer: PeerId = match peer_id.parse() {
+                Ok(peer) => peer,
+                Err(_) => {
+                    error!("invalid peer id: {}", peer_id);
+                    return;
+                }
+            };
+            let text = text.trim();
+            if !text.is_empty() && !swarm.is_connected(&peer) {
+                let chat = Message::Chat(ChatMessage {
+                    text: text.to_owned(),
+                    to: Some(peer_id.to_owned()),
+                    id: None,
+                });
+                leave_for_offline_peer(swarm, &peer, chat);
+                return;
+            }
+            if !swarm.behaviour().supports(&peer, "chat") {
+                info!("{} hasn't said it supports chat, the message may go unseen", peer_id);
+            }
+            if let Some(presence) = swarm.behaviour().presence.of(&peer) {
+                if presence.availability != Availability::Online {
+                    info!("{} is {}", peer_id, presence::describe(presence));
+                }
+            }
+            // peers that ack direct messages get them again until they do
+            let id = swarm.behaviour().supports(&peer, "ack").then(rand::random::<u64>);
+            let to = Some(peer_id.to_owned());
+            if publish_chat(swarm, TOPIC.clone(), text, to, id) {
+                if let Some(id) = id {
+                    swarm.behaviour_mut().acks.track(id, peer, text);
+                }
+            }
+        }
+        None => error!("missing arguments. format should be: msg <peer id> <text>"),
+    }
+}
+
+// a connected relay can pass it on even if we go offline ourselves,
+// otherwise it waits in our own outbox
+fn leave_for_offline_peer(swarm: &mut Swarm<BookBehavior>, peer: &PeerId, message: Message) {
+    let relay = RELAYS.iter().map(|(r, _)| r).find(|r| swarm.is_connected(r));
+    let sealed = relay.and_then(|_| sealing::seal_message(&KEYS, peer, &message));
+    match (relay, sealed) {
+        (Some(relay), Some(sealed)) => {
+            let deposit = Deposit {
+                relay: relay.to_string(),
+                message: sealed,
+            };
+            publish(swarm, TOPIC.clone(), &Message::Deposit(deposit));
+            info!("{} is offline, left the message with relay {}", peer, relay);
+        }
+        _ => {
+            swarm.behaviour_mut().outbox.push(&peer.to_string(), message);
+            info!("{} is offline, the message will be delivered when it's back", peer);
+        }
+    }
+}
+
+// false when there was nothing to send
+fn publish_chat(
+    swarm: &mut Swarm<BookBehavior>,
+    topic: Topic,
+    text: &str,
+    to: Option<String>,
+    id: Option<u64>,
+) -> bool {
+    if text.is_empty() {
+        error!("nothing to say");
+        return false;
+    }
+    let chat = ChatMessage {
+        text: text.to_owned(),
+        to,
+        id,
+    };
+    publish(swarm, topic, &Message::Chat(chat));
+    true
+}
+
+// library peers connected now, the ones a broadcast expects to answer
+fn library_peers(swarm: &Swarm<BookBehavior>) -> BTreeSet<String> {
+    let behaviour = swarm.behaviour();
+    swarm
+        .connected_peers()
+        .filter(|peer| behaviour.capabilities.contains_key(peer))
+        .map(PeerId::to_string)
+        .collect()
+}
+
+// "requests" lists the queries still waiting for answers and the work
+// running in the background, "requests cancel <id>" is "cancel <id>"
+pub fn handle_requests(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    match cmd.strip_prefix("requests").unwrap_or_default().trim() {
+        "" => (),
+        rest if rest.starts_with("cancel") => return handle_cancel(rest, swarm),
+        _ => return error!("format should be: requests or requests cancel <id>"),
+    }
+    let mut empty = true;
+    for request in swarm.behaviour().inflight.iter() {
+        empty = false;
+        let waiting: Vec<&str> = request.waiting_on().map(String::as_str).collect();
+        let waiting = match waiting.len() {
+            0 => String::new(),
+            _ => format!(", waiting on {}", waiting.join(", ")),
+        };
+        let answered = match request.broadcast {
+            true => format!(
+                "{} answered of {} peers connected when it went out",
+                request.answered.len(),
+                request.expected.len()
+            ),
+            false => format!("{} of {} answered", request.answered.len(), request.expected.len()),
+        };
+        let elapsed = request.started.elapsed().as_secs();
+        info!("#{} {}, {}s, {}{}", request.id, request.what, elapsed, answered, waiting);
+    }
+    for (id, what, elapsed) in operations::running() {
+        empty = false;
+        info!("#{} {}, running for {}s", id, what, elapsed.as_secs());
+    }
+    if empty {
+        info!("no requests waiting for answers");
+    }
+}
+
+// "cancel <id>" stops waiting for a request, or stops background work at its
+// next step without leaving half of it behind
+pub fn handle_cancel(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let id = match cmd.strip_prefix("cancel").map(str::trim).map(str::parse::<u32>) {
+        Some(Ok(id)) => id,
+        _ => return error!("format should be: cancel <id>, requests shows them"),
+    };
+    if let Some(what) = swarm.behaviour_mut().inflight.cancel(id) {
+        info!("cancelled #{} {}, answers still coming are dropped", id, what);
+    } else if let Some(what) = operations::cancel(id) {
+        info!("cancelling #{} {}", id, what);
+    } else {
+        error!("nothing running as #{}", id);
+    }
+}
+
+pub fn handle_queue(swarm: &mut Swarm<BookBehavior>) {
+    let now = unix_time();
+    let mut empty = true;
+    for pending in swarm.behaviour().outbox.iter() {
+        empty = false;
+        let what = match &pending.message {
+            Message::Chat(chat) => format!("message \"{}\"", chat.text),
+            Message::ListResponse(res) => {
+                let books = res.summary.as_ref().map_or(res.data.len(), |s| s.total);
+                format!("catalog of {} books", books)
+            }
+            other => format!("{:?}", other),
+        };
+        let expires = match pending.expires_at {
+            Some(at) => format!(", dropped in {}s", at.saturating_sub(now)),
+            None => String::new(),
+        };
+        info!(
+            "{} for {}, queued {}{}",
+            what,
+            pending.to,
+            activity::ago(now.saturating_sub(pending.queued_at)),
+            expires
+        );
+    }
+    for unacked in swarm.behaviour().acks.iter() {
+        empty = false;
+        info!(
+            "message \"{}\" for {}, sent {} and not confirmed yet",
+            unacked.text,
+            unacked.to,
+            activity::ago(now.saturating_sub(unacked.first_sent))
+        );
+    }
+    if empty {
+        info!("nothing queued");
+    }
+    if let Some(mailbox) = swarm.behaviour().mailbox.as_ref() {
+        info!("holding {} sealed messages for others", mailbox.len());
+    }
+}
+
+pub fn handle_group(cmd: &str) {
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    let mut groups = Groups::load();
+    match args.as_slice() {
+        ["add", group, peer] => {
+            if peer.parse::<libp2p::PeerId>().is_err() {
+                error!("invalid peer id: {}", peer);
+                return;
+            }
+            if groups.add(group, peer) {
+                info!("added {} to @{}", peer, group);
+            } else {
+                info!("{} is already in @{}", peer, group);
+            }
+        }
+        ["rm", group, peer] => {
+            if groups.remove(group, peer) {
+                info!("removed {} from @{}", peer, group);
+            } else {
+                error!("{} is not in @{}", peer, group);
+            }
+        }
+        _ => {
+            error!("format should be: group add <group> <peer id> or group rm <group> <peer id>");
+            return;
+        }
+    }
+    if let Err(e) = groups.save() {
+        error!("error saving groups: {}", e);
+    }
+}
+
+pub fn handle_list_groups() {
+    let groups = Groups::load();
+    for (group, members) in groups.iter() {
+        info!("@{} ({})", group, members.len());
+        members.iter().for_each(|m| info!("  {}", m));
+    }
+}
+
+pub fn handle_silent(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let behaviour = swarm.behaviour_mut();
+    match cmd.strip_prefix("silent").map(str::trim) {
+        Some("on") => behaviour.silent = true,
+        Some("off") => behaviour.silent = false,
+        Some("") => (),
+        _ => {
+            error!("format should be: silent [on|off]");
+            return;
+        }
+    }
+    info!(
+        "silent mode is {}",
+        if behaviour.silent { "on" } else { "off" }
+    );
+}
+
+pub fn handle_cache(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let cache = &mut swarm.behaviour_mut().remote_catalogs;
+    match cmd {
+        "cache stats" => {
+            let stats = cache.stats();
+            info!(
+                "{} peer catalogs cached, {} of {} bytes",
+                stats.peers, stats.bytes, stats.budget
+            );
+            info!(
+                "{} lookups found a catalog, {} didn't, {} catalogs dropped to stay under budget",
+                stats.hits, stats.misses, stats.evicted
+            );
+        }
+        "cache clear" => info!("dropped {} peer catalogs", cache.clear()),
+        _ => error!("format should be: cache stats|clear"),
+    }
+}
+
+pub fn handle_power(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let power = &mut swarm.behaviour_mut().power;
+    match cmd.strip_prefix("power").map(str::trim) {
+        Some("") => (),
+        Some(mode) => match mode.parse() {
+            Ok(mode) => power.set(mode),
+            Err(e) => {
+                error!("format should be: power [normal|low|auto], {}", e);
+                return;
+            }
+        },
+        None => return,
+    }
+    info!(
+        "power mode is {}, low power is {}",
+        power.mode,
+        if power.low() { "on" } else { "off" }
+    );
+}
+
+pub fn handle_quota(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let quotas = &mut swarm.behaviour_mut().quotas;
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    let parsed = match args.as_slice() {
+        [] => Ok(()),
+        ["responses", n] => parse_limit(n).map(|l| quotas.limits.responses_per_hour = l),
+        ["bytes", n] => parse_limit(n).map(|l| quotas.limits.bytes_per_hour = l),
+        _ => {
+            error!("format should be: quota [responses|bytes <n|off>]");
+            return;
+        }
+    };
+    if let Err(e) = parsed {
+        error!("invalid limit: {}", e);
+        return;
+    }
+
+    let show = |limit: Option<u64>| limit.map_or("unlimited".to_owned(), |n| n.to_string());
+    info!(
+        "Quota per peer per hour: {} responses, {} bytes",
+        show(quotas.limits.responses_per_hour.map(u64::from)),
+        show(quotas.limits.bytes_per_hour)
+    );
+    for (peer, usage) in quotas.usage() {
+        info!("{}: {} responses, {} bytes", peer, usage.responses, usage.bytes);
+    }
+}
+
+pub fn handle_policy(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let policy = &mut swarm.behaviour_mut().policy;
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    let parsed = match args.as_slice() {
+        [] => Ok(()),
+        ["all", audience] => audience.parse().map(|a| policy.list_all = a),
+        ["one", audience] => audience.parse().map(|a| policy.list_one = a),
+        _ => {
+            error!("format should be: policy [all|one <anyone|friends|nobody>]");
+            return;
+        }
+    };
+    if let Err(e) = parsed {
+        error!("invalid policy: {}", e);
+        return;
+    }
+    info!("Answering \"ls books all\" from: {}", policy.list_all);
+    info!("Answering \"ls books <peer id>\" from: {}", policy.list_one);
+}
+
+fn parse_limit<T: std::str::FromStr>(input: &str) -> std::result::Result<Option<T>, T::Err> {
+    match input {
+        "off" => Ok(None),
+        n => n.parse().map(Some),
+    }
+}
+
+pub fn handle_peer_scores(swarm: &mut Swarm<BookBehavior>) {
+    let scores = &swarm.behaviour().scores;
+    let mut peers: Vec<_> = scores.iter().collect();
+    if peers.is_empty() {
+        info!("No peers scored yet");
+        return;
+    }
+    // worst first
+    peers.sort_by(|a, b| {
+        a.1.score
+            .partial_cmp(&b.1.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    for (peer, score) in peers {
+        let state = if scores.graylisted(peer) { " graylisted" } else { "" };
+        info!(
+            "{}: {:.1} ({} invalid, {} over flood limit){}",
+            peer, score.score, score.invalid, score.flooded, state
+        );
+    }
+}
+
+pub fn handle_reputation(swarm: &mut Swarm<BookBehavior>) {
+    let mut peers: Vec<_> = swarm.behaviour().reputation.iter().collect();
+    if peers.is_empty() {
+        info!("No reputation yet, peers earn it as they lend, send and misbehave");
+        return;
+    }
+    // best first
+    peers.sort_by_key(|(_, record)| std::cmp::Reverse(record.score()));
+    for (peer, record) in peers {
+        info!(
+            "{}: {} ({} loans returned, {} failed transfers, {} invalid messages, {} floods), \
+             {}x quota",
+            peer,
+            record.score(),
+            record.loans,
+            record.failed_transfers,
+            record.invalid,
+            record.spam,
+            record.quota_factor()
+        );
+    }
+}
+
+pub fn handle_bandwidth(swarm: &mut Swarm<BookBehavior>) {
+    let behaviour = swarm.behaviour();
+    info!(
+        "This run: {} bytes in, {} bytes out",
+        behaviour.wire.total_inbound(),
+        behaviour.wire.total_outbound()
+    );
+    let mut peers: Vec<_> = behaviour.traffic.iter().collect();
+    // busiest first
+    peers.sort_by_key(|(_, t)| std::cmp::Reverse(t.bytes_sent + t.bytes_received));
+    for (peer, t) in peers {
+        info!(
+            "{}: sent {} bytes in {} messages, received {} bytes in {} messages",
+            peer, t.bytes_sent, t.messages_sent, t.bytes_received, t.messages_received
+        );
+    }
+}
+
+// "activity" shows the last day, "activity --since 2w" looks further back
+pub fn handle_activity(cmd: &str) {
+    let window = match cmd.strip_prefix("activity").map(str::trim) {
+        Some("") => 24 * 60 * 60,
+        Some(rest) => match rest.strip_prefix("--since").and_then(activity::parse_duration) {
+            Some(window) => window,
+            None => {
+                error!("usage: activity [--since 1d], with m, h, d or w");
+                return;
+            }
+        },
+        None => return,
+    };
+    let now = unix_time();
+    let entries = activity::since(now.saturating_sub(window));
+    if entries.is_empty() {
+        info!("no activity in that time");
+        return;
+    }
+    for entry in entries {
+        info!("{}: {}", activity::ago(now.saturating_sub(entry.at)), entry.activity);
+    }
+}
+
+// "audit" sums up each requester, "audit <peer id>" lists what that one asked for
+pub fn handle_audit(cmd: &str) {
+    let usage = "usage: audit [<peer id>] [--since 7d], with m, h, d or w";
+    let mut window = 7 * 24 * 60 * 60;
+    let mut peer = None;
+    let mut args = cmd.split_whitespace().skip(1);
+    while let Some(arg) = args.next() {
+        match arg {
+            "--since" => match args.next().and_then(activity::parse_duration) {
+                Some(since) => window = since,
+                None => {
+                    error!("{}", usage);
+                    return;
+                }
+            },
+            arg if peer.is_none() && arg.parse::<PeerId>().is_ok() => peer = Some(arg.to_owned()),
+            _ => {
+                error!("{}", usage);
+                return;
+            }
+        }
+    }
+    let now = unix_time();
+    let entries: Vec<audit::Entry> = audit::since(now.saturating_sub(window))
+        .into_iter()
+        .filter(|entry| match peer {
+            Some(ref peer) => &entry.peer == peer,
+            None => true,
+        })
+        .collect();
+    if entries.is_empty() {
+        info!("no requests in that time");
+        return;
+    }
+    if peer.is_some() {
+        for entry in entries {
+            info!("{}: {}", activity::ago(now.saturating_sub(entry.at)), entry.access);
+        }
+        return;
+    }
+    // served, refused, last request, asked to be forgotten
+    let mut requesters: BTreeMap<&str, (usize, usize, u64, bool)> = BTreeMap::new();
+    for entry in &entries {
+        let counts = requesters.entry(&entry.peer).or_default();
+        match entry.access {
+            Access::Catalog { .. } | Access::Book { .. } | Access::Download { .. } => {
+                counts.0 += 1
+            }
+            Access::Refused { .. } => counts.1 += 1,
+            Access::Forgotten { .. } => counts.3 = true,
+        }
+        counts.2 = entry.at;
+    }
+    for (peer, (served, refused, last, forgotten)) in requesters {
+        info!(
+            "{}: served {}, refused {}, last {}{}",
+            peer,
+            served,
+            refused,
+            activity::ago(now.saturating_sub(last)),
+            if forgotten { ", asked to be forgotten" } else { "" }
+        );
+    }
+}
+
+// what the next report holds, nothing is collected while off
+pub fn handle_telemetry(swarm: &mut Swarm<BookBehavior>) {
+    match CONFIG.telemetry.endpoint {
+        Some(ref endpoint) => info!("Anonymous statistics go to {} once a day", endpoint),
+        None => {
+            info!("Not sending statistics, set [telemetry] endpoint in the config to opt in");
+            return;
+        }
+    }
+    let report = swarm.behaviour().telemetry.report();
+    info!("Next report: version {}, {} peers seen", report.agent, report.peers_seen);
+    for (version, peers) in &report.versions {
+        info!("  {}: {} peers", version, peers);
+    }
+}
+
+// debug dump [<file>], a support bundle to look over and attach to a bug report
+pub async fn handle_debug(cmd: &str, swarm: &mut Swarm<BookBehavior>) {
+    let path = match cmd.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["debug", "dump"] => format!("debug-{}.json", unix_time()),
+        ["debug", "dump", path] => path.to_string(),
+        _ => {
+            error!("format should be: debug dump [<file>]");
+            return;
+        }
+    };
+    let library = match read_local_library().await {
+        Ok(library) => library,
+        Err(e) => {
+            error!("error fetching local library: {}", e);
+            return;
+        }
+    };
+    let behaviour = swarm.behaviour();
+    let status = debug::Status {
+        peer_id: PEER_ID.to_string(),
+        topic: TOPIC.id().to_string(),
+        listening: swarm.listeners().map(ToString::to_string).collect(),
+        external: swarm.external_addresses().map(|a| a.addr.to_string()).collect(),
+        peers_online: swarm.network_info().num_peers(),
+        read_only: archive::read_only(),
+        running: operations::running()
+            .into_iter()
+            .map(|(id, what, running)| format!("#{} {} ({}s)", id, what, running.as_secs()))
+            .collect(),
+        requests_waiting: behaviour.inflight.iter().count(),
+    };
+    let mut peers = behaviour.discovered_peers();
+    peers.extend(swarm.connected_peers());
+    peers.extend(behaviour.liveness.peers());
+    let peers = peers
+        .into_iter()
+        .map(|peer| debug::Peer {
+            id: peer.to_string(),
+            name: behaviour.names.get(&peer).cloned(),
+            impostor: behaviour.impostors.contains(&peer),
+            state: describe_state(behaviour.liveness.state(&peer, swarm.is_connected(&peer))),
+            capabilities: behaviour.capabilities.get(&peer).map(|c| c.iter().cloned().collect()),
+            books: behaviour.adverts.get(&peer).and_then(|a| a.books),
+        })
+        .collect();
+    let dump = debug::Dump::new(status, peers, &library);
+    let json = serde_json::to_string_pretty(&dump).expect("a dump serializes");
+    match fs::write(datadir::user_path(&path), json).await {
+        Ok(()) => info!("wrote {}, have a look before attaching it to a report", path),
+        Err(e) => error!("unable to write {}: {}", path, e),
+    }
+}
+
+pub fn handle_status(swarm: &mut Swarm<BookBehavior>) {
+    info!("Peer Id: {}", *PEER_ID);
+    info!("Topic: {}", TOPIC.id());
+    swarm.listeners().for_each(|a| info!("listening on {}", a));
+    let mut external = swarm.external_addresses().peekable();
+    if external.peek().is_none() {
+        info!("no known external address");
+    }
+    external.for_each(|a| info!("external address {}", a.addr));
+    info!("{} peers online", swarm.network_info().num_peers());
+    if archive::read_only() {
+        info!("read-only archive");
+    }
+    if let Some(supernode) = swarm.behaviour().supernode.as_ref() {
+        info!(
+            "supernode for {} peers' catalogs, {} books",
+            supernode.peers(),
+            supernode.books()
+        );
+    }
 }
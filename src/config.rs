@@ -0,0 +1,572 @@
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use log::{error, LevelFilter};
+use once_cell::sync::Lazy;
+use peer2peer::eviction::Eviction;
+use peer2peer::protocol::Visibility;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::Path;
+
+pub const CONFIG_PATH: &str = "./config.toml";
+
+pub static CONFIG: Lazy<Config> = Lazy::new(|| load(CONFIG_PATH));
+
+// node settings read from config.toml. every field is optional so an
+// empty or missing file gives the same behavior as before config existed
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    // nickname announced to peers, who pin it to our peer id, e.g. "alice"
+    pub name: Option<String>,
+    // what peers see of the library before they ask for it, e.g. "Alice's SciFi
+    // Shelf", along with how many books it shares
+    pub title: Option<String>,
+    // e.g. "debug", used instead of RUST_LOG. changes apply without a restart
+    pub log_level: Option<String>,
+    // keeps separate communities on the same lan apart, e.g. "book-club-42"
+    pub network: Option<String>,
+    // path to a swarm.key file. only nodes holding the same key can connect,
+    // and if no network is set the topic is derived from the key
+    pub psk_file: Option<String>,
+    // extra channels to join at startup, e.g. ["scifi", "romance"]
+    pub channels: Vec<String>,
+    // always-on peers to dial at startup and keep reconnecting to,
+    // e.g. "/ip4/203.0.113.7/tcp/4001/p2p/12D3KooW..."
+    pub bootstrap: Vec<String>,
+    // addresses to listen on. pick an interface by using its ip instead of
+    // 0.0.0.0 / ::, or a fixed port instead of 0
+    pub listen: Vec<String>,
+    // publicly reachable addresses of this node, announced when registering
+    // at a rendezvous point, e.g. "/dns4/books.example.org/tcp/4001"
+    pub external: Vec<String>,
+    pub identity: IdentityConfig,
+    pub connections: ConnectionsConfig,
+    pub discovery: DiscoveryConfig,
+    pub rendezvous: RendezvousConfig,
+    pub relay: RelayConfig,
+    pub sync: SyncConfig,
+    pub trash: TrashConfig,
+    pub history: HistoryConfig,
+    pub silent: SilentConfig,
+    pub archive: ArchiveConfig,
+    pub supernode: SupernodeConfig,
+    pub policy: PolicyConfig,
+    // catalog fields left out for everyone and for friends, e.g.
+    // public = ["publisher", "tags"]. a book can have its own
+    pub visibility: Visibility,
+    pub quota: QuotaConfig,
+    pub nat: NatConfig,
+    pub proxy: ProxyConfig,
+    pub api: ApiConfig,
+    pub ipfs: IpfsConfig,
+    pub mqtt: MqttConfig,
+    pub notify: NotifyConfig,
+    pub tracing: TracingConfig,
+    pub telemetry: TelemetryConfig,
+    pub logging: LoggingConfig,
+    pub power: PowerConfig,
+    pub cache: CacheConfig,
+    pub downloads: DownloadsConfig,
+}
+
+impl Config {
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        if self.listen.is_empty() {
+            return vec![
+                "/ip4/0.0.0.0/tcp/0".parse().expect("valid ipv4 listen address"),
+                "/ip6/::/tcp/0".parse().expect("valid ipv6 listen address"),
+            ];
+        }
+        self.listen
+            .iter()
+            .filter_map(|s| match s.parse() {
+                Ok(addr) => Some(addr),
+                Err(e) => {
+                    error!("invalid listen address {}: {}", s, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // bootstrap addresses must end in /p2p/<peer id> so we know who to expect.
+    // /dns4, /dns6 and /dnsaddr hostnames are resolved when dialing
+    pub fn log_level(&self) -> Result<Option<LevelFilter>, String> {
+        match self.log_level {
+            Some(ref level) => match level.parse() {
+                Ok(level) => Ok(Some(level)),
+                Err(_) => Err(format!("invalid log level {}", level)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn bootstrap_peers(&self) -> Vec<(PeerId, Multiaddr)> {
+        peer_addrs(&self.bootstrap, "bootstrap")
+    }
+
+    pub fn rendezvous_points(&self) -> Vec<(PeerId, Multiaddr)> {
+        peer_addrs(&self.rendezvous.points, "rendezvous")
+    }
+
+    pub fn relay_peers(&self) -> Vec<(PeerId, Multiaddr)> {
+        peer_addrs(&self.relay.peers, "relay")
+    }
+
+    pub fn external_addrs(&self) -> Vec<Multiaddr> {
+        self.external
+            .iter()
+            .filter_map(|s| match s.parse() {
+                Ok(addr) => Some(addr),
+                Err(e) => {
+                    error!("invalid external address {}: {}", s, e);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+fn peer_addrs(addrs: &[String], kind: &str) -> Vec<(PeerId, Multiaddr)> {
+    addrs
+        .iter()
+        .filter_map(|s| {
+            let addr: Multiaddr = match s.parse() {
+                Ok(addr) => addr,
+                Err(e) => {
+                    error!("invalid {} address {}: {}", kind, s, e);
+                    return None;
+                }
+            };
+            match addr.iter().last() {
+                Some(Protocol::P2p(hash)) => match PeerId::from_multihash(hash) {
+                    Ok(peer) => Some((peer, addr)),
+                    Err(_) => {
+                        error!("invalid peer id in {} address {}", kind, s);
+                        None
+                    }
+                },
+                _ => {
+                    error!("{} address {} has no /p2p/<peer id>", kind, s);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IdentityConfig {
+    pub store: KeyStore,
+    // keep identity.key encrypted with a passphrase asked for at startup
+    pub encrypt: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStore {
+    // identity.key next to the library
+    #[default]
+    File,
+    // the os keyring, via secret-tool on linux or security on macos
+    Keyring,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConnectionsConfig {
+    // total established connections, further ones are refused
+    pub max: Option<u32>,
+    pub max_per_peer: Option<u32>,
+    // above this many connected peers, the least recently useful are disconnected
+    pub prune_above: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    // udp broadcast discovery for networks where mdns is blocked
+    pub beacon: bool,
+    pub beacon_port: u16,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            // windows' own mdns responder often holds port 5353
+            beacon: cfg!(windows),
+            beacon_port: 4002,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RendezvousConfig {
+    // community hubs to register at and discover peers through
+    pub points: Vec<String>,
+    // defaults to the network topic
+    pub namespace: Option<String>,
+    // act as a rendezvous point for others
+    pub server: bool,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RelayConfig {
+    // trusted always-on peers that hold messages for friends who are offline
+    pub peers: Vec<String>,
+    // hold sealed messages for others until their recipient is back
+    pub serve: bool,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    // nodes with the same secret belong to the same owner and replicate the
+    // whole library, private books included
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TrashConfig {
+    // removed books can be restored for this long
+    pub keep_days: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        TrashConfig { keep_days: 30 }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    // changes can be listed and undone for this long, older ones are folded
+    // into the library they added up to
+    pub keep_days: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig { keep_days: 90 }
+    }
+}
+
+// lurker mode: browse others without answering broadcasts or announcing ourselves
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SilentConfig {
+    pub enabled: bool,
+    // members of this group still get answers to requests aimed at us
+    pub allow_group: Option<String>,
+}
+
+// a stable community node: serves its catalog and files, takes no changes
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SupernodeConfig {
+    // keep the catalogs of peers that allow it and answer searches for them
+    // while they are offline
+    pub enabled: bool,
+    // let supernodes keep our public catalog
+    pub allow: bool,
+}
+
+// who gets an answer to each kind of request. friends are the members of
+// any of our groups. silent mode and quotas apply on top of this
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PolicyConfig {
+    // broadcast "ls books all"
+    pub list_all: Audience,
+    // "ls books <peer id>" aimed at us
+    pub list_one: Audience,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Audience {
+    #[default]
+    Anyone,
+    Friends,
+    Nobody,
+}
+
+impl std::str::FromStr for Audience {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "anyone" => Ok(Audience::Anyone),
+            "friends" => Ok(Audience::Friends),
+            "nobody" => Ok(Audience::Nobody),
+            _ => Err(format!("{} is not anyone, friends or nobody", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Audience {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Audience::Anyone => "anyone",
+            Audience::Friends => "friends",
+            Audience::Nobody => "nobody",
+        })
+    }
+}
+
+// per peer, per hour. unset means unlimited
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct QuotaConfig {
+    pub responses_per_hour: Option<u32>,
+    pub bytes_per_hour: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NatConfig {
+    // ask the router to forward our port via nat-pmp or upnp
+    pub port_mapping: bool,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    // dial every peer through this socks5 proxy, e.g. tor on "127.0.0.1:9050"
+    pub socks5: Option<String>,
+}
+
+impl ProxyConfig {
+    // a typo here must not quietly fall back to direct connections
+    pub fn socks5_addr(&self) -> Option<SocketAddr> {
+        self.socks5
+            .as_ref()
+            .map(|s| s.parse().expect("invalid socks5 proxy address"))
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    // e.g. "127.0.0.1:8080" - the api is disabled when unset
+    pub listen: Option<String>,
+    // when empty the api is open to anyone who can reach it
+    pub tokens: Vec<ApiToken>,
+    // where friends reach the api, for download links, e.g.
+    // "https://books.example.org". defaults to http:// and the listen address
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ApiToken {
+    pub token: String,
+    pub scope: Scope,
+}
+
+// admin can do everything read can, plus modify the library
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Admin,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IpfsConfig {
+    // http api of a local ipfs daemon to publish files with, e.g. "127.0.0.1:5001"
+    pub api: Option<String>,
+    // shown to peers with a book's cid, for fetching it without ipfs
+    pub gateway: String,
+}
+
+impl Default for IpfsConfig {
+    fn default() -> Self {
+        IpfsConfig {
+            api: None,
+            gateway: "https://ipfs.io".to_owned(),
+        }
+    }
+}
+
+// node events for home automation, e.g. a dashboard showing who is online
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    // e.g. "homeassistant.local:1883", the bridge is off when unset
+    pub broker: Option<String>,
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // events go to <prefix>/<kind>, e.g. "peer2peer/peer_joined"
+    pub prefix: String,
+    // another topic for a kind of event, or "" to leave it out
+    pub topics: BTreeMap<String, String>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            broker: None,
+            client_id: None,
+            username: None,
+            password: None,
+            prefix: "peer2peer".to_owned(),
+            topics: BTreeMap::new(),
+        }
+    }
+}
+
+// activity posted where a community talks, e.g. its discord server
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    pub discord_webhook: Option<String>,
+    pub matrix: Option<MatrixConfig>,
+    // kinds of activity to post, as in the activity log
+    pub events: Vec<String>,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        NotifyConfig {
+            discord_webhook: None,
+            matrix: None,
+            events: vec!["wishlist_match".to_owned(), "loan_offered".to_owned()],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MatrixConfig {
+    // e.g. "https://matrix.org"
+    pub homeserver: String,
+    // the room id, e.g. "!abcdef:matrix.org", which the bot account has joined
+    pub room: String,
+    // access token of the bot account
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    // how much of peers' catalogs to keep in memory, as json
+    pub max_bytes: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig { max_bytes: 10_000_000 }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PowerConfig {
+    // "low" for small boards, "auto" to switch to low power on battery
+    pub mode: PowerMode,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerMode {
+    #[default]
+    Normal,
+    Low,
+    Auto,
+}
+
+impl std::str::FromStr for PowerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(PowerMode::Normal),
+            "low" => Ok(PowerMode::Low),
+            "auto" => Ok(PowerMode::Auto),
+            _ => Err(format!("{} is not normal, low or auto", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for PowerMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PowerMode::Normal => "normal",
+            PowerMode::Low => "low",
+            PowerMode::Auto => "auto",
+        })
+    }
+}
+
+// spans of requests and their answers, sent to an opentelemetry collector
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TracingConfig {
+    // otlp over http, e.g. "http://127.0.0.1:4318" for jaeger
+    pub otlp: Option<String>,
+}
+
+// anonymous statistics for the maintainers, off unless an endpoint is set
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    // where to post a report once a day, e.g. "https://stats.example.org/report"
+    pub endpoint: Option<String>,
+}
+
+// a copy of the log as json lines, for daemons nobody watches the terminal of
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    // e.g. "node.log". reopened on SIGHUP, after logrotate moved it away
+    pub file: Option<String>,
+    // started anew past this size, e.g. 10485760
+    pub max_bytes: Option<u64>,
+    // how many old files are kept as "node.log.1", "node.log.2" and so on
+    pub keep: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            file: None,
+            max_bytes: None,
+            keep: 3,
+        }
+    }
+}
+
+// books fetched from peers with `download`, kept in ./downloads
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DownloadsConfig {
+    // the most they may take together, e.g. 1073741824. no cap when unset
+    pub max_bytes: Option<u64>,
+    pub evict: Eviction,
+}
+
+fn load(path: &str) -> Config {
+    read(path).expect("unable to load config file")
+}
+
+// the config as it is on disk now, for reloading it. no file is the defaults
+pub fn read(path: &str) -> Result<Config, String> {
+    if !Path::new(path).exists() {
+        return Ok(Config::default());
+    }
+    let content = std::fs::read_to_string(path).map_err(|e| format!("unable to read: {}", e))?;
+    toml::from_str(&content).map_err(|e| format!("unable to parse: {}", e))
+}
@@ -0,0 +1,206 @@
+use crate::clock;
+use crate::deletions::Deletions;
+use crate::store;
+use crate::sync::Replica;
+use crate::unix_time;
+use log::error;
+use peer2peer::protocol::{Book, Library};
+use peer2peer::schema;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+pub const CONFLICTS_PATH: &str = "./conflicts.json";
+
+// a book edited on both sides since they last merged. the newer edit was kept,
+// the other one waits here until it's reviewed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Conflict {
+    pub id: usize,
+    #[serde(default)]
+    pub uid: String,
+    // where the other version came from, e.g. a device's peer id
+    pub source: String,
+    pub at: u64,
+    pub kept: Book,
+    pub other: Book,
+    // the later of the two revisions, an edit above it settles the conflict
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Conflicts {
+    conflicts: Vec<Conflict>,
+}
+
+impl Conflicts {
+    pub fn load() -> Self {
+        store::load(CONFLICTS_PATH)
+    }
+
+    fn save(&self) {
+        if let Err(e) = store::save(CONFLICTS_PATH, self) {
+            error!("unable to save conflicts: {}", e);
+        }
+    }
+
+    // the same conflict arrives again with every sync until it's resolved
+    pub fn add(&mut self, source: &str, kept: Book, other: Book) {
+        let known = self.conflicts.iter().any(|c| {
+            c.uid == other.uid && same_content(&c.other, &other) && same_content(&c.kept, &kept)
+        });
+        if known {
+            return;
+        }
+        let revision = kept.revision.max(other.revision);
+        self.conflicts.push(Conflict {
+            id: kept.id,
+            uid: kept.uid.clone(),
+            source: source.to_owned(),
+            at: unix_time(),
+            kept,
+            other,
+            revision,
+        });
+        self.save();
+    }
+
+    // a later edit of the book, here or on another device, settles its conflicts
+    pub fn settle(&mut self, library: &Library) {
+        let before = self.conflicts.len();
+        self.conflicts.retain(|c| {
+            !library.iter().any(|b| {
+                b.uid == c.uid
+                    && match (b.revision, c.revision) {
+                        (Some(edited), Some(conflicted)) => edited > conflicted,
+                        _ => b.modified.unwrap_or(0) > c.at,
+                    }
+            })
+        });
+        if self.conflicts.len() != before {
+            self.save();
+        }
+    }
+
+    // index as listed by iter
+    pub fn take(&mut self, index: usize) -> Option<Conflict> {
+        if index >= self.conflicts.len() {
+            return None;
+        }
+        let conflict = self.conflicts.remove(index);
+        self.save();
+        Some(conflict)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Conflict> {
+        self.conflicts.iter()
+    }
+}
+
+// everything but the edit time and revision
+pub fn same_content(a: &Book, b: &Book) -> bool {
+    let strip = |book: &Book| {
+        serde_json::to_value(Book {
+            modified: None,
+            revision: None,
+            ..book.clone()
+        })
+        .expect("books always serialize")
+    };
+    strip(a) == strip(b)
+}
+
+// where the devices left off when they last merged: the unix time, for books
+// edited by devices that don't count revisions yet, and the highest revision
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Base {
+    pub at: u64,
+    pub revision: u64,
+}
+
+// merges remote into local by uid and returns whether local changed. the
+// later edit wins. when both sides edited a book after `base` the losing
+// version is returned as (kept, other), under our id. a book deleted on
+// either side goes on both, unless it was edited after
+pub fn merge(
+    local: &mut Library,
+    deletions: &mut Deletions,
+    remote: Replica,
+    base: Base,
+) -> (bool, Vec<(Book, Book)>) {
+    deletions.extend(&remote.deleted);
+    let gone = |book: &Book| match deletions.get(&book.uid) {
+        Some(at) => clock::bounded(book.modified.unwrap_or(0)) <= at,
+        None => false,
+    };
+    let before = local.len();
+    local.retain(|b| !gone(b));
+    let mut changed = local.len() != before;
+    let mut index: HashMap<String, usize> =
+        local.iter().enumerate().map(|(i, b)| (b.uid.clone(), i)).collect();
+    let mut ids: HashSet<usize> = local.iter().map(|b| b.id).collect();
+    let mut conflicts = Vec::new();
+    for mut book in remote.books {
+        // from a device that doesn't keep uids yet
+        if book.uid.is_empty() {
+            book.uid = schema::legacy_uid(&book);
+        }
+        if gone(&book) {
+            continue;
+        }
+        let i = match index.get(&book.uid) {
+            Some(&i) => i,
+            None => {
+                // their number for it may be another book's here
+                if !ids.insert(book.id) {
+                    book.id = ids.iter().max().map_or(0, |id| id + 1);
+                    ids.insert(book.id);
+                }
+                index.insert(book.uid.clone(), local.len());
+                local.push(book);
+                changed = true;
+                continue;
+            }
+        };
+        book.id = local[i].id;
+        if same_content(&local[i], &book) {
+            continue;
+        }
+        let (newer, both_edited) = match (local[i].revision, book.revision) {
+            (Some(ours), Some(theirs)) => {
+                // the same revision on two devices is two edits made apart,
+                // settled by content so both devices keep the same one
+                let newer = match theirs.cmp(&ours) {
+                    Ordering::Equal => encoded(&book) > encoded(&local[i]),
+                    ordering => ordering == Ordering::Greater,
+                };
+                (newer, ours > base.revision && theirs > base.revision)
+            }
+            _ => {
+                let ours = clock::bounded(local[i].modified.unwrap_or(0));
+                let theirs = clock::bounded(book.modified.unwrap_or(0));
+                (theirs > ours, ours > base.at && theirs > base.at)
+            }
+        };
+        if newer {
+            let other = std::mem::replace(&mut local[i], book);
+            changed = true;
+            if both_edited {
+                conflicts.push((local[i].clone(), other));
+            }
+        } else if both_edited {
+            conflicts.push((local[i].clone(), book));
+        }
+    }
+    // edited after it was deleted somewhere, it's back everywhere
+    for book in local.iter() {
+        deletions.forget(&book.uid);
+    }
+    (changed, conflicts)
+}
+
+
+fn encoded(book: &Book) -> String {
+    serde_json::to_string(book).expect("books always serialize")
+}
@@ -0,0 +1,83 @@
+use libp2p::{core::ConnectedPoint, identify::IdentifyInfo, Multiaddr, PeerId};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// a ping goes out on every connection every 15s, and right after connecting
+const PROBE_TIMEOUT: Duration = Duration::from_secs(40);
+
+// what we know about a connected peer's connections, for ls peers --verbose
+#[derive(Debug, Default)]
+pub struct Details {
+    pub endpoints: Vec<ConnectedPoint>,
+    pub agent_version: Option<String>,
+    pub protocols: Vec<String>,
+    pub listen_addrs: Vec<Multiaddr>,
+    // from the latest ping on any of its connections
+    pub rtt: Option<Duration>,
+}
+
+// forgotten once the last connection to a peer closes
+#[derive(Debug, Default)]
+pub struct Connections {
+    peers: HashMap<PeerId, Details>,
+    // peers someone asked to ping, waiting for the next result
+    probes: HashMap<PeerId, Instant>,
+}
+
+impl Connections {
+    pub fn opened(&mut self, peer: PeerId, endpoint: ConnectedPoint) {
+        self.peers.entry(peer).or_default().endpoints.push(endpoint);
+    }
+
+    pub fn closed(&mut self, peer: &PeerId, endpoint: &ConnectedPoint) {
+        if let Some(details) = self.peers.get_mut(peer) {
+            details.endpoints.retain(|e| e != endpoint);
+            if details.endpoints.is_empty() {
+                self.peers.remove(peer);
+            }
+        }
+    }
+
+    pub fn identified(&mut self, peer: PeerId, info: &IdentifyInfo) {
+        if let Some(details) = self.peers.get_mut(&peer) {
+            details.agent_version = Some(info.agent_version.clone());
+            details.protocols = info.protocols.clone();
+            // identify repeats addresses reported by more than one listener
+            details.listen_addrs = info.listen_addrs.clone();
+            details.listen_addrs.sort();
+            details.listen_addrs.dedup();
+        }
+    }
+
+    pub fn pinged(&mut self, peer: &PeerId, rtt: Duration) {
+        if let Some(details) = self.peers.get_mut(peer) {
+            details.rtt = Some(rtt);
+        }
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&Details> {
+        self.peers.get(peer)
+    }
+
+    pub fn probe(&mut self, peer: PeerId) {
+        self.probes.insert(peer, Instant::now());
+    }
+
+    // true when the result was asked for with the ping command
+    pub fn probed(&mut self, peer: &PeerId) -> bool {
+        self.probes.remove(peer).is_some()
+    }
+
+    pub fn unanswered_probes(&mut self) -> Vec<PeerId> {
+        let expired: Vec<PeerId> = self
+            .probes
+            .iter()
+            .filter(|(_, asked)| asked.elapsed() > PROBE_TIMEOUT)
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in &expired {
+            self.probes.remove(peer);
+        }
+        expired
+    }
+}
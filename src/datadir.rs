@@ -0,0 +1,74 @@
+use crate::Result;
+use once_cell::sync::OnceCell;
+use std::env;
+use std::path::{Path, PathBuf};
+
+const APP: &str = "peer2peer";
+
+// where the node was started from, for paths typed at the prompt
+static START_DIR: OnceCell<PathBuf> = OnceCell::new();
+
+// files a node keeps, any of them means the directory already holds one
+const MARKERS: &[&str] = &["library.json", "identity.key", "config.toml"];
+
+// moves into the directory the node keeps its files in, so every file is
+// read and written relative to it: --data-dir if given, else the current
+// directory if a node already lives there, else the platform's data directory
+pub fn enter() -> Result<PathBuf> {
+    let start = env::current_dir()?;
+    let dir = match flag() {
+        Some(dir) => start.join(dir),
+        None if MARKERS.iter().any(|m| Path::new(m).exists()) => start.clone(),
+        None => platform_dir().ok_or("no data directory, start with --data-dir <path>")?,
+    };
+    std::fs::create_dir_all(&dir)?;
+    env::set_current_dir(&dir)?;
+    let _ = START_DIR.set(start);
+    Ok(dir)
+}
+
+// a file named in a command, relative to where the node was started
+pub fn user_path(path: &str) -> PathBuf {
+    match START_DIR.get() {
+        Some(start) => start.join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+fn flag() -> Option<String> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("--data-dir") {
+            Some("") => return args.next(),
+            Some(rest) => return rest.strip_prefix('=').map(str::to_owned),
+            None => continue,
+        }
+    }
+    None
+}
+
+// %APPDATA%\peer2peer on windows, ~/Library/Application Support/peer2peer
+// on macos and $XDG_DATA_HOME/peer2peer, usually ~/.local/share, elsewhere.
+// the same places the directories crate picks, looked up here as the node
+// needs nothing else from it
+#[cfg(windows)]
+fn platform_dir() -> Option<PathBuf> {
+    env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join(APP))
+}
+
+#[cfg(target_os = "macos")]
+fn platform_dir() -> Option<PathBuf> {
+    let home = PathBuf::from(env::var_os("HOME")?);
+    Some(home.join("Library").join("Application Support").join(APP))
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn platform_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join(APP)),
+        _ => {
+            let home = PathBuf::from(env::var_os("HOME")?);
+            Some(home.join(".local").join("share").join(APP))
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::store;
+use crate::unix_time;
+use log::error;
+use peer2peer::protocol::Library;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+pub const DELETIONS_PATH: &str = "./deletions.json";
+// a device away for longer than this gets books deleted meanwhile back
+const KEEP_FOR: u64 = 365 * 24 * 60 * 60;
+
+// books gone from the library for good, by uid, with the unix time they went.
+// devices syncing the library drop them too instead of sending them back
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Deletions {
+    deleted: BTreeMap<String, u64>,
+}
+
+impl Deletions {
+    pub fn load() -> Self {
+        store::load(DELETIONS_PATH)
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = store::save(DELETIONS_PATH, self) {
+            error!("unable to save deletions: {}", e);
+        }
+    }
+
+    // the books of before missing from after were deleted now, and a book
+    // that's back, e.g. through undo, isn't deleted anymore. whether any
+    // changed
+    pub fn record(&mut self, before: &Library, after: &Library) -> bool {
+        let kept: HashSet<&str> = after.iter().map(|b| b.uid.as_str()).collect();
+        let now = unix_time();
+        let mut changed = false;
+        for book in before.iter().filter(|b| !kept.contains(b.uid.as_str())) {
+            if !book.uid.is_empty() && !self.deleted.contains_key(&book.uid) {
+                self.deleted.insert(book.uid.clone(), now);
+                changed = true;
+            }
+        }
+        let before = self.deleted.len();
+        self.deleted.retain(|uid, at| !kept.contains(uid.as_str()) && *at + KEEP_FOR > now);
+        changed || self.deleted.len() != before
+    }
+
+    // when the book went, if it did
+    pub fn get(&self, uid: &str) -> Option<u64> {
+        self.deleted.get(uid).copied()
+    }
+
+    // takes on another device's deletions, the earlier time where both have one
+    pub fn extend(&mut self, other: &Deletions) {
+        for (uid, at) in &other.deleted {
+            let ours = self.deleted.entry(uid.clone()).or_insert(*at);
+            *ours = (*ours).min(*at);
+        }
+    }
+
+    pub fn forget(&mut self, uid: &str) {
+        self.deleted.remove(uid);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &u64)> {
+        self.deleted.iter()
+    }
+}
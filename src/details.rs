@@ -0,0 +1,114 @@
+// the direct book protocol: a BookRequest goes to one peer on a stream of its
+// own and the BookDetail comes back on it. nothing goes through the topic, so
+// nobody else reads the answer and it isn't held to floodsub's frame size.
+// both travel in the same envelope as topic messages
+use crate::protocol::{decode, encode, BookDetail, BookRequest, Message};
+use async_trait::async_trait;
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed, ProtocolName};
+use libp2p::futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response::{
+    ProtocolSupport, RequestResponse, RequestResponseCodec, RequestResponseConfig,
+};
+use std::io;
+use std::iter;
+use std::time::Duration;
+
+// a book with a full review and its tags stays well under it
+pub const MAX_DETAIL_SIZE: usize = 64 * 1024;
+// a peer that hasn't answered by then counts as not answering
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub type Details = RequestResponse<BookCodec>;
+
+#[derive(Debug, Clone)]
+pub struct BookProtocol;
+
+impl ProtocolName for BookProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/library/book/1.0.0"
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BookCodec;
+
+pub fn behaviour() -> Details {
+    let mut config = RequestResponseConfig::default();
+    config.set_request_timeout(TIMEOUT);
+    RequestResponse::new(BookCodec, iter::once((BookProtocol, ProtocolSupport::Full)), config)
+}
+
+async fn read<T>(io: &mut T) -> io::Result<Message>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let data = read_length_prefixed(io, MAX_DETAIL_SIZE).await?;
+    decode(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write<T>(io: &mut T, message: &Message) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+{
+    let data = encode(message);
+    if data.len() > MAX_DETAIL_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "book details too large"));
+    }
+    write_length_prefixed(io, data).await?;
+    io.close().await
+}
+
+fn unexpected() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "not a message of the book protocol")
+}
+
+#[async_trait]
+impl RequestResponseCodec for BookCodec {
+    type Protocol = BookProtocol;
+    type Request = BookRequest;
+    type Response = BookDetail;
+
+    async fn read_request<T>(&mut self, _: &BookProtocol, io: &mut T) -> io::Result<BookRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        match read(io).await? {
+            Message::BookRequest(req) => Ok(req),
+            _ => Err(unexpected()),
+        }
+    }
+
+    async fn read_response<T>(&mut self, _: &BookProtocol, io: &mut T) -> io::Result<BookDetail>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        match read(io).await? {
+            Message::BookDetail(detail) => Ok(*detail),
+            _ => Err(unexpected()),
+        }
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &BookProtocol,
+        io: &mut T,
+        req: BookRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write(io, &Message::BookRequest(req)).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &BookProtocol,
+        io: &mut T,
+        detail: BookDetail,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write(io, &Message::BookDetail(Box::new(detail))).await
+    }
+}
@@ -0,0 +1,116 @@
+use crate::store;
+use log::error;
+use once_cell::sync::Lazy;
+use peer2peer::eviction::{self, Eviction, Held};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tokio::sync::{Mutex, MutexGuard};
+
+pub const DOWNLOADS_PATH: &str = "./downloads.json";
+pub const DOWNLOADS_DIR: &str = "./downloads";
+
+// held from loading the downloads to saving them, so two downloads finishing
+// together both count against the cap
+static LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+// cids being fetched, each goes to its own part file once
+static FETCHING: Lazy<std::sync::Mutex<HashSet<String>>> = Lazy::new(Default::default);
+
+pub async fn lock() -> MutexGuard<'static, ()> {
+    LOCK.lock().await
+}
+
+// false when the cid is being fetched already
+pub fn fetching(cid: &str) -> bool {
+    FETCHING.lock().map(|mut f| f.insert(cid.to_owned())).unwrap_or(false)
+}
+
+pub fn fetched(cid: &str) {
+    if let Ok(mut fetching) = FETCHING.lock() {
+        fetching.remove(cid);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Download {
+    pub id: u64,
+    pub cid: String,
+    // whose catalog it was downloaded from, and its id there
+    pub peer: String,
+    pub book: usize,
+    pub title: String,
+    pub author: String,
+    pub path: String,
+    pub bytes: u64,
+    // unix times, used is the last download or `downloads open` of it
+    pub fetched: u64,
+    pub used: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub favorite: bool,
+}
+
+// the files fetched from peers, oldest first
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Downloads {
+    files: Vec<Download>,
+    // ids aren't reused, an id in an old message stays that download's
+    #[serde(default)]
+    last_id: u64,
+}
+
+impl Downloads {
+    pub fn load() -> Self {
+        store::load(DOWNLOADS_PATH)
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = store::save(DOWNLOADS_PATH, self) {
+            error!("unable to save downloads: {}", e);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Download> {
+        self.files.iter()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.files.iter().map(|d| d.bytes).sum()
+    }
+
+    pub fn next_id(&mut self) -> u64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Download> {
+        self.files.iter_mut().find(|d| d.id == id)
+    }
+
+    pub fn by_cid(&mut self, cid: &str) -> Option<&mut Download> {
+        self.files.iter_mut().find(|d| d.cid == cid)
+    }
+
+    pub fn add(&mut self, download: Download) {
+        self.files.push(download);
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Download> {
+        let i = self.files.iter().position(|d| d.id == id)?;
+        Some(self.files.remove(i))
+    }
+
+    // the downloads to remove for `bytes` more to fit under the cap
+    pub fn to_evict(&self, bytes: u64, max: u64, evict: Eviction) -> Option<Vec<u64>> {
+        let held: Vec<Held> = self
+            .files
+            .iter()
+            .map(|d| Held {
+                id: d.id,
+                bytes: d.bytes,
+                used: d.used,
+                favorite: d.favorite,
+            })
+            .collect();
+        eviction::to_evict(&held, bytes, max, evict)
+    }
+}
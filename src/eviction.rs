@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+// which downloads go to make room for a new one
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Eviction {
+    // the one opened least recently
+    #[default]
+    Lru,
+    // the same, but never one marked a favorite
+    KeepFavorites,
+}
+
+// a file taking up room under the cap
+#[derive(Debug, Clone, Copy)]
+pub struct Held {
+    pub id: u64,
+    pub bytes: u64,
+    // unix time it was last used
+    pub used: u64,
+    pub favorite: bool,
+}
+
+// the files to remove for `bytes` more to fit under `max`, the least recently
+// used first. none when not even removing all it may would make room
+pub fn to_evict(held: &[Held], bytes: u64, max: u64, eviction: Eviction) -> Option<Vec<u64>> {
+    let mut candidates: Vec<&Held> = held
+        .iter()
+        .filter(|h| eviction == Eviction::Lru || !h.favorite)
+        .collect();
+    candidates.sort_by_key(|h| h.used);
+    let mut used: u64 = held.iter().map(|h| h.bytes).sum();
+    let mut evicted = Vec::new();
+    for held in candidates {
+        if used.saturating_add(bytes) <= max {
+            break;
+        }
+        used -= held.bytes;
+        evicted.push(held.id);
+    }
+    (used.saturating_add(bytes) <= max).then_some(evicted)
+}
@@ -0,0 +1,43 @@
+use crate::clock::MAX_SKEW;
+use crate::sealing;
+use crate::unix_time;
+use data_encoding::BASE64;
+use libp2p::{identity, PeerId};
+use peer2peer::protocol::Forget;
+
+// how long a request waits for peers that are offline, and how long after
+// it was signed it's honored. a replay can only make us forget again
+pub const KEEP_ASKING: u64 = 30 * 24 * 60 * 60;
+
+fn statement(from: &str, at: u64) -> Vec<u8> {
+    format!("peer2peer forget\n{}\n{}", from, at).into_bytes()
+}
+
+pub fn sign(keys: &identity::Keypair) -> Forget {
+    let from = PeerId::from(keys.public()).to_string();
+    let at = unix_time();
+    let signature = keys
+        .sign(&statement(&from, at))
+        .expect("ed25519 signing can't fail");
+    Forget {
+        from,
+        at,
+        signature: BASE64.encode(&signature),
+    }
+}
+
+// the peer that wants to be forgotten, if it really signed this. like a
+// tombstone it may come from anyone passing it on
+pub fn verify(forget: &Forget) -> Option<PeerId> {
+    let now = unix_time();
+    if forget.at + KEEP_ASKING < now || forget.at > now + MAX_SKEW {
+        return None;
+    }
+    let from: PeerId = forget.from.parse().ok()?;
+    let signature = BASE64.decode(forget.signature.as_bytes()).ok()?;
+    if sealing::public_key(&from)?.verify(&statement(&forget.from, forget.at), &signature) {
+        Some(from)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,66 @@
+use crate::protocol::FileFormat;
+
+// how many bytes of a file detect needs to see
+pub const HEAD: usize = 68;
+// the name and content of the first, uncompressed entry of an epub
+const EPUB_MIMETYPE: &[u8] = b"mimetypeapplication/epub+zip";
+
+impl FileFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            FileFormat::Epub => "EPUB",
+            FileFormat::Pdf => "PDF",
+            FileFormat::Mobi => "MOBI",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            FileFormat::Epub => "application/epub+zip",
+            FileFormat::Pdf => "application/pdf",
+            FileFormat::Mobi => "application/x-mobipocket-ebook",
+        }
+    }
+}
+
+// by the magic bytes at the start of the file: a pdf header, a zip whose
+// first entry is the epub mimetype, or a palm database of type BOOKMOBI,
+// which kindle's azw files are too
+pub fn detect(head: &[u8]) -> Option<FileFormat> {
+    if head.starts_with(b"%PDF-") {
+        return Some(FileFormat::Pdf);
+    }
+    if head.starts_with(b"PK\x03\x04") && head.get(30..58) == Some(EPUB_MIMETYPE) {
+        return Some(FileFormat::Epub);
+    }
+    if head.get(60..68) == Some(b"BOOKMOBI") {
+        return Some(FileFormat::Mobi);
+    }
+    None
+}
+
+// what a file name claims to be, e.g. "dune.epub"
+pub fn claimed(name: &str) -> Option<FileFormat> {
+    let (_, extension) = name.rsplit_once('.')?;
+    match extension.to_lowercase().as_str() {
+        "epub" => Some(FileFormat::Epub),
+        "pdf" => Some(FileFormat::Pdf),
+        "mobi" | "azw" | "azw3" | "prc" => Some(FileFormat::Mobi),
+        _ => None,
+    }
+}
+
+// the format of a file, or why it can't be shared as what its name says.
+// a file named as none of them, e.g. notes.txt, has no format and passes
+pub fn check(name: &str, head: &[u8]) -> Result<Option<FileFormat>, String> {
+    match (claimed(name), detect(head)) {
+        (Some(claimed), Some(detected)) if claimed != detected => Err(format!(
+            "{} is named as {} but is {}",
+            name,
+            claimed.name(),
+            detected.name()
+        )),
+        (Some(claimed), None) => Err(format!("{} isn't a valid {} file", name, claimed.name())),
+        (_, detected) => Ok(detected),
+    }
+}
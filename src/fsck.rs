@@ -0,0 +1,127 @@
+use crate::commands::{read_local_library, write_local_library};
+use crate::config::CONFIG;
+use crate::history;
+use crate::links::Links;
+use crate::{unix_time, Result, STORAGE_PATH};
+use log::info;
+use peer2peer::schema;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs;
+
+// a write in progress renames its temporary file within this long
+const STALE_TMP: Duration = Duration::from_secs(60);
+
+// what a check found, everything in it was repaired
+#[derive(Debug, Default)]
+pub struct Report {
+    pub books: usize,
+    // books that shared an id with an earlier one, (old id, new id)
+    pub renumbered: Vec<(usize, usize)>,
+    // books whose attached file is gone from the disk, (id, path)
+    pub missing_files: Vec<(usize, String)>,
+    pub dead_links: usize,
+    pub stale_tmp: bool,
+    // history batches older than history.keep_days, folded into one
+    pub folded: usize,
+    // size of the library file before and after
+    pub before: u64,
+    pub after: u64,
+}
+
+impl Report {
+    pub fn clean(&self) -> bool {
+        self.renumbered.is_empty()
+            && self.missing_files.is_empty()
+            && self.dead_links == 0
+            && !self.stale_tmp
+            && self.folded == 0
+            && self.before == self.after
+    }
+
+    pub fn log(&self) {
+        info!("checked {} books in {}", self.books, STORAGE_PATH);
+        for (old, new) in &self.renumbered {
+            info!("book {} had the id of another book, it is book {} now", old, new);
+        }
+        for (id, path) in &self.missing_files {
+            info!("book {}'s file {} is gone, attach it again if it moved", id, path);
+        }
+        if self.dead_links > 0 {
+            info!("dropped {} download links to missing books or files", self.dead_links);
+        }
+        if self.stale_tmp {
+            info!("removed a half-written library left by an earlier crash");
+        }
+        if self.folded > 0 {
+            let days = CONFIG.history.keep_days;
+            info!("folded {} history entries older than {} days", self.folded, days);
+        }
+        if self.before != self.after {
+            info!("compacted the library from {} to {} bytes", self.before, self.after);
+        }
+        if self.clean() {
+            info!("no problems found");
+        }
+    }
+}
+
+// validates the library and repairs what can be repaired without asking. a
+// library that doesn't parse is left alone for a person to look at
+pub async fn check() -> Result<Report> {
+    let content = fs::read(STORAGE_PATH).await?;
+    let before = content.len() as u64;
+    let mut library = read_local_library().await?;
+    let mut report = Report {
+        books: library.len(),
+        before,
+        after: before,
+        ..Report::default()
+    };
+
+    let mut seen = HashSet::new();
+    let mut next_id = library.iter().map(|b| b.id + 1).max().unwrap_or(0);
+    for book in library.iter_mut() {
+        if !seen.insert(book.id) {
+            report.renumbered.push((book.id, next_id));
+            book.id = next_id;
+            next_id += 1;
+        }
+    }
+    for book in library.iter_mut() {
+        if let Some(file) = book.file.take() {
+            if Path::new(&file).exists() {
+                book.file = Some(file);
+            } else {
+                report.missing_files.push((book.id, file));
+            }
+        }
+    }
+
+    let with_files = library.iter().filter(|b| b.file.is_some()).map(|b| b.id).collect();
+    report.dead_links = Links::load().prune(&with_files);
+
+    // rewriting also drops whitespace and fields a hand edit or an older
+    // version left behind
+    let json = schema::to_snapshot(&library, schema::history_of(&content))?;
+    let repaired = !report.renumbered.is_empty() || !report.missing_files.is_empty();
+    if repaired || json.len() as u64 != before {
+        write_local_library(&library).await?;
+        report.after = fs::metadata(STORAGE_PATH).await?.len();
+    }
+
+    let tmp = format!("{}.tmp", STORAGE_PATH);
+    let stale = match fs::metadata(&tmp).await.and_then(|m| m.modified()) {
+        Ok(modified) => matches!(modified.elapsed(), Ok(age) if age > STALE_TMP),
+        Err(_) => false,
+    };
+    if stale {
+        fs::remove_file(&tmp).await?;
+        report.stale_tmp = true;
+    }
+
+    let cutoff = unix_time().saturating_sub(CONFIG.history.keep_days * 24 * 60 * 60);
+    report.folded = history::compact(cutoff)?;
+    Ok(report)
+}
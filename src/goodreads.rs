@@ -0,0 +1,179 @@
+// books from the CSV exports of Goodreads and StoryGraph. columns are found by
+// their header, so either export works, and so do older ones with fewer
+// columns. shelves become tags, the exclusive shelf or read status becomes
+// the book's status
+use crate::protocol::{Book, ReadingStatus};
+
+// the columns each export names the same thing
+const TITLE: &[&str] = &["title"];
+const AUTHOR: &[&str] = &["author", "authors"];
+const MORE_AUTHORS: &[&str] = &["additional authors"];
+const PUBLISHER: &[&str] = &["publisher"];
+const ISBN: &[&str] = &["isbn13", "isbn", "isbn/uid"];
+const YEAR: &[&str] = &["original publication year", "year published"];
+const RATING: &[&str] = &["my rating", "star rating"];
+const SHELVES: &[&str] = &["bookshelves", "tags"];
+const STATUS: &[&str] = &["exclusive shelf", "read status"];
+const READ_AT: &[&str] = &["date read", "last date read"];
+
+pub fn parse(input: &str) -> Result<Vec<Book>, String> {
+    let mut rows = records(input)?.into_iter();
+    let header: Vec<String> = match rows.next() {
+        Some(header) => header.iter().map(|h| h.trim().to_lowercase()).collect(),
+        None => return Ok(Vec::new()),
+    };
+    let column = |names: &[&str]| names.iter().find_map(|n| header.iter().position(|h| h == n));
+    let (title, author) = match (column(TITLE), column(AUTHOR)) {
+        (Some(title), Some(author)) => (title, author),
+        _ => return Err("not a Goodreads or StoryGraph export, it has no title or author".into()),
+    };
+    let columns = [
+        column(MORE_AUTHORS),
+        column(PUBLISHER),
+        column(ISBN),
+        column(YEAR),
+        column(RATING),
+        column(SHELVES),
+        column(STATUS),
+        column(READ_AT),
+    ];
+    let mut books = Vec::new();
+    for row in rows {
+        let get = |index: Option<usize>| {
+            let value = index.and_then(|i| row.get(i)).map(|v| clean(v));
+            value.filter(|v| !v.is_empty())
+        };
+        let [more_authors, publisher, isbn, year, rating, shelves, status, read_at] =
+            columns.map(get);
+        let (title, author) = match (get(Some(title)), get(Some(author))) {
+            (Some(title), Some(author)) => (title, author),
+            _ => continue,
+        };
+        let author = match more_authors {
+            Some(more) => format!("{}, {}", author, more),
+            None => author,
+        };
+        let status = status.as_deref().and_then(reading_status);
+        // the exclusive shelf shows up among the shelves again
+        let tags = shelves
+            .map(|shelves| {
+                shelves
+                    .split(',')
+                    .map(|s| s.trim().to_lowercase())
+                    .filter(|s| !s.is_empty() && reading_status(s).is_none())
+                    .collect()
+            })
+            .unwrap_or_default();
+        books.push(Book {
+            title,
+            author,
+            publisher: publisher.unwrap_or_default(),
+            public: false,
+            rating: rating.as_deref().and_then(stars),
+            year: year.and_then(|y| y.parse().ok()),
+            isbn,
+            tags,
+            status,
+            read_at: read_at.as_deref().and_then(unix_date),
+            ..Default::default()
+        });
+    }
+    Ok(books)
+}
+
+// goodreads writes isbns as ="0345391802" so spreadsheets keep leading zeros
+fn clean(value: &str) -> String {
+    let value = value.trim();
+    let value = match value.strip_prefix("=\"").and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => inner,
+        None => value,
+    };
+    value.trim().to_owned()
+}
+
+fn reading_status(shelf: &str) -> Option<ReadingStatus> {
+    match shelf.trim().to_lowercase().as_str() {
+        "to-read" => Some(ReadingStatus::ToRead),
+        "currently-reading" => Some(ReadingStatus::Reading),
+        "read" => Some(ReadingStatus::Read),
+        "did-not-finish" => Some(ReadingStatus::Abandoned),
+        _ => None,
+    }
+}
+
+// goodreads uses 0 for unrated, storygraph quarter stars like 3.75
+fn stars(rating: &str) -> Option<u8> {
+    let rating: f64 = rating.parse().ok()?;
+    if rating < 0.5 {
+        return None;
+    }
+    Some(rating.round().min(5.0) as u8)
+}
+
+// "2019/03/14" or "2019-03-14" as unix time at midnight utc
+fn unix_date(date: &str) -> Option<u64> {
+    let mut parts = date.split(['/', '-']).map(|p| p.trim().parse::<u64>());
+    let (year, month, day) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(y)), Some(Ok(m)), Some(Ok(d))) => (y, m, d),
+        _ => return None,
+    };
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // days since 1970-01-01 for the proleptic gregorian calendar, shifted to
+    // start the year in march so leap days come last
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y / 400;
+    let of_era = y % 400;
+    let of_year = (153 * m + 2) / 5 + day - 1;
+    let of_cycle = of_era * 365 + of_era / 4 - of_era / 100 + of_year;
+    let days = era * 146_097 + of_cycle - 719_468;
+    Some(days * 24 * 60 * 60)
+}
+
+// rows of fields. fields may be quoted, and quoted ones may hold commas, line
+// breaks and doubled quotes
+fn records(input: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = input.trim_start_matches('\u{feff}').chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() => {
+                let start = line;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => return Err(format!("line {}: unclosed quote", start)),
+                    }
+                }
+            }
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                line += 1;
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows.retain(|row| row.iter().any(|field| !field.trim().is_empty()));
+    Ok(rows)
+}
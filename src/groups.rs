@@ -0,0 +1,65 @@
+use crate::store;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const GROUPS_PATH: &str = "./groups.json";
+
+// named sets of peer ids, e.g. "family" or "book-club", used to target
+// requests and to limit who can see a shared book
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Groups {
+    groups: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Groups {
+    pub fn load() -> Self {
+        store::load(GROUPS_PATH)
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        store::save(GROUPS_PATH, self)
+    }
+
+    pub fn add(&mut self, group: &str, peer: &str) -> bool {
+        self.groups
+            .entry(group.to_owned())
+            .or_default()
+            .insert(peer.to_owned())
+    }
+
+    pub fn remove(&mut self, group: &str, peer: &str) -> bool {
+        let removed = match self.groups.get_mut(group) {
+            Some(members) => members.remove(peer),
+            None => false,
+        };
+        // drop groups that became empty
+        self.groups.retain(|_, members| !members.is_empty());
+        removed
+    }
+
+    // a member that moved to a new peer id keeps its groups, returns how many
+    pub fn replace_member(&mut self, old: &str, new: &str) -> usize {
+        let mut replaced = 0;
+        for members in self.groups.values_mut() {
+            if members.remove(old) {
+                members.insert(new.to_owned());
+                replaced += 1;
+            }
+        }
+        replaced
+    }
+
+    pub fn members(&self, group: &str) -> Option<&BTreeSet<String>> {
+        self.groups.get(group)
+    }
+
+    pub fn contains(&self, group: &str, peer: &str) -> bool {
+        self.members(group)
+            .map(|members| members.contains(peer))
+            .unwrap_or(false)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &BTreeSet<String>)> {
+        self.groups.iter()
+    }
+}
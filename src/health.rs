@@ -0,0 +1,87 @@
+use crate::{Result, STORAGE_PATH};
+use log::{debug, error, info};
+use peer2peer::schema;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+// addresses the swarm listens on right now
+static LISTENING: AtomicUsize = AtomicUsize::new(0);
+
+pub fn listen_addr_added() {
+    LISTENING.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn listen_addr_expired() {
+    let _ = LISTENING.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+}
+
+// probes for container orchestrators, apart from the api so they need no
+// token: /healthz while the process runs, /readyz once it can serve peers
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("health checks on http://{}", listener.local_addr()?);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            if let Err(e) = answer(stream).await {
+                error!("health check connection error: {}", e);
+            }
+        });
+    }
+}
+
+async fn answer(mut stream: TcpStream) -> Result<()> {
+    // probes send a request line and a few headers, the first read holds it
+    let mut buf = [0u8; 1024];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = match path {
+        "/healthz" => (200, "ok".to_owned()),
+        "/readyz" => match ready().await {
+            Ok(()) => (200, "ready".to_owned()),
+            // whoever can reach the port learns only that, the reason is logged
+            Err(e) => {
+                debug!("not ready: {}", e);
+                (503, "not ready".to_owned())
+            }
+        },
+        _ => (404, "not found".to_owned()),
+    };
+    let reason = match status {
+        200 => "OK",
+        503 => "Service Unavailable",
+        _ => "Not Found",
+    };
+    let res = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}\n",
+        status,
+        reason,
+        body.len() + 1,
+        body
+    );
+    stream.write_all(res.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// listening for peers, and the library can be read and written
+async fn ready() -> Result<()> {
+    if LISTENING.load(Ordering::Relaxed) == 0 {
+        return Err("not listening for peers yet".into());
+    }
+    let content = tokio::fs::read(STORAGE_PATH)
+        .await
+        .map_err(|e| format!("unable to read {}: {}", STORAGE_PATH, e))?;
+    schema::parse(&content).map_err(|e| format!("unable to parse {}: {}", STORAGE_PATH, e))?;
+    let metadata = tokio::fs::metadata(STORAGE_PATH).await?;
+    if metadata.permissions().readonly() {
+        return Err(format!("{} is read-only", STORAGE_PATH).into());
+    }
+    Ok(())
+}
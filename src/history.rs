@@ -0,0 +1,319 @@
+use crate::clock;
+use crate::store;
+use crate::unix_time;
+use log::info;
+use once_cell::sync::Lazy;
+use peer2peer::protocol::{Book, Library};
+use peer2peer::schema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+use std::sync::Mutex;
+
+pub const HISTORY_PATH: &str = "./history.log";
+// bookkeeping that changes with every edit, not worth listing as a change
+const STAMPS: &[&str] = &["modified", "revision"];
+
+// the id of the last batch, read from the log once rather than on every write
+static LAST: Lazy<Mutex<Option<u64>>> = Lazy::new(|| Mutex::new(None));
+
+// what happened to one book
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Change {
+    Added { book: Book },
+    Edited { before: Book, after: Book },
+    Shared { before: Book, after: Book },
+    Unshared { before: Book, after: Book },
+    Deleted { book: Book },
+}
+
+impl Change {
+    pub fn id(&self) -> usize {
+        match self {
+            Change::Added { book } | Change::Deleted { book } => book.id,
+            Change::Edited { after, .. }
+            | Change::Shared { after, .. }
+            | Change::Unshared { after, .. } => after.id,
+        }
+    }
+
+    // e.g. "edited rating, tags of Dune" or "moved Dune to the trash"
+    pub fn describe(&self) -> String {
+        match self {
+            Change::Added { book } => format!("added {} by {}", book.title, book.author),
+            Change::Deleted { book } => format!("deleted {} by {}", book.title, book.author),
+            Change::Shared { after, .. } => match after.visible_to {
+                Some(ref group) => format!("shared {} with @{}", after.title, group),
+                None => format!("shared {}", after.title),
+            },
+            Change::Unshared { after, .. } => format!("stopped sharing {}", after.title),
+            Change::Edited { before, after } => match (before.trashed, after.trashed) {
+                (None, Some(_)) => format!("moved {} to the trash", after.title),
+                (Some(_), None) => format!("restored {}", after.title),
+                _ => match changed_fields(before, after).join(", ") {
+                    fields if fields.is_empty() => format!("edited {}", after.title),
+                    fields => format!("edited {} of {}", fields, after.title),
+                },
+            },
+        }
+    }
+
+    fn apply(&self, library: &mut Library) {
+        library.retain(|b| b.id != self.id());
+        match self {
+            Change::Added { book } => library.push(book.clone()),
+            Change::Deleted { .. } => {}
+            Change::Edited { after, .. }
+            | Change::Shared { after, .. }
+            | Change::Unshared { after, .. } => library.push(after.clone()),
+        }
+    }
+}
+
+// the changes of one write of the library, undone together
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Batch {
+    pub id: u64,
+    pub at: u64,
+    // the batch this one undid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<u64>,
+    // found at startup, made while the node wasn't running or before the
+    // history began. these can't be undone
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub outside: bool,
+    pub changes: Vec<Change>,
+}
+
+// every batch, oldest first. a line this version doesn't understand ends
+// the history there, replaying past a gap would make up a library
+pub fn load() -> Vec<Batch> {
+    let content = match std::fs::read_to_string(HISTORY_PATH) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+    content
+        .lines()
+        .map_while(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+// the library as the history has it at the given unix time, or now
+pub fn replay(batches: &[Batch], until: Option<u64>) -> Library {
+    let mut library = Library::new();
+    for batch in batches {
+        if matches!(until, Some(until) if batch.at > until) {
+            break;
+        }
+        for change in &batch.changes {
+            change.apply(&mut library);
+        }
+    }
+    library.sort_by_key(|b| b.id);
+    library
+}
+
+// appends what changed between two libraries, if anything did. the last
+// batch after, for the snapshot of the library to name
+pub fn record(before: &Library, after: &Library, undoes: Option<u64>) -> std::io::Result<u64> {
+    append(diff(before, after), undoes, false)
+}
+
+// the library is what the history adds up to, its file a snapshot of that.
+// a snapshot the history got ahead of, e.g. after a crash between writing
+// the two, is rebuilt from the history. one edited while the node wasn't
+// running, or older than the history, is caught up into it instead
+pub fn restore(path: &str) -> schema::Result<()> {
+    let content = std::fs::read(path)?;
+    let snapshot = schema::parse(&content)?;
+    let batches = load();
+    let last = batches.last().map_or(0, |b| b.id);
+    *LAST.lock().unwrap() = Some(last);
+    let history = replay(&batches, None);
+    let at = schema::history_of(&content);
+    let library = match at {
+        Some(at) if at < last => {
+            info!("{} missed changes {} to {}, rebuilt it from the history", path, at + 1, last);
+            history.clone()
+        }
+        _ => snapshot,
+    };
+    let changes = diff(&history, &library);
+    if changes.is_empty() && at == Some(last) {
+        return Ok(());
+    }
+    let last = append(changes, None, true)?;
+    store::write(path, schema::to_snapshot(&library, Some(last))?)?;
+    Ok(())
+}
+
+// folds the batches from before the cutoff into one of the books they added
+// up to, numbered and dated like the last of them, so books deleted back then
+// and the old versions of the others leave the disk. returns how many batches
+// were folded
+pub fn compact(cutoff: u64) -> std::io::Result<usize> {
+    // no batch is appended while the log is rewritten
+    let _last = LAST.lock().unwrap();
+    let batches = load();
+    // lines past one this version doesn't understand are left alone
+    let lines = match std::fs::read_to_string(HISTORY_PATH) {
+        Ok(content) => content.lines().count(),
+        Err(_) => return Ok(0),
+    };
+    if lines != batches.len() {
+        return Ok(0);
+    }
+    let old = batches.iter().take_while(|b| b.at < cutoff).count();
+    if old == 0 || (old == 1 && batches[0].outside && batches[0].undoes.is_none()) {
+        return Ok(0);
+    }
+    let (folded, kept) = batches.split_at(old);
+    let newest = &folded[old - 1];
+    let base = Batch {
+        id: newest.id,
+        at: newest.at,
+        undoes: None,
+        outside: true,
+        changes: replay(folded, None).into_iter().map(|book| Change::Added { book }).collect(),
+    };
+    let mut content = Vec::new();
+    for batch in std::iter::once(&base).chain(kept) {
+        serde_json::to_writer(&mut content, batch)?;
+        content.push(b'\n');
+    }
+    store::write(HISTORY_PATH, content)?;
+    Ok(old)
+}
+
+fn append(changes: Vec<Change>, undoes: Option<u64>, outside: bool) -> std::io::Result<u64> {
+    let mut last = LAST.lock().unwrap();
+    let known = match *last {
+        Some(known) => known,
+        None => load().last().map_or(0, |b| b.id),
+    };
+    if changes.is_empty() {
+        *last = Some(known);
+        return Ok(known);
+    }
+    let id = known + 1;
+    let batch = Batch {
+        id,
+        at: unix_time(),
+        undoes,
+        outside,
+        changes,
+    };
+    let line = serde_json::to_string(&batch)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(HISTORY_PATH)?;
+    writeln!(file, "{}", line)?;
+    // on disk before the snapshot says the library got this far
+    file.sync_data()?;
+    *last = Some(id);
+    Ok(id)
+}
+
+fn diff(before: &Library, after: &Library) -> Vec<Change> {
+    let old: BTreeMap<usize, &Book> = before.iter().map(|b| (b.id, b)).collect();
+    let new: BTreeMap<usize, &Book> = after.iter().map(|b| (b.id, b)).collect();
+    let mut changes = Vec::new();
+    for (id, book) in &new {
+        let before = match old.get(id) {
+            Some(before) => *before,
+            None => {
+                changes.push(Change::Added { book: (*book).clone() });
+                continue;
+            }
+        };
+        if to_value(before) == to_value(book) {
+            continue;
+        }
+        let (before, after) = (before.clone(), (*book).clone());
+        let sharing = (before.public, &before.visible_to) != (after.public, &after.visible_to);
+        changes.push(match (sharing, after.public) {
+            (true, true) => Change::Shared { before, after },
+            (true, false) => Change::Unshared { before, after },
+            (false, _) => Change::Edited { before, after },
+        });
+    }
+    for (id, book) in &old {
+        if !new.contains_key(id) {
+            changes.push(Change::Deleted { book: (*book).clone() });
+        }
+    }
+    changes
+}
+
+fn to_value(book: &Book) -> Value {
+    serde_json::to_value(book).unwrap_or_default()
+}
+
+// the same but for when it was last edited
+pub fn same(a: &Book, b: &Book) -> bool {
+    changed_fields(a, b).is_empty()
+}
+
+// the fields that differ, e.g. ["rating", "tags"]
+fn changed_fields(before: &Book, after: &Book) -> Vec<String> {
+    let (before, after) = match (to_value(before), to_value(after)) {
+        (Value::Object(before), Value::Object(after)) => (before, after),
+        _ => return Vec::new(),
+    };
+    let keys: HashSet<&String> = before.keys().chain(after.keys()).collect();
+    let mut changed: Vec<String> = keys
+        .into_iter()
+        .filter(|k| !STAMPS.contains(&k.as_str()) && before.get(*k) != after.get(*k))
+        .cloned()
+        .collect();
+    changed.sort();
+    changed
+}
+
+// the most recent batch that can still be undone: made here, not an undo
+// itself and not undone yet
+pub fn last_undoable(batches: &[Batch]) -> Option<&Batch> {
+    let undone: HashSet<u64> = batches.iter().filter_map(|b| b.undoes).collect();
+    batches
+        .iter()
+        .rev()
+        .find(|b| !b.outside && b.undoes.is_none() && !undone.contains(&b.id))
+}
+
+// puts back what the batch changed, where the book wasn't changed again
+// since. returns the books left alone
+pub fn revert(batch: &Batch, library: &mut Library, now: u64, revision: u64) -> Vec<String> {
+    let mut skipped = Vec::new();
+    for change in batch.changes.iter().rev() {
+        let current = library.iter().position(|b| b.id == change.id());
+        let (expected, restore) = match change {
+            Change::Added { book } => (Some(book), None),
+            Change::Deleted { book } => (None, Some(book)),
+            Change::Edited { before, after }
+            | Change::Shared { before, after }
+            | Change::Unshared { before, after } => (Some(after), Some(before)),
+        };
+        let unchanged = match (current, expected) {
+            (Some(i), Some(expected)) => to_value(&library[i]) == to_value(expected),
+            (None, None) => true,
+            _ => false,
+        };
+        if !unchanged {
+            skipped.push(format!("{} ({})", change.id(), change.describe()));
+            continue;
+        }
+        if let Some(i) = current {
+            library.remove(i);
+        }
+        if let Some(book) = restore {
+            let mut book = book.clone();
+            // newer than what other devices hold, so syncing keeps the undo
+            clock::stamp(&mut book, now, revision);
+            library.push(book);
+        }
+    }
+    skipped
+}
@@ -0,0 +1,57 @@
+use crate::config::CONFIG;
+use crate::TOPIC;
+use libp2p::rendezvous::{client, Cookie, Namespace};
+use libp2p::PeerId;
+use log::error;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// registrations live for two hours by default, refresh well before that.
+// discovery uses the same interval since the cookie only fetches new peers
+const REFRESH: Duration = Duration::from_secs(10 * 60);
+
+// keeps us registered at the configured rendezvous points and asks them
+// for the other peers registered under our namespace
+pub struct Hubs {
+    namespace: Namespace,
+    cookies: HashMap<PeerId, Cookie>,
+    next_refresh: Instant,
+}
+
+impl Hubs {
+    pub fn new() -> Self {
+        let name = CONFIG
+            .rendezvous
+            .namespace
+            .clone()
+            .unwrap_or_else(|| TOPIC.id().to_owned());
+        let namespace = Namespace::new(name).unwrap_or_else(|_| {
+            error!("rendezvous namespace too long, using the default");
+            Namespace::from_static("library")
+        });
+        Hubs {
+            namespace,
+            cookies: HashMap::new(),
+            next_refresh: Instant::now() + REFRESH,
+        }
+    }
+
+    pub fn refresh(&self, client: &mut client::Behaviour, point: PeerId) {
+        client.register(self.namespace.clone(), point, None);
+        let cookie = self.cookies.get(&point).cloned();
+        client.discover(Some(self.namespace.clone()), cookie, None, point);
+    }
+
+    pub fn refresh_due(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_refresh {
+            return false;
+        }
+        self.next_refresh = now + REFRESH;
+        true
+    }
+
+    pub fn discovered(&mut self, point: PeerId, cookie: Cookie) {
+        self.cookies.insert(point, cookie);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::operations;
+use peer2peer::protocol::Book;
+use std::collections::{BTreeSet, HashSet};
+use std::time::{Duration, Instant};
+
+// how long a request waits for answers. a broadcast is over then, anyone can
+// answer it, and a request aimed at peers ends sooner once they all did
+pub const WINDOW: Duration = Duration::from_secs(30);
+
+// what an answer has to be to belong to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    // a whole catalog, or how many books it holds
+    Catalog,
+    Search(String),
+    Book(usize),
+}
+
+// what came back, summed up once a request is over
+pub enum Answer<'a> {
+    Books(&'a [Book]),
+    // the answer to a --count, how many books without the books
+    Count(usize),
+    Refused,
+}
+
+#[derive(Debug)]
+pub struct Request {
+    pub id: u32,
+    // the command that sent it, e.g. "ls books @family"
+    pub what: String,
+    pub kind: Kind,
+    pub started: Instant,
+    // peers an answer is expected from. for a broadcast the library peers
+    // connected when it went out, though others may answer too
+    pub expected: BTreeSet<String>,
+    pub answered: BTreeSet<String>,
+    pub broadcast: bool,
+    cancelled: bool,
+    refused: usize,
+    // books received, and the titles and authors among them. the same book
+    // offered by several peers counts once
+    books: usize,
+    unique: HashSet<(String, String)>,
+    // answers from peers that had answered already, e.g. sent again
+    repeats: usize,
+}
+
+impl Request {
+    pub fn waiting_on(&self) -> impl Iterator<Item = &String> {
+        self.expected.iter().filter(move |p| !self.answered.contains(*p))
+    }
+
+    fn asked(&self, peer: &str) -> bool {
+        self.broadcast || self.expected.contains(peer)
+    }
+
+    // a broadcast or a group, worth a summary when it's over
+    pub fn fanned_out(&self) -> bool {
+        self.broadcast || self.expected.len() > 1
+    }
+
+    // e.g. "3 peers answered, 1 timed out, 40 unique books, 2 duplicates
+    // collapsed"
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} peers answered", self.answered.len());
+        if self.refused > 0 {
+            summary.push_str(&format!(" ({} refused)", self.refused));
+        }
+        let timed_out = self.waiting_on().count();
+        summary.push_str(&format!(", {} timed out", timed_out));
+        if self.unique.is_empty() && self.books > 0 {
+            summary.push_str(&format!(", {} books in total", self.books));
+        } else {
+            let duplicates = self.books - self.unique.len();
+            summary.push_str(&format!(
+                ", {} unique books, {} duplicates collapsed",
+                self.unique.len(),
+                duplicates
+            ));
+        }
+        if self.repeats > 0 {
+            summary.push_str(&format!(", {} repeated answers not counted", self.repeats));
+        }
+        summary
+    }
+
+    fn record(&mut self, peer: &str, answer: Answer) {
+        self.answered.insert(peer.to_owned());
+        match answer {
+            Answer::Books(books) => {
+                self.books += books.len();
+                self.unique.extend(books.iter().map(Book::key));
+            }
+            Answer::Count(total) => self.books += total,
+            Answer::Refused => self.refused += 1,
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.started.elapsed() >= WINDOW
+            || !self.broadcast && !self.cancelled && self.waiting_on().next().is_none()
+    }
+}
+
+// the queries we sent and are still waiting on, for the `requests` command
+#[derive(Default)]
+pub struct InFlight {
+    requests: Vec<Request>,
+}
+
+impl InFlight {
+    pub fn start(&mut self, what: String, kind: Kind, expected: BTreeSet<String>, broadcast: bool) {
+        self.requests.push(Request {
+            id: operations::next_id(),
+            what,
+            kind,
+            started: Instant::now(),
+            expected,
+            answered: BTreeSet::new(),
+            broadcast,
+            cancelled: false,
+            refused: 0,
+            books: 0,
+            unique: HashSet::new(),
+            repeats: 0,
+        });
+    }
+
+    // counts an answer towards the oldest request it can belong to. false
+    // when it only belongs to cancelled ones or to none at all, it's dropped
+    // then: anyone can send an answer nobody asked for
+    pub fn answered(&mut self, peer: &str, kind: &Kind, answer: Answer) -> bool {
+        self.take_answer(peer, |k| k == kind, answer)
+    }
+
+    // a refusal doesn't say what it refuses, any catalog or search will do
+    pub fn refused(&mut self, peer: &str) -> bool {
+        self.take_answer(peer, |k| !matches!(k, Kind::Book(_)), Answer::Refused)
+    }
+
+    fn take_answer(&mut self, peer: &str, fits: impl Fn(&Kind) -> bool, answer: Answer) -> bool {
+        let (mut asked, mut cancelled, mut repeated) = (false, false, None);
+        for request in self.requests.iter_mut() {
+            if !fits(&request.kind) || !request.asked(peer) {
+                continue;
+            }
+            asked = true;
+            if request.cancelled {
+                cancelled = true;
+            } else if request.answered.contains(peer) {
+                repeated = repeated.or(Some(request));
+            } else {
+                request.record(peer, answer);
+                return true;
+            }
+        }
+        // shown again, but counted once
+        if let Some(request) = repeated {
+            request.repeats += 1;
+        }
+        asked && !cancelled
+    }
+
+    // what the cancelled request was. it's kept until its window is over,
+    // so the answers still on their way are dropped
+    pub fn cancel(&mut self, id: u32) -> Option<String> {
+        let request = self.requests.iter_mut().find(|r| r.id == id && !r.cancelled)?;
+        request.cancelled = true;
+        Some(request.what.clone())
+    }
+
+    // drops and returns the requests that are over, cancelled ones aside
+    pub fn expire(&mut self) -> Vec<Request> {
+        if !self.requests.iter().any(Request::done) {
+            return Vec::new();
+        }
+        let (done, open): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.requests).into_iter().partition(Request::done);
+        self.requests = open;
+        done.into_iter().filter(|r| !r.cancelled).collect()
+    }
+
+    // the requests still waiting, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &Request> {
+        self.requests.iter().filter(|r| !r.cancelled)
+    }
+}
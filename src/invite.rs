@@ -0,0 +1,128 @@
+use crate::Result;
+use crate::store;
+use data_encoding::BASE64URL_NOPAD;
+use libp2p::{multiaddr::Protocol, pnet::PreSharedKey, Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+
+pub const INVITES_PATH: &str = "./invites.json";
+const PREFIX: &str = "peer2peer-invite:";
+
+// everything a friend needs to reach us, as one string to paste
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Invite {
+    pub peer: String,
+    pub addrs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    // hex of the swarm key, without it nobody gets into a private network
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub psk: Option<String>,
+}
+
+impl Invite {
+    pub fn new(peer: &PeerId, addrs: &[Multiaddr]) -> Self {
+        Invite {
+            peer: peer.to_string(),
+            addrs: addrs.iter().map(|a| a.to_string()).collect(),
+            name: None,
+            network: None,
+            psk: None,
+        }
+    }
+
+    pub fn with_psk(mut self, psk: &PreSharedKey) -> Self {
+        // the last line of the swarm.key format is the key itself
+        self.psk = psk.to_string().lines().last().map(str::to_owned);
+        self
+    }
+
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("unable to serialize invite");
+        format!("{}{}", PREFIX, BASE64URL_NOPAD.encode(&json))
+    }
+
+    // checked up front, so a mangled paste is refused rather than half applied
+    pub fn decode(text: &str) -> Result<Self> {
+        let encoded = text.trim().strip_prefix(PREFIX).ok_or("not a peer2peer invite")?;
+        let json = BASE64URL_NOPAD.decode(encoded.as_bytes())?;
+        let invite: Invite = serde_json::from_slice(&json)?;
+        invite.peer_id()?;
+        for addr in &invite.addrs {
+            addr.parse::<Multiaddr>()?;
+        }
+        if invite.addrs.is_empty() {
+            return Err("invite has no addresses".into());
+        }
+        invite.psk()?;
+        Ok(invite)
+    }
+
+    pub fn peer_id(&self) -> Result<PeerId> {
+        Ok(self.peer.parse()?)
+    }
+
+    pub fn psk(&self) -> Result<Option<PreSharedKey>> {
+        match &self.psk {
+            Some(hex) => Ok(Some(format!("/key/swarm/psk/1.0.0/\n/base16/\n{}\n", hex).parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    // each address ends in /p2p/<peer id>, like bootstrap addresses in the config
+    pub fn bootstrap(&self) -> Vec<(PeerId, Multiaddr)> {
+        let peer = match self.peer_id() {
+            Ok(peer) => peer,
+            Err(_) => return Vec::new(),
+        };
+        self.addrs
+            .iter()
+            .filter_map(|a| a.parse::<Multiaddr>().ok())
+            .map(|mut addr| {
+                if !matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
+                    addr.push(Protocol::P2p(peer.into()));
+                }
+                (peer, addr)
+            })
+            .collect()
+    }
+}
+
+// invites accepted so far. they act as extra bootstrap peers, and supply the
+// network and swarm key when the config doesn't set them
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Invites {
+    accepted: Vec<Invite>,
+}
+
+impl Invites {
+    pub fn load() -> Self {
+        store::load(INVITES_PATH)
+    }
+
+    fn save(&self) -> Result<()> {
+        store::save_private(INVITES_PATH, self)?;
+        Ok(())
+    }
+
+    // a newer invite from the same peer replaces the older one
+    pub fn accept(&mut self, invite: Invite) -> Result<()> {
+        self.accepted.retain(|i| i.peer != invite.peer);
+        self.accepted.push(invite);
+        self.save()
+    }
+
+    pub fn bootstrap_peers(&self) -> Vec<(PeerId, Multiaddr)> {
+        self.accepted.iter().flat_map(Invite::bootstrap).collect()
+    }
+
+    // the latest invite that sets one wins
+    pub fn network(&self) -> Option<&String> {
+        self.accepted.iter().rev().find_map(|i| i.network.as_ref())
+    }
+
+    pub fn psk(&self) -> Option<PreSharedKey> {
+        self.accepted.iter().rev().find_map(|i| i.psk().ok().flatten())
+    }
+}
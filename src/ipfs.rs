@@ -0,0 +1,153 @@
+use crate::progress::Progress;
+use crate::Result;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    time,
+};
+
+// adding a large file takes the daemon a while to hash
+const TIMEOUT: Duration = Duration::from_secs(60);
+const BOUNDARY: &str = "peer2peer-ipfs-boundary";
+// the status line and headers of an answer, anything longer is not the daemon
+const MAX_HEAD: usize = 16 * 1024;
+
+#[derive(Deserialize)]
+struct Added {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+// adds a file through the http api of a local ipfs daemon such as kubo, which
+// pins it and keeps providing it, and returns its cid
+pub async fn add(api: SocketAddr, name: &str, content: &[u8]) -> Result<String> {
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n",
+        BOUNDARY,
+        name.replace('"', "")
+    )
+    .into_bytes();
+    body.extend_from_slice(content);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+    let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+    let res = post(api, "/api/v0/add?cid-version=1&pin=true", &content_type, &body).await?;
+    // one line per file added, the last one is ours
+    let last = res.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or_default();
+    let added: Added = serde_json::from_str(last)?;
+    Ok(added.hash)
+}
+
+// lets the daemon forget a file we added, e.g. when the attach was cancelled
+pub async fn unpin(api: SocketAddr, cid: &str) -> Result<()> {
+    post(api, &format!("/api/v0/pin/rm?arg={}", cid), "text/plain", &[]).await?;
+    Ok(())
+}
+
+// writes the content of a file to `out` as it comes, from our own store or
+// fetched from the peers that provide it, and returns its size. the cid comes
+// from a peer, so it's checked before it goes into the request. past `max`
+// bytes the transfer stops with an error
+pub async fn cat<W: AsyncWrite + Unpin>(
+    api: SocketAddr,
+    cid: &str,
+    out: &mut W,
+    max: Option<u64>,
+    progress: &mut Progress,
+) -> Result<u64> {
+    if !is_cid(cid) {
+        return Err(format!("{:?} isn't a cid", cid).into());
+    }
+    let target = format!("/api/v0/cat?arg={}", cid);
+    let (mut stream, mut body) = call(api, &target, "text/plain", &[]).await?;
+    let mut written = 0;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        written += body.len() as u64;
+        if let Some(max) = max.filter(|max| written > *max) {
+            return Err(format!("the file is larger than {} bytes", max).into());
+        }
+        out.write_all(&body).await?;
+        progress.inc(body.len() as u64);
+        let n = time::timeout(TIMEOUT, stream.read(&mut buffer)).await??;
+        if n == 0 {
+            progress.finish();
+            return Ok(written);
+        }
+        body = buffer[..n].to_vec();
+    }
+}
+
+async fn post(api: SocketAddr, target: &str, content_type: &str, body: &[u8]) -> Result<String> {
+    let (mut stream, mut res) = call(api, target, content_type, body).await?;
+    time::timeout(TIMEOUT, stream.read_to_end(&mut res)).await??;
+    Ok(String::from_utf8_lossy(&res).into_owned())
+}
+
+// the connection once the daemon took the request, with what came of the
+// body along with the head
+async fn call(
+    api: SocketAddr,
+    target: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(TcpStream, Vec<u8>)> {
+    let head = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\n\
+         Content-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        target,
+        api,
+        content_type,
+        body.len()
+    );
+
+    let mut stream = time::timeout(TIMEOUT, TcpStream::connect(api)).await??;
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    let mut res = read_head(&mut stream).await?;
+
+    let end = res.windows(4).position(|w| w == b"\r\n\r\n").ok_or("malformed http response")?;
+    let head = String::from_utf8_lossy(&res[..end]);
+    if !matches!(head.split_whitespace().nth(1), Some(status) if status.starts_with('2')) {
+        return Err(format!("ipfs answered: {}", head.lines().next().unwrap_or_default()).into());
+    }
+    let body = res.split_off(end + 4);
+    Ok((stream, body))
+}
+
+// reads until the end of the headers, and whatever came with them
+async fn read_head<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Vec<u8>> {
+    let mut res = Vec::new();
+    let mut buffer = [0; 4096];
+    while !res.windows(4).any(|w| w == b"\r\n\r\n") {
+        if res.len() > MAX_HEAD {
+            return Err("malformed http response".into());
+        }
+        let n = time::timeout(TIMEOUT, stream.read(&mut buffer)).await??;
+        if n == 0 {
+            return Err("malformed http response".into());
+        }
+        res.extend_from_slice(&buffer[..n]);
+    }
+    Ok(res)
+}
+
+// close enough to tell a cid from a file name: a base58 v0 hash, or a v1 cid
+// in the base32 multibase the daemon and gateways use
+pub fn is_cid(value: &str) -> bool {
+    let base58 = |c: char| c.is_ascii_alphanumeric() && !"0OIl".contains(c);
+    let base32 = |c: char| c.is_ascii_lowercase() || ('2'..='7').contains(&c);
+    match value.strip_prefix('b') {
+        _ if value.len() == 46 && value.starts_with("Qm") => value.chars().all(base58),
+        Some(rest) => rest.len() >= 50 && rest.chars().all(base32),
+        None => false,
+    }
+}
+
+// where anyone can fetch it, without running ipfs themselves
+pub fn gateway_url(gateway: &str, cid: &str) -> String {
+    format!("{}/ipfs/{}", gateway.trim_end_matches('/'), cid)
+}
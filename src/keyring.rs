@@ -0,0 +1,59 @@
+use std::io::Write;
+use std::process::{Command, ExitStatus, Stdio};
+
+// the os keyring through its command line tools, so no keyring library has to
+// be linked: secret-tool (libsecret / secret service) on linux, security on macos.
+// the key is stored hex encoded under service "peer2peer", account "identity"
+const SERVICE: &str = "peer2peer";
+const ACCOUNT: &str = "identity";
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+// None when the keyring has no key for us yet
+pub fn load() -> Result<Option<String>> {
+    let output = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args(["find-generic-password", "-s", SERVICE, "-a", ACCOUNT, "-w"])
+            .output()?
+    } else {
+        Command::new("secret-tool")
+            .args(["lookup", "service", SERVICE, "account", ACCOUNT])
+            .output()?
+    };
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let secret = String::from_utf8(output.stdout)?.trim().to_owned();
+    Ok(if secret.is_empty() { None } else { Some(secret) })
+}
+
+pub fn store(secret: &str) -> Result<()> {
+    // both read the secret from stdin, keeping it out of the process list.
+    // security takes it as part of a command in its interactive mode
+    let status = if cfg!(target_os = "macos") {
+        let command =
+            format!("add-generic-password -U -s {} -a {} -w {}\n", SERVICE, ACCOUNT, secret);
+        with_stdin(Command::new("security").arg("-i"), &command)?
+    } else {
+        let label = "--label=peer2peer identity";
+        let mut secret_tool = Command::new("secret-tool");
+        secret_tool.args(["store", label, "service", SERVICE, "account", ACCOUNT]);
+        with_stdin(&mut secret_tool, secret)?
+    };
+    // security -i exits cleanly whatever became of the commands it read
+    if !status.success() || load()?.as_deref() != Some(secret) {
+        return Err("the keyring refused to store the key".into());
+    }
+    Ok(())
+}
+
+fn with_stdin(command: &mut Command, input: &str) -> Result<ExitStatus> {
+    let mut child = command.stdin(Stdio::piped()).stdout(Stdio::null()).spawn()?;
+    // dropped once written, so the tool sees the end of its input
+    child
+        .stdin
+        .take()
+        .ok_or("unable to write to the keyring tool")?
+        .write_all(input.as_bytes())?;
+    Ok(child.wait()?)
+}
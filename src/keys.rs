@@ -0,0 +1,215 @@
+use crate::config::{KeyStore, CONFIG};
+use crate::keyring;
+use crate::store;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac, NewMac};
+use libp2p::identity;
+use log::info;
+use once_cell::sync::{Lazy, OnceCell};
+use sha2::Sha256;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+pub const KEY_PATH: &str = "./identity.key";
+// starts a passphrase protected key file, plain ones are the bare protobuf key
+const ENCRYPTED_MAGIC: &[u8] = b"peer2peer encrypted key v1\n";
+const KDF_ROUNDS: u32 = 100_000;
+// lets the node start unattended with an encrypted key
+const PASSPHRASE_ENV: &str = "PEER2PEER_PASSPHRASE";
+// asks this many times before giving up on a mistyped passphrase
+const PASSPHRASE_TRIES: usize = 3;
+
+// kept from startup so a rotated key is encrypted without asking again
+static PASSPHRASE: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+static LOADED: OnceCell<identity::Keypair> = OnceCell::new();
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+// keep the same peer id across restarts, so known peers can find us again.
+// done once at startup, before anything needs the key
+pub fn load() -> Result<()> {
+    let keys = match CONFIG.identity.store {
+        KeyStore::File => from_file()?,
+        KeyStore::Keyring => from_keyring()?,
+    };
+    let _ = LOADED.set(keys);
+    Ok(())
+}
+
+pub fn loaded() -> identity::Keypair {
+    LOADED.get().expect("the identity key is loaded at startup").clone()
+}
+
+// stores a new identity in place of the current one, used from the next start
+pub fn replace(keys: &identity::Keypair) -> Result<()> {
+    match CONFIG.identity.store {
+        KeyStore::File => write_file(keys),
+        KeyStore::Keyring => store_in_keyring(keys),
+    }
+}
+
+fn from_file() -> Result<identity::Keypair> {
+    if Path::new(KEY_PATH).exists() {
+        let bytes = std::fs::read(KEY_PATH)?;
+        let keys = decode_file(&bytes)?;
+        if CONFIG.identity.encrypt && !bytes.starts_with(ENCRYPTED_MAGIC) {
+            write_file(&keys)?;
+            info!("encrypted {} with your passphrase", KEY_PATH);
+        }
+        return Ok(keys);
+    }
+    let keys = identity::Keypair::generate_ed25519();
+    write_file(&keys)?;
+    info!("generated new identity in {}", KEY_PATH);
+    Ok(keys)
+}
+
+fn decode_file(bytes: &[u8]) -> Result<identity::Keypair> {
+    let protobuf = match bytes.strip_prefix(ENCRYPTED_MAGIC) {
+        Some(sealed) => unseal(sealed)?,
+        None => bytes.to_vec(),
+    };
+    Ok(identity::Keypair::from_protobuf_encoding(&protobuf)?)
+}
+
+// asks again after a mistyped passphrase, but not for one from the environment
+fn unseal(sealed: &[u8]) -> Result<Vec<u8>> {
+    for _ in 0..PASSPHRASE_TRIES {
+        let passphrase = passphrase("passphrase for identity.key")?;
+        if let Some(protobuf) = decrypt(sealed, &passphrase) {
+            *PASSPHRASE.lock().unwrap() = Some(passphrase);
+            return Ok(protobuf);
+        }
+        if std::env::var(PASSPHRASE_ENV).is_ok() {
+            return Err(format!("wrong passphrase in {}", PASSPHRASE_ENV).into());
+        }
+        eprintln!("wrong passphrase, try again");
+    }
+    Err("wrong passphrase for identity.key".into())
+}
+
+fn write_file(keys: &identity::Keypair) -> Result<()> {
+    let bytes = keys.to_protobuf_encoding()?;
+    let content = if CONFIG.identity.encrypt {
+        let mut known = PASSPHRASE.lock().unwrap();
+        let passphrase = match known.take() {
+            Some(passphrase) => passphrase,
+            None => new_passphrase()?,
+        };
+        let mut content = ENCRYPTED_MAGIC.to_vec();
+        content.extend_from_slice(&encrypt(&bytes, &passphrase));
+        *known = Some(passphrase);
+        content
+    } else {
+        bytes
+    };
+    // the only copy of the node's identity, so never half written
+    store::write_private(KEY_PATH, content)?;
+    Ok(())
+}
+
+fn store_in_keyring(keys: &identity::Keypair) -> Result<()> {
+    keyring::store(&HEXLOWER.encode(&keys.to_protobuf_encoding()?))
+}
+
+// the keyring protects the key itself, so it's stored without a passphrase
+fn from_keyring() -> Result<identity::Keypair> {
+    if let Some(hex) = keyring::load()? {
+        let bytes = HEXLOWER.decode(hex.as_bytes())?;
+        return Ok(identity::Keypair::from_protobuf_encoding(&bytes)?);
+    }
+    // first start with the keyring: keep the peer id we already have
+    let existing = Path::new(KEY_PATH).exists();
+    let keys = if existing {
+        decode_file(&std::fs::read(KEY_PATH)?)?
+    } else {
+        identity::Keypair::generate_ed25519()
+    };
+    store_in_keyring(&keys)?;
+    if existing {
+        info!("moved {} into the os keyring, the file can be deleted", KEY_PATH);
+    } else {
+        info!("generated new identity in the os keyring");
+    }
+    Ok(keys)
+}
+
+fn passphrase(prompt: &str) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    eprint!("{}: ", prompt);
+    let _ = std::io::stderr().flush();
+    // hide what's typed where stty is available, it's only cosmetic
+    let hidden = Command::new("stty")
+        .arg("-echo")
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    let mut line = String::new();
+    let read = std::io::stdin().read_line(&mut line);
+    if hidden {
+        let _ = Command::new("stty").arg("echo").status();
+        eprintln!();
+    }
+    read?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_owned())
+}
+
+fn new_passphrase() -> Result<String> {
+    loop {
+        let passphrase = passphrase("new passphrase for identity.key")?;
+        if passphrase.is_empty() {
+            return Err("encrypting the identity key needs a passphrase".into());
+        }
+        if std::env::var(PASSPHRASE_ENV).is_ok() || passphrase == self::passphrase("repeat it")? {
+            return Ok(passphrase);
+        }
+        eprintln!("the passphrases differ, try again");
+    }
+}
+
+// pbkdf2-hmac-sha256, a single block is exactly one key
+fn derive_key(passphrase: &str, salt: &[u8]) -> Key {
+    let prf = Hmac::<Sha256>::new_varkey(passphrase.as_bytes()).expect("hmac takes any key length");
+    let mut mac = prf.clone();
+    mac.update(salt);
+    mac.update(&1u32.to_be_bytes());
+    let mut block = mac.finalize().into_bytes();
+    let mut key = block;
+    for _ in 1..KDF_ROUNDS {
+        let mut mac = prf.clone();
+        mac.update(&block);
+        block = mac.finalize().into_bytes();
+        key.iter_mut().zip(block.iter()).for_each(|(k, b)| *k ^= b);
+    }
+    Key::clone_from_slice(&key)
+}
+
+// salt || nonce || ciphertext
+fn encrypt(plaintext: &[u8], passphrase: &str) -> Vec<u8> {
+    let salt: [u8; 16] = rand::random();
+    let nonce: [u8; 12] = rand::random();
+    let ciphertext = ChaCha20Poly1305::new(&derive_key(passphrase, &salt))
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .expect("unable to encrypt identity key");
+    let mut sealed = salt.to_vec();
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    sealed
+}
+
+fn decrypt(sealed: &[u8], passphrase: &str) -> Option<Vec<u8>> {
+    if sealed.len() < 28 {
+        return None;
+    }
+    let (salt, rest) = sealed.split_at(16);
+    let (nonce, ciphertext) = rest.split_at(12);
+    ChaCha20Poly1305::new(&derive_key(passphrase, salt))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .ok()
+}
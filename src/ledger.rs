@@ -0,0 +1,206 @@
+use crate::sealing;
+use crate::store;
+use data_encoding::BASE64;
+use libp2p::{identity, PeerId};
+use log::error;
+use peer2peer::protocol::{LoanEvent, LoanRecord};
+use serde::{Deserialize, Serialize};
+
+pub const LEDGER_PATH: &str = "./ledger.json";
+
+fn statement(record: &LoanRecord) -> Vec<u8> {
+    let event = match record.event {
+        LoanEvent::Lent => "lent",
+        LoanEvent::Returned => "returned",
+        LoanEvent::Extended => "extended",
+    };
+    format!(
+        "peer2peer loan\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+        record.id,
+        event,
+        record.lender,
+        record.borrower,
+        record.book,
+        record.title,
+        record.at,
+        record.due.unwrap_or(0)
+    )
+    .into_bytes()
+}
+
+// adds our signature for whichever side of the loan we are on
+pub fn sign(keys: &identity::Keypair, record: &mut LoanRecord) {
+    let us = PeerId::from(keys.public()).to_string();
+    let signature = keys
+        .sign(&statement(record))
+        .expect("ed25519 signing can't fail");
+    let signature = Some(BASE64.encode(&signature));
+    if record.lender == us {
+        record.lender_signature = signature;
+    } else if record.borrower == us {
+        record.borrower_signature = signature;
+    }
+}
+
+fn signed_by(peer: &str, signature: Option<&String>, statement: &[u8]) -> bool {
+    let valid = || -> Option<bool> {
+        let peer: PeerId = peer.parse().ok()?;
+        let signature = BASE64.decode(signature?.as_bytes()).ok()?;
+        Some(sealing::public_key(&peer)?.verify(statement, &signature))
+    };
+    valid().unwrap_or(false)
+}
+
+pub fn lender_signed(record: &LoanRecord) -> bool {
+    signed_by(&record.lender, record.lender_signature.as_ref(), &statement(record))
+}
+
+pub fn borrower_signed(record: &LoanRecord) -> bool {
+    signed_by(&record.borrower, record.borrower_signature.as_ref(), &statement(record))
+}
+
+// what both sides sign, so a record can be compared with the one proposed
+fn unsigned(record: &LoanRecord) -> LoanRecord {
+    LoanRecord {
+        lender_signature: None,
+        borrower_signature: None,
+        ..record.clone()
+    }
+}
+
+// records about the same loan: loan ids are only unique to the pair, as
+// either side picks them
+fn same_loan(a: &LoanRecord, b: &LoanRecord) -> bool {
+    a.id == b.id && a.lender == b.lender && a.borrower == b.borrower && a.book == b.book
+}
+
+// loans and returns both sides signed, and proposals waiting for the other
+// side's signature. the entries are only ever appended to
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    entries: Vec<LoanRecord>,
+    #[serde(default)]
+    pending: Vec<LoanRecord>,
+}
+
+impl Ledger {
+    pub fn load() -> Self {
+        store::load(LEDGER_PATH)
+    }
+
+    fn save(&self) {
+        if let Err(e) = store::save(LEDGER_PATH, self) {
+            error!("unable to save the ledger: {}", e);
+        }
+    }
+
+    // a loan id not used in our ledger yet
+    pub fn new_id(&self) -> u32 {
+        loop {
+            let id = rand::random::<u32>();
+            if !self.entries.iter().chain(&self.pending).any(|r| r.id == id) {
+                return id;
+            }
+        }
+    }
+
+    // ours waiting for a countersignature, or theirs waiting for ours. a newer
+    // proposal for the same loan and event replaces the older one
+    pub fn propose(&mut self, record: LoanRecord) {
+        self.pending.retain(|r| !(same_loan(r, &record) && r.event == record.event));
+        self.pending.push(record);
+        self.save();
+    }
+
+    pub fn take_pending(&mut self, record: &LoanRecord) -> Option<LoanRecord> {
+        let index = self
+            .pending
+            .iter()
+            .position(|r| same_loan(r, record) && r.event == record.event)?;
+        let record = self.pending.remove(index);
+        self.save();
+        Some(record)
+    }
+
+    // a record both sides signed. false unless it is the one we agreed to, so
+    // nobody can slip a different record into our ledger
+    pub fn settle(&mut self, record: LoanRecord) -> bool {
+        let index = self
+            .pending
+            .iter()
+            .position(|r| unsigned(r) == unsigned(&record));
+        match index {
+            Some(index) if lender_signed(&record) && borrower_signed(&record) => {
+                self.pending.remove(index);
+                self.entries.push(record);
+                self.save();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // stores a record we just countersigned
+    pub fn append(&mut self, record: LoanRecord) {
+        self.entries.push(record);
+        self.save();
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &LoanRecord> {
+        self.entries.iter()
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &LoanRecord> {
+        self.pending.iter()
+    }
+
+    // agreed loans that haven't come back, with their latest due date
+    pub fn out(&self) -> Vec<(&LoanRecord, Option<u64>)> {
+        self.entries
+            .iter()
+            .filter(|r| r.event == LoanEvent::Lent && !self.returned(r))
+            .map(|lent| (lent, self.due(lent)))
+            .collect()
+    }
+
+    // extensions replace the due date the loan started with
+    pub fn due(&self, lent: &LoanRecord) -> Option<u64> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|r| same_loan(r, lent) && r.event == LoanEvent::Extended)
+            .map_or(lent.due, |extended| extended.due)
+    }
+
+    // copies of one of our books that are out with borrowers
+    pub fn lent_out(&self, us: &str, book: usize) -> u32 {
+        let out = self.out();
+        out.iter().filter(|(lent, _)| lent.lender == us && lent.book == book).count() as u32
+    }
+
+    fn returned(&self, lent: &LoanRecord) -> bool {
+        self.entries
+            .iter()
+            .any(|r| same_loan(r, lent) && r.event == LoanEvent::Returned)
+    }
+
+    // the agreed loan with this id, unless it came back already
+    pub fn outstanding(&self, id: u32) -> Option<&LoanRecord> {
+        self.entries
+            .iter()
+            .find(|r| r.id == id && r.event == LoanEvent::Lent && !self.returned(r))
+    }
+
+    // the agreed loan a return or an extension is about, with both sides'
+    // signatures still on it. None for one between other peers, or for
+    // another book
+    pub fn outstanding_for(&self, record: &LoanRecord) -> Option<&LoanRecord> {
+        self.entries.iter().find(|r| {
+            r.event == LoanEvent::Lent
+                && same_loan(r, record)
+                && !self.returned(r)
+                && lender_signed(r)
+                && borrower_signed(r)
+        })
+    }
+}
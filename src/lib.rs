@@ -0,0 +1,35 @@
+// the parts of a node that touch neither the network nor the disk: the
+// messages peers exchange and the catalog rules. they only depend on serde,
+// serde_json and regex, so this library also builds for wasm32 and can be
+// shared with a browser peer. logging and the library file's schema, which
+// write files, the book protocol's streams, which need libp2p, and config
+// comparisons, which need toml, are left out of wasm32 builds
+pub mod protocol;
+// fielded searches over catalogs, run by the responder
+pub mod query;
+// the catalog and book details a request gets
+pub mod respond;
+// books to and from citation managers
+pub mod bibtex;
+// reading lists exported from Goodreads and StoryGraph
+pub mod goodreads;
+// what a book's file really is, whatever its name says
+pub mod formats;
+// which downloaded files go when they reach their disk cap
+pub mod eviction;
+// the library file's versions and how older ones are read
+#[cfg(not(target_arch = "wasm32"))]
+pub mod schema;
+// log files and sinks for the node binaries and programs embedding them
+#[cfg(not(target_arch = "wasm32"))]
+pub mod logging;
+// one book's details, asked of a peer over a stream of their own
+#[cfg(not(target_arch = "wasm32"))]
+pub mod details;
+// which settings changed when a node's config file is read again
+#[cfg(not(target_arch = "wasm32"))]
+pub mod settings;
+
+// virtual nodes on a virtual clock, for reproducible protocol tests
+#[cfg(feature = "simulation")]
+pub mod sim;
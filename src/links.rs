@@ -0,0 +1,82 @@
+use crate::store;
+use crate::unix_time;
+use data_encoding::BASE64URL_NOPAD;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+pub const LINKS_PATH: &str = "./links.json";
+
+// a download link for one book's file. the token is the only credential, so
+// it is long and random, and it stops working once it expires
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Link {
+    pub book: usize,
+    pub expires: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Links {
+    links: BTreeMap<String, Link>,
+}
+
+impl Links {
+    // read again for every download, the api runs apart from the command loop
+    pub fn load() -> Self {
+        store::load(LINKS_PATH)
+    }
+
+    fn save(&self) {
+        if let Err(e) = store::save_private(LINKS_PATH, self) {
+            error!("unable to save links: {}", e);
+        }
+    }
+
+    // a new token for the book, expired ones are dropped on the way
+    pub fn create(&mut self, book: usize, valid_for: u64) -> String {
+        let now = unix_time();
+        self.links.retain(|_, link| link.expires > now);
+        let token = BASE64URL_NOPAD.encode(&rand::random::<[u8; 24]>());
+        self.links.insert(
+            token.clone(),
+            Link {
+                book,
+                expires: now + valid_for,
+            },
+        );
+        self.save();
+        token
+    }
+
+    pub fn book(&self, token: &str) -> Option<usize> {
+        let link = self.links.get(token)?;
+        if link.expires <= unix_time() {
+            return None;
+        }
+        Some(link.book)
+    }
+
+    // drops expired links and those to books that have no file, returns how
+    // many went
+    pub fn prune(&mut self, books: &HashSet<usize>) -> usize {
+        let now = unix_time();
+        let before = self.links.len();
+        self.links.retain(|_, link| link.expires > now && books.contains(&link.book));
+        let pruned = before - self.links.len();
+        if pruned > 0 {
+            self.save();
+        }
+        pruned
+    }
+
+    // how many links to the book stopped working
+    pub fn revoke(&mut self, book: usize) -> usize {
+        let before = self.links.len();
+        self.links.retain(|_, link| link.book != book);
+        let revoked = before - self.links.len();
+        if revoked > 0 {
+            self.save();
+        }
+        revoked
+    }
+}
@@ -0,0 +1,51 @@
+use crate::peers::PeerStore;
+use crate::unix_time;
+use libp2p::PeerId;
+use std::collections::HashMap;
+
+// three missed presence heartbeats
+const STALE_AFTER: u64 = 3 * 60;
+
+pub enum State {
+    Connected,
+    // seconds since we last heard from it
+    RecentlySeen(u64),
+    Gone(u64),
+}
+
+// when each peer last showed signs of life: a connection, identify or any
+// message, even relayed through others. unlike mdns this doesn't depend on
+// the peer being on our lan
+#[derive(Debug, Default)]
+pub struct Liveness {
+    heard: HashMap<PeerId, u64>,
+}
+
+impl Liveness {
+    // peers from earlier runs start out as gone since they were last connected
+    pub fn new(store: &PeerStore) -> Self {
+        Liveness {
+            heard: store.last_seen().collect(),
+        }
+    }
+
+    pub fn heard(&mut self, peer: PeerId) {
+        self.heard.insert(peer, unix_time());
+    }
+
+    pub fn state(&self, peer: &PeerId, connected: bool) -> Option<State> {
+        if connected {
+            return Some(State::Connected);
+        }
+        let ago = unix_time().saturating_sub(*self.heard.get(peer)?);
+        if ago < STALE_AFTER {
+            Some(State::RecentlySeen(ago))
+        } else {
+            Some(State::Gone(ago))
+        }
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.heard.keys()
+    }
+}
@@ -0,0 +1,207 @@
+// where log records go besides the terminal: a file of json lines, started
+// anew past a size, and any sink a program embedding the node adds
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static SINKS: Lazy<RwLock<Vec<Arc<dyn Sink>>>> = Lazy::new(Default::default);
+
+// one log record, formatted once for every sink
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    // unix time in milliseconds
+    pub time: u64,
+    pub level: Level,
+    // the module that logged it, e.g. "peer2peer::commands"
+    pub target: String,
+    pub message: String,
+}
+
+impl Entry {
+    pub fn from_record(record: &Record) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Entry {
+            time,
+            level: record.level(),
+            target: record.target().to_owned(),
+            message: record.args().to_string(),
+        }
+    }
+
+    // e.g. {"time":1700000000000,"level":"INFO","target":"peer2peer","message":"..."}
+    pub fn to_json(&self) -> String {
+        json!({
+            "time": self.time,
+            "level": self.level.as_str(),
+            "target": self.target,
+            "message": self.message,
+        })
+        .to_string()
+    }
+}
+
+// gets every record that passes the log level. it must not log itself
+pub trait Sink: Send + Sync {
+    fn write(&self, entry: &Entry);
+
+    fn flush(&self) {}
+}
+
+// sinks can be added before or after init
+pub fn add_sink(sink: Arc<dyn Sink>) {
+    SINKS.write().unwrap().push(sink);
+}
+
+// makes the console logger, e.g. pretty_env_logger's, and the sinks the
+// logger of the process
+pub fn init(console: Box<dyn Log>, level: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_boxed_logger(Box::new(Tee { console }))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+struct Tee {
+    console: Box<dyn Log>,
+}
+
+impl Log for Tee {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.console.log(record);
+        let sinks = SINKS.read().unwrap();
+        if sinks.is_empty() {
+            return;
+        }
+        let entry = Entry::from_record(record);
+        for sink in sinks.iter() {
+            sink.write(&entry);
+        }
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        for sink in SINKS.read().unwrap().iter() {
+            sink.flush();
+        }
+    }
+}
+
+type Hook = Box<dyn Fn(&Path) + Send + Sync>;
+
+struct Open {
+    file: File,
+    written: u64,
+}
+
+// json lines appended to a file. past max_bytes the file is renamed to
+// "<path>.1", older ones move up to "<path>.<keep>" and the oldest is removed
+pub struct FileSink {
+    path: PathBuf,
+    max_bytes: Option<u64>,
+    keep: usize,
+    open: Mutex<Open>,
+    rotated: Option<Hook>,
+}
+
+impl FileSink {
+    pub fn open(path: impl Into<PathBuf>, max_bytes: Option<u64>, keep: usize) -> io::Result<Self> {
+        let path = path.into();
+        let open = Mutex::new(append(&path)?);
+        Ok(FileSink {
+            path,
+            max_bytes,
+            keep,
+            open,
+            rotated: None,
+        })
+    }
+
+    // called with the file that was just rotated out, e.g. to compress it
+    // or ship it somewhere. not called when no old files are kept
+    pub fn on_rotate(mut self, hook: impl Fn(&Path) + Send + Sync + 'static) -> Self {
+        self.rotated = Some(Box::new(hook));
+        self
+    }
+
+    // starts a new file now, whatever the size of this one
+    pub fn rotate(&self) -> io::Result<()> {
+        let rotated = self.rotate_locked(&mut self.open.lock().unwrap())?;
+        self.rotated(rotated);
+        Ok(())
+    }
+
+    // writes to a new file at the path, for after something else moved the
+    // old one away, e.g. logrotate
+    pub fn reopen(&self) -> io::Result<()> {
+        *self.open.lock().unwrap() = append(&self.path)?;
+        Ok(())
+    }
+
+    // the file it was moved to, if it was kept
+    fn rotate_locked(&self, open: &mut Open) -> io::Result<Option<PathBuf>> {
+        let numbered = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        let rotated = if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+            None
+        } else {
+            for n in (1..self.keep).rev() {
+                if numbered(n).exists() {
+                    std::fs::rename(numbered(n), numbered(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, numbered(1))?;
+            Some(numbered(1))
+        };
+        *open = append(&self.path)?;
+        Ok(rotated)
+    }
+
+    // outside the lock, a hook may well log
+    fn rotated(&self, rotated: Option<PathBuf>) {
+        if let (Some(hook), Some(path)) = (&self.rotated, rotated) {
+            hook(&path);
+        }
+    }
+}
+
+impl Sink for FileSink {
+    fn write(&self, entry: &Entry) {
+        let line = entry.to_json() + "\n";
+        let mut open = self.open.lock().unwrap();
+        // logging about a broken log file would only go round in circles
+        if open.file.write_all(line.as_bytes()).is_err() {
+            return;
+        }
+        open.written += line.len() as u64;
+        if !matches!(self.max_bytes, Some(max) if open.written >= max) {
+            return;
+        }
+        let rotated = self.rotate_locked(&mut open);
+        drop(open);
+        match rotated {
+            Ok(rotated) => self.rotated(rotated),
+            Err(e) => eprintln!("unable to rotate {}: {}", self.path.display(), e),
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self.open.lock().unwrap().file.flush();
+    }
+}
+
+fn append(path: &Path) -> io::Result<Open> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let written = file.metadata()?.len();
+    Ok(Open { file, written })
+}
@@ -0,0 +1,75 @@
+use crate::store;
+use crate::unix_time;
+use log::error;
+use peer2peer::protocol::SealedMessage;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const MAILBOX_PATH: &str = "./mailbox.json";
+// a relay is a favor to friends, not unlimited storage
+const MAX_PER_PEER: usize = 100;
+const KEEP_FOR: u64 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Held {
+    at: u64,
+    message: SealedMessage,
+}
+
+// sealed messages a relay holds for offline peers. it can't read them, only
+// pass them on once the recipient reconnects
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Mailbox {
+    held: HashMap<String, Vec<Held>>,
+}
+
+impl Mailbox {
+    pub fn load() -> Self {
+        let mut mailbox: Mailbox = store::load(MAILBOX_PATH);
+        mailbox.expire();
+        mailbox
+    }
+
+    fn save(&self) {
+        if let Err(e) = store::save(MAILBOX_PATH, self) {
+            error!("unable to save mailbox: {}", e);
+        }
+    }
+
+    fn expire(&mut self) {
+        let cutoff = unix_time().saturating_sub(KEEP_FOR);
+        for held in self.held.values_mut() {
+            held.retain(|h| h.at >= cutoff);
+        }
+        self.held.retain(|_, held| !held.is_empty());
+    }
+
+    // false when the recipient's box is full
+    pub fn hold(&mut self, message: SealedMessage) -> bool {
+        self.expire();
+        let held = self.held.entry(message.to.clone()).or_default();
+        if held.len() >= MAX_PER_PEER {
+            return false;
+        }
+        held.push(Held {
+            at: unix_time(),
+            message,
+        });
+        self.save();
+        true
+    }
+
+    pub fn take_for(&mut self, peer: &str) -> Vec<SealedMessage> {
+        match self.held.remove(peer) {
+            Some(held) => {
+                self.save();
+                held.into_iter().map(|h| h.message).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.held.values().map(Vec::len).sum()
+    }
+}
@@ -1,86 +1,688 @@
+use crate::acks::Acks;
+use crate::activity::Activity;
+use crate::api::ApiRequest;
+use crate::audit::Access;
+use crate::bookmarks::Bookmarks;
+use crate::cache::CatalogCache;
+use crate::clubs::{Clubs, Merge};
+use crate::ledger::Ledger;
 use crate::commands::{
-    handle_add_book, handle_list_books, handle_list_peers, handle_share_book,
-    respond_with_public_books,
+    expire_shares, handle_accept_invite, handle_activity, handle_add_book, handle_attach,
+    handle_audit, handle_bandwidth, handle_bookmark, handle_cache, handle_cancel, handle_club,
+    handle_condition, handle_conflicts, handle_copies, handle_debug, handle_dedup, handle_devices,
+    handle_download, handle_downloads, handle_export, handle_forget_me, handle_fsck, handle_group,
+    handle_hide, handle_history, handle_import, handle_invite, handle_join_channel,
+    handle_leave_channel, handle_lend, handle_link, handle_list_bookmarks, handle_list_books,
+    handle_list_channels, handle_list_clubs, handle_list_groups, handle_list_loans,
+    handle_list_peers, handle_list_pins, handle_loan, handle_loans, handle_merge,
+    handle_missing_volumes, handle_msg, handle_node, handle_peer_scores, handle_ping, handle_policy,
+    handle_power, handle_presence, handle_queue, handle_quota, handle_rate, handle_recommend,
+    handle_reputation, handle_requests, handle_review, handle_restore, handle_revoke,
+    handle_rm_book, handle_rm_books, handle_rotate_key, handle_say, handle_search, handle_series,
+    handle_share_all, handle_share_book, handle_shelve, handle_show_book, handle_silent,
+    handle_snapshot, handle_status, handle_telemetry, handle_trash, handle_trust, handle_undo,
+    handle_unlink, match_wishlist, merge_from_device, purge_trash, read_local_library,
+    respond_with_book, respond_with_public_books, send_library_to_devices, show_summary, Requester,
 };
 use libp2p::{
-    core::upgrade,
+    bandwidth::BandwidthSinks,
+    core::{either::EitherTransport, upgrade, ConnectedPoint},
+    dns::TokioDnsConfig,
     floodsub::{Floodsub, FloodsubEvent, Topic},
+    identify::{Identify, IdentifyConfig, IdentifyEvent},
     identity,
-    mdns::{Mdns, MdnsEvent},
+    mdns::{Mdns, MdnsConfig, MdnsEvent},
     mplex,
     noise::{Keypair, NoiseConfig, X25519Spec},
+    ping,
     futures::StreamExt,
-    swarm::{NetworkBehaviourEventProcess, Swarm, SwarmBuilder},
+    pnet::{PnetConfig, PreSharedKey},
+    rendezvous,
+    request_response::{RequestId, RequestResponseEvent, RequestResponseMessage, ResponseChannel},
+    multiaddr::Protocol,
+    swarm::{
+        behaviour::toggle::Toggle, dial_opts::DialOpts, AddressScore,
+        NetworkBehaviourEventProcess, Swarm, SwarmBuilder, SwarmEvent,
+    },
     tcp::TokioTcpConfig,
-    NetworkBehaviour, PeerId, Transport,
+    Multiaddr, NetworkBehaviour, PeerId, Transport, TransportExt,
 };
-use log::{error, info};
+use crate::config::{Audience, PolicyConfig, CONFIG, CONFIG_PATH};
+use crate::connections::Connections;
+use crate::groups::Groups;
+use crate::hubs::Hubs;
+use crate::inflight::{Answer, InFlight, Kind};
+use crate::invite::Invites;
+use crate::liveness::Liveness;
+use crate::mailbox::Mailbox;
+use crate::outbox::Outbox;
+use crate::peers::PeerStore;
+use crate::pins::Pins;
+use crate::power::Power;
+use crate::presence::Presences;
+use crate::pruning::Pruner;
+use crate::pubsub::Pubsub;
+use crate::quota::Quotas;
+use crate::reconnect::Reconnector;
+use crate::reload::Reloader;
+use crate::reputation::Reputation;
+use crate::resume::Resume;
+use crate::rotation::Rotations;
+use crate::scoring::PeerScores;
+use crate::supernode::Supernode;
+use crate::sync::{DeviceSync, Replica};
+use crate::telemetry::Telemetry;
+use crate::systemd::Watchdog;
+use crate::traces::{Requests, Span};
+use crate::traffic::TrafficStats;
+use log::{debug, error, info};
 use once_cell::sync::Lazy;
-use serde::{Deserialize, Serialize};
-use tokio::{sync::mpsc, io::AsyncBufReadExt};
+use peer2peer::details::{self, Details};
+use peer2peer::logging::{self, FileSink};
+use peer2peer::protocol::{
+    advertised_agent_version, agent_version, decode, encode, BookDetail, BookRequest, ClubState,
+    LoanEvent, LoanRecord, named_agent_version, parse_advert, parse_capabilities, parse_name,
+    public_catalog, valid_name, valid_title, Advert, Book, ChatMessage, Library, ListMode,
+    ListRequest, ListResponse, Message, Nack, NackReason, Relayed, SealedMessage, HIDEABLE,
+    MAX_TITLE,
+};
+use peer2peer::query::Query;
+use peer2peer::respond::{self, Catalog};
+use peer2peer::schema;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::num::NonZeroU32;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader, Lines, Stdin},
+    sync::mpsc,
+    time,
+};
+mod acks;
+mod activity;
+mod api;
+mod archive;
+mod audit;
+mod beacon;
+mod bookmarks;
+mod bulk;
+mod bundle;
+mod cache;
+mod checksums;
+mod clock;
+mod clubs;
 mod commands;
+mod config;
+mod conflicts;
+mod connections;
+mod datadir;
+mod debug;
+mod deletions;
+mod downloads;
+mod forget;
+mod fsck;
+mod groups;
+mod health;
+mod history;
+mod hubs;
+mod inflight;
+mod invite;
+mod ipfs;
+mod keyring;
+mod keys;
+mod ledger;
+mod links;
+mod liveness;
+mod mailbox;
+mod merge;
+mod mqtt;
+mod notify;
+mod nat;
+mod operations;
+mod outbox;
+mod peers;
+mod pins;
+mod power;
+mod presence;
+mod progress;
+mod pruning;
+mod pubsub;
+mod quota;
+mod ratings;
+mod recommend;
+mod reconnect;
+mod reload;
+mod reputation;
+mod resume;
+mod rotation;
+mod scoring;
+mod sealing;
+mod series;
+mod snapshots;
+mod socks;
+mod store;
+mod supernode;
+mod sync;
+mod systemd;
+mod telemetry;
+mod tombstone;
+mod traces;
+mod traffic;
 
-const STORAGE_PATH: &str = "./library.json";
-type Library = Vec<Book>;
+pub const STORAGE_PATH: &str = "./library.json";
+// floodsub peers drop frames over 2048 bytes, this leaves room for the
+// sender, sequence number and topic around the message
+const MAX_RESPONSE_SIZE: usize = 1900;
+// seconds a catalog waits for a requester that dropped off, after that the
+// books may have changed and it's better to ask again
+const RESPONSE_TTL: u64 = 600;
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
 
 // lazy static constants
-static KEYS: Lazy<identity::Keypair> = Lazy::new(|| identity::Keypair::generate_ed25519());
+static KEYS: Lazy<identity::Keypair> = Lazy::new(keys::loaded);
 static PEER_ID: Lazy<PeerId> = Lazy::new(|| PeerId::from(KEYS.public()));
-static TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("library"));
+static INVITES: Lazy<Invites> = Lazy::new(Invites::load);
+// the config's key file wins over one that came with an invite
+static PSK: Lazy<Option<PreSharedKey>> = Lazy::new(|| match CONFIG.psk_file.as_ref() {
+    Some(path) => Some(
+        std::fs::read_to_string(path)
+            .expect("unable to read psk file")
+            .parse()
+            .expect("unable to parse psk file"),
+    ),
+    None => INVITES.psk(),
+});
+static TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new(topic_name()));
+static RENDEZVOUS_POINTS: Lazy<Vec<(PeerId, Multiaddr)>> = Lazy::new(|| CONFIG.rendezvous_points());
+static RELAYS: Lazy<Vec<(PeerId, Multiaddr)>> = Lazy::new(|| CONFIG.relay_peers());
+// rendezvous points, relays and peers whose invite we accepted are dialed and kept connected like any other bootstrap peer
+static BOOTSTRAP: Lazy<Vec<(PeerId, Multiaddr)>> = Lazy::new(|| {
+    let mut peers = CONFIG.bootstrap_peers();
+    peers.extend(INVITES.bootstrap_peers());
+    peers.extend(RENDEZVOUS_POINTS.iter().cloned());
+    peers.extend(RELAYS.iter().cloned());
+    peers
+});
+
+// nodes only see each other's messages when they agree on the topic
+fn topic_name() -> String {
+    match (CONFIG.network.as_ref().or_else(|| INVITES.network()), PSK.as_ref()) {
+        (Some(network), _) => format!("library/{}", network),
+        (None, Some(psk)) => format!("library/{}", psk.fingerprint()),
+        (None, None) => "library".to_owned(),
+    }
+}
+
+pub fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("system clock is before 1970")
+        .as_secs()
+}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Book {
-    id: usize,
-    title: String,
-    author: String,
-    publisher: String,
-    public: bool,
+// channels are sub-topics of the network topic, so they never leak across networks
+fn channel_topic(channel: &str) -> Topic {
+    Topic::new(format!("{}/{}", TOPIC.id(), channel))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-enum ListMode {
-    ALL,
-    One(String),
+// book clubs get a sub-topic of their own, apart from channels of the same name
+fn club_topic(club: &str) -> Topic {
+    Topic::new(format!("{}/club/{}", TOPIC.id(), club))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ListRequest {
-    mode: ListMode,
+fn club_of(topic: &Topic) -> Option<&str> {
+    topic.id().strip_prefix(TOPIC.id())?.strip_prefix("/club/")
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ListResponse {
-    mode: ListMode,
-    data: Library,
-    receiver: String,
+// heartbeats stay off the main topic, older nodes never subscribe to it
+fn presence_topic() -> Topic {
+    Topic::new(format!("{}#presence", TOPIC.id()))
 }
 
+// peers learn our nickname, title and how many books everyone may see from
+// identify. the count is that of the start, identify can't change it later
+fn own_agent_version(books: Option<usize>) -> String {
+    let version = env!("CARGO_PKG_VERSION");
+    let title = match CONFIG.title {
+        Some(ref title) if valid_title(title) => Some(title.clone()),
+        Some(ref title) => {
+            error!("ignoring title {}, use up to {} printable characters", title, MAX_TITLE);
+            None
+        }
+        None => None,
+    };
+    let agent = match CONFIG.name.as_deref() {
+        Some(name) if valid_name(name) => named_agent_version(version, name),
+        Some(name) => {
+            error!("ignoring name {}, use up to 32 letters, digits, '-', '_' or '.'", name);
+            agent_version(version)
+        }
+        None => agent_version(version),
+    };
+    advertised_agent_version(&agent, &Advert { title, books })
+}
+
+// what goes back to a list request: the catalog, or why there won't be one
+type Reply = std::result::Result<Catalog, Nack>;
+
 enum EventType {
-    Response(ListResponse),
+    Response((Topic, Reply)),
     Input(String),
+    Api(ApiRequest),
+    ExternalAddr(Multiaddr),
+    Discovered(PeerId, Vec<Multiaddr>),
+    Subscribed(PeerId),
+    SyncOut(Replica, bool),
+    Tombstone(Vec<usize>),
+    Outgoing(Box<(Topic, Message)>),
+    BookReply(Box<(ResponseChannel<BookDetail>, BookDetail)>),
+    Tick,
 }
 
 #[derive(NetworkBehaviour)]
 #[behaviour(event_process = true)]
 pub struct BookBehavior {
-    floodsub: Floodsub,
+    floodsub: Pubsub,
     mdns: Mdns,
+    // mdns speaks either ipv4 or ipv6, so a second instance covers ipv6 interfaces
+    mdns6: Toggle<Mdns>,
+    rendezvous: rendezvous::client::Behaviour,
+    rendezvous_server: Toggle<rendezvous::server::Behaviour>,
+    identify: Identify,
+    ping: ping::Behaviour,
+    // one book's details, asked of and answered to a single peer
+    details: Details,
+    // responses are published on the topic the request arrived on
+    #[behaviour(ignore)]
+    response_sender: mpsc::UnboundedSender<(Topic, Reply)>,
+    // latest public books received from each peer, keyed by peer id
+    #[behaviour(ignore)]
+    remote_catalogs: CatalogCache,
+    // channels joined on top of the main topic
+    #[behaviour(ignore)]
+    channels: BTreeSet<String>,
+    #[behaviour(ignore)]
+    clubs: Clubs,
+    #[behaviour(ignore)]
+    peer_store: PeerStore,
+    #[behaviour(ignore)]
+    reconnect: Reconnector,
+    #[behaviour(ignore)]
+    pruner: Pruner,
+    #[behaviour(ignore)]
+    silent: bool,
+    #[behaviour(ignore)]
+    policy: PolicyConfig,
+    #[behaviour(ignore)]
+    quotas: Quotas,
+    #[behaviour(ignore)]
+    reputation: Reputation,
+    #[behaviour(ignore)]
+    scores: PeerScores,
+    #[behaviour(ignore)]
+    telemetry: Telemetry,
+    #[behaviour(ignore)]
+    traffic: TrafficStats,
+    // raw bytes through all connections this run, protocol overhead included
+    #[behaviour(ignore)]
+    wire: Arc<BandwidthSinks>,
+    // what each identified peer says it understands
     #[behaviour(ignore)]
-    response_sender: mpsc::UnboundedSender<ListResponse>,
+    capabilities: HashMap<PeerId, BTreeSet<String>>,
+    // nicknames peers announced, and those announcing one pinned to someone else
+    #[behaviour(ignore)]
+    names: HashMap<PeerId, String>,
+    // titles and book counts peers announced
+    #[behaviour(ignore)]
+    adverts: HashMap<PeerId, Advert>,
+    #[behaviour(ignore)]
+    impostors: HashSet<PeerId>,
+    #[behaviour(ignore)]
+    presence: Presences,
+    #[behaviour(ignore)]
+    liveness: Liveness,
+    // endpoints, protocols and latency of connected peers
+    #[behaviour(ignore)]
+    connections: Connections,
+    // set once a port mapping task is running, it reports back on the sender
+    #[behaviour(ignore)]
+    port_mapping: Option<mpsc::UnboundedSender<Multiaddr>>,
+    // set once the lan beacon is running, it reports peers it hears about
+    #[behaviour(ignore)]
+    beacon: Option<mpsc::UnboundedSender<(PeerId, Vec<Multiaddr>)>>,
+    #[behaviour(ignore)]
+    hubs: Hubs,
+    // peers found by rendezvous are dialed from the event loop
+    #[behaviour(ignore)]
+    discovered: mpsc::UnboundedSender<(PeerId, Vec<Multiaddr>)>,
+    // messages waiting for their peer to come back
+    #[behaviour(ignore)]
+    outbox: Outbox,
+    // peers that may have messages waiting for them, see deliver_queued
+    #[behaviour(ignore)]
+    subscribed: mpsc::UnboundedSender<PeerId>,
+    // set when serving as a relay for others
+    #[behaviour(ignore)]
+    mailbox: Option<Mailbox>,
+    // set when keeping consenting peers' catalogs for searches
+    #[behaviour(ignore)]
+    supernode: Option<Supernode>,
+    // set when paired with our own other devices
+    #[behaviour(ignore)]
+    sync: Option<DeviceSync>,
+    // libraries to send to our devices, true when it has to go out even if unchanged
+    #[behaviour(ignore)]
+    sync_sender: mpsc::UnboundedSender<(Replica, bool)>,
+    // ids of books we stopped offering, announced from the event loop
+    #[behaviour(ignore)]
+    tombstones: mpsc::UnboundedSender<Vec<usize>>,
+    // replies published from the event loop, where traffic is counted
+    #[behaviour(ignore)]
+    outgoing: mpsc::UnboundedSender<(Topic, Message)>,
+    // book details we looked up, sent back from the event loop
+    #[behaviour(ignore)]
+    book_replies: mpsc::UnboundedSender<(ResponseChannel<BookDetail>, BookDetail)>,
+    // the book each of our book requests asked for
+    #[behaviour(ignore)]
+    book_requests: HashMap<RequestId, usize>,
+    // direct messages waiting to be confirmed
+    #[behaviour(ignore)]
+    acks: Acks,
+    // requests sent with a trace, see traces.rs
+    #[behaviour(ignore)]
+    traces: Requests,
+    // queries waiting for answers, see inflight.rs
+    #[behaviour(ignore)]
+    inflight: InFlight,
+    // picks up edits to config.toml, see reload.rs
+    #[behaviour(ignore)]
+    reloader: Reloader,
+    // set when systemd expects to hear from us, see systemd.rs
+    #[behaviour(ignore)]
+    watchdog: Watchdog,
+    #[behaviour(ignore)]
+    power: Power,
+    #[behaviour(ignore)]
+    resume: Resume,
+}
+
+impl BookBehavior {
+    fn discovered_peers(&self) -> BTreeSet<PeerId> {
+        let mut peers: BTreeSet<PeerId> = self.mdns.discovered_nodes().copied().collect();
+        if let Some(mdns6) = self.mdns6.as_ref() {
+            peers.extend(mdns6.discovered_nodes());
+        }
+        peers
+    }
+
+    fn mdns_has_node(&self, peer: &PeerId) -> bool {
+        self.mdns.has_node(peer) || matches!(self.mdns6.as_ref(), Some(m) if m.has_node(peer))
+    }
+
+    // peers that never identified are older nodes and only get the basics
+    fn supports(&self, peer: &PeerId, capability: &str) -> bool {
+        matches!(self.capabilities.get(peer), Some(caps) if caps.contains(capability))
+    }
+
+    // a catalog we asked for, sent in the clear or sealed to us. `authentic`
+    // when it came straight from `from` or was sealed by it, a relayed one
+    // could have been written by anyone
+    fn received_catalog(&mut self, from: PeerId, res: ListResponse, authentic: bool) {
+        if self.impostors.contains(&from) {
+            error!("ignoring catalog from {}, not trusted yet", from);
+            return;
+        }
+        let source = from.to_string();
+        let kind = match res.query {
+            Some(ref text) => Kind::Search(text.clone()),
+            None => Kind::Catalog,
+        };
+        let answer = match res.summary {
+            Some(ref summary) => Answer::Count(summary.total),
+            None => Answer::Books(&res.data),
+        };
+        if !self.inflight.answered(&source, &kind, answer) {
+            debug!("dropping an answer from {} that nobody is waiting for", source);
+            return;
+        }
+        self.interacted(&from);
+        self.traces.answered(res.trace.as_deref(), "catalog", &source);
+        if let Some(ref summary) = res.summary {
+            let source = match res.query {
+                Some(ref text) => format!("{} for \"{}\"", from, text),
+                None => from.to_string(),
+            };
+            show_summary(&source, summary);
+            return;
+        }
+        if let Some(ref text) = res.query {
+            show_relayed(&from, &res.relayed);
+            show_matches(&from, text, res.data, self.remote_catalogs.all());
+            return;
+        }
+        // only versions the peer vouched for itself are kept, a forged high
+        // one would hide every real catalog after it
+        let version = res.version.filter(|_| authentic);
+        if self.remote_catalogs.stale(&source, version) {
+            info!("ignoring an older catalog from {} that arrived late", source);
+            return;
+        }
+        info!("response from {}:", from);
+        res.data.iter().for_each(|r| info!("{:?}", r));
+        if let Some(supernode) = self.supernode.as_mut() {
+            supernode.received(&source, res.rehost == Some(true), &res.data);
+        }
+        activity::record(Activity::CatalogReceived {
+            peer: from.to_string(),
+            books: res.data.len(),
+        });
+        Bookmarks::load().fill_in(&from.to_string(), &res.data);
+        // only books new in their catalog, so a match is told once
+        let previous = self.remote_catalogs.peek(&from.to_string());
+        let known: HashSet<_> =
+            previous.into_iter().flatten().map(Book::key).collect();
+        let new = res.data.iter().filter(|b| !known.contains(&b.key())).cloned();
+        tokio::spawn(match_wishlist(from.to_string(), new.collect()));
+        self.remote_catalogs.insert(source, res.data, version);
+    }
+
+    // a catalog meant for this peer alone can be sealed to it when it says
+    // it opens them
+    fn requester(&self, peer: &PeerId) -> Requester {
+        Requester {
+            peer: peer.to_string(),
+            sealable: self.supports(peer, "sealed-catalogs") && sealing::public_key(peer).is_some(),
+        }
+    }
+
+    // a supernode answers a search with what it keeps for peers that aren't
+    // connected, the requester's own books aside
+    fn relayed_matches(
+        &self,
+        requester: &PeerId,
+        query: &Option<Query>,
+        req: &ListRequest,
+    ) -> Vec<Relayed> {
+        match (&self.supernode, query, req.summary) {
+            (Some(supernode), Some(query), None) => supernode.matches(query, |peer| {
+                peer == requester.to_string()
+                    || matches!(peer.parse::<PeerId>(), Ok(p) if self.connections.get(&p).is_some())
+            }),
+            _ => Vec::new(),
+        }
+    }
+
+    // what we keep about a peer that asked to be forgotten. its reputation
+    // and what the audit log and the ledger say stay, they're our own record
+    fn forget(&mut self, peer: &PeerId) -> Vec<String> {
+        let id = peer.to_string();
+        let mut dropped = Vec::new();
+        if self.remote_catalogs.remove(&id) {
+            dropped.push("catalog".to_owned());
+        }
+        if let Some(supernode) = self.supernode.as_mut() {
+            if supernode.forget(&id) {
+                dropped.push("aggregated catalog".to_owned());
+            }
+        }
+        let titles = Bookmarks::load().forget_titles(&id);
+        if titles > 0 {
+            dropped.push(format!("{} bookmarked titles", titles));
+        }
+        if self.peer_store.forget(peer) {
+            dropped.push("addresses".to_owned());
+        }
+        if self.presence.forget(peer) {
+            dropped.push("presence".to_owned());
+        }
+        if self.traffic.forget(&id) {
+            dropped.push("traffic counts".to_owned());
+        }
+        let pinned = Pins::load().forget(&id);
+        if self.names.remove(peer).is_some() || pinned {
+            dropped.push("nickname".to_owned());
+        }
+        dropped
+    }
+
+    fn refuse(&self, topic: &Topic, peer: &PeerId, reason: NackReason, retry_after: Option<u64>) {
+        let nack = Nack {
+            receiver: peer.to_string(),
+            reason,
+            retry_after,
+        };
+        let _ = self.response_sender.send((topic.clone(), Err(nack)));
+    }
+
+    fn hold_for_recipient(&mut self, depositor: &PeerId, direct: bool, message: SealedMessage) {
+        let mailbox = match self.mailbox.as_mut() {
+            Some(mailbox) => mailbox,
+            None => {
+                debug!("not a relay, ignoring deposit from {}", depositor);
+                return;
+            }
+        };
+        // only the sender may deposit its own messages
+        if message.from != depositor.to_string() || !sealing::verify(&message) {
+            debug!("rejecting forged deposit from {}", depositor);
+            if direct {
+                self.scores.invalid(depositor);
+                self.reputation.invalid(&depositor.to_string());
+            }
+            return;
+        }
+        let to = message.to.clone();
+        if !mailbox.hold(message) {
+            info!("mailbox for {} is full, dropping message from {}", to, depositor);
+            return;
+        }
+        info!("holding a sealed message from {} for {}", depositor, to);
+        // the recipient may be connected already
+        if let Ok(peer) = to.parse() {
+            let _ = self.subscribed.send(peer);
+        }
+    }
+
+    // a known nickname under another peer id isn't trusted until `trust <peer id>`
+    fn check_name(&mut self, peer: PeerId, name: String) {
+        // identify repeats itself, warn once
+        if let Some(pinned) = Pins::load().conflict(&name, &peer.to_string()) {
+            if self.impostors.insert(peer) {
+                error!("WARNING: {} calls itself {}, but {} is the {} you know", peer, name, pinned, name);
+                error!("its catalog is ignored and it isn't served until you run: trust {}", peer);
+            }
+        }
+        self.names.insert(peer, name);
+    }
+
+    // pins a peer's nickname the first time we deal with it
+    fn interacted(&mut self, peer: &PeerId) {
+        if self.impostors.contains(peer) {
+            return;
+        }
+        if let Some(name) = self.names.get(peer) {
+            let mut pins = Pins::load();
+            if pins.conflict(name, &peer.to_string()).is_none() && pins.pin(name, &peer.to_string()) {
+                info!("pinned {} to {}", name, peer);
+            }
+        }
+    }
+
+    // in silent mode only targeted requests from the allowed group are answered
+    fn should_answer(&self, mode: &ListMode, requester: &PeerId) -> bool {
+        if !self.silent {
+            return true;
+        }
+        match (mode, &CONFIG.silent.allow_group) {
+            (ListMode::One(_), Some(group)) => Groups::load().contains(group, &requester.to_string()),
+            _ => false,
+        }
+    }
+
+    // whether the response policy lets this requester have an answer
+    fn allowed_by_policy(&self, mode: &ListMode, requester: &PeerId) -> bool {
+        let audience = match mode {
+            ListMode::ALL => self.policy.list_all,
+            ListMode::One(_) => self.policy.list_one,
+        };
+        match audience {
+            Audience::Anyone => true,
+            Audience::Friends => {
+                let requester = requester.to_string();
+                Groups::load().iter().any(|(_, members)| members.contains(&requester))
+            }
+            Audience::Nobody => false,
+        }
+    }
+
+    // silent mode, the response policy, trust and quotas, in that order. a
+    // refusal is audited, and the requester told why when that's no secret
+    fn admit(&mut self, topic: &Topic, mode: &ListMode, source: &PeerId) -> bool {
+        let requester = source.to_string();
+        let refused = |reason: &str| {
+            audit::record(&requester, Access::Refused { reason: reason.to_owned() })
+        };
+        if !self.should_answer(mode, source) {
+            debug!("silent, ignoring {:?} from {}", mode, source);
+            refused("silent");
+            return false;
+        }
+        if !self.allowed_by_policy(mode, source) {
+            debug!("policy, ignoring {:?} from {}", mode, source);
+            refused("policy");
+            return false;
+        }
+        if self.impostors.contains(source) {
+            error!("not answering {}, not trusted yet", source);
+            refused("not trusted");
+            self.refuse(topic, source, NackReason::NotTrusted, None);
+            return false;
+        }
+        let factor = self.reputation.quota_factor(&requester);
+        if !self.quotas.allow_response(&requester, factor) {
+            info!("{} is over its quota, not answering", source);
+            refused("over quota");
+            let retry_after = self.quotas.retry_after(&requester);
+            self.refuse(topic, source, NackReason::RateLimited, retry_after);
+            return false;
+        }
+        true
+    }
 }
 
 impl NetworkBehaviourEventProcess<MdnsEvent> for BookBehavior {
     fn inject_event(&mut self, event: MdnsEvent) {
         match event {
             MdnsEvent::Discovered(discovered_list) => {
-                for (peer, _addr) in discovered_list {
+                for (peer, addr) in discovered_list {
+                    self.peer_store.record(&peer, &addr);
                     self.floodsub.add_node_to_partial_view(peer);
                 }
             }
             MdnsEvent::Expired(expired_list) => {
                 for (peer, _addr) in expired_list {
-                    if !self.mdns.has_node(&peer) {
+                    if !self.mdns_has_node(&peer) {
                         self.floodsub.remove_node_from_partial_view(&peer);
                     }
                 }
@@ -89,49 +691,1220 @@ impl NetworkBehaviourEventProcess<MdnsEvent> for BookBehavior {
     }
 }
 
+impl NetworkBehaviourEventProcess<IdentifyEvent> for BookBehavior {
+    fn inject_event(&mut self, event: IdentifyEvent) {
+        if let IdentifyEvent::Received { peer_id, info } = event {
+            self.liveness.heard(peer_id);
+            self.connections.identified(peer_id, &info);
+            match parse_capabilities(&info.agent_version) {
+                Some(caps) => {
+                    debug!("{} runs {}", peer_id, info.agent_version);
+                    self.capabilities.insert(peer_id, caps);
+                    self.telemetry.saw(peer_id, &info.agent_version);
+                    if let Some(name) = parse_name(&info.agent_version) {
+                        self.check_name(peer_id, name);
+                    }
+                    self.adverts.insert(peer_id, parse_advert(&info.agent_version));
+                }
+                None => debug!("{} is not a library node: {}", peer_id, info.agent_version),
+            }
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<RequestResponseEvent<BookRequest, BookDetail>>
+    for BookBehavior
+{
+    fn inject_event(&mut self, event: RequestResponseEvent<BookRequest, BookDetail>) {
+        match event {
+            RequestResponseEvent::Message {
+                peer,
+                message: RequestResponseMessage::Request { request, channel, .. },
+            } => {
+                if request.peer != PEER_ID.to_string() {
+                    return;
+                }
+                // as targeted as "ls books <peer id>", so the same rules apply
+                let mode = ListMode::One(request.peer);
+                if !self.admit(&TOPIC, &mode, &peer) {
+                    return;
+                }
+                self.interacted(&peer);
+                info!("request for book {} from {}", request.id, peer);
+                let source = peer.to_string();
+                let span = Span::serve("book", request.trace.as_deref(), &source);
+                let sender = self.book_replies.clone();
+                respond_with_book(sender, source, channel, request.id, span);
+            }
+            RequestResponseEvent::Message {
+                peer,
+                message: RequestResponseMessage::Response { request_id, response },
+            } => {
+                let asked = self.book_requests.remove(&request_id);
+                if asked != Some(response.id) || response.receiver != PEER_ID.to_string() {
+                    error!("ignoring a book from {} that wasn't asked for", peer);
+                    return;
+                }
+                if self.impostors.contains(&peer) {
+                    error!("ignoring book from {}, not trusted yet", peer);
+                    return;
+                }
+                let source = peer.to_string();
+                let answer = Answer::Books(response.book.as_slice());
+                if !self.inflight.answered(&source, &Kind::Book(response.id), answer) {
+                    return;
+                }
+                self.interacted(&peer);
+                self.traces.answered(response.trace.as_deref(), "book", &source);
+                show_book_detail(&peer, response, self.remote_catalogs.all());
+            }
+            RequestResponseEvent::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                if let Some(id) = self.book_requests.remove(&request_id) {
+                    let source = peer.to_string();
+                    if self.inflight.answered(&source, &Kind::Book(id), Answer::Refused) {
+                        error!("{} didn't show book {}: {}", peer, id, error);
+                    }
+                }
+            }
+            RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                debug!("book request from {} failed: {}", peer, error)
+            }
+            RequestResponseEvent::ResponseSent { .. } => (),
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<ping::Event> for BookBehavior {
+    fn inject_event(&mut self, event: ping::Event) {
+        match event.result {
+            Ok(ping::Success::Ping { rtt }) => {
+                self.connections.pinged(&event.peer, rtt);
+                if self.connections.probed(&event.peer) {
+                    info!("reply from {} in {}ms", event.peer, rtt.as_millis());
+                }
+            }
+            Ok(ping::Success::Pong) => (),
+            Err(e) if self.connections.probed(&event.peer) => {
+                error!("ping to {} failed: {}", event.peer, e)
+            }
+            Err(e) => debug!("ping to {} failed: {}", event.peer, e),
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<rendezvous::client::Event> for BookBehavior {
+    fn inject_event(&mut self, event: rendezvous::client::Event) {
+        match event {
+            rendezvous::client::Event::Discovered {
+                rendezvous_node,
+                registrations,
+                cookie,
+            } => {
+                self.hubs.discovered(rendezvous_node, cookie);
+                for registration in registrations {
+                    let peer = registration.record.peer_id();
+                    if peer != *PEER_ID {
+                        info!("rendezvous found {}", peer);
+                        let _ = self
+                            .discovered
+                            .send((peer, registration.record.addresses().to_vec()));
+                    }
+                }
+            }
+            rendezvous::client::Event::Registered {
+                rendezvous_node,
+                namespace,
+                ..
+            } => info!("registered as {} at {}", namespace, rendezvous_node),
+            rendezvous::client::Event::RegisterFailed(
+                rendezvous::client::RegisterError::NoExternalAddresses,
+            ) => error!("unable to register at rendezvous point, no external address is known. \
+                set external in config.toml or enable nat port mapping"),
+            rendezvous::client::Event::RegisterFailed(e) => {
+                error!("unable to register at rendezvous point: {}", e)
+            }
+            rendezvous::client::Event::DiscoverFailed {
+                rendezvous_node,
+                error,
+                ..
+            } => error!("discovery at {} failed: {:?}", rendezvous_node, error),
+            rendezvous::client::Event::Expired { .. } => (),
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<rendezvous::server::Event> for BookBehavior {
+    fn inject_event(&mut self, event: rendezvous::server::Event) {
+        match event {
+            rendezvous::server::Event::PeerRegistered { peer, registration } => {
+                info!("{} registered as {}", peer, registration.namespace)
+            }
+            event => debug!("rendezvous server: {:?}", event),
+        }
+    }
+}
+
 impl NetworkBehaviourEventProcess<FloodsubEvent> for BookBehavior {
     fn inject_event(&mut self, event: FloodsubEvent) {
         match event {
             FloodsubEvent::Message(msg) => {
-                if let Ok(res) = serde_json::from_slice::<ListResponse>(&msg.data) {
+                self.traffic.received(&msg.source.to_string(), msg.data.len());
+                if self.scores.graylisted(&msg.source) {
+                    debug!("dropping message from graylisted {}", msg.source);
+                    return;
+                }
+                // a relayed message could name anyone as its source, only one
+                // straight from its source counts for or against it
+                let direct = self.floodsub.direct(&msg);
+                if direct && self.scores.received(&msg.source) {
+                    self.reputation.spam(&msg.source.to_string());
+                }
+                self.pruner.touch(&msg.source);
+                self.liveness.heard(msg.source);
+                let message = match decode(&msg.data) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        debug!("invalid message from {}: {}", msg.source, e);
+                        if direct {
+                            self.scores.invalid(&msg.source);
+                            self.reputation.invalid(&msg.source.to_string());
+                        }
+                        return;
+                    }
+                };
+                if direct {
+                    self.scores.valid(&msg.source);
+                }
+                if let Message::ListResponse(res) = message {
                     if res.receiver == PEER_ID.to_string() {
-                        info!("response from {}:", msg.source);
-                        res.data.iter().for_each(|r| info!("{:?}", r));
-                    } 
-                } else if let Ok(req) = serde_json::from_slice::<ListRequest>(&msg.data) {
+                        self.received_catalog(msg.source, res, direct);
+                    }
+                } else if let Message::Chat(chat) = message {
+                    match chat.to {
+                        Some(ref to) if to == &PEER_ID.to_string() => {
+                            if let Some(id) = chat.id {
+                                // acked every time it arrives, an earlier ack may have been lost
+                                let topic =
+                                    msg.topics.first().cloned().unwrap_or_else(|| TOPIC.clone());
+                                let ack = acks::sign(&KEYS, msg.source.to_string(), id);
+                                let _ = self.outgoing.send((topic, Message::Ack(ack)));
+                                if !self.acks.first_time(msg.source, id) {
+                                    return;
+                                }
+                            }
+                            info!("[direct] {}: {}", msg.source, chat.text);
+                            self.interacted(&msg.source);
+                            activity::record(Activity::DirectMessage {
+                                peer: msg.source.to_string(),
+                            });
+                        }
+                        Some(_) => (),
+                        None => {
+                            let topic = msg.topics.first().cloned().unwrap_or_else(|| TOPIC.clone());
+                            if topic == *TOPIC {
+                                info!("{}: {}", msg.source, chat.text);
+                            } else if let Some(club) = club_of(&topic) {
+                                info!("[club {}] {}: {}", club, msg.source, chat.text);
+                            } else {
+                                info!("[{}] {}: {}", topic.id(), msg.source, chat.text);
+                            }
+                        }
+                    }
+                } else if let Message::Deposit(deposit) = message {
+                    if deposit.relay == PEER_ID.to_string() {
+                        self.hold_for_recipient(&msg.source, direct, deposit.message);
+                    }
+                } else if let Message::Sealed(sealed) = message {
+                    if sealed.to == PEER_ID.to_string() {
+                        match sealing::open_message(&KEYS, &sealed) {
+                            Some(Message::ListResponse(res)) => match sealed.from.parse() {
+                                Ok(from) if res.receiver == PEER_ID.to_string() => {
+                                    self.received_catalog(from, res, true)
+                                }
+                                _ => debug!("sealed catalog from {} not for us", sealed.from),
+                            },
+                            Some(Message::Chat(chat)) => {
+                                info!("[direct via relay] {}: {}", sealed.from, chat.text);
+                                activity::record(Activity::DirectMessage { peer: sealed.from });
+                            }
+                            Some(other) => debug!("unexpected sealed message {:?}", other),
+                            None => error!("unable to open sealed message from {}", sealed.from),
+                        }
+                    }
+                } else if let Message::Sync(sync) = message {
+                    // other owners sync over the same topic, theirs just don't open
+                    if let Some(remote) = self.sync.as_ref().and_then(|s| s.open(&sync)) {
+                        if archive::read_only() {
+                            debug!("read-only archive, not merging {}'s library", msg.source);
+                            return;
+                        }
+                        let sync = self.sync.as_mut().expect("sync is on");
+                        if sync.devices.insert(msg.source) {
+                            info!("your device {} is online", msg.source);
+                        }
+                        let base = sync.start_merge(&msg.source, &remote);
+                        let device = msg.source.to_string();
+                        merge_from_device(device, remote, base, self.sync_sender.clone());
+                    }
+                } else if let Message::Rotation(rotation) = message {
+                    match rotation::verify(&rotation) {
+                        Some((old, new)) => {
+                            rotation::follow(&old, &new);
+                            if self.impostors.remove(&new) {
+                                info!("{} rotated its key from {}, trusting it", new, old);
+                            }
+                        }
+                        None => {
+                            debug!("invalid key rotation from {}", msg.source);
+                            if direct {
+                                self.scores.invalid(&msg.source);
+                                self.reputation.invalid(&msg.source.to_string());
+                            }
+                        }
+                    }
+                } else if let Message::Tombstone(tombstone) = message {
+                    let from = match tombstone::verify(&tombstone) {
+                        Some(from) => from,
+                        None => {
+                            debug!("invalid or stale tombstone from {}", msg.source);
+                            return;
+                        }
+                    };
+                    let removed = self
+                        .remote_catalogs
+                        .retain(&from.to_string(), |b| !tombstone.ids.contains(&b.id));
+                    if removed > 0 {
+                        info!("{} no longer offers {} books", from, removed);
+                    }
+                } else if let Message::Forget(forget) = message {
+                    let from = match forget::verify(&forget) {
+                        Some(from) if from != *PEER_ID => from,
+                        Some(_) => return,
+                        None => {
+                            debug!("invalid or stale request to forget from {}", msg.source);
+                            return;
+                        }
+                    };
+                    let dropped = self.forget(&from);
+                    if dropped.is_empty() {
+                        debug!("{} asked to be forgotten, we held nothing", from);
+                        return;
+                    }
+                    info!("{} asked to be forgotten, dropped {}", from, dropped.join(", "));
+                    audit::record(&from.to_string(), Access::Forgotten { dropped });
+                } else if let Message::Ack(ack) = message {
+                    let ours = ack.receiver == PEER_ID.to_string();
+                    if ours && acks::from(&ack, &msg.source, direct) {
+                        if let Some(unacked) = self.acks.acked(&msg.source, ack.id) {
+                            info!("{} received your message \"{}\"", msg.source, unacked.text);
+                        }
+                    }
+                } else if let Message::Nack(nack) = message {
+                    if nack.receiver == PEER_ID.to_string() {
+                        if !self.inflight.refused(&msg.source.to_string()) {
+                            return;
+                        }
+                        error!("{} won't send its catalog: {}", msg.source, describe_nack(&nack));
+                        let failed =
+                            matches!(nack.reason, NackReason::TooLarge | NackReason::Unavailable);
+                        if direct && failed {
+                            self.reputation.failed_transfer(&msg.source.to_string());
+                        }
+                    }
+                } else if let Message::Club(state) = message {
+                    let topic = club_topic(&state.club);
+                    if !msg.topics.contains(&topic) || self.clubs.get(&state.club).is_none() {
+                        return;
+                    }
+                    // the newest state wins, one dated ahead would win every time
+                    if !clock::plausible(state.updated, "club state", &msg.source.to_string()) {
+                        return;
+                    }
+                    let club = state.club.clone();
+                    self.clubs.add_member(&club, &msg.source.to_string());
+                    match self.clubs.merge(state) {
+                        Merge::Adopted => {
+                            info!("[club {}] {} updated the club:", club, msg.source);
+                            if let Some(club) = self.clubs.get(&club) {
+                                show_club(&club.state, self.remote_catalogs.all());
+                            }
+                        }
+                        Merge::Behind(ours) => {
+                            let _ = self.outgoing.send((topic, Message::Club(ours)));
+                        }
+                        Merge::Same => (),
+                    }
+                } else if let Message::Loan(record) = message {
+                    // only the two sides of a loan exchange its records
+                    let (us, source) = (PEER_ID.to_string(), msg.source.to_string());
+                    let between_us = (record.lender == us && record.borrower == source)
+                        || (record.borrower == us && record.lender == source);
+                    if between_us && archive::read_only() {
+                        info!("declined loan {} from {}, this is an archive", record.id, source);
+                    } else if between_us {
+                        self.interacted(&msg.source);
+                        receive_loan(&msg.source, record, &mut self.reputation);
+                    }
+                } else if let Message::Presence(presence) = message {
+                    self.presence.heard(msg.source, presence);
+                } else if let Message::ListRequest(req) = message {
+                    let topic = msg.topics.first().cloned().unwrap_or_else(|| TOPIC.clone());
+                    if !respond::addressed_to(&req, &PEER_ID.to_string()) {
+                        return;
+                    }
+                    if !self.admit(&topic, &req.mode, &msg.source) {
+                        return;
+                    }
+                    let query = match req.query.as_deref().map(Query::parse).transpose() {
+                        Ok(query) => query,
+                        Err(e) => {
+                            debug!("invalid query from {}: {}", msg.source, e);
+                            let reason = "invalid query".to_owned();
+                            audit::record(&msg.source.to_string(), Access::Refused { reason });
+                            return;
+                        }
+                    };
+                    self.interacted(&msg.source);
+                    let source = msg.source.to_string();
+                    let span = Span::serve("catalog", req.trace.as_deref(), &source);
                     match req.mode {
                         ListMode::ALL => {
-                            info!("request for all: {:?} from {:?}", req, msg.source);
+                            let relayed = self.relayed_matches(&msg.source, &query, &req);
+                            info!(
+                                "request for all: {:?} from {:?} on {}",
+                                req,
+                                msg.source,
+                                topic.id()
+                            );
                             respond_with_public_books(
                                 self.response_sender.clone(),
-                                msg.source.to_string(),
+                                self.requester(&msg.source),
+                                topic,
+                                query,
+                                req.summary,
+                                span,
+                                relayed,
                             );
                         }
                         ListMode::One(ref peer_id) => {
                             if peer_id == &PEER_ID.to_string() {
-                                info!("request for one: {:?} from {:?}", req, msg.source);
+                                info!(
+                                    "request for one: {:?} from {:?} on {}",
+                                    req,
+                                    msg.source,
+                                    topic.id()
+                                );
                                 respond_with_public_books(
                                     self.response_sender.clone(),
-                                    msg.source.to_string(),
+                                    self.requester(&msg.source),
+                                    topic,
+                                    query,
+                                    req.summary,
+                                    span,
+                                    Vec::new(),
                                 );
                             }
                         }
                     }
                 }
             }
-            _ => (),
+            FloodsubEvent::Subscribed { peer_id, topic } if topic == *TOPIC => {
+                let _ = self.subscribed.send(peer_id);
+            }
+            FloodsubEvent::Subscribed { peer_id, topic } => {
+                let club = match club_of(&topic) {
+                    Some(club) => club,
+                    None => return,
+                };
+                if self.clubs.add_member(club, &peer_id.to_string()) {
+                    info!("[club {}] {} joined", club, peer_id);
+                }
+                // a newcomer learns what the club reads from those already in it
+                if let Some(state) = self.clubs.get(club).map(|c| c.state.clone()) {
+                    if state.updated > 0 {
+                        let _ = self.outgoing.send((topic.clone(), Message::Club(state)));
+                    }
+                }
+            }
+            FloodsubEvent::Unsubscribed { peer_id, topic } => {
+                if let Some(club) = club_of(&topic) {
+                    if self.clubs.remove_member(club, &peer_id.to_string()) {
+                        info!("[club {}] {} left", club, peer_id);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// direct messages go out again until their recipient acks them
+fn resend_unacked(swarm: &mut Swarm<BookBehavior>) {
+    let (retries, expired) = swarm.behaviour_mut().acks.due();
+    let retries: Vec<(PeerId, Message)> = retries
+        .into_iter()
+        .map(|(id, unacked)| {
+            let chat = ChatMessage {
+                text: unacked.text.clone(),
+                to: Some(unacked.to.to_string()),
+                id: Some(id),
+            };
+            (unacked.to, Message::Chat(chat))
+        })
+        .collect();
+    for unacked in expired {
+        error!("{} never confirmed your message \"{}\"", unacked.to, unacked.text);
+    }
+    for (peer, message) in retries {
+        if swarm.is_connected(&peer) {
+            publish(swarm, TOPIC.clone(), &message);
+        }
+    }
+}
+
+// only peers announcing the capability know what to do with one
+// readable by the receiver only, sealed by us
+fn sealed_to(receiver: &str, message: &Message) -> Option<Message> {
+    let peer = receiver.parse().ok()?;
+    sealing::seal_message(&KEYS, &peer, message).map(Message::Sealed)
+}
+
+fn send_nack(swarm: &mut Swarm<BookBehavior>, topic: Topic, nack: Nack) {
+    match nack.receiver.parse() {
+        Ok(peer) if swarm.behaviour().supports(&peer, "nack") => {
+            publish(swarm, topic, &Message::Nack(nack));
+        }
+        _ => debug!("not telling {} why: {:?}", nack.receiver, nack.reason),
+    }
+}
+
+// search results are shown but don't replace the peer's cached catalog. they
+// are checked again here, an older peer answers with everything
+fn show_matches(peer: &PeerId, text: &str, books: Library, catalogs: &HashMap<String, Library>) {
+    let query = match Query::parse(text) {
+        Ok(query) => query,
+        Err(e) => {
+            error!("{} answered an invalid query: {}", peer, e);
+            return;
+        }
+    };
+    let books: Vec<Book> = books.into_iter().filter(|b| query.matches(b)).collect();
+    info!("{} matches for \"{}\" from {}:", books.len(), text, peer);
+    for book in &books {
+        let score = ratings::score(catalogs, &peer.to_string(), book);
+        info!("{:?}, {}", book, ratings::describe(score));
+    }
+}
+
+// a supernode's matches from peers that are away, with whose books they are
+fn show_relayed(supernode: &PeerId, relayed: &[Relayed]) {
+    let now = unix_time();
+    for from in relayed {
+        info!(
+            "{} matches from {} via supernode {}, as of {}:",
+            from.data.len(),
+            from.peer,
+            supernode,
+            activity::ago(now.saturating_sub(from.fetched_at))
+        );
+        from.data.iter().for_each(|b| info!("{:?}", b));
+    }
+}
+
+fn show_book_detail(peer: &PeerId, detail: BookDetail, catalogs: &HashMap<String, Library>) {
+    let book = match detail.book {
+        Some(book) => book,
+        None => {
+            info!("{} has no book {} it can show you", peer, detail.id);
+            return;
+        }
+    };
+    info!("book {} from {}:", detail.id, peer);
+    info!("  title: {}", book.title);
+    info!("  author: {}", book.author);
+    info!("  publisher: {}", book.publisher);
+    if let Some(year) = book.year {
+        info!("  year: {}", year);
+    }
+    if let Some(ref isbn) = book.isbn {
+        info!("  isbn: {}", isbn);
+    }
+    if !book.tags.is_empty() {
+        info!("  tags: {}", book.tags.join(", "));
+    }
+    if let Some(ref cid) = book.cid {
+        let url = ipfs::gateway_url(&CONFIG.ipfs.gateway, cid);
+        match book.format {
+            Some(format) => info!("  file: {} ({})", url, format.name()),
+            None => info!("  file: {}", url),
+        }
+    }
+    // a file only on their disk comes through a download link they make
+    if let Some(file) = detail.file {
+        let format = file.format.map_or("file", |f| f.name());
+        let size = file.bytes as f64 / 1_000_000.0;
+        info!("  on their disk: the {}, {:.1} MB, ask them for a download link", format, size);
+    }
+    if let (Some(series), Some(volume)) = (&book.series, book.volume) {
+        info!("  series: {} #{}", series, volume);
+    }
+    if let Some(condition) = book.condition {
+        info!("  condition: {}", format!("{:?}", condition).to_lowercase());
+    }
+    // a book without a count is a single copy
+    match book.copies {
+        Some(0) => info!("  every copy is lent out"),
+        Some(copies) => info!("  copies available: {}", copies),
+        None => (),
+    }
+    let score = ratings::score(catalogs, &peer.to_string(), &book);
+    info!("  {}", ratings::describe(score));
+    if let Some(ref review) = book.review {
+        info!("  their review:");
+        review.lines().for_each(|line| info!("    {}", line));
+    }
+}
+
+// the club's book, with the peers whose catalogs have a copy, and its schedule
+fn show_club(state: &ClubState, catalogs: &HashMap<String, Library>) {
+    match state.book {
+        Some(ref book) => {
+            let key = book.key();
+            let mut owners: Vec<&String> = catalogs
+                .iter()
+                .filter(|(_, catalog)| catalog.iter().any(|b| b.key() == key))
+                .map(|(peer, _)| peer)
+                .collect();
+            owners.sort();
+            info!("  reading: {} by {}", book.title, book.author);
+            if !owners.is_empty() {
+                let owners: Vec<&str> = owners.iter().map(|p| p.as_str()).collect();
+                info!("  offered by: {}", owners.join(", "));
+            }
+        }
+        None => info!("  no book picked yet"),
+    }
+    let now = unix_time();
+    for milestone in &state.schedule {
+        info!("  {}: {}", clubs::due(milestone.by, now), milestone.reading);
+    }
+}
+
+// a proposal waits for "loan accept", a countersigned record of ours goes in
+// the ledger
+fn receive_loan(peer: &PeerId, record: LoanRecord, reputation: &mut Reputation) {
+    let (peer_id, us) = (peer.to_string(), PEER_ID.to_string());
+    // only loans between the sender and us
+    let between_us = (record.lender == peer_id && record.borrower == us)
+        || (record.borrower == peer_id && record.lender == us);
+    if !between_us {
+        debug!("loan record from {} about someone else's loan", peer);
+        return;
+    }
+    let mut ledger = Ledger::load();
+    let theirs_signed = match record.lender == peer_id {
+        true => ledger::lender_signed(&record),
+        false => ledger::borrower_signed(&record),
+    };
+    if !theirs_signed {
+        debug!("badly signed loan record from {}", peer);
+        return;
+    }
+    if !clock::plausible(record.at, "loan record", &peer_id) {
+        return;
+    }
+    let ours_signed = match record.lender == us {
+        true => record.lender_signature.is_some(),
+        false => record.borrower_signature.is_some(),
+    };
+    if ours_signed {
+        let (id, event) = (record.id, record.event);
+        if ledger.settle(record) {
+            if event == LoanEvent::Returned {
+                reputation.loan(&peer_id);
+            }
+            info!("{} signed loan {} ({}), it's in the ledger", peer, id, describe_event(event));
+        }
+        return;
+    }
+    match record.event {
+        // only a lender can say it lent something
+        LoanEvent::Lent if record.lender == peer_id => {
+            activity::record(Activity::LoanOffered {
+                peer: peer.to_string(),
+                title: record.title.clone(),
+            });
+            let due = record.due.map(|due| clubs::due(due, unix_time()));
+            info!(
+                "{} wants to lend you {} (loan {}{}), loan accept {} to sign for it",
+                peer,
+                record.title,
+                record.id,
+                due.map(|due| format!(", {}", due)).unwrap_or_default(),
+                record.id
+            );
+        }
+        // and only a lender can give more time
+        LoanEvent::Extended
+            if record.lender == peer_id && ledger.outstanding_for(&record).is_some() =>
+        {
+            let due = record.due.map(|due| clubs::due(due, unix_time()));
+            info!(
+                "{} gives you more time with {} (loan {}, {}), loan accept {} to agree",
+                peer,
+                record.title,
+                record.id,
+                due.unwrap_or_else(|| "no due date".to_owned()),
+                record.id
+            );
+        }
+        LoanEvent::Returned if ledger.outstanding_for(&record).is_some() => {
+            info!(
+                "{} says {} came back (loan {}), loan accept {} to agree",
+                peer, record.title, record.id, record.id
+            );
+        }
+        _ => {
+            debug!("unexpected loan record from {}", peer);
+            return;
+        }
+    }
+    ledger.propose(record);
+}
+
+fn describe_event(event: LoanEvent) -> &'static str {
+    match event {
+        LoanEvent::Lent => "lent",
+        LoanEvent::Returned => "returned",
+        LoanEvent::Extended => "extended",
+    }
+}
+
+fn describe_nack(nack: &Nack) -> String {
+    let reason = match nack.reason {
+        NackReason::RateLimited => "we asked too often",
+        NackReason::NotTrusted => "it doesn't trust us",
+        NackReason::TooLarge => "it's too large to send",
+        NackReason::Unavailable => "its library is unavailable",
+    };
+    match nack.retry_after {
+        Some(seconds) => format!("{}, try again in {}m", reason, seconds / 60 + 1),
+        None => reason.to_owned(),
+    }
+}
+
+// floodsub sends a copy to every connected peer on the topic, so it counts against each
+fn publish(swarm: &mut Swarm<BookBehavior>, topic: Topic, message: &Message) -> usize {
+    let data = encode(message);
+    let bytes = data.len();
+    let peers: Vec<String> = swarm.connected_peers().map(|p| p.to_string()).collect();
+    let behaviour = swarm.behaviour_mut();
+    for peer in &peers {
+        behaviour.traffic.sent(peer, bytes);
+    }
+    behaviour.floodsub.publish(topic, data);
+    bytes
+}
+
+// publishing right after the connection opens would be lost, floodsub only
+// sends to peers once they told us they subscribed
+fn deliver_queued(swarm: &mut Swarm<BookBehavior>, peer: PeerId) {
+    if !swarm.is_connected(&peer) {
+        return;
+    }
+    for pending in swarm.behaviour_mut().outbox.take_for(&peer.to_string()) {
+        let waited = unix_time().saturating_sub(pending.queued_at);
+        info!("{} is back, delivering message queued {}", peer, activity::ago(waited));
+        publish(swarm, TOPIC.clone(), &pending.message);
+    }
+    let held = match swarm.behaviour_mut().mailbox.as_mut() {
+        Some(mailbox) => mailbox.take_for(&peer.to_string()),
+        None => Vec::new(),
+    };
+    if !held.is_empty() {
+        info!("forwarding {} held messages to {}", held.len(), peer);
+    }
+    for sealed in held {
+        publish(swarm, TOPIC.clone(), &Message::Sealed(sealed));
+    }
+    for rotation in Rotations::load().recent() {
+        publish(swarm, TOPIC.clone(), &Message::Rotation(rotation.clone()));
+    }
+}
+
+// silent nodes don't announce themselves, presence included
+fn send_presence(swarm: &mut Swarm<BookBehavior>) {
+    let behaviour = swarm.behaviour_mut();
+    let low_power = behaviour.power.low();
+    if behaviour.silent || !behaviour.presence.heartbeat_due(low_power) {
+        return;
+    }
+    let presence = behaviour.presence.own.clone();
+    if swarm.connected_peers().next().is_some() {
+        publish(swarm, presence_topic(), &Message::Presence(presence));
+    }
+}
+
+// broadcast, but only our own devices hold the key to open it
+fn sync_devices(swarm: &mut Swarm<BookBehavior>, replica: Replica, force: bool) {
+    if swarm.connected_peers().next().is_none() {
+        return;
+    }
+    let sync = match swarm.behaviour_mut().sync.as_mut() {
+        Some(sync) => sync,
+        None => return,
+    };
+    if !sync.should_send(&replica, force) {
+        return;
+    }
+    let message = sync.seal(&replica);
+    debug!("sending {} books to our other devices", replica.books.len());
+    publish(swarm, TOPIC.clone(), &Message::Sync(message));
+}
+
+fn is_bootstrap(peer: &PeerId) -> bool {
+    BOOTSTRAP.iter().any(|(p, _)| p == peer)
+}
+
+// after a suspend our connections are most likely dead without either side
+// having noticed. dropping them has known and bootstrap peers redialed, which
+// registers at rendezvous points and asks them for peers again
+fn wake_up(swarm: &mut Swarm<BookBehavior>, slept: time::Duration) {
+    info!("woke up after {}s, reconnecting", slept.as_secs());
+    let peers: Vec<PeerId> = swarm.connected_peers().copied().collect();
+    for peer in peers {
+        let _ = swarm.disconnect_peer_id(peer);
+    }
+    // peers forgot our presence while we were gone
+    let presence = &mut swarm.behaviour_mut().presence;
+    presence.set(presence.own.clone());
+}
+
+// the once-a-second chores, every ten seconds in low power mode
+fn housekeeping(swarm: &mut Swarm<BookBehavior>) {
+    redial_due_peers(swarm);
+    prune_idle_peers(swarm);
+    refresh_rendezvous(swarm);
+    drop_graylisted_peers(swarm);
+    let behaviour = swarm.behaviour_mut();
+    let low_power = behaviour.power.low();
+    behaviour.traffic.save_if_due(low_power);
+    behaviour.reputation.save_if_due(low_power);
+    behaviour.peer_store.save_if_due(low_power);
+    send_presence(swarm);
+    resend_unacked(swarm);
+    swarm.behaviour_mut().traces.expire();
+    for request in swarm.behaviour_mut().inflight.expire() {
+        if request.fanned_out() {
+            info!("#{} {} is over: {}", request.id, request.what, request.summary());
+        }
+    }
+    refresh_supernode(swarm);
+    if let Some(report) = swarm.behaviour_mut().telemetry.due() {
+        telemetry::send(report);
+    }
+    for pending in swarm.behaviour_mut().outbox.expire() {
+        info!("{} didn't come back in time, dropped what was queued for it", pending.to);
+    }
+    reload_config(swarm);
+    for peer in swarm.behaviour_mut().connections.unanswered_probes() {
+        error!("ping to {} failed, no reply", peer);
+    }
+    let behaviour = swarm.behaviour_mut();
+    if let Some(sync) = behaviour.sync.as_mut() {
+        if sync.check_due() {
+            send_library_to_devices(behaviour.sync_sender.clone());
+        }
+    }
+}
+
+// a supernode asks peers for their catalog when they show up and everyone
+// now and then, those who allow it are kept when they answer
+fn refresh_supernode(swarm: &mut Swarm<BookBehavior>) {
+    let (due, joined) = match swarm.behaviour_mut().supernode.as_mut() {
+        Some(supernode) => {
+            supernode.expire();
+            (supernode.refresh_due(), supernode.take_joined())
+        }
+        None => return,
+    };
+    let mut modes: Vec<ListMode> = joined.into_iter().map(ListMode::One).collect();
+    if due {
+        modes = vec![ListMode::ALL];
+    }
+    for mode in modes {
+        let req = ListRequest {
+            mode,
+            query: None,
+            summary: None,
+            trace: None,
+        };
+        publish(swarm, TOPIC.clone(), &Message::ListRequest(req));
+    }
+}
+
+// applies what can change while connected, the rest waits for a restart
+fn reload_config(swarm: &mut Swarm<BookBehavior>) {
+    let changes = match swarm.behaviour_mut().reloader.check() {
+        Some(changes) => changes,
+        None => return,
+    };
+    info!("reloaded {}", CONFIG_PATH);
+    if let Some(level) = changes.log_level {
+        log::set_max_level(level);
+        info!("log level is now {}", level);
+    }
+    let behaviour = swarm.behaviour_mut();
+    if let Some(silent) = changes.silent {
+        behaviour.silent = silent;
+        info!("silent mode is {}", if silent { "on" } else { "off" });
+    }
+    if let Some(policy) = changes.policy {
+        behaviour.policy = policy;
+        info!("Answering \"ls books all\" from: {}", policy.list_all);
+        info!("Answering \"ls books <peer id>\" from: {}", policy.list_one);
+    }
+    if let Some(limits) = changes.quota {
+        behaviour.quotas.limits = limits;
+        info!("quota limits updated");
+    }
+    if let Some(mode) = changes.power {
+        behaviour.power.set(mode);
+        info!("power mode is {}", mode);
+    }
+    if let Some(budget) = changes.cache {
+        behaviour.remote_catalogs.set_budget(budget);
+        info!("catalog cache budget is now {} bytes", budget);
+    }
+    for channel in changes.joined {
+        if behaviour.floodsub.subscribe(channel_topic(&channel)) {
+            info!("joined channel: {}", channel);
+        }
+        behaviour.channels.insert(channel);
+    }
+    for channel in changes.left {
+        if behaviour.channels.remove(&channel) {
+            behaviour.floodsub.unsubscribe(channel_topic(&channel));
+            info!("left channel: {}", channel);
+        }
+    }
+    for (peer, addr) in changes.bootstrap {
+        if let Err(e) = swarm.dial(addr) {
+            error!("unable to dial bootstrap peer {}: {}", peer, e);
+        }
+    }
+    if !changes.restart.is_empty() {
+        info!("restart to apply changes to: {}", changes.restart.join(", "));
+    }
+}
+
+fn redial_due_peers(swarm: &mut Swarm<BookBehavior>) {
+    for (peer, addrs) in swarm.behaviour_mut().reconnect.due() {
+        info!("reconnecting to {}", peer);
+        if let Err(e) = swarm.dial(DialOpts::peer_id(peer).addresses(addrs).build()) {
+            debug!("unable to redial {}: {}", peer, e);
+        }
+    }
+}
+
+fn prune_idle_peers(swarm: &mut Swarm<BookBehavior>) {
+    for peer in swarm.behaviour_mut().pruner.select(is_bootstrap) {
+        info!("disconnecting idle peer {}", peer);
+        // otherwise floodsub would immediately dial it again
+        swarm.behaviour_mut().floodsub.remove_node_from_partial_view(&peer);
+        let _ = swarm.disconnect_peer_id(peer);
+    }
+}
+
+fn drop_graylisted_peers(swarm: &mut Swarm<BookBehavior>) {
+    swarm.behaviour_mut().scores.decay();
+    let graylisted: Vec<PeerId> = swarm
+        .connected_peers()
+        .filter(|peer| swarm.behaviour().scores.graylisted(peer))
+        .copied()
+        .collect();
+    for peer in graylisted {
+        info!("disconnecting misbehaving peer {}", peer);
+        swarm.behaviour_mut().floodsub.remove_node_from_partial_view(&peer);
+        let _ = swarm.disconnect_peer_id(peer);
+    }
+}
+
+// map the first lan address we listen on through the router
+fn start_port_mapping(swarm: &mut Swarm<BookBehavior>, address: &Multiaddr) {
+    if !CONFIG.nat.port_mapping {
+        return;
+    }
+    let mut parts = address.iter();
+    let local = match (parts.next(), parts.next()) {
+        (Some(Protocol::Ip4(ip)), Some(Protocol::Tcp(port))) if ip.is_private() => {
+            std::net::SocketAddrV4::new(ip, port)
+        }
+        _ => return,
+    };
+    if let Some(sender) = swarm.behaviour_mut().port_mapping.take() {
+        tokio::spawn(nat::map_port(local, sender));
+    }
+}
+
+fn start_beacon(swarm: &mut Swarm<BookBehavior>, address: &Multiaddr) {
+    if !CONFIG.discovery.beacon {
+        return;
+    }
+    // the beacon is ipv4 broadcast, so it has to announce the ipv4 listener's port
+    let mut parts = address.iter();
+    let tcp_port = match (parts.next(), parts.next()) {
+        (Some(Protocol::Ip4(_)), Some(Protocol::Tcp(port))) => port,
+        _ => return,
+    };
+    if let Some(sender) = swarm.behaviour_mut().beacon.take() {
+        let beacon_port = CONFIG.discovery.beacon_port;
+        let topic = TOPIC.id().to_owned();
+        tokio::spawn(beacon::run(beacon_port, tcp_port, *PEER_ID, topic, sender));
+    }
+}
+
+// peers found by the lan beacon or a rendezvous point are treated like ones found by mdns
+fn handle_discovered(swarm: &mut Swarm<BookBehavior>, peer: PeerId, addrs: Vec<Multiaddr>) {
+    if swarm.is_connected(&peer) {
+        return;
+    }
+    info!("discovered {} at {:?}", peer, addrs);
+    if let Err(e) = swarm.dial(DialOpts::peer_id(peer).addresses(addrs).build()) {
+        debug!("unable to dial {}: {}", peer, e);
+    }
+}
+
+fn refresh_rendezvous(swarm: &mut Swarm<BookBehavior>) {
+    if !swarm.behaviour_mut().hubs.refresh_due() {
+        return;
+    }
+    for (point, _) in RENDEZVOUS_POINTS.iter() {
+        if swarm.is_connected(point) {
+            let behaviour = swarm.behaviour_mut();
+            behaviour.hubs.refresh(&mut behaviour.rendezvous, *point);
+        }
+    }
+}
+
+fn handle_swarm_event<E: std::fmt::Debug>(
+    swarm: &mut Swarm<BookBehavior>,
+    event: SwarmEvent<(), E>,
+) {
+    if let SwarmEvent::ConnectionClosed { peer_id, endpoint, .. } = &event {
+        swarm.behaviour_mut().connections.closed(peer_id, endpoint);
+    }
+    match event {
+        SwarmEvent::ConnectionEstablished {
+            peer_id,
+            endpoint,
+            num_established,
+            ..
+        } => {
+            // a peer can have several connections, only announce the first
+            if num_established.get() == 1 {
+                swarm.behaviour_mut().pruner.connected(peer_id);
+                info!(
+                    "{} joined ({} peers online)",
+                    peer_id,
+                    swarm.network_info().num_peers()
+                );
+                activity::record(Activity::PeerJoined {
+                    peer: peer_id.to_string(),
+                });
+            }
+            let behaviour = swarm.behaviour_mut();
+            behaviour.liveness.heard(peer_id);
+            behaviour.connections.opened(peer_id, endpoint.clone());
+            // only dialed addresses are worth remembering, inbound ones use ephemeral ports
+            if let ConnectedPoint::Dialer { address, .. } = &endpoint {
+                behaviour.peer_store.record(&peer_id, address);
+            }
+            behaviour.reconnect.connected(&peer_id);
+            if num_established.get() == 1 && RENDEZVOUS_POINTS.iter().any(|(p, _)| p == &peer_id) {
+                behaviour.hubs.refresh(&mut behaviour.rendezvous, peer_id);
+            }
+            // peers re-dialed from the store aren't known to mdns yet, so floodsub
+            // needs to be told about them to include them when publishing
+            if behaviour.peer_store.contains(&peer_id) || is_bootstrap(&peer_id) {
+                behaviour.floodsub.add_node_to_partial_view(peer_id);
+            }
+        }
+        SwarmEvent::ConnectionClosed {
+            peer_id,
+            num_established: 0,
+            ..
+        } => {
+            info!(
+                "{} left ({} peers online)",
+                peer_id,
+                swarm.network_info().num_peers()
+            );
+            activity::record(Activity::PeerLeft {
+                peer: peer_id.to_string(),
+            });
+            let behaviour = swarm.behaviour_mut();
+            behaviour.capabilities.remove(&peer_id);
+            behaviour.liveness.heard(peer_id);
+            if behaviour.pruner.closed(&peer_id) || behaviour.scores.graylisted(&peer_id) {
+                // we dropped it on purpose, don't come right back
+            } else if let Some((_, addr)) = BOOTSTRAP.iter().find(|(p, _)| p == &peer_id) {
+                behaviour.reconnect.schedule(peer_id, vec![addr.clone()], true);
+            } else if behaviour.peer_store.contains(&peer_id) {
+                let addrs = behaviour.peer_store.addrs_of(&peer_id);
+                behaviour.reconnect.schedule(peer_id, addrs, false);
+            }
         }
+        SwarmEvent::OutgoingConnectionError {
+            peer_id: Some(peer_id),
+            error,
+        } => {
+            if swarm.behaviour_mut().connections.probed(&peer_id) {
+                error!("ping to {} failed, unable to connect: {}", peer_id, error);
+            } else {
+                debug!("unable to reach {}: {}", peer_id, error);
+            }
+            swarm.behaviour_mut().reconnect.failed(&peer_id);
+        }
+        SwarmEvent::NewListenAddr { address, .. } => {
+            info!("listening on {}", address);
+            health::listen_addr_added();
+            systemd::ready();
+            start_port_mapping(swarm, &address);
+            start_beacon(swarm, &address);
+        }
+        SwarmEvent::ExpiredListenAddr { address, .. } => {
+            info!("no longer listening on {}", address);
+            health::listen_addr_expired();
+        }
+        event => debug!("Unhandled swarm event: {:?}", event),
+    }
+}
+
+// "--health-port 8081" serves /healthz and /readyz on that port, on
+// 127.0.0.1 unless "--health-addr 0.0.0.0" opens it to other hosts
+fn health_addr() -> Option<SocketAddr> {
+    let port = arg("--health-port")?;
+    let port: u16 = port.parse().expect("--health-port needs a port");
+    let ip = match arg("--health-addr") {
+        Some(ip) => ip.parse().expect("--health-addr needs an ip address"),
+        None => IpAddr::V4(Ipv4Addr::LOCALHOST),
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+// the value of "--name value" or "--name=value"
+fn arg(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix(name) {
+            Some("") => args.next(),
+            Some(rest) => rest.strip_prefix('=').map(str::to_owned),
+            None => continue,
+        };
+        return Some(value.unwrap_or_default());
     }
+    None
+}
+
+// "--no-stdin", for docker or systemd where nobody types commands
+fn headless() -> bool {
+    std::env::args().skip(1).any(|arg| arg == "--no-stdin")
+}
+
+// the next command typed. once stdin is closed the node carries on with swarm
+// and api events alone
+async fn read_command(stdin: &mut Option<Lines<BufReader<Stdin>>>) -> Option<String> {
+    let lines = match stdin.as_mut() {
+        Some(lines) => lines,
+        None => return std::future::pending().await,
+    };
+    match lines.next_line().await {
+        Ok(Some(line)) => return Some(line),
+        Ok(None) => info!("stdin closed, running headless"),
+        Err(e) => error!("unable to read from stdin, running headless: {}", e),
+    }
+    *stdin = None;
+    None
+}
+
+fn init_logging() {
+    let mut console = pretty_env_logger::formatted_builder();
+    let level = CONFIG.log_level().expect("invalid log level in config");
+    match level {
+        // filtered by level alone, so a reload can change it
+        Some(_) => console.parse_filters("trace"),
+        None => console.parse_filters(&std::env::var("RUST_LOG").unwrap_or_default()),
+    };
+    let console = console.build();
+    let level = level.unwrap_or_else(|| console.filter());
+    logging::init(Box::new(console), level).expect("a logger was already set");
+    let path = match CONFIG.logging.file.as_ref() {
+        Some(path) => path,
+        None => return,
+    };
+    let sink = match FileSink::open(path, CONFIG.logging.max_bytes, CONFIG.logging.keep) {
+        Ok(sink) => Arc::new(sink),
+        Err(e) => {
+            error!("unable to open log file {}: {}", path, e);
+            return;
+        }
+    };
+    logging::add_sink(sink.clone());
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => return error!("unable to listen for SIGHUP: {}", e),
+        };
+        while hangup.recv().await.is_some() {
+            match sink.reopen() {
+                Ok(()) => info!("reopened log file {}", path),
+                Err(e) => error!("unable to reopen log file: {}", e),
+            }
+        }
+    });
 }
 
 #[tokio::main]
-async fn main() {
-    pretty_env_logger::init();
+async fn main() -> Result<()> {
+    // before anything reads a file, config.toml included
+    let data_dir = datadir::enter().expect("unable to use the data directory");
+    init_logging();
+    info!("data directory: {}", data_dir.display());
+    if Path::new(CONFIG_PATH).exists() {
+        info!("loaded config from {}", CONFIG_PATH);
+    }
+    keys::load().map_err(|e| format!("unable to load the identity key: {}", e))?;
     info!("Peer Id: {}", PEER_ID.clone());
+    info!("Topic: {}", TOPIC.id());
+    if archive::read_only() {
+        info!("read-only archive, the library can't be changed while the node runs");
+    }
+
+    schema::migrate(STORAGE_PATH).map_err(|e| format!("unable to open {}: {}", STORAGE_PATH, e))?;
+    // the library file is a snapshot of the history, caught up with it either way
+    if !archive::read_only() {
+        if let Err(e) = history::restore(STORAGE_PATH) {
+            error!("unable to catch the history up with {}: {}", STORAGE_PATH, e);
+        }
+    }
 
     // multi-producer, single-consumer queue for sending values across asynchronous tasks.
     // aka - async channel for communicating between different parts of the application
     let (response_sender, mut response_receiver) = mpsc::unbounded_channel();
+    let (api_sender, mut api_receiver) = mpsc::unbounded_channel();
+    let (external_sender, mut external_receiver) = mpsc::unbounded_channel();
+    let (discovered_sender, mut discovered_receiver) = mpsc::unbounded_channel();
+    let (subscribed_sender, mut subscribed_receiver) = mpsc::unbounded_channel();
+    let (sync_sender, mut sync_receiver) = mpsc::unbounded_channel();
+    let (tombstone_sender, mut tombstone_receiver) = mpsc::unbounded_channel();
+    let (outgoing_sender, mut outgoing_receiver) = mpsc::unbounded_channel();
+    let (book_reply_sender, mut book_reply_receiver) = mpsc::unbounded_channel();
 
     // authentication keys using noise protocol
     let auth_keys = Keypair::<X25519Spec>::new()
@@ -139,52 +1912,265 @@ async fn main() {
         .expect("unable to create auth keys");
 
     // create transport
-    let transport = TokioTcpConfig::new() // use Tokio's async TCP
+    let tcp = TokioTcpConfig::new(); // use Tokio's async TCP
+    let tcp = match CONFIG.proxy.socks5_addr() {
+        // the proxy resolves hostnames itself, and we still listen on plain tcp
+        Some(proxy) => {
+            info!("dialing through socks5 proxy {}", proxy);
+            EitherTransport::Left(socks::Socks5Transport::new(proxy).or_transport(tcp))
+        }
+        // resolves /dns4, /dns6 and /dnsaddr addresses so bootstrap peers can be given by hostname
+        None => EitherTransport::Right(
+            TokioDnsConfig::system(tcp).expect("unable to read system dns config"),
+        ),
+    };
+    let (tcp, wire) = tcp.with_bandwidth_logging();
+    // on a private network every connection first proves knowledge of the pre-shared key
+    let tcp = match *PSK {
+        Some(psk) => EitherTransport::Left(
+            tcp.and_then(move |socket, _| PnetConfig::new(psk).handshake(socket)),
+        ),
+        None => EitherTransport::Right(tcp),
+    };
+    let transport = tcp
         .upgrade(upgrade::Version::V1) //upgrade connection to use Noise protocol for secure communication
         .authenticate(NoiseConfig::xx(auth_keys).into_authenticated()) // authenticate after upgrade - NoiseConfig::xx is guaranteed to be interoperable with other libp2p apps
         .multiplex(mplex::MplexConfig::new()) // negotiate a (sub)stream multiplexer on top of authenticated transport for multiple substreams on same transport
         .boxed(); // only capture Output and Error types
 
+    let listen_addrs = CONFIG.listen_addrs();
+    let wants_ipv6 = listen_addrs
+        .iter()
+        .any(|a| matches!(a.iter().next(), Some(Protocol::Ip6(_))));
+    let mdns6 = if wants_ipv6 {
+        let config = MdnsConfig {
+            enable_ipv6: true,
+            ..Default::default()
+        };
+        Mdns::new(config)
+            .await
+            .map_err(|e| error!("ipv6 mdns unavailable: {}", e))
+            .ok()
+    } else {
+        None
+    };
+
+    let visibility = &CONFIG.visibility;
+    for field in visibility.public.iter().chain(&visibility.friends) {
+        if !HIDEABLE.contains(&field.as_str()) {
+            error!("ignoring {} under [visibility], it isn't a field that can be hidden", field);
+        }
+    }
+
     // define logic for network and peers
     // floodsub to handle events
     // mdns for discovering local peers
+    let peer_store = PeerStore::load();
+    let liveness = Liveness::new(&peer_store);
+    let everyone = |_: &str| false;
+    let books = read_local_library().await.ok().map(|l| public_catalog(l, everyone).len());
     let mut behavior = BookBehavior {
-        floodsub: Floodsub::new(PEER_ID.clone()),
+        floodsub: Pubsub::new(Floodsub::new(*PEER_ID)),
         mdns: Mdns::new(Default::default())
             .await
             .expect("unable to create mdns"),
+        mdns6: mdns6.into(),
+        rendezvous: rendezvous::client::Behaviour::new(KEYS.clone()),
+        rendezvous_server: CONFIG
+            .rendezvous
+            .server
+            .then(|| rendezvous::server::Behaviour::new(rendezvous::server::Config::default()))
+            .into(),
+        identify: Identify::new(
+            IdentifyConfig::new("/library/1.0.0".to_owned(), KEYS.public())
+                .with_agent_version(own_agent_version(books)),
+        ),
+        // a single lost ping shouldn't drop an otherwise working connection
+        ping: ping::Behaviour::new(
+            ping::Config::new().with_max_failures(NonZeroU32::new(3).expect("non zero")),
+        ),
+        details: details::behaviour(),
         response_sender,
+        remote_catalogs: CatalogCache::new(CONFIG.cache.max_bytes),
+        channels: BTreeSet::new(),
+        clubs: Clubs::load(),
+        peer_store,
+        reconnect: Reconnector::default(),
+        pruner: Pruner::default(),
+        silent: CONFIG.silent.enabled,
+        policy: CONFIG.policy,
+        quotas: Quotas::new(),
+        reputation: Reputation::load(),
+        scores: PeerScores::new(),
+        telemetry: Telemetry::new(),
+        traffic: TrafficStats::load(),
+        wire,
+        capabilities: HashMap::new(),
+        names: HashMap::new(),
+        adverts: HashMap::new(),
+        impostors: HashSet::new(),
+        presence: Presences::new(),
+        liveness,
+        connections: Connections::default(),
+        port_mapping: Some(external_sender),
+        beacon: Some(discovered_sender.clone()),
+        hubs: Hubs::new(),
+        discovered: discovered_sender,
+        outbox: Outbox::load(),
+        subscribed: subscribed_sender,
+        mailbox: CONFIG.relay.serve.then(Mailbox::load),
+        supernode: CONFIG.supernode.enabled.then(Supernode::load),
+        sync: CONFIG.sync.secret.as_deref().map(DeviceSync::new),
+        sync_sender,
+        tombstones: tombstone_sender.clone(),
+        outgoing: outgoing_sender,
+        book_replies: book_reply_sender,
+        book_requests: HashMap::new(),
+        acks: Acks::default(),
+        traces: Requests::default(),
+        inflight: InFlight::default(),
+        reloader: Reloader::new(),
+        watchdog: Watchdog::from_env(),
+        power: Power::new(CONFIG.power.mode),
+        resume: Resume::new(),
     };
 
     behavior.floodsub.subscribe(TOPIC.clone());
+    behavior.floodsub.subscribe(presence_topic());
+    for channel in &CONFIG.channels {
+        behavior.floodsub.subscribe(channel_topic(channel));
+        behavior.channels.insert(channel.clone());
+    }
+    for club in behavior.clubs.names() {
+        behavior.floodsub.subscribe(club_topic(club));
+    }
 
     // manage connections based on transport and behavior using tokio runtime
     let mut swarm = SwarmBuilder::new(transport, behavior, PEER_ID.clone())
         .executor(Box::new(|future| {
             tokio::spawn(future);
         }))
+        .connection_limits(pruning::connection_limits())
         .build();
 
-    // async read stdin
-    let mut stdin = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    // async read stdin, unless there's no terminal to read commands from
+    let mut stdin = if headless() {
+        info!("running headless, stdin is not read");
+        None
+    } else {
+        Some(BufReader::new(tokio::io::stdin()).lines())
+    };
 
-    // start swarm
-    Swarm::listen_on(
-        &mut swarm,
-        "/ip4/0.0.0.0/tcp/0"
-            .parse()
-            .expect("unable to get local socket"),
-    )
-    .expect("swarm unable to start");
+    // start swarm. an address family missing on this host shouldn't stop the others
+    let mut listening = false;
+    for addr in listen_addrs {
+        match swarm.listen_on(addr.clone()) {
+            Ok(_) => listening = true,
+            Err(e) => error!("unable to listen on {}: {}", addr, e),
+        }
+    }
+    if !listening {
+        panic!("swarm unable to start");
+    }
+
+    // reconnect to peers from previous runs without waiting for mdns
+    for (peer, addrs) in swarm.behaviour().peer_store.dial_targets() {
+        if let Err(e) = swarm.dial(DialOpts::peer_id(peer).addresses(addrs).build()) {
+            info!("unable to redial known peer {}: {}", peer, e);
+        }
+    }
+    for addr in CONFIG.external_addrs() {
+        swarm.add_external_address(addr, AddressScore::Infinite);
+    }
+    for (peer, addr) in BOOTSTRAP.iter() {
+        if let Err(e) = swarm.dial(addr.clone()) {
+            error!("unable to dial bootstrap peer {}: {}", peer, e);
+        }
+    }
+
+    let mut ticker = time::interval(time::Duration::from_secs(1));
+
+    // an archive's library stays as it is, shares that ended are only left
+    // out of its answers
+    if !archive::read_only() {
+        tokio::spawn(async {
+            loop {
+                if let Err(e) = purge_trash().await {
+                    debug!("unable to purge the trash: {}", e);
+                }
+                time::sleep(time::Duration::from_secs(60 * 60)).await;
+            }
+        });
+
+        // quiet unless it had something to repair
+        tokio::spawn(async {
+            loop {
+                match fsck::check().await {
+                    Ok(report) if !report.clean() => report.log(),
+                    Ok(_) => (),
+                    Err(e) => error!("unable to check {}: {}", STORAGE_PATH, e),
+                }
+                time::sleep(time::Duration::from_secs(24 * 60 * 60)).await;
+            }
+        });
+
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = expire_shares(&tombstone_sender).await {
+                    debug!("unable to expire shares: {}", e);
+                }
+                time::sleep(time::Duration::from_secs(60)).await;
+            }
+        });
+    }
+
+    if let Some(addr) = health_addr() {
+        tokio::spawn(async move {
+            if let Err(e) = health::serve(addr).await {
+                error!("health checks stopped: {}", e);
+            }
+        });
+    }
+
+    if let Some(broker) = CONFIG.mqtt.broker.clone() {
+        mqtt::start(broker);
+    }
+
+    if let Some(endpoint) = CONFIG.tracing.otlp.clone() {
+        traces::start(endpoint);
+    }
+
+    let sender = api_sender.clone();
+    tokio::spawn(async move {
+        match api::listener().await {
+            Ok(Some(listener)) => {
+                if let Err(e) = api::serve(listener, sender).await {
+                    error!("api server stopped: {}", e);
+                }
+            }
+            Ok(None) => (),
+            Err(e) => error!("unable to start the api: {}", e),
+        }
+    });
 
     // event loop
     loop {
         let event_type = {
             tokio::select! {
-                line = stdin.next_line() => Some(EventType::Input(line.expect("unable to get line").expect("unable to read line from stdin"))),
+                line = read_command(&mut stdin) => line.map(EventType::Input),
                 response = response_receiver.recv() => Some(EventType::Response(response.expect("unable to get response"))),
+                // api_sender stays alive in this scope, so recv only yields None on shutdown
+                req = api_receiver.recv() => req.map(EventType::Api),
+                addr = external_receiver.recv() => addr.map(EventType::ExternalAddr),
+                found = discovered_receiver.recv() => found.map(|(peer, addrs)| EventType::Discovered(peer, addrs)),
+                peer = subscribed_receiver.recv() => peer.map(EventType::Subscribed),
+                library = sync_receiver.recv() => library.map(|(library, force)| EventType::SyncOut(library, force)),
+                ids = tombstone_receiver.recv() => ids.map(EventType::Tombstone),
+                out = outgoing_receiver.recv() => out.map(|out| EventType::Outgoing(Box::new(out))),
+                reply = book_reply_receiver.recv() => reply.map(|reply| EventType::BookReply(Box::new(reply))),
+                _ = ticker.tick() => Some(EventType::Tick),
                 event = swarm.select_next_some() => {
-                    info!("Unhandled swarm event: {:?}", event);
+                    handle_swarm_event(&mut swarm, event);
                     None
                 },
             }
@@ -192,16 +2178,198 @@ async fn main() {
 
         if let Some(event) = event_type {
             match event {
-                EventType::Response(res) => {
-                    let json =
-                        serde_json::to_string(&res).expect("unable to jsonify event type response");
-                    swarm.behaviour_mut().floodsub.publish(TOPIC.clone(), json.as_bytes());
+                EventType::Response((topic, Ok(catalog))) => {
+                    let (res, sealed) = (catalog.response, catalog.sealed);
+                    let receiver = res.receiver.clone();
+                    // a summary is audited as the books it covers
+                    let books = match res.summary {
+                        Some(ref summary) => summary.total,
+                        None => res.data.len(),
+                    };
+                    let wire_size = |message: &Message| match sealed {
+                        true => sealed_to(&receiver, message).map_or(0, |s| encode(&s).len()),
+                        false => encode(message).len(),
+                    };
+                    let mut response = Message::ListResponse(res);
+                    // books relayed for others give way before our own answer is refused
+                    while wire_size(&response) > MAX_RESPONSE_SIZE {
+                        let trimmed = match response {
+                            Message::ListResponse(ref mut res) => supernode::trim(&mut res.relayed),
+                            _ => false,
+                        };
+                        if !trimmed {
+                            break;
+                        }
+                    }
+                    let response = match sealed {
+                        true => match sealed_to(&receiver, &response) {
+                            Some(sealed) => sealed,
+                            None => {
+                                error!("unable to seal the catalog for {}", receiver);
+                                continue;
+                            }
+                        },
+                        false => response,
+                    };
+                    let size = encode(&response).len();
+                    if size > MAX_RESPONSE_SIZE {
+                        error!("catalog of {} books is too large to send to {}", books, receiver);
+                        audit::record(&receiver, Access::Refused { reason: "too large".to_owned() });
+                        let nack = Nack {
+                            receiver,
+                            reason: NackReason::TooLarge,
+                            retry_after: None,
+                        };
+                        send_nack(&mut swarm, topic, nack);
+                        continue;
+                    }
+                    let bytes = match receiver.parse() {
+                        // gone while we were collecting the books, it gets them when it's back
+                        Ok(peer) if !swarm.is_connected(&peer) => {
+                            swarm.behaviour_mut().outbox.push_expiring(
+                                &receiver,
+                                response,
+                                RESPONSE_TTL,
+                            );
+                            info!("{} is offline, keeping the catalog for when it's back", peer);
+                            size
+                        }
+                        _ => publish(&mut swarm, topic, &response),
+                    };
+                    audit::record(&receiver, Access::Catalog { books });
+                    swarm.behaviour_mut().quotas.record_bytes(&receiver, bytes);
+                    activity::record(Activity::CatalogSent {
+                        peer: receiver,
+                        bytes,
+                    });
+                }
+                EventType::Response((topic, Err(nack))) => send_nack(&mut swarm, topic, nack),
+                EventType::Api(req) => api::answer(req, &mut swarm),
+                EventType::ExternalAddr(addr) => {
+                    swarm.add_external_address(addr, AddressScore::Infinite);
+                }
+                EventType::Discovered(peer, addrs) => handle_discovered(&mut swarm, peer, addrs),
+                EventType::Subscribed(peer) => {
+                    if let Some(sync) = swarm.behaviour_mut().sync.as_mut() {
+                        sync.peer_appeared();
+                    }
+                    deliver_queued(&mut swarm, peer);
+                    if let Some(supernode) = swarm.behaviour_mut().supernode.as_mut() {
+                        supernode.joined(&peer.to_string());
+                    }
+                }
+                EventType::SyncOut(library, force) => sync_devices(&mut swarm, library, force),
+                EventType::Outgoing(out) => {
+                    let (topic, message) = *out;
+                    publish(&mut swarm, topic, &message);
+                }
+                EventType::BookReply(reply) => {
+                    let (channel, detail) = *reply;
+                    let receiver = detail.receiver.clone();
+                    if swarm.behaviour_mut().details.send_response(channel, detail).is_err() {
+                        debug!("{} stopped waiting for its book", receiver);
+                    }
+                }
+                EventType::Tombstone(ids) => {
+                    let tombstone = tombstone::sign(&KEYS, ids);
+                    publish(&mut swarm, TOPIC.clone(), &Message::Tombstone(tombstone));
+                }
+                EventType::Tick => {
+                    if let Some(slept) = swarm.behaviour_mut().resume.slept() {
+                        wake_up(&mut swarm, slept);
+                    }
+                    swarm.behaviour_mut().watchdog.pet();
+                    if swarm.behaviour_mut().power.housekeeping_due() {
+                        housekeeping(&mut swarm);
+                    }
+                }
+                EventType::Input(line) if archive::refuses(&line) => {
+                    error!("this node is a read-only archive, {} changes nothing here", line);
                 }
                 EventType::Input(line) => match line.as_str() {
-                    "ls peers" => handle_list_peers(&mut swarm).await,
+                    cmd if cmd.starts_with("ls peers") => handle_list_peers(cmd, &mut swarm).await,
+                    "peers score" => handle_peer_scores(&mut swarm),
+                    "peers reputation" => handle_reputation(&mut swarm),
+                    "forget me" => handle_forget_me(&mut swarm),
+                    "telemetry" => handle_telemetry(&mut swarm),
+                    "bandwidth" => handle_bandwidth(&mut swarm),
+                    cmd if cmd.starts_with("activity") => handle_activity(cmd),
+                    cmd if cmd.starts_with("audit") => handle_audit(cmd),
+                    "queue" => handle_queue(&mut swarm),
+                    cmd if cmd.starts_with("requests") => handle_requests(cmd, &mut swarm),
+                    cmd if cmd.starts_with("cancel ") => handle_cancel(cmd, &mut swarm),
+                    "fsck" => handle_fsck().await,
+                    "ls bookmarks" => handle_list_bookmarks(&mut swarm),
+                    cmd if cmd.starts_with("bookmark ") => handle_bookmark(cmd, &mut swarm),
+                    "devices" => handle_devices(&mut swarm),
+                    cmd if cmd.starts_with("presence") => handle_presence(cmd, &mut swarm),
+                    "rotate key" => handle_rotate_key(&mut swarm),
+                    cmd if cmd.starts_with("ping ") => handle_ping(cmd, &mut swarm),
+                    "invite" => handle_invite(&mut swarm),
+                    cmd if cmd.starts_with("accept-invite") => handle_accept_invite(cmd, &mut swarm),
+                    cmd if cmd.starts_with("conflicts") => handle_conflicts(cmd).await,
+                    cmd if cmd.starts_with("show book ") => handle_show_book(cmd, &mut swarm),
+                    cmd if cmd.starts_with("series ") => handle_series(cmd).await,
+                    cmd if cmd.starts_with("rate ") => handle_rate(cmd).await,
+                    cmd if cmd.starts_with("review ") => handle_review(cmd).await,
+                    cmd if cmd.starts_with("shelve ") => handle_shelve(cmd).await,
+                    cmd if cmd.starts_with("condition ") => handle_condition(cmd).await,
+                    cmd if cmd.starts_with("hide") => handle_hide(cmd).await,
+                    cmd if cmd.starts_with("copies ") => handle_copies(cmd).await,
+                    cmd if cmd.starts_with("attach ") => handle_attach(cmd).await,
+                    cmd if cmd.starts_with("link book ") => handle_link(cmd).await,
+                    cmd if cmd.starts_with("unlink book ") => handle_unlink(cmd),
+                    "recommend" => handle_recommend(&mut swarm).await,
+                    cmd if cmd.starts_with("lend ") => handle_lend(cmd, &mut swarm).await,
+                    cmd if cmd.starts_with("loan ") => handle_loan(cmd, &mut swarm),
+                    "ls loans" => handle_list_loans(),
+                    "loans" => handle_loans(&mut swarm),
+                    "missing volumes" => handle_missing_volumes(&mut swarm).await,
+                    cmd if cmd.starts_with("search ") => handle_search(cmd, &mut swarm),
                     cmd if cmd.starts_with("ls books") => handle_list_books(cmd, &mut swarm).await,
                     cmd if cmd.starts_with("add book") => handle_add_book(cmd).await,
+                    cmd if cmd.starts_with("export ") => handle_export(cmd).await,
+                    cmd if cmd.starts_with("import ") => handle_import(cmd),
+                    cmd if cmd.starts_with("node ") => handle_node(cmd),
                     cmd if cmd.starts_with("share book") => handle_share_book(cmd).await,
+                    cmd if cmd.starts_with("share all") => handle_share_all(cmd).await,
+                    // before "rm book", which it also starts with
+                    cmd if cmd.starts_with("rm books") => {
+                        handle_rm_books(cmd, &swarm.behaviour().tombstones).await
+                    }
+                    cmd if cmd.starts_with("rm book") => {
+                        handle_rm_book(cmd, &swarm.behaviour().tombstones).await
+                    }
+                    cmd if cmd.starts_with("trash") => handle_trash(cmd).await,
+                    cmd if cmd.starts_with("restore") => handle_restore(cmd).await,
+                    cmd if cmd.starts_with("revoke") => {
+                        handle_revoke(cmd, &swarm.behaviour().tombstones).await
+                    }
+                    cmd if cmd.starts_with("say ") => handle_say(cmd, &mut swarm),
+                    cmd if cmd.starts_with("msg ") => handle_msg(cmd, &mut swarm),
+                    "ls channels" => handle_list_channels(&mut swarm),
+                    "ls clubs" => handle_list_clubs(&mut swarm),
+                    cmd if cmd.starts_with("club ") => handle_club(cmd, &mut swarm),
+                    "ls groups" => handle_list_groups(),
+                    "ls pins" => handle_list_pins(),
+                    cmd if cmd.starts_with("trust ") => handle_trust(cmd, &mut swarm),
+                    "status" => handle_status(&mut swarm),
+                    cmd if cmd.starts_with("history") => handle_history(cmd),
+                    "undo" => handle_undo().await,
+                    cmd if cmd.starts_with("merge ") => handle_merge(cmd).await,
+                    cmd if cmd.starts_with("dedup") => handle_dedup(cmd),
+                    cmd if cmd.starts_with("downloads") => handle_downloads(cmd).await,
+                    cmd if cmd.starts_with("download ") => handle_download(cmd, &mut swarm),
+                    cmd if cmd.starts_with("snapshot") => handle_snapshot(cmd).await,
+                    cmd if cmd.starts_with("debug") => handle_debug(cmd, &mut swarm).await,
+                    cmd if cmd.starts_with("silent") => handle_silent(cmd, &mut swarm),
+                    cmd if cmd.starts_with("power") => handle_power(cmd, &mut swarm),
+                    cmd if cmd.starts_with("cache") => handle_cache(cmd, &mut swarm),
+                    cmd if cmd.starts_with("quota") => handle_quota(cmd, &mut swarm),
+                    cmd if cmd.starts_with("policy") => handle_policy(cmd, &mut swarm),
+                    cmd if cmd.starts_with("group ") => handle_group(cmd),
+                    cmd if cmd.starts_with("join ") => handle_join_channel(cmd, &mut swarm),
+                    cmd if cmd.starts_with("leave ") => handle_leave_channel(cmd, &mut swarm),
                     _ => error!("command unknown"),
                 },
             }
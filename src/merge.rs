@@ -0,0 +1,112 @@
+use crate::clock;
+use crate::history;
+use peer2peer::protocol::{Book, Library};
+use peer2peer::schema;
+use std::collections::{HashMap, HashSet};
+
+// what to do with a book both libraries hold in different versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    // keep ours and list theirs under `conflicts`, to pick one later
+    Ask,
+    Ours,
+    Theirs,
+    // the one edited last, ours when neither says
+    Newer,
+}
+
+impl Policy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "ask" => Some(Policy::Ask),
+            "ours" => Some(Policy::Ours),
+            "theirs" => Some(Policy::Theirs),
+            "newer" => Some(Policy::Newer),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Outcome {
+    pub added: usize,
+    // the same book on both sides, or twice in theirs
+    pub duplicates: usize,
+    // ours replaced by theirs
+    pub replaced: usize,
+    pub kept: usize,
+    // ours as kept and theirs under our id, left for a person to pick from
+    pub conflicts: Vec<(Book, Book)>,
+}
+
+// adds their books to ours. a book is the same when its title and author
+// are, whatever its id. their trashed books stay behind
+pub fn merge(ours: &mut Library, theirs: Library, policy: Policy, now: u64) -> Outcome {
+    let mut outcome = Outcome::default();
+    let revision = clock::next_revision(ours);
+    // books of ours one of theirs was already merged into
+    let mut merged = HashSet::new();
+    let mut next_id = ours.iter().map(|b| b.id + 1).max().unwrap_or(0);
+    let mut index: HashMap<(String, String), usize> = ours
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| b.trashed.is_none())
+        .map(|(i, b)| (b.key(), i))
+        .collect();
+    let mut uids: HashSet<String> = ours.iter().map(|b| b.uid.clone()).collect();
+    for theirs in theirs.into_iter().filter(|b| b.trashed.is_none()) {
+        let i = match index.get(&theirs.key()) {
+            Some(i) => *i,
+            None => {
+                index.insert(theirs.key(), ours.len());
+                merged.insert(ours.len());
+                // theirs may be a copy of one of ours under another title
+                let uid = match uids.insert(theirs.uid.clone()) && !theirs.uid.is_empty() {
+                    true => theirs.uid.clone(),
+                    false => schema::new_uid(),
+                };
+                uids.insert(uid.clone());
+                let mut book = Book {
+                    id: next_id,
+                    uid,
+                    ..theirs
+                };
+                clock::stamp(&mut book, now, revision);
+                ours.push(book);
+                next_id += 1;
+                outcome.added += 1;
+                continue;
+            }
+        };
+        let ours = &mut ours[i];
+        let theirs = Book {
+            id: ours.id,
+            uid: ours.uid.clone(),
+            ..theirs
+        };
+        // a book they hold twice is merged once, as it came first
+        if !merged.insert(i) || history::same(ours, &theirs) {
+            outcome.duplicates += 1;
+            continue;
+        }
+        let take_theirs = match policy {
+            Policy::Ours | Policy::Ask => false,
+            Policy::Theirs => true,
+            // a date past the allowed skew counts as the bound, so it can't win every merge
+            Policy::Newer => {
+                let edited = |book: &Book| clock::bounded(book.modified.unwrap_or(0));
+                edited(&theirs) > edited(ours)
+            }
+        };
+        if take_theirs {
+            *ours = theirs;
+            clock::stamp(ours, now, revision);
+            outcome.replaced += 1;
+        } else if policy == Policy::Ask {
+            outcome.conflicts.push((ours.clone(), theirs));
+        } else {
+            outcome.kept += 1;
+        }
+    }
+    outcome
+}
@@ -0,0 +1,180 @@
+use crate::activity::Entry;
+use crate::config::{MqttConfig, CONFIG};
+use crate::{Result, PEER_ID};
+use log::{debug, error, info};
+use once_cell::sync::OnceCell;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+    time,
+};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+const TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY: Duration = Duration::from_secs(30);
+// events kept while the broker is away, the oldest go first past it
+const MAX_QUEUED: usize = 1000;
+
+// set once the bridge runs. events are handed over here so recording one
+// never waits on the broker
+static EVENTS: OnceCell<mpsc::UnboundedSender<(String, Vec<u8>)>> = OnceCell::new();
+
+// keeps a connection to the broker and publishes activity to it. events that
+// happen while the broker is away are queued and sent once it's back
+pub fn start(broker: String) {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    if EVENTS.set(sender).is_err() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut queued = VecDeque::new();
+        loop {
+            match connect(&broker, &CONFIG.mqtt).await {
+                Ok(stream) => {
+                    info!("publishing events to mqtt broker {}", broker);
+                    if let Err(e) = bridge(stream, &mut receiver, &mut queued).await {
+                        error!("lost mqtt broker {}: {}", broker, e);
+                    }
+                }
+                Err(e) => debug!("unable to reach mqtt broker {}: {}", broker, e),
+            }
+            time::sleep(RETRY).await;
+            while let Ok(event) = receiver.try_recv() {
+                queue(&mut queued, event);
+            }
+        }
+    });
+}
+
+fn queue(queued: &mut VecDeque<(String, Vec<u8>)>, event: (String, Vec<u8>)) {
+    if queued.len() == MAX_QUEUED {
+        queued.pop_front();
+        debug!("dropped the oldest event queued for the mqtt broker");
+    }
+    queued.push_back(event);
+}
+
+// an event leaves the queue once it's written, so one the connection drops
+// on is sent again
+async fn flush(stream: &mut TcpStream, queued: &mut VecDeque<(String, Vec<u8>)>) -> Result<()> {
+    while let Some((topic, payload)) = queued.front() {
+        stream.write_all(&packet(0x30, &[string(topic), payload.clone()].concat())).await?;
+        queued.pop_front();
+    }
+    Ok(())
+}
+
+// <prefix>/<kind>, e.g. "peer2peer/book_shared", unless the config names
+// another topic for the kind. an empty topic leaves the kind out
+pub fn publish(entry: &Entry) {
+    let sender = match EVENTS.get() {
+        Some(sender) => sender,
+        None => return,
+    };
+    let payload = match serde_json::to_value(entry) {
+        Ok(payload) => payload,
+        Err(_) => return,
+    };
+    let kind = payload["kind"].as_str().unwrap_or_default();
+    let topic = match CONFIG.mqtt.topics.get(kind) {
+        Some(topic) if topic.is_empty() => return,
+        Some(topic) => topic.clone(),
+        None => format!("{}/{}", CONFIG.mqtt.prefix.trim_end_matches('/'), kind),
+    };
+    let _ = sender.send((topic, payload.to_string().into_bytes()));
+}
+
+async fn bridge(
+    mut stream: TcpStream,
+    events: &mut mpsc::UnboundedReceiver<(String, Vec<u8>)>,
+    queued: &mut VecDeque<(String, Vec<u8>)>,
+) -> Result<()> {
+    flush(&mut stream, queued).await?;
+    let mut ping = time::interval(KEEP_ALIVE / 2);
+    let mut incoming = [0u8; 256];
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => {
+                    queue(queued, event);
+                    flush(&mut stream, queued).await?
+                }
+                None => return Ok(()),
+            },
+            _ = ping.tick() => stream.write_all(&packet(0xc0, &[])).await?,
+            // ping responses, nothing else comes our way without subscriptions
+            read = stream.read(&mut incoming) => {
+                if read? == 0 {
+                    return Err("connection closed".into());
+                }
+            }
+        }
+    }
+}
+
+async fn connect(broker: &str, config: &MqttConfig) -> Result<TcpStream> {
+    let mut stream = time::timeout(TIMEOUT, TcpStream::connect(broker)).await??;
+    // mqtt 3.1.1, clean session
+    let mut flags = 0x02;
+    let mut payload = string(&client_id(config));
+    if let Some(ref username) = config.username {
+        flags |= 0x80;
+        payload.extend(string(username));
+    }
+    if let Some(ref password) = config.password {
+        flags |= 0x40;
+        payload.extend(string(password));
+    }
+    let mut body = string("MQTT");
+    body.extend([4, flags]);
+    body.extend((KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    body.extend(payload);
+    stream.write_all(&packet(0x10, &body)).await?;
+
+    let mut connack = [0u8; 4];
+    time::timeout(TIMEOUT, stream.read_exact(&mut connack)).await??;
+    match connack {
+        [0x20, 2, _, 0] => Ok(stream),
+        [0x20, 2, _, 4] | [0x20, 2, _, 5] => Err("broker refused our credentials".into()),
+        [0x20, 2, _, code] => Err(format!("broker refused the connection ({})", code).into()),
+        _ => Err("not an mqtt broker".into()),
+    }
+}
+
+// brokers only have to take 23 characters, and two clients with the same id
+// keep pushing each other off
+fn client_id(config: &MqttConfig) -> String {
+    if let Some(ref id) = config.client_id {
+        return id.clone();
+    }
+    let peer = PEER_ID.to_string();
+    format!("peer2peer-{}", &peer[peer.len() - 12..])
+}
+
+// fixed header, then the remaining length seven bits at a time
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn string(value: &str) -> Vec<u8> {
+    let mut out = (value.len() as u16).to_be_bytes().to_vec();
+    out.extend_from_slice(value.as_bytes());
+    out
+}
@@ -0,0 +1,230 @@
+use crate::Result;
+use libp2p::{multiaddr::Protocol, Multiaddr};
+use log::{debug, error, info};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    sync::mpsc,
+    time,
+};
+
+const NATPMP_PORT: u16 = 5351;
+const SSDP_ADDR: &str = "239.255.255.250:1900";
+const LEASE: Duration = Duration::from_secs(60 * 60);
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+// keeps a port mapping for our tcp listener alive on the home router, trying
+// nat-pmp first and then upnp. reports the external address once it's mapped
+pub async fn map_port(local: SocketAddrV4, external: mpsc::UnboundedSender<Multiaddr>) {
+    let mut reported = None;
+    loop {
+        let mapped = match natpmp_map(local.port()).await {
+            Ok(addr) => Some(addr),
+            Err(e) => {
+                debug!("nat-pmp unavailable: {}", e);
+                match upnp_map(local).await {
+                    Ok(addr) => Some(addr),
+                    Err(e) => {
+                        debug!("upnp unavailable: {}", e);
+                        None
+                    }
+                }
+            }
+        };
+        match mapped {
+            Some(addr) if reported != Some(addr) => {
+                info!("router forwards {} to {}", addr, local);
+                let _ = external.send(to_multiaddr(addr));
+                reported = Some(addr);
+            }
+            Some(_) => (),
+            None if reported.is_none() => {
+                error!("no port mapping protocol answered, external address unknown");
+                return;
+            }
+            None => error!("unable to renew port mapping"),
+        }
+        // renew well before the lease runs out
+        time::sleep(LEASE / 2).await;
+    }
+}
+
+fn to_multiaddr(addr: SocketAddrV4) -> Multiaddr {
+    Multiaddr::empty()
+        .with(Protocol::Ip4(*addr.ip()))
+        .with(Protocol::Tcp(addr.port()))
+}
+
+// default route from the kernel routing table. linux only, elsewhere upnp is used
+fn default_gateway() -> Result<Ipv4Addr> {
+    let routes = std::fs::read_to_string("/proc/net/route")?;
+    for line in routes.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() > 2 && fields[1] == "00000000" {
+            let gateway = u32::from_str_radix(fields[2], 16)?;
+            return Ok(Ipv4Addr::from(gateway.to_le_bytes()));
+        }
+    }
+    Err("no default route".into())
+}
+
+async fn natpmp_request(socket: &UdpSocket, req: &[u8], len: usize) -> Result<Vec<u8>> {
+    socket.send(req).await?;
+    let mut buf = [0u8; 16];
+    let n = time::timeout(TIMEOUT, socket.recv(&mut buf)).await??;
+    if n < len || buf[1] != req[1] + 128 {
+        return Err("unexpected nat-pmp response".into());
+    }
+    let result = u16::from_be_bytes([buf[2], buf[3]]);
+    if result != 0 {
+        return Err(format!("nat-pmp error code {}", result).into());
+    }
+    Ok(buf[..n].to_vec())
+}
+
+async fn natpmp_map(port: u16) -> Result<SocketAddrV4> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket
+        .connect(SocketAddrV4::new(default_gateway()?, NATPMP_PORT))
+        .await?;
+
+    let res = natpmp_request(&socket, &[0, 0], 12).await?;
+    let ip = Ipv4Addr::new(res[8], res[9], res[10], res[11]);
+
+    let mut req = vec![0, 2, 0, 0];
+    req.extend_from_slice(&port.to_be_bytes());
+    req.extend_from_slice(&port.to_be_bytes());
+    req.extend_from_slice(&(LEASE.as_secs() as u32).to_be_bytes());
+    let res = natpmp_request(&socket, &req, 16).await?;
+    let external_port = u16::from_be_bytes([res[10], res[11]]);
+
+    Ok(SocketAddrV4::new(ip, external_port))
+}
+
+async fn upnp_map(local: SocketAddrV4) -> Result<SocketAddrV4> {
+    let location = ssdp_discover().await?;
+    let description = http(&location, "GET", None).await?;
+    let (service, control_path) = find_wan_service(&description)?;
+    let control_url = join_url(&location, &control_path)?;
+
+    let add = format!(
+        "<NewRemoteHost></NewRemoteHost>\
+         <NewExternalPort>{port}</NewExternalPort>\
+         <NewProtocol>TCP</NewProtocol>\
+         <NewInternalPort>{port}</NewInternalPort>\
+         <NewInternalClient>{ip}</NewInternalClient>\
+         <NewEnabled>1</NewEnabled>\
+         <NewPortMappingDescription>peer2peer</NewPortMappingDescription>\
+         <NewLeaseDuration>{lease}</NewLeaseDuration>",
+        port = local.port(),
+        ip = local.ip(),
+        lease = LEASE.as_secs()
+    );
+    soap(&control_url, &service, "AddPortMapping", &add).await?;
+
+    let res = soap(&control_url, &service, "GetExternalIPAddress", "").await?;
+    let ip = between(&res, "<NewExternalIPAddress>", "</NewExternalIPAddress>")
+        .ok_or("router did not report an external address")?
+        .trim()
+        .parse()?;
+    Ok(SocketAddrV4::new(ip, local.port()))
+}
+
+async fn ssdp_discover() -> Result<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\n\
+         ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n",
+        SSDP_ADDR
+    );
+    socket.send_to(search.as_bytes(), SSDP_ADDR).await?;
+    let mut buf = [0u8; 2048];
+    let (n, _) = time::timeout(TIMEOUT, socket.recv_from(&mut buf)).await??;
+    let res = String::from_utf8_lossy(&buf[..n]);
+    res.lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("location")
+                .then(|| value.trim().to_owned())
+        })
+        .ok_or_else(|| "gateway did not send a location".into())
+}
+
+fn find_wan_service(description: &str) -> Result<(String, String)> {
+    for service in [
+        "urn:schemas-upnp-org:service:WANIPConnection:1",
+        "urn:schemas-upnp-org:service:WANPPPConnection:1",
+    ] {
+        if let Some(start) = description.find(service) {
+            if let Some(control) = between(&description[start..], "<controlURL>", "</controlURL>") {
+                return Ok((service.to_owned(), control.trim().to_owned()));
+            }
+        }
+    }
+    Err("gateway has no wan connection service".into())
+}
+
+async fn soap(url: &str, service: &str, action: &str, args: &str) -> Result<String> {
+    let body = format!(
+        "<?xml version=\"1.0\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body><u:{action} xmlns:u=\"{service}\">{args}</u:{action}></s:Body></s:Envelope>",
+        action = action,
+        service = service,
+        args = args
+    );
+    let header = format!("SOAPAction: \"{}#{}\"", service, action);
+    http(url, "POST", Some((&header, &body))).await
+}
+
+// just enough http/1.0 for talking to a router on the lan
+async fn http(url: &str, method: &str, post: Option<(&str, &str)>) -> Result<String> {
+    let rest = url.strip_prefix("http://").ok_or("only http urls are supported")?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let addr: SocketAddr = host.parse()?;
+
+    let mut req = format!("{} {} HTTP/1.0\r\nHost: {}\r\n", method, path, host);
+    if let Some((header, body)) = post {
+        req.push_str(&format!(
+            "{}\r\nContent-Type: text/xml; charset=\"utf-8\"\r\nContent-Length: {}\r\n\r\n{}",
+            header,
+            body.len(),
+            body
+        ));
+    } else {
+        req.push_str("\r\n");
+    }
+
+    let mut stream = time::timeout(TIMEOUT, TcpStream::connect(addr)).await??;
+    stream.write_all(req.as_bytes()).await?;
+    let mut res = Vec::new();
+    time::timeout(TIMEOUT, stream.read_to_end(&mut res)).await??;
+    let res = String::from_utf8_lossy(&res).into_owned();
+
+    let (head, body) = res.split_once("\r\n\r\n").ok_or("malformed http response")?;
+    if !matches!(head.split_whitespace().nth(1), Some(status) if status.starts_with('2')) {
+        return Err(format!("router answered: {}", head.lines().next().unwrap_or_default()).into());
+    }
+    Ok(body.to_owned())
+}
+
+fn join_url(base: &str, path: &str) -> Result<String> {
+    if path.starts_with("http://") {
+        return Ok(path.to_owned());
+    }
+    let rest = base.strip_prefix("http://").ok_or("only http urls are supported")?;
+    let host = rest.split('/').next().unwrap_or(rest);
+    Ok(format!("http://{}{}", host, path))
+}
+
+fn between<'a>(text: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let from = text.find(start)? + start.len();
+    let to = text[from..].find(end)? + from;
+    Some(&text[from..to])
+}
@@ -0,0 +1,145 @@
+use crate::activity::{Activity, Entry};
+use crate::config::{NotifyConfig, CONFIG};
+use crate::Result;
+use log::{debug, error};
+use serde_json::json;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// posts the configured kinds of activity to a discord webhook and a matrix
+// room, for communities that talk there rather than on the network
+pub fn publish(entry: &Entry) {
+    let config = &CONFIG.notify;
+    if config.discord_webhook.is_none() && config.matrix.is_none() {
+        return;
+    }
+    let kind = match serde_json::to_value(entry) {
+        Ok(value) => value["kind"].as_str().unwrap_or_default().to_owned(),
+        Err(_) => return,
+    };
+    if !config.events.contains(&kind) {
+        return;
+    }
+    let text = describe(&entry.activity);
+    // the call comes from the event loop, which must not wait on the internet
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = send(&CONFIG.notify, &text) {
+            error!("unable to post notification: {}", e);
+        }
+    });
+}
+
+fn describe(activity: &Activity) -> String {
+    match CONFIG.name {
+        Some(ref name) => format!("{}: {}", name, activity),
+        None => activity.to_string(),
+    }
+}
+
+fn send(config: &NotifyConfig, text: &str) -> Result<()> {
+    if let Some(ref webhook) = config.discord_webhook {
+        let body = json!({ "content": text }).to_string();
+        request("POST", webhook, None, &body)?;
+    }
+    if let Some(ref matrix) = config.matrix {
+        // the transaction id only has to be unique for our access token
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/peer2peer{}",
+            matrix.homeserver.trim_end_matches('/'),
+            escape(&matrix.room),
+            rand::random::<u64>()
+        );
+        let body = json!({ "msgtype": "m.text", "body": text }).to_string();
+        request("PUT", &url, Some(&matrix.token), &body)?;
+    }
+    Ok(())
+}
+
+// room ids look like !abc:example.org
+fn escape(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// one json request over http/1.0, https for anything but a local test server
+pub fn request(method: &str, url: &str, token: Option<&str>, body: &str) -> Result<()> {
+    let (tls, rest) = match url.split_once("://") {
+        Some(("https", rest)) => (true, rest),
+        Some(("http", rest)) => (false, rest),
+        _ => return Err(format!("unsupported url {}", url).into()),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse()?),
+        None => (authority, if tls { 443 } else { 80 }),
+    };
+    let mut req = format!(
+        "{} {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        host,
+        body.len()
+    );
+    if let Some(token) = token {
+        req.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    req.push_str("\r\n");
+    req.push_str(body);
+
+    let mut socket = TcpStream::connect((host, port))?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.set_write_timeout(Some(TIMEOUT))?;
+    let mut res = Vec::new();
+    if tls {
+        let name = rustls::ServerName::try_from(host)?;
+        let mut conn = rustls::ClientConnection::new(tls_config(), name)?;
+        let mut stream = rustls::Stream::new(&mut conn, &mut socket);
+        stream.write_all(req.as_bytes())?;
+        // some servers hang up without a close_notify, what came before is fine
+        if let Err(e) = stream.read_to_end(&mut res) {
+            if res.is_empty() {
+                return Err(e.into());
+            }
+        }
+    } else {
+        socket.write_all(req.as_bytes())?;
+        socket.read_to_end(&mut res)?;
+    }
+    let res = String::from_utf8_lossy(&res);
+    let status = res.lines().next().unwrap_or_default();
+    if !matches!(status.split_whitespace().nth(1), Some(code) if code.starts_with('2')) {
+        return Err(format!("{} answered: {}", host, status).into());
+    }
+    debug!("posted to {}", host);
+    Ok(())
+}
+
+fn tls_config() -> Arc<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+}
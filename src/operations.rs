@@ -0,0 +1,80 @@
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+// requests and background work share the numbers, so `cancel <id>` doesn't
+// need to be told which one it is
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+static RUNNING: Lazy<Mutex<BTreeMap<u32, Running>>> = Lazy::new(Default::default);
+
+struct Running {
+    what: String,
+    started: Instant,
+    cancel: watch::Sender<bool>,
+}
+
+pub fn next_id() -> u32 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// work a command started in the background, e.g. an import. the work asks
+// whether it was cancelled between its steps and cleans up after itself, it
+// is never killed halfway through a write. dropping it marks it done
+pub struct Operation {
+    pub id: u32,
+    cancelled: watch::Receiver<bool>,
+}
+
+impl Operation {
+    pub fn start(what: &str) -> Self {
+        let id = next_id();
+        let (cancel, cancelled) = watch::channel(false);
+        let running = Running {
+            what: what.to_owned(),
+            started: Instant::now(),
+            cancel,
+        };
+        RUNNING.lock().unwrap().insert(id, running);
+        Operation { id, cancelled }
+    }
+
+    pub fn cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+
+    // returns once cancelled, to race a step that can't stop to ask
+    pub async fn until_cancelled(&mut self) {
+        while !*self.cancelled.borrow() {
+            if self.cancelled.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        RUNNING.lock().unwrap().remove(&self.id);
+    }
+}
+
+// what the operation was, if it's still running. it stops at its next step
+pub fn cancel(id: u32) -> Option<String> {
+    let running = RUNNING.lock().unwrap();
+    let op = running.get(&id)?;
+    let _ = op.cancel.send(true);
+    Some(op.what.clone())
+}
+
+// id, what and for how long, oldest first
+pub fn running() -> Vec<(u32, String, Duration)> {
+    RUNNING
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(id, op)| (*id, op.what.clone(), op.started.elapsed()))
+        .collect()
+}
@@ -0,0 +1,91 @@
+use crate::store;
+use crate::unix_time;
+use log::error;
+use peer2peer::protocol::Message;
+use serde::{Deserialize, Serialize};
+
+pub const OUTBOX_PATH: &str = "./outbox.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Pending {
+    pub to: String,
+    pub queued_at: u64,
+    // unix time after which it's no longer worth sending, none keeps it
+    // until the peer is back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    pub message: Message,
+}
+
+// messages for peers that were offline when they were sent. they go out as
+// soon as the peer is back and subscribed to the topic, even after a restart
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Outbox {
+    pending: Vec<Pending>,
+}
+
+impl Outbox {
+    pub fn load() -> Self {
+        store::load(OUTBOX_PATH)
+    }
+
+    fn save(&self) {
+        if let Err(e) = store::save(OUTBOX_PATH, self) {
+            error!("unable to save outbox: {}", e);
+        }
+    }
+
+    pub fn push(&mut self, to: &str, message: Message) {
+        self.queue(to, message, None);
+    }
+
+    // for answers that go stale, like a catalog the peer asked for
+    pub fn push_expiring(&mut self, to: &str, message: Message, ttl: u64) {
+        self.queue(to, message, Some(unix_time() + ttl));
+    }
+
+    fn queue(&mut self, to: &str, message: Message, expires_at: Option<u64>) {
+        self.pending.push(Pending {
+            to: to.to_owned(),
+            queued_at: unix_time(),
+            expires_at,
+            message,
+        });
+        self.save();
+    }
+
+    // drops and returns what's past its expiry
+    pub fn expire(&mut self) -> Vec<Pending> {
+        let now = unix_time();
+        if !self.pending.iter().any(|p| expired(p, now)) {
+            return Vec::new();
+        }
+        let (expired, kept) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|p| expired(p, now));
+        self.pending = kept;
+        self.save();
+        expired
+    }
+
+    // removes and returns everything queued for the peer, oldest first
+    pub fn take_for(&mut self, peer: &str) -> Vec<Pending> {
+        if !self.pending.iter().any(|p| p.to == peer) {
+            return Vec::new();
+        }
+        let (taken, kept) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|p| p.to == peer);
+        self.pending = kept;
+        self.save();
+        taken
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Pending> {
+        self.pending.iter()
+    }
+}
+
+fn expired(pending: &Pending, now: u64) -> bool {
+    matches!(pending.expires_at, Some(at) if at <= now)
+}
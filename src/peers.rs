@@ -0,0 +1,102 @@
+use crate::store;
+use crate::unix_time;
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub const PEERS_PATH: &str = "./peers.json";
+// keep the file small, the newest addresses are the most likely to work
+const MAX_ADDRS_PER_PEER: usize = 8;
+const LOW_POWER_SAVE_EVERY: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KnownPeer {
+    pub addrs: Vec<String>,
+    pub last_seen: u64,
+}
+
+// peers we have seen before and where we found them, so they can be
+// dialed again on the next start without waiting for mdns
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PeerStore {
+    peers: HashMap<String, KnownPeer>,
+    #[serde(skip)]
+    last_save: Option<Instant>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl PeerStore {
+    pub fn load() -> Self {
+        store::load(PEERS_PATH)
+    }
+
+    // changes are written on the next tick, or every ten minutes in low
+    // power mode
+    pub fn save_if_due(&mut self, low_power: bool) {
+        let waiting = matches!(self.last_save, Some(at) if at.elapsed() < LOW_POWER_SAVE_EVERY);
+        if !self.dirty || (low_power && waiting) {
+            return;
+        }
+        self.last_save = Some(Instant::now());
+        self.dirty = false;
+        self.save();
+    }
+
+    fn save(&self) {
+        if let Err(e) = store::save(PEERS_PATH, self) {
+            error!("unable to save peer store: {}", e);
+        }
+    }
+
+    pub fn contains(&self, peer: &PeerId) -> bool {
+        self.peers.contains_key(&peer.to_string())
+    }
+
+    pub fn record(&mut self, peer: &PeerId, addr: &Multiaddr) {
+        let known = self.peers.entry(peer.to_string()).or_default();
+        // dialed addresses end in /p2p/<peer id>, store them without it to avoid duplicates
+        let mut addr = addr.clone();
+        if let Some(Protocol::P2p(_)) = addr.iter().last() {
+            addr.pop();
+        }
+        let addr = addr.to_string();
+        known.addrs.retain(|a| a != &addr);
+        known.addrs.insert(0, addr);
+        known.addrs.truncate(MAX_ADDRS_PER_PEER);
+        known.last_seen = unix_time();
+        self.dirty = true;
+    }
+
+    pub fn forget(&mut self, peer: &PeerId) -> bool {
+        let known = self.peers.remove(&peer.to_string()).is_some();
+        self.dirty |= known;
+        known
+    }
+
+    pub fn addrs_of(&self, peer: &PeerId) -> Vec<Multiaddr> {
+        self.peers
+            .get(&peer.to_string())
+            .map(|known| known.addrs.iter().filter_map(|a| a.parse().ok()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn last_seen(&self) -> impl Iterator<Item = (PeerId, u64)> + '_ {
+        self.peers
+            .iter()
+            .filter_map(|(peer, known)| Some((peer.parse().ok()?, known.last_seen)))
+    }
+
+    pub fn dial_targets(&self) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        self.peers
+            .iter()
+            .filter_map(|(peer, known)| {
+                let peer = peer.parse().ok()?;
+                let addrs = known.addrs.iter().filter_map(|a| a.parse().ok()).collect();
+                Some((peer, addrs))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,68 @@
+use crate::store;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const PINS_PATH: &str = "./pins.json";
+
+// nicknames and the peer id we first dealt with under each, trust on first
+// use. a different peer id announcing a pinned name is likely an impostor
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Pins {
+    names: BTreeMap<String, String>,
+}
+
+impl Pins {
+    pub fn load() -> Self {
+        store::load(PINS_PATH)
+    }
+
+    fn save(&self) {
+        if let Err(e) = store::save(PINS_PATH, self) {
+            error!("unable to save pins: {}", e);
+        }
+    }
+
+    // the peer id the name is pinned to, when that's someone else
+    pub fn conflict(&self, name: &str, peer: &str) -> Option<&String> {
+        self.names.get(name).filter(|pinned| pinned.as_str() != peer)
+    }
+
+    // true when the binding is new or replaces another one
+    pub fn pin(&mut self, name: &str, peer: &str) -> bool {
+        if self.names.get(name).map(String::as_str) == Some(peer) {
+            return false;
+        }
+        self.names.insert(name.to_owned(), peer.to_owned());
+        self.save();
+        true
+    }
+
+    // keeps the names of a peer that rotated its key
+    pub fn replace_peer(&mut self, old: &str, new: &str) -> bool {
+        let mut replaced = false;
+        for pinned in self.names.values_mut().filter(|pinned| pinned.as_str() == old) {
+            *pinned = new.to_owned();
+            replaced = true;
+        }
+        if replaced {
+            self.save();
+        }
+        replaced
+    }
+
+    // drops the names pinned to a peer that asked to be forgotten, whether any were
+    pub fn forget(&mut self, peer: &str) -> bool {
+        let before = self.names.len();
+        self.names.retain(|_, pinned| pinned.as_str() != peer);
+        let forgotten = self.names.len() != before;
+        if forgotten {
+            self.save();
+        }
+        forgotten
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.names.iter()
+    }
+}
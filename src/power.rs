@@ -0,0 +1,77 @@
+use crate::config::PowerMode;
+use log::info;
+use std::time::{Duration, Instant};
+
+// in low power, the once-a-second housekeeping runs this much less often
+const LOW_POWER_EVERY: u64 = 10;
+const CHECK_BATTERY: Duration = Duration::from_secs(60);
+
+// fewer wakeups, no presence heartbeats and fewer writes, for laptops on
+// battery and small boards
+pub struct Power {
+    pub mode: PowerMode,
+    low: bool,
+    ticks: u64,
+    checked: Option<Instant>,
+}
+
+impl Power {
+    pub fn new(mode: PowerMode) -> Self {
+        let mut power = Power {
+            mode,
+            low: false,
+            ticks: 0,
+            checked: None,
+        };
+        power.update();
+        power
+    }
+
+    pub fn low(&self) -> bool {
+        self.low
+    }
+
+    pub fn set(&mut self, mode: PowerMode) {
+        self.mode = mode;
+        self.checked = None;
+        self.update();
+    }
+
+    // called every tick, true when housekeeping should run on this one
+    pub fn housekeeping_due(&mut self) -> bool {
+        self.update();
+        self.ticks = (self.ticks + 1) % LOW_POWER_EVERY;
+        !self.low || self.ticks == 0
+    }
+
+    fn update(&mut self) {
+        let low = match self.mode {
+            PowerMode::Normal => false,
+            PowerMode::Low => true,
+            PowerMode::Auto => {
+                if matches!(self.checked, Some(at) if at.elapsed() < CHECK_BATTERY) {
+                    return;
+                }
+                self.checked = Some(Instant::now());
+                on_battery()
+            }
+        };
+        if low != self.low {
+            self.low = low;
+            info!("low power mode is {}", if low { "on" } else { "off" });
+        }
+    }
+}
+
+// a battery that's discharging. only linux says so in a file, elsewhere
+// auto never switches
+fn on_battery() -> bool {
+    let supplies = match std::fs::read_dir("/sys/class/power_supply") {
+        Ok(supplies) => supplies,
+        Err(_) => return false,
+    };
+    supplies.flatten().any(|supply| {
+        let read = |name| std::fs::read_to_string(supply.path().join(name)).unwrap_or_default();
+        read("type").trim() == "Battery" && read("status").trim() == "Discharging"
+    })
+}
@@ -0,0 +1,101 @@
+use libp2p::PeerId;
+use peer2peer::protocol::{Availability, Presence};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const HEARTBEAT: Duration = Duration::from_secs(60);
+// a peer that missed this many heartbeats has no known presence anymore
+const FORGET_AFTER: Duration = Duration::from_secs(3 * 60);
+const MAX_STATUS: usize = 100;
+
+// our own presence and the last one heard from each peer
+pub struct Presences {
+    pub own: Presence,
+    next_heartbeat: Instant,
+    // set by a change of our own, which goes out even in low power mode
+    changed: bool,
+    peers: HashMap<PeerId, (Presence, Instant)>,
+}
+
+impl Presences {
+    pub fn new() -> Self {
+        Presences {
+            own: Presence {
+                availability: Availability::Online,
+                status: None,
+            },
+            next_heartbeat: Instant::now(),
+            changed: true,
+            peers: HashMap::new(),
+        }
+    }
+
+    // goes out with the next tick rather than waiting for the heartbeat
+    pub fn set(&mut self, presence: Presence) {
+        self.own = presence;
+        self.next_heartbeat = Instant::now();
+        self.changed = true;
+    }
+
+    // low power mode skips the heartbeats, peers soon forget our presence then
+    pub fn heartbeat_due(&mut self, low_power: bool) -> bool {
+        let now = Instant::now();
+        if now < self.next_heartbeat || (low_power && !self.changed) {
+            return false;
+        }
+        self.next_heartbeat = now + HEARTBEAT;
+        self.changed = false;
+        true
+    }
+
+    pub fn heard(&mut self, peer: PeerId, mut presence: Presence) {
+        presence.status = presence
+            .status
+            .map(|status| status.chars().take(MAX_STATUS).collect());
+        self.peers.insert(peer, (presence, Instant::now()));
+    }
+
+    pub fn forget(&mut self, peer: &PeerId) -> bool {
+        self.peers.remove(peer).is_some()
+    }
+
+    pub fn of(&self, peer: &PeerId) -> Option<&Presence> {
+        match self.peers.get(peer) {
+            Some((presence, at)) if at.elapsed() < FORGET_AFTER => Some(presence),
+            _ => None,
+        }
+    }
+}
+
+// "away", "dnd in a meeting" or "online"
+pub fn parse(input: &str) -> Option<Presence> {
+    let input = input.trim();
+    let (availability, status) = input.split_once(' ').unwrap_or((input, ""));
+    let availability = match availability {
+        "online" => Availability::Online,
+        "away" => Availability::Away,
+        "dnd" => Availability::DoNotDisturb,
+        _ => return None,
+    };
+    let status = status.trim();
+    Some(Presence {
+        availability,
+        status: if status.is_empty() {
+            None
+        } else {
+            Some(status.chars().take(MAX_STATUS).collect())
+        },
+    })
+}
+
+pub fn describe(presence: &Presence) -> String {
+    let availability = match presence.availability {
+        Availability::Online => "online",
+        Availability::Away => "away",
+        Availability::DoNotDisturb => "do not disturb",
+    };
+    match presence.status {
+        Some(ref status) => format!("{}: {}", availability, status),
+        None => availability.to_owned(),
+    }
+}
@@ -0,0 +1,100 @@
+use log::info;
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+// how often progress is logged when stderr isn't a terminal
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+const BAR_WIDTH: usize = 30;
+
+// progress reporter for imports, transfers, and other bulk operations.
+// draws a bar with rate/eta on a tty, falls back to periodic log lines otherwise
+pub struct Progress {
+    label: String,
+    total: Option<u64>,
+    done: u64,
+    started: Instant,
+    last_report: Instant,
+    tty: bool,
+}
+
+impl Progress {
+    pub fn new(label: &str, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        Progress {
+            label: label.to_owned(),
+            total,
+            done: 0,
+            started: now,
+            last_report: now,
+            tty: std::io::stderr().is_terminal(),
+        }
+    }
+
+    pub fn inc(&mut self, amount: u64) {
+        self.done += amount;
+        if self.tty {
+            self.draw();
+        } else if self.last_report.elapsed() >= LOG_INTERVAL {
+            self.last_report = Instant::now();
+            info!("{}", self.summary());
+        }
+    }
+
+    pub fn finish(&mut self) {
+        if self.tty {
+            self.draw();
+            eprintln!();
+        }
+        info!(
+            "{}: finished {} in {:.1}s",
+            self.label,
+            self.done,
+            self.started.elapsed().as_secs_f64()
+        );
+    }
+
+    fn rate(&self) -> f64 {
+        let secs = self.started.elapsed().as_secs_f64();
+        if secs > 0.0 {
+            self.done as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    fn eta(&self) -> Option<Duration> {
+        let remaining = self.total?.saturating_sub(self.done);
+        let rate = self.rate();
+        if rate > 0.0 {
+            Some(Duration::from_secs_f64(remaining as f64 / rate))
+        } else {
+            None
+        }
+    }
+
+    fn summary(&self) -> String {
+        let mut line = match self.total {
+            Some(total) => format!("{}: {}/{}", self.label, self.done, total),
+            None => format!("{}: {}", self.label, self.done),
+        };
+        line.push_str(&format!(" ({:.1}/s", self.rate()));
+        if let Some(eta) = self.eta() {
+            line.push_str(&format!(", eta {}s", eta.as_secs()));
+        }
+        line.push(')');
+        line
+    }
+
+    fn draw(&self) {
+        let bar = match self.total {
+            Some(total) if total > 0 => {
+                let filled = (self.done.min(total) as usize * BAR_WIDTH) / total as usize;
+                format!("[{}{}] ", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled))
+            }
+            _ => String::new(),
+        };
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r{}{}", bar, self.summary());
+        let _ = stderr.flush();
+    }
+}
@@ -0,0 +1,819 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+// features a node understands beyond plain list requests and responses. they
+// travel in the identify agent version, e.g. "peer2peer/0.1.0 (chat,channels)",
+// so newer nodes can tell what an older peer will understand
+pub const CAPABILITIES: &[&str] =
+    &["chat", "channels", "sealed", "nack", "ack", "details", "loans", "sealed-catalogs"];
+
+// version of the envelope this node writes. v1 messages were bare json
+// objects told apart by their fields. v2 puts "v" and a "type" tag next to
+// the same fields, so v1 nodes still read v2 messages and ignore the extras
+pub const WIRE_VERSION: u32 = 2;
+
+// inbound limits, checked before a message is parsed. floodsub already drops
+// frames over 2 KiB, these hold however else a message arrives
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+// real messages nest three levels deep, a book inside a response's data
+pub const MAX_DEPTH: usize = 8;
+pub const MAX_BOOKS: usize = 10_000;
+// authors or publishers listed in a summary, the rest only count towards its total
+pub const MAX_SUMMARY_ENTRIES: usize = 20;
+// the owner's review of a book, a few paragraphs
+pub const MAX_REVIEW: usize = 2000;
+
+pub type Library = Vec<Book>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Book {
+    // ours alone, another device may number the same book differently
+    pub id: usize,
+    // the same on every device the book is synced to, never sent to peers
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub uid: String,
+    pub title: String,
+    pub author: String,
+    pub publisher: String,
+    pub public: bool,
+    // limits a public book to the members of one group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visible_to: Option<String>,
+    // fields left out of what peers get, instead of the ones configured for
+    // every book
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hidden: Option<Visibility>,
+    // unix time of the last local change, shown to people. devices that don't
+    // set a revision yet order their edits by it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+    // lamport counter of the last change, the higher one wins when devices
+    // sync whatever their clocks say
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u64>,
+    // unix time it was moved to the trash, trashed books are never shared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trashed: Option<u64>,
+    // unix time a share ends, the book goes back to private then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_until: Option<u64>,
+    // e.g. "Earthsea" and 2 for the second book of a series
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u32>,
+    // the owner's score from 1 to 5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+    // where a paper copy sits, e.g. "living room, top shelf". never shared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<Condition>,
+    // how many we own, None for one. catalogs carry how many aren't lent out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copies: Option<u32>,
+    // of publication
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub year: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isbn: Option<String>,
+    // e.g. "favorites" or "sci-fi", lowercase
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // where we are with it, and the unix time we last finished it. both are
+    // kept to ourselves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ReadingStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_at: Option<u64>,
+    // an ipfs content id for the book's file, fetchable from any gateway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
+    // what the file is, found from its first bytes when it was attached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<FileFormat>,
+    // where the file is on our disk, for download links. never shared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    // the owner's words on the book, up to MAX_REVIEW characters. only sent
+    // with the book's details, catalogs leave it out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadingStatus {
+    ToRead,
+    Reading,
+    Read,
+    Abandoned,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileFormat {
+    Epub,
+    Pdf,
+    Mobi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    New,
+    Good,
+    Fair,
+    Poor,
+}
+
+impl Book {
+    // copies of a book on different shelves have their own ids, so they are
+    // matched by title and author, ignoring case and spacing
+    pub fn key(&self) -> (String, String) {
+        (normalize(&self.title), normalize(&self.author))
+    }
+}
+
+fn normalize(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ListMode {
+    ALL,
+    One(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListRequest {
+    pub mode: ListMode,
+    // only books matching this search, see query.rs. older nodes ignore it
+    // and answer with their whole catalog
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    // just numbers instead of the books. older nodes send the books anyway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<SummaryMode>,
+    // w3c traceparent of the requester's span, see traces.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListResponse {
+    pub mode: ListMode,
+    pub data: Library,
+    pub receiver: String,
+    // the search these books matched, None for a whole catalog
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    // answers a summary request, data is empty then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+    // w3c traceparent of the responder's span, so the requester can tie it
+    // to its request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<String>,
+    // the sender lets supernodes keep this catalog and answer searches with
+    // it while the sender is offline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rehost: Option<bool>,
+    // a supernode's matches from catalogs it keeps for other peers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relayed: Vec<Relayed>,
+    // goes up with every response the sender builds. floodsub doesn't keep
+    // order, and an answer that arrives late mustn't replace a newer one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+}
+
+// books of one peer, passed on by a supernode. they are as the peer last
+// sent them, the supernode can't vouch for more
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Relayed {
+    pub peer: String,
+    // unix time the supernode got the catalog
+    pub fetched_at: u64,
+    pub data: Library,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryMode {
+    Count,
+    ByAuthor,
+    ByPublisher,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Summary {
+    pub mode: SummaryMode,
+    pub total: usize,
+    // books per author or publisher, the largest MAX_SUMMARY_ENTRIES of them.
+    // empty for a plain count
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub counts: BTreeMap<String, usize>,
+}
+
+impl Summary {
+    pub fn of(books: &[Book], mode: SummaryMode) -> Summary {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for book in books {
+            let key = match mode {
+                SummaryMode::Count => continue,
+                SummaryMode::ByAuthor => &book.author,
+                SummaryMode::ByPublisher => &book.publisher,
+            };
+            *counts.entry(key.clone()).or_default() += 1;
+        }
+        if counts.len() > MAX_SUMMARY_ENTRIES {
+            let mut largest: Vec<(String, usize)> = counts.into_iter().collect();
+            // stable, so ties stay in name order
+            largest.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+            largest.truncate(MAX_SUMMARY_ENTRIES);
+            counts = largest.into_iter().collect();
+        }
+        Summary {
+            mode,
+            total: books.len(),
+            counts,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub text: String,
+    // recipient peer id for a direct message, None when said to everyone
+    pub to: Option<String>,
+    // set on direct messages to peers that acknowledge them, see Ack
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+}
+
+// an encoded message only `to` can read, signed by `from`. sealed and
+// signature are base64, the sealing itself is up to the node
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SealedMessage {
+    pub from: String,
+    pub to: String,
+    pub sealed: String,
+    pub signature: String,
+}
+
+// the whole library for our own other devices, encrypted with the key they
+// share. base64, opaque to everyone else on the topic
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncMessage {
+    pub data: String,
+}
+
+// announces that the node `old` now runs as `new`. both keys sign
+// "peer2peer key rotation\n{old}\n{new}\n{at}", signatures are base64
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub old: String,
+    pub new: String,
+    pub at: u64,
+    pub old_signature: String,
+    pub new_signature: String,
+}
+
+// tells peers caching `from`'s catalog that these books are no longer offered.
+// signed by `from` over "peer2peer tombstone\n{from}\n{ids joined by ,}\n{at}"
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub from: String,
+    pub ids: Vec<usize>,
+    pub at: u64,
+    pub signature: String,
+}
+
+// asks every peer to drop what it keeps about `from`: its cached catalog,
+// the books bookmarked from it and where to find it. signed by `from` over
+// "peer2peer forget\n{from}\n{at}"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Forget {
+    pub from: String,
+    pub at: u64,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Availability {
+    Online,
+    Away,
+    DoNotDisturb,
+}
+
+// sent on the presence topic as a heartbeat and whenever it changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Presence {
+    pub availability: Availability,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NackReason {
+    RateLimited,
+    NotTrusted,
+    // the catalog wouldn't fit in a single message
+    TooLarge,
+    // the node couldn't read its own library
+    Unavailable,
+}
+
+// tells `receiver` why its request won't be answered, rather than leaving it
+// waiting. only sent to peers announcing the "nack" capability
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Nack {
+    pub receiver: String,
+    pub reason: NackReason,
+    // seconds until asking again can succeed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+// sent back to `receiver` once its direct message with this id was shown
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Ack {
+    pub receiver: String,
+    pub id: u64,
+    // by the sender over both, an ack relayed by other peers can't be
+    // trusted to come from the floodsub source otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+// asks `peer` for everything it shares about one of its books, by id. sent
+// to the peer alone over the direct book protocol, never on a topic
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookRequest {
+    pub peer: String,
+    pub id: usize,
+    // w3c traceparent of the requester's span, see traces.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<String>,
+}
+
+// the answer to a BookRequest, over the same stream. None when there's no
+// such book or `receiver` may not see it, the two look the same on purpose
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookDetail {
+    pub receiver: String,
+    pub id: usize,
+    pub book: Option<Book>,
+    // the owner's copy of the book's file, when it has one on its disk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<FileAvailability>,
+    // w3c traceparent of the responder's span, so the requester can tie it
+    // to its request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<String>,
+}
+
+// what the owner of a book has of its file. the file itself comes through a
+// download link from the owner, or from ipfs when the book carries a cid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileAvailability {
+    pub format: Option<FileFormat>,
+    pub bytes: u64,
+}
+
+// what a book club reads and when, sent on the club's topic whenever it
+// changes and to members that are behind. the newest state wins
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClubState {
+    pub club: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub book: Option<ClubBook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<Milestone>,
+    // unix time of the last change, 0 for a club nobody set up yet
+    pub updated: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClubBook {
+    pub title: String,
+    pub author: String,
+}
+
+impl ClubBook {
+    // the same as Book::key, to find copies of it in catalogs
+    pub fn key(&self) -> (String, String) {
+        (normalize(&self.title), normalize(&self.author))
+    }
+}
+
+// read this far by unix time `by`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Milestone {
+    pub by: u64,
+    pub reading: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoanEvent {
+    Lent,
+    Returned,
+    // a new due date for a loan that's still out
+    Extended,
+}
+
+// one entry of the lending ledger two peers keep. one side proposes it with
+// its signature, the other countersigns and sends it back, then both store
+// it. a return or extension repeats the loan's id. each side signs
+// "peer2peer loan" and the fields from id to due, one per line, with 0 for no
+// due date
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoanRecord {
+    pub id: u32,
+    pub event: LoanEvent,
+    pub lender: String,
+    pub borrower: String,
+    // the lender's book id
+    pub book: usize,
+    pub title: String,
+    pub at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lender_signature: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub borrower_signature: Option<String>,
+}
+
+// asks one relay to hold a sealed message until its recipient is back
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Deposit {
+    pub relay: String,
+    pub message: SealedMessage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    ListRequest(ListRequest),
+    ListResponse(ListResponse),
+    Chat(ChatMessage),
+    Deposit(Deposit),
+    Sealed(SealedMessage),
+    Sync(SyncMessage),
+    Rotation(KeyRotation),
+    Tombstone(Tombstone),
+    Forget(Forget),
+    Presence(Presence),
+    Nack(Nack),
+    Ack(Ack),
+    // only on the direct book protocol, see details.rs
+    BookRequest(BookRequest),
+    // boxed, a whole book would make every message as large
+    BookDetail(Box<BookDetail>),
+    Club(ClubState),
+    Loan(LoanRecord),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope<M> {
+    v: u32,
+    #[serde(flatten)]
+    message: M,
+}
+
+pub fn encode(message: &Message) -> Vec<u8> {
+    let envelope = Envelope {
+        v: WIRE_VERSION,
+        message,
+    };
+    serde_json::to_vec(&envelope).expect("messages always serialize")
+}
+
+pub fn decode(data: &[u8]) -> serde_json::Result<Message> {
+    use serde::de::Error;
+    if data.len() > MAX_MESSAGE_SIZE {
+        return Err(Error::custom(format!("message of {} bytes is too large", data.len())));
+    }
+    if too_deep(data) {
+        return Err(Error::custom("message is nested too deeply"));
+    }
+    let value: serde_json::Value = serde_json::from_slice(data)?;
+    let message = if value.get("type").is_some() {
+        let envelope: Envelope<Message> = serde_json::from_value(value)?;
+        envelope.message
+    } else {
+        decode_v1(value)?
+    };
+    let count = match &message {
+        Message::ListResponse(res) => {
+            res.data.len()
+                + res.summary.as_ref().map_or(0, |s| s.counts.len())
+                + res.relayed.iter().map(|r| r.data.len()).sum::<usize>()
+        }
+        Message::Tombstone(tombstone) => tombstone.ids.len(),
+        _ => 0,
+    };
+    if count > MAX_BOOKS {
+        return Err(Error::custom(format!("message lists {} books", count)));
+    }
+    Ok(message)
+}
+
+// counts brackets outside of strings, so a hostile message can't make the
+// parser recurse before it's been looked at
+fn too_deep(data: &[u8]) -> bool {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in data {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > MAX_DEPTH {
+                    return true;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+    }
+    false
+}
+
+// v1 had no tag, so guess from the fields in the same order v1 nodes did
+fn decode_v1(value: serde_json::Value) -> serde_json::Result<Message> {
+    if let Ok(res) = serde_json::from_value(value.clone()) {
+        return Ok(Message::ListResponse(res));
+    }
+    if let Ok(chat) = serde_json::from_value(value.clone()) {
+        return Ok(Message::Chat(chat));
+    }
+    serde_json::from_value(value).map(Message::ListRequest)
+}
+
+// the books a requester may see: public ones, minus those limited to a group
+// the requester isn't in. `in_group` answers whether the requester is a member
+pub fn public_catalog(library: Library, in_group: impl Fn(&str) -> bool) -> Library {
+    catalog_for(library, in_group, false, &Visibility::default())
+}
+
+// what one peer gets. friends, the members of any of our groups, may be shown
+// fields others aren't
+pub fn catalog_for(
+    library: Library,
+    in_group: impl Fn(&str) -> bool,
+    friend: bool,
+    visibility: &Visibility,
+) -> Library {
+    library
+        .into_iter()
+        .filter(|b| b.public && b.trashed.is_none())
+        .filter(|b| match b.visible_to {
+            Some(ref group) => in_group(group),
+            None => true,
+        })
+        .map(|mut b| {
+            let hidden = b.hidden.take();
+            hide(&mut b, hidden.as_ref().unwrap_or(visibility).hidden(friend));
+            b
+        })
+        // group names, edit times, share ends, shelves, our disk and how our
+        // devices tell books apart are our own business
+        .map(|b| Book {
+            uid: String::new(),
+            visible_to: None,
+            modified: None,
+            revision: None,
+            shared_until: None,
+            location: None,
+            file: None,
+            ..b
+        })
+        .collect()
+}
+
+// the fields that can be left out of a catalog. title and author can't, they
+// are what it's for. the series takes the volume along, the reading status
+// when the book was read and the cid the file's format
+pub const HIDEABLE: &[&str] = &[
+    "publisher",
+    "series",
+    "rating",
+    "condition",
+    "copies",
+    "year",
+    "isbn",
+    "tags",
+    "status",
+    "cid",
+    "review",
+];
+
+// fields left out of what everyone gets, and of what friends get
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Visibility {
+    pub public: Vec<String>,
+    pub friends: Vec<String>,
+}
+
+// the reading status was kept to ourselves before it could be shared
+impl Default for Visibility {
+    fn default() -> Self {
+        Visibility {
+            public: vec!["status".to_owned()],
+            friends: vec!["status".to_owned()],
+        }
+    }
+}
+
+impl Visibility {
+    pub fn hidden(&self, friend: bool) -> &[String] {
+        if friend {
+            &self.friends
+        } else {
+            &self.public
+        }
+    }
+
+    // whether friends are shown what everyone else is
+    pub fn same_for_friends(&self) -> bool {
+        self.friends.iter().all(|f| self.public.contains(f))
+            && self.public.iter().all(|f| self.friends.contains(f))
+    }
+}
+
+fn hide(book: &mut Book, fields: &[String]) {
+    for field in fields {
+        match field.as_str() {
+            "publisher" => book.publisher.clear(),
+            "series" => {
+                book.series = None;
+                book.volume = None;
+            }
+            "rating" => book.rating = None,
+            "condition" => book.condition = None,
+            "copies" => book.copies = None,
+            "year" => book.year = None,
+            "isbn" => book.isbn = None,
+            "tags" => book.tags.clear(),
+            "status" => {
+                book.status = None;
+                book.read_at = None;
+            }
+            "cid" => {
+                book.cid = None;
+                book.format = None;
+            }
+            "review" => book.review = None,
+            _ => {}
+        }
+    }
+}
+
+pub fn agent_version(version: &str) -> String {
+    format!("peer2peer/{} ({})", version, CAPABILITIES.join(","))
+}
+
+// a nickname goes between the version and the capabilities, e.g.
+// "peer2peer/0.1.0 alice (chat,channels)", where older nodes skip over it
+pub fn named_agent_version(version: &str, name: &str) -> String {
+    format!("peer2peer/{} {} ({})", version, name, CAPABILITIES.join(","))
+}
+
+// short and free of spaces and brackets, so it can't break the agent version
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+// None for unnamed nodes and anything that isn't a library node
+pub fn parse_name(agent_version: &str) -> Option<String> {
+    let rest = agent_version.strip_prefix("peer2peer/")?;
+    let before = rest.split('(').next().unwrap_or_default();
+    let name = before.split_whitespace().nth(1)?;
+    if valid_name(name) {
+        Some(name.to_owned())
+    } else {
+        None
+    }
+}
+
+// what a node tells about its library before anyone asks for its catalog,
+// e.g. "Alice's SciFi Shelf" with 412 books
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Advert {
+    pub title: Option<String>,
+    pub books: Option<usize>,
+}
+
+pub const MAX_TITLE: usize = 64;
+
+// short and printable, a title is shown as it is
+pub fn valid_title(title: &str) -> bool {
+    !title.trim().is_empty()
+        && title.chars().count() <= MAX_TITLE
+        && !title.chars().any(char::is_control)
+}
+
+// goes after the nickname as "title=<percent-encoded>" and "books=<n>", e.g.
+// "peer2peer/0.1.0 alice title=Alice%27s%20Shelf books=412 (chat)". older
+// nodes skip over it like over the nickname
+pub fn advertised_agent_version(agent_version: &str, advert: &Advert) -> String {
+    let mut tokens = Vec::new();
+    if let Some(ref title) = advert.title {
+        tokens.push(format!("title={}", percent_encode(title)));
+    }
+    if let Some(books) = advert.books {
+        tokens.push(format!("books={}", books));
+    }
+    if tokens.is_empty() {
+        return agent_version.to_owned();
+    }
+    match agent_version.split_once(" (") {
+        Some((head, capabilities)) => format!("{} {} ({}", head, tokens.join(" "), capabilities),
+        None => format!("{} {}", agent_version, tokens.join(" ")),
+    }
+}
+
+// an empty advert for nodes that don't send one
+pub fn parse_advert(agent_version: &str) -> Advert {
+    let mut advert = Advert::default();
+    let rest = match agent_version.strip_prefix("peer2peer/") {
+        Some(rest) => rest,
+        None => return advert,
+    };
+    let before = rest.split('(').next().unwrap_or_default();
+    for token in before.split_whitespace().skip(1) {
+        if let Some(title) = token.strip_prefix("title=") {
+            advert.title = percent_decode(title).filter(|t| valid_title(t));
+        } else if let Some(books) = token.strip_prefix("books=") {
+            advert.books = books.parse().ok();
+        }
+    }
+    advert
+}
+
+// everything but letters, digits and -_.~ is escaped, spaces and brackets
+// included, so the title stays one token
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut rest = text.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+// None when the agent isn't a library node at all
+pub fn parse_capabilities(agent_version: &str) -> Option<BTreeSet<String>> {
+    let rest = agent_version.strip_prefix("peer2peer/")?;
+    let list = rest
+        .split_once('(')
+        .and_then(|(_, list)| list.strip_suffix(')'))
+        .unwrap_or_default();
+    Some(
+        list.split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .map(str::to_owned)
+            .collect(),
+    )
+}
@@ -0,0 +1,65 @@
+use crate::config::CONFIG;
+use libp2p::{swarm::ConnectionLimits, PeerId};
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+// hard limits enforced by the swarm itself, connections beyond them are refused
+pub fn connection_limits() -> ConnectionLimits {
+    ConnectionLimits::default()
+        .with_max_established(CONFIG.connections.max)
+        .with_max_established_per_peer(CONFIG.connections.max_per_peer)
+}
+
+// remembers when each connected peer last sent us something, so once
+// there are too many connections the idlest ones can be dropped first
+#[derive(Default)]
+pub struct Pruner {
+    last_useful: HashMap<PeerId, Instant>,
+    pruned: HashSet<PeerId>,
+}
+
+impl Pruner {
+    pub fn connected(&mut self, peer: PeerId) {
+        self.last_useful.insert(peer, Instant::now());
+    }
+
+    pub fn touch(&mut self, peer: &PeerId) {
+        if let Some(last) = self.last_useful.get_mut(peer) {
+            *last = Instant::now();
+        }
+    }
+
+    // returns true if the connection was closed because we pruned it
+    pub fn closed(&mut self, peer: &PeerId) -> bool {
+        self.last_useful.remove(peer);
+        self.pruned.remove(peer)
+    }
+
+    // least recently useful peers to disconnect to get back under prune_above
+    pub fn select(&mut self, keep: impl Fn(&PeerId) -> bool) -> Vec<PeerId> {
+        let limit = match CONFIG.connections.prune_above {
+            Some(limit) => limit as usize,
+            None => return Vec::new(),
+        };
+        let live = self.last_useful.len() - self.pruned.len();
+        if live <= limit {
+            return Vec::new();
+        }
+
+        let mut candidates: Vec<(PeerId, Instant)> = self
+            .last_useful
+            .iter()
+            .filter(|(peer, _)| !keep(peer) && !self.pruned.contains(peer))
+            .map(|(peer, last)| (*peer, *last))
+            .collect();
+        candidates.sort_by_key(|(_, last)| *last);
+
+        let victims: Vec<PeerId> = candidates
+            .into_iter()
+            .take(live - limit)
+            .map(|(peer, _)| peer)
+            .collect();
+        self.pruned.extend(victims.iter().copied());
+        victims
+    }
+}
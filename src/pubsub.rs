@@ -0,0 +1,165 @@
+use libp2p::core::connection::{ConnectionId, ListenerId};
+use libp2p::core::{ConnectedPoint, Multiaddr, PeerId};
+use libp2p::floodsub::protocol::FloodsubProtocol;
+use libp2p::floodsub::{Floodsub, FloodsubEvent, FloodsubMessage, FloodsubRpc};
+use libp2p::swarm::{
+    DialError, NetworkBehaviour, NetworkBehaviourAction, OneShotHandler, PollParameters,
+};
+use std::collections::{HashMap, VecDeque};
+use std::ops::{Deref, DerefMut};
+use std::task::{Context, Poll};
+
+// how many messages we remember the sender of, far more than arrive
+// between being received and being handled
+const REMEMBERED: usize = 4096;
+
+#[derive(Debug)]
+pub enum Received {
+    Rpc(FloodsubRpc),
+    Sent,
+}
+
+impl From<FloodsubRpc> for Received {
+    fn from(rpc: FloodsubRpc) -> Self {
+        Received::Rpc(rpc)
+    }
+}
+
+impl From<()> for Received {
+    fn from(_: ()) -> Self {
+        Received::Sent
+    }
+}
+
+type Handler = OneShotHandler<FloodsubProtocol, FloodsubRpc, Received>;
+
+// floodsub that remembers which connection each message came in on. the
+// source a message names is only the sender's claim, and floodsub relays
+// unsigned, but a message from the peer on the other end of the connection
+// is from whom that peer proved to be
+pub struct Pubsub {
+    floodsub: Floodsub,
+    via: HashMap<(PeerId, Vec<u8>), PeerId>,
+    order: VecDeque<(PeerId, Vec<u8>)>,
+}
+
+impl Pubsub {
+    pub fn new(floodsub: Floodsub) -> Self {
+        Pubsub {
+            floodsub,
+            via: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    // whether the message came straight from its source rather than through
+    // a peer relaying it, only then the source can be held to it
+    pub fn direct(&self, msg: &FloodsubMessage) -> bool {
+        let key = (msg.source, msg.sequence_number.clone());
+        self.via.get(&key) == Some(&msg.source)
+    }
+
+    fn remember(&mut self, via: PeerId, msg: &FloodsubMessage) {
+        let key = (msg.source, msg.sequence_number.clone());
+        // floodsub hands on the first copy to arrive and drops the others
+        if self.via.contains_key(&key) {
+            return;
+        }
+        self.via.insert(key.clone(), via);
+        self.order.push_back(key);
+        if self.order.len() > REMEMBERED {
+            if let Some(oldest) = self.order.pop_front() {
+                self.via.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl Deref for Pubsub {
+    type Target = Floodsub;
+
+    fn deref(&self) -> &Floodsub {
+        &self.floodsub
+    }
+}
+
+impl DerefMut for Pubsub {
+    fn deref_mut(&mut self) -> &mut Floodsub {
+        &mut self.floodsub
+    }
+}
+
+impl NetworkBehaviour for Pubsub {
+    type ConnectionHandler = Handler;
+    type OutEvent = FloodsubEvent;
+
+    fn new_handler(&mut self) -> Handler {
+        Default::default()
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer: &PeerId,
+        connection: &ConnectionId,
+        endpoint: &ConnectedPoint,
+        failed_addresses: Option<&Vec<Multiaddr>>,
+        other_established: usize,
+    ) {
+        self.floodsub.inject_connection_established(
+            peer,
+            connection,
+            endpoint,
+            failed_addresses,
+            other_established,
+        )
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer: &PeerId,
+        connection: &ConnectionId,
+        endpoint: &ConnectedPoint,
+        _: Handler,
+        remaining_established: usize,
+    ) {
+        let handler = self.floodsub.new_handler();
+        self.floodsub.inject_connection_closed(
+            peer,
+            connection,
+            endpoint,
+            handler,
+            remaining_established,
+        )
+    }
+
+    fn inject_event(&mut self, peer: PeerId, connection: ConnectionId, event: Received) {
+        // floodsub ignores its own sends
+        let rpc = match event {
+            Received::Rpc(rpc) => rpc,
+            Received::Sent => return,
+        };
+        for msg in &rpc.messages {
+            self.remember(peer, msg);
+        }
+        self.floodsub.inject_event(peer, connection, rpc.into())
+    }
+
+    fn inject_dial_failure(&mut self, peer: Option<PeerId>, _: Handler, error: &DialError) {
+        let handler = self.floodsub.new_handler();
+        self.floodsub.inject_dial_failure(peer, handler, error)
+    }
+
+    fn inject_new_listener(&mut self, id: ListenerId) {
+        self.floodsub.inject_new_listener(id)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        params: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<FloodsubEvent, Handler>> {
+        self.floodsub
+            .poll(cx, params)
+            .map(|action| action.map_handler(|_| Handler::default()))
+    }
+}
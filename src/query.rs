@@ -0,0 +1,155 @@
+// fielded searches that responders run over their own catalog, e.g.
+// `author:le_guin title:/disposs.*/ sea`. every term has to match. a term
+// without a field matches title, author or publisher, `_` stands for a space,
+// and a value between slashes is a pattern. all matching ignores case
+use crate::protocol::Book;
+use regex::{Regex, RegexBuilder};
+
+// queries arrive from strangers, so everything about them is bounded. the
+// regex crate never backtracks, a match takes time linear in the text
+pub const MAX_QUERY_LEN: usize = 256;
+pub const MAX_TERMS: usize = 8;
+pub const MAX_PATTERN_LEN: usize = 64;
+// bytes a compiled pattern and its lazily built dfa may take
+const MAX_PATTERN_SIZE: usize = 256 * 1024;
+const MAX_NESTING: u32 = 16;
+
+#[derive(Debug, Clone)]
+pub struct Query {
+    text: String,
+    terms: Vec<Term>,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, String> {
+        let text = input.trim();
+        if text.is_empty() {
+            return Err("empty query".to_owned());
+        }
+        if text.len() > MAX_QUERY_LEN {
+            return Err(format!("query is longer than {} bytes", MAX_QUERY_LEN));
+        }
+        let terms = text
+            .split_whitespace()
+            .map(Term::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if terms.len() > MAX_TERMS {
+            return Err(format!("query has more than {} terms", MAX_TERMS));
+        }
+        Ok(Query {
+            text: text.to_owned(),
+            terms,
+        })
+    }
+
+    // the query as typed, which is what goes on the wire
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn matches(&self, book: &Book) -> bool {
+        self.terms.iter().all(|term| term.matches(book))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Title,
+    Author,
+    Publisher,
+}
+
+impl Field {
+    fn parse(name: &str) -> Result<Field, String> {
+        match name.to_lowercase().as_str() {
+            "title" => Ok(Field::Title),
+            "author" => Ok(Field::Author),
+            "publisher" => Ok(Field::Publisher),
+            _ => Err(format!("unknown field {}, expected title, author or publisher", name)),
+        }
+    }
+
+    fn of(self, book: &Book) -> &str {
+        match self {
+            Field::Title => &book.title,
+            Field::Author => &book.author,
+            Field::Publisher => &book.publisher,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Term {
+    // None searches every field
+    field: Option<Field>,
+    matcher: Matcher,
+}
+
+impl Term {
+    fn parse(term: &str) -> Result<Term, String> {
+        // a bare pattern may contain a colon of its own
+        let (field, value) = match term.split_once(':') {
+            Some((name, value)) if !term.starts_with('/') => (Some(Field::parse(name)?), value),
+            _ => (None, term),
+        };
+        let matcher = match value.strip_prefix('/').and_then(|v| v.strip_suffix('/')) {
+            Some(pattern) => Matcher::Pattern(Pattern::compile(pattern)?),
+            None if value.is_empty() => return Err(format!("{} needs a value", term)),
+            None => Matcher::Words(value.replace('_', " ").to_lowercase()),
+        };
+        Ok(Term { field, matcher })
+    }
+
+    fn matches(&self, book: &Book) -> bool {
+        match self.field {
+            Some(field) => self.matcher.matches(field.of(book)),
+            None => [&book.title, &book.author, &book.publisher]
+                .iter()
+                .any(|value| self.matcher.matches(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    // anywhere in the field
+    Words(String),
+    Pattern(Pattern),
+}
+
+impl Matcher {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Matcher::Words(words) => value.to_lowercase().contains(words.as_str()),
+            Matcher::Pattern(pattern) => pattern.is_match(value),
+        }
+    }
+}
+
+// a regex, e.g. `^the\sd[a-z]+d$`, compiled with bounds on its size so a
+// stranger's pattern can't make the responder build a huge automaton
+#[derive(Debug, Clone)]
+struct Pattern(Regex);
+
+impl Pattern {
+    fn compile(pattern: &str) -> Result<Pattern, String> {
+        if pattern.is_empty() {
+            return Err("empty pattern".to_owned());
+        }
+        if pattern.len() > MAX_PATTERN_LEN {
+            return Err(format!("pattern is longer than {} bytes", MAX_PATTERN_LEN));
+        }
+        RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .size_limit(MAX_PATTERN_SIZE)
+            .dfa_size_limit(MAX_PATTERN_SIZE)
+            .nest_limit(MAX_NESTING)
+            .build()
+            .map(Pattern)
+            .map_err(|e| format!("invalid pattern {}: {}", pattern, e))
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        self.0.is_match(text)
+    }
+}
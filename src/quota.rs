@@ -0,0 +1,87 @@
+use crate::config::CONFIG;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaLimits {
+    pub responses_per_hour: Option<u32>,
+    pub bytes_per_hour: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct Usage {
+    window_start: Instant,
+    pub responses: u32,
+    pub bytes: u64,
+}
+
+impl Usage {
+    fn new() -> Self {
+        Usage {
+            window_start: Instant::now(),
+            responses: 0,
+            bytes: 0,
+        }
+    }
+}
+
+// how much each peer has made us serve in the current hour
+pub struct Quotas {
+    pub limits: QuotaLimits,
+    usage: HashMap<String, Usage>,
+}
+
+impl Quotas {
+    pub fn new() -> Self {
+        Quotas {
+            limits: QuotaLimits {
+                responses_per_hour: CONFIG.quota.responses_per_hour,
+                bytes_per_hour: CONFIG.quota.bytes_per_hour,
+            },
+            usage: HashMap::new(),
+        }
+    }
+
+    fn current(&mut self, peer: &str) -> &mut Usage {
+        let usage = self.usage.entry(peer.to_owned()).or_insert_with(Usage::new);
+        if usage.window_start.elapsed() >= WINDOW {
+            *usage = Usage::new();
+        }
+        usage
+    }
+
+    // counts the response against the peer's quota if it still has room. the
+    // quota is scaled by `factor`, from the peer's reputation
+    pub fn allow_response(&mut self, peer: &str, factor: f64) -> bool {
+        let limits = self.limits;
+        let usage = self.current(peer);
+        let scaled = |max: u64| ((max as f64 * factor) as u64).max(1);
+        let over_responses = matches!(limits.responses_per_hour,
+            Some(max) if u64::from(usage.responses) >= scaled(u64::from(max)));
+        let over_bytes = matches!(limits.bytes_per_hour, Some(max) if usage.bytes >= scaled(max));
+        if !over_responses && !over_bytes {
+            usage.responses += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    // seconds until the peer's current window is over
+    pub fn retry_after(&self, peer: &str) -> Option<u64> {
+        let usage = self.usage.get(peer)?;
+        Some(WINDOW.saturating_sub(usage.window_start.elapsed()).as_secs())
+    }
+
+    pub fn record_bytes(&mut self, peer: &str, bytes: usize) {
+        self.current(peer).bytes += bytes as u64;
+    }
+
+    pub fn usage(&self) -> impl Iterator<Item = (&String, &Usage)> {
+        self.usage
+            .iter()
+            .filter(|(_, usage)| usage.window_start.elapsed() < WINDOW)
+    }
+}
@@ -0,0 +1,47 @@
+use peer2peer::protocol::{Book, Library};
+use std::collections::HashMap;
+
+// what the network thinks of a book: the average of the ratings peers give
+// their own copies, one per peer
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Score {
+    sum: u32,
+    pub count: u32,
+}
+
+impl Score {
+    fn add(&mut self, rating: Option<u8>) {
+        if let Some(rating @ 1..=5) = rating {
+            self.sum += u32::from(rating);
+            self.count += 1;
+        }
+    }
+
+    pub fn average(&self) -> f64 {
+        f64::from(self.sum) / f64::from(self.count.max(1))
+    }
+}
+
+// the book as `from` just sent it, together with the copies in the catalogs
+// we received from everyone else. None when nobody rated it
+pub fn score(catalogs: &HashMap<String, Library>, from: &str, book: &Book) -> Option<Score> {
+    let key = book.key();
+    let mut score = Score::default();
+    score.add(book.rating);
+    for (_, catalog) in catalogs.iter().filter(|(peer, _)| peer.as_str() != from) {
+        // several copies on one shelf still make one rating
+        score.add(catalog.iter().filter(|b| b.key() == key).find_map(|b| b.rating));
+    }
+    if score.count == 0 {
+        return None;
+    }
+    Some(score)
+}
+
+pub fn describe(score: Option<Score>) -> String {
+    match score {
+        Some(score) if score.count == 1 => format!("rated {:.1} by 1 peer", score.average()),
+        Some(score) => format!("rated {:.1} by {} peers", score.average(), score.count),
+        None => "not rated".to_owned(),
+    }
+}
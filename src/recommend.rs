@@ -0,0 +1,75 @@
+use peer2peer::protocol::{Book, Library};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+// at most this many suggestions, best first
+const LIMIT: usize = 10;
+
+// a book we don't have, and the peers like us who offer it
+pub struct Recommendation {
+    pub title: String,
+    pub author: String,
+    pub score: f64,
+    // peer id, how alike our libraries are, and a few books we share
+    pub because: Vec<(String, f64, Vec<String>)>,
+}
+
+// how alike two shelves are, from 0 for nothing in common to 1 for the same
+// books (jaccard similarity)
+fn similarity(ours: &BTreeSet<(String, String)>, theirs: &BTreeSet<(String, String)>) -> f64 {
+    let common = ours.intersection(theirs).count();
+    let all = ours.union(theirs).count();
+    if all == 0 {
+        return 0.0;
+    }
+    common as f64 / all as f64
+}
+
+// books we don't own, scored by the summed similarity of the peers offering
+// them, so a book several like-minded peers have comes first. peers sharing
+// nothing with us don't count
+pub fn recommend(local: &Library, catalogs: &HashMap<String, Library>) -> Vec<Recommendation> {
+    let ours: BTreeSet<_> = local.iter().filter(|b| b.trashed.is_none()).map(Book::key).collect();
+    let titles: HashMap<_, _> = local.iter().map(|b| (b.key(), b.title.clone())).collect();
+    let mut candidates: BTreeMap<(String, String), Recommendation> = BTreeMap::new();
+    for (peer, catalog) in catalogs {
+        let theirs: BTreeSet<_> = catalog.iter().map(Book::key).collect();
+        let alike = similarity(&ours, &theirs);
+        if alike == 0.0 {
+            continue;
+        }
+        let in_common: Vec<String> = ours
+            .intersection(&theirs)
+            .filter_map(|k| titles.get(k).cloned())
+            .take(3)
+            .collect();
+        for book in catalog {
+            let k = book.key();
+            if ours.contains(&k) {
+                continue;
+            }
+            let candidate = candidates.entry(k).or_insert_with(|| Recommendation {
+                title: book.title.trim().to_owned(),
+                author: book.author.trim().to_owned(),
+                score: 0.0,
+                because: Vec::new(),
+            });
+            // a peer listing the same book twice still only vouches once
+            if candidate.because.iter().any(|(p, _, _)| p == peer) {
+                continue;
+            }
+            candidate.score += alike;
+            candidate.because.push((peer.clone(), alike, in_common.clone()));
+        }
+    }
+    let mut recommendations: Vec<Recommendation> = candidates.into_values().collect();
+    for r in recommendations.iter_mut() {
+        r.because.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal).then_with(|| a.0.cmp(&b.0))
+        });
+    }
+    // stable, so equal scores stay in title order
+    recommendations.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+    recommendations.truncate(LIMIT);
+    recommendations
+}
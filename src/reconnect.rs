@@ -0,0 +1,79 @@
+use libp2p::{Multiaddr, PeerId};
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(300);
+// bootstrap peers are retried forever, everyone else eventually given up on
+const MAX_ATTEMPTS: u32 = 12;
+
+struct Retry {
+    attempts: u32,
+    next_at: Instant,
+    addrs: Vec<Multiaddr>,
+    forever: bool,
+}
+
+// schedules redials of dropped peers with exponential backoff and jitter,
+// so a flaky link doesn't permanently shrink the swarm
+#[derive(Default)]
+pub struct Reconnector {
+    pending: HashMap<PeerId, Retry>,
+}
+
+impl Reconnector {
+    pub fn schedule(&mut self, peer: PeerId, addrs: Vec<Multiaddr>, forever: bool) {
+        if addrs.is_empty() {
+            return;
+        }
+        self.pending.insert(
+            peer,
+            Retry {
+                attempts: 0,
+                next_at: Instant::now() + backoff(0),
+                addrs,
+                forever,
+            },
+        );
+    }
+
+    // a dial to a scheduled peer failed, wait longer before the next attempt
+    pub fn failed(&mut self, peer: &PeerId) {
+        if let Some(retry) = self.pending.get_mut(peer) {
+            if !retry.forever && retry.attempts >= MAX_ATTEMPTS {
+                self.pending.remove(peer);
+            } else {
+                retry.next_at = Instant::now() + backoff(retry.attempts);
+            }
+        }
+    }
+
+    pub fn connected(&mut self, peer: &PeerId) {
+        self.pending.remove(peer);
+    }
+
+    // peers whose retry is due now. they stay scheduled until they connect or give up
+    pub fn due(&mut self) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let now = Instant::now();
+        self.pending
+            .iter_mut()
+            .filter(|(_, retry)| retry.next_at <= now)
+            .map(|(peer, retry)| {
+                retry.attempts += 1;
+                // push the deadline out in case the dial never reports back
+                retry.next_at = now + backoff(retry.attempts);
+                (*peer, retry.addrs.clone())
+            })
+            .collect()
+    }
+}
+
+fn backoff(attempts: u32) -> Duration {
+    let delay = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts))
+        .min(MAX_DELAY);
+    // +/- 25% so peers that dropped together don't all redial in lockstep
+    let jitter = rand::thread_rng().gen_range(0.75..1.25);
+    delay.mul_f64(jitter)
+}
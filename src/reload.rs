@@ -0,0 +1,146 @@
+use crate::config::{self, Config, PolicyConfig, PowerMode, CONFIG_PATH};
+use crate::quota::QuotaLimits;
+use libp2p::{Multiaddr, PeerId};
+use log::{error, LevelFilter};
+use peer2peer::settings;
+use std::time::SystemTime;
+
+// settings applied to a running node, by section or by key. any other setting
+// that changes waits for a restart. log_level and bootstrap are only partly
+// live, changes() decides those itself
+const LIVE: &[&str] = &[
+    "log_level",
+    "silent.enabled",
+    "policy",
+    "quota",
+    "power.mode",
+    "cache.max_bytes",
+    "channels",
+    "bootstrap",
+];
+
+// what changed in config.toml since it was last read
+#[derive(Default)]
+pub struct Changes {
+    pub log_level: Option<LevelFilter>,
+    pub silent: Option<bool>,
+    pub policy: Option<PolicyConfig>,
+    pub quota: Option<QuotaLimits>,
+    pub power: Option<PowerMode>,
+    pub cache: Option<usize>,
+    pub joined: Vec<String>,
+    pub left: Vec<String>,
+    pub bootstrap: Vec<(PeerId, Multiaddr)>,
+    // settings the swarm was built with, they wait for a restart
+    pub restart: Vec<String>,
+}
+
+// watches config.toml so settings that are safe to change on a running node
+// apply without dropping its connections
+pub struct Reloader {
+    modified: Option<SystemTime>,
+    config: Config,
+}
+
+impl Reloader {
+    pub fn new() -> Self {
+        Reloader {
+            modified: modified(),
+            config: config::read(CONFIG_PATH).unwrap_or_default(),
+        }
+    }
+
+    // polled every tick. a file that doesn't parse is reported once and the
+    // running settings are kept until it's saved again
+    pub fn check(&mut self) -> Option<Changes> {
+        let modified = modified();
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        let new = match config::read(CONFIG_PATH) {
+            Ok(new) => new,
+            Err(e) => {
+                error!("not reloading {}: {}", CONFIG_PATH, e);
+                return None;
+            }
+        };
+        let log_level = match new.log_level() {
+            Ok(level) => level,
+            Err(e) => {
+                error!("not reloading {}: {}", CONFIG_PATH, e);
+                return None;
+            }
+        };
+        let old = std::mem::replace(&mut self.config, new);
+        Some(changes(&old, &self.config, log_level))
+    }
+}
+
+fn changes(old: &Config, new: &Config, log_level: Option<LevelFilter>) -> Changes {
+    let mut changes = Changes::default();
+    if new.log_level != old.log_level {
+        // the logger only filters by level when the config set one at startup
+        match (&old.log_level, log_level) {
+            (Some(_), Some(level)) => changes.log_level = Some(level),
+            _ => changes.restart.push("log_level".to_owned()),
+        }
+    }
+    if new.silent.enabled != old.silent.enabled {
+        changes.silent = Some(new.silent.enabled);
+    }
+    if new.policy != old.policy {
+        changes.policy = Some(new.policy);
+    }
+    if new.quota != old.quota {
+        changes.quota = Some(QuotaLimits {
+            responses_per_hour: new.quota.responses_per_hour,
+            bytes_per_hour: new.quota.bytes_per_hour,
+        });
+    }
+    if new.power.mode != old.power.mode {
+        changes.power = Some(new.power.mode);
+    }
+    if new.cache.max_bytes != old.cache.max_bytes {
+        changes.cache = Some(new.cache.max_bytes);
+    }
+    changes.joined = new
+        .channels
+        .iter()
+        .filter(|c| !old.channels.contains(c))
+        .cloned()
+        .collect();
+    changes.left = old
+        .channels
+        .iter()
+        .filter(|c| !new.channels.contains(c))
+        .cloned()
+        .collect();
+    // new bootstrap peers are dialed, dropping one means not reconnecting to it
+    changes.bootstrap = new
+        .bootstrap_peers()
+        .into_iter()
+        .filter(|(_, addr)| !old.bootstrap.contains(&addr.to_string()))
+        .collect();
+    if old.bootstrap.iter().any(|addr| !new.bootstrap.contains(addr)) {
+        changes.restart.push("bootstrap".to_owned());
+    }
+
+    changes
+        .restart
+        .extend(settings::needing_restart(&table(old), &table(new), LIVE));
+    changes
+}
+
+// a config that can't be turned back into toml compares as empty, so its
+// changes are only reported as far as the fields above go
+fn table(config: &Config) -> toml::Value {
+    toml::Value::try_from(config).unwrap_or_else(|e| {
+        error!("can't compare {}: {}", CONFIG_PATH, e);
+        toml::Value::Table(Default::default())
+    })
+}
+
+fn modified() -> Option<SystemTime> {
+    std::fs::metadata(CONFIG_PATH).and_then(|m| m.modified()).ok()
+}
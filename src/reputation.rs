@@ -0,0 +1,128 @@
+use crate::store;
+use crate::unix_time;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub const REPUTATION_PATH: &str = "./reputation.json";
+const SAVE_EVERY: Duration = Duration::from_secs(60);
+const LOW_POWER_SAVE_EVERY: Duration = Duration::from_secs(10 * 60);
+// what each signal is worth. a returned loan takes trust on both sides, a
+// malformed message is cheap to send
+const LOAN: i64 = 10;
+const FAILED_TRANSFER: i64 = -2;
+const INVALID: i64 = -5;
+const SPAM: i64 = -3;
+const MAX: i64 = 100;
+const MIN: i64 = -100;
+// bad marks are halved once a week, so a peer that behaves again recovers
+const FORGIVE_EVERY: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub failed_transfers: u32,
+    pub invalid: u32,
+    pub spam: u32,
+    pub loans: u32,
+}
+
+impl Record {
+    pub fn score(&self) -> i64 {
+        let score = LOAN * i64::from(self.loans)
+            + FAILED_TRANSFER * i64::from(self.failed_transfers)
+            + INVALID * i64::from(self.invalid)
+            + SPAM * i64::from(self.spam);
+        score.clamp(MIN, MAX)
+    }
+
+    // how much of the configured quotas the peer gets
+    pub fn quota_factor(&self) -> f64 {
+        match self.score() {
+            s if s <= -20 => 0.25,
+            s if s < 0 => 0.5,
+            s if s >= 20 => 2.0,
+            _ => 1.0,
+        }
+    }
+}
+
+// how each peer behaved towards us over time, kept across restarts unlike
+// the scores of this run. it scales the quotas a peer is answered under
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Reputation {
+    peers: HashMap<String, Record>,
+    forgiven_at: u64,
+    #[serde(skip)]
+    last_save: Option<Instant>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl Reputation {
+    pub fn load() -> Self {
+        store::load(REPUTATION_PATH)
+    }
+
+    // written at most once a minute, every ten minutes in low power mode
+    pub fn save_if_due(&mut self, low_power: bool) {
+        self.forgive();
+        let every = if low_power { LOW_POWER_SAVE_EVERY } else { SAVE_EVERY };
+        if !self.dirty || matches!(self.last_save, Some(at) if at.elapsed() < every) {
+            return;
+        }
+        self.last_save = Some(Instant::now());
+        self.dirty = false;
+        if let Err(e) = store::save(REPUTATION_PATH, self) {
+            error!("unable to save reputation: {}", e);
+        }
+    }
+
+    fn forgive(&mut self) {
+        let now = unix_time();
+        if now.saturating_sub(self.forgiven_at) < FORGIVE_EVERY {
+            return;
+        }
+        if self.forgiven_at != 0 {
+            for record in self.peers.values_mut() {
+                record.failed_transfers /= 2;
+                record.invalid /= 2;
+                record.spam /= 2;
+            }
+        }
+        self.forgiven_at = now;
+        self.dirty = true;
+    }
+
+    fn record(&mut self, peer: &str) -> &mut Record {
+        self.dirty = true;
+        self.peers.entry(peer.to_owned()).or_default()
+    }
+
+    // asked for something it then couldn't or wouldn't deliver
+    pub fn failed_transfer(&mut self, peer: &str) {
+        self.record(peer).failed_transfers += 1;
+    }
+
+    pub fn invalid(&mut self, peer: &str) {
+        self.record(peer).invalid += 1;
+    }
+
+    // once for every time it went over the flood limit, not every message
+    pub fn spam(&mut self, peer: &str) {
+        self.record(peer).spam += 1;
+    }
+
+    // a loan that came back and both sides signed for
+    pub fn loan(&mut self, peer: &str) {
+        self.record(peer).loans += 1;
+    }
+
+    pub fn quota_factor(&self, peer: &str) -> f64 {
+        self.peers.get(peer).map(Record::quota_factor).unwrap_or(1.0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Record)> {
+        self.peers.iter()
+    }
+}
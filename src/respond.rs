@@ -0,0 +1,144 @@
+// how a node answers requests for its books, apart from where the library,
+// the groups and the ledger come from. the node and the network tests both
+// answer by these
+use crate::protocol::{
+    catalog_for, Book, FileAvailability, Library, ListMode, ListRequest, ListResponse, Summary,
+    SummaryMode, Visibility,
+};
+use crate::query::Query;
+
+// a catalog holding books of a group or fields for friends goes sealed to its
+// receiver, everyone on the topic sees the rest
+pub struct Catalog {
+    pub response: ListResponse,
+    pub sealed: bool,
+}
+
+// the peer asking, as far as what it may see goes
+pub struct Requester<'a> {
+    pub peer: String,
+    // whether it can open a catalog sealed to it
+    pub sealable: bool,
+    // a member of any of our groups
+    pub friend: bool,
+    pub in_group: &'a dyn Fn(&str) -> bool,
+}
+
+// our side of the answer
+pub struct Shelf<'a> {
+    pub books: Library,
+    pub visibility: &'a Visibility,
+    // unix time, shares that ended by then are left out
+    pub now: u64,
+    // whether a supernode may offer our catalog to others
+    pub rehost: bool,
+    // how many copies of the book are out on loan
+    pub lent_out: &'a dyn Fn(usize) -> u32,
+}
+
+// whether a list request is for us
+pub fn addressed_to(req: &ListRequest, us: &str) -> bool {
+    match req.mode {
+        ListMode::ALL => true,
+        ListMode::One(ref peer) => peer == us,
+    }
+}
+
+pub fn share_ended(book: &Book, now: u64) -> bool {
+    book.public && matches!(book.shared_until, Some(until) if until <= now)
+}
+
+// catalogs say how many copies are on the shelf rather than how many we own.
+// a book with every copy lent out is still listed, with 0
+fn with_available_copies(mut catalog: Library, lent_out: &dyn Fn(usize) -> u32) -> Library {
+    for book in catalog.iter_mut() {
+        let lent = lent_out(book.id);
+        if lent > 0 {
+            book.copies = Some(book.copies.unwrap_or(1).saturating_sub(lent));
+        }
+    }
+    catalog
+}
+
+// the catalog the requester gets, matching the query or summed up. trace,
+// relayed catalogs and version are the caller's to fill in
+pub fn catalog(
+    shelf: Shelf,
+    requester: &Requester,
+    query: Option<&Query>,
+    summary: Option<SummaryMode>,
+) -> Catalog {
+    let (visibility, lent_out) = (shelf.visibility, shelf.lent_out);
+    let mut books = shelf.books;
+    books.retain(|b| !share_ended(b, shelf.now));
+    let in_group = requester.in_group;
+    // a supernode could pass on books meant for a group or fields meant for
+    // friends, or keep offering a share after it ends
+    let rehost = shelf.rehost
+        && !books.iter().any(|b| {
+            b.shared_until.is_some()
+                || matches!(b.visible_to, Some(ref g) if in_group(g))
+                || requester.friend && !b.hidden.as_ref().unwrap_or(visibility).same_for_friends()
+        });
+    // anyone on the topic could read what isn't sealed, so a peer that can't
+    // open a sealed catalog gets what everyone sees
+    let restricted = requester.friend
+        || books
+            .iter()
+            .any(|b| matches!(b.visible_to, Some(ref g) if in_group(g)));
+    let sealed = restricted && requester.sealable;
+    let in_group = |group: &str| sealed && in_group(group);
+    let catalog = catalog_for(books, in_group, requester.friend && sealed, visibility);
+    let mut data = with_available_copies(catalog, lent_out);
+    // reviews only come with a book's details, they'd crowd catalogs out
+    for book in data.iter_mut() {
+        book.review = None;
+    }
+    if let Some(query) = query {
+        data.retain(|b| query.matches(b));
+    }
+    let summary = summary.map(|mode| Summary::of(&data, mode));
+    if summary.is_some() {
+        data.clear();
+    }
+    let response = ListResponse {
+        mode: ListMode::ALL,
+        query: query.map(|q| q.as_str().to_owned()),
+        summary,
+        receiver: requester.peer.clone(),
+        data,
+        trace: None,
+        rehost: if rehost { Some(true) } else { None },
+        relayed: Vec::new(),
+        version: None,
+    };
+    Catalog { response, sealed }
+}
+
+// the one book, if it's in the catalog the requester would get, with its
+// review. the details go to the requester alone, so friends get their fields
+pub fn book(shelf: Shelf, requester: &Requester, id: usize) -> Option<Book> {
+    let mut books = shelf.books;
+    books.retain(|b| b.id == id && !share_ended(b, shelf.now));
+    let in_group = requester.in_group;
+    let catalog = catalog_for(books, in_group, requester.friend, shelf.visibility);
+    with_available_copies(catalog, shelf.lent_out).pop()
+}
+
+// our copy of the book's file for its details, `bytes` being its size on our
+// disk. a requester the cid is hidden from isn't told about the file either
+pub fn file(
+    ours: &Book,
+    requester: &Requester,
+    visibility: &Visibility,
+    bytes: Option<u64>,
+) -> Option<FileAvailability> {
+    let hidden = ours.hidden.as_ref().unwrap_or(visibility).hidden(requester.friend);
+    match bytes {
+        Some(bytes) if !hidden.iter().any(|f| f == "cid") => Some(FileAvailability {
+            format: ours.format,
+            bytes,
+        }),
+        _ => None,
+    }
+}
@@ -0,0 +1,37 @@
+use std::time::{Duration, Instant, SystemTime};
+
+// longer than a peer waits for our pings before giving up on us
+const ASLEEP: Duration = Duration::from_secs(60);
+
+// notices the machine was suspended between two ticks. linux' monotonic
+// clock stands still during sleep while the wall clock moves on, elsewhere
+// the monotonic clock keeps going and the tick just comes late
+pub struct Resume {
+    last_tick: Instant,
+    last_wall: SystemTime,
+}
+
+impl Resume {
+    pub fn new() -> Self {
+        Resume {
+            last_tick: Instant::now(),
+            last_wall: SystemTime::now(),
+        }
+    }
+
+    // how long we were gone, if it was long enough for connections to die.
+    // a wall clock set forward by as much looks the same and is handled alike
+    pub fn slept(&mut self) -> Option<Duration> {
+        let (tick, wall) = (Instant::now(), SystemTime::now());
+        let ticked = tick.duration_since(self.last_tick);
+        let walled = wall.duration_since(self.last_wall).unwrap_or_default();
+        self.last_tick = tick;
+        self.last_wall = wall;
+        let gap = ticked.max(walled);
+        if gap >= ASLEEP {
+            Some(gap)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::activity::{self, Activity};
+use crate::bookmarks::Bookmarks;
+use crate::groups::Groups;
+use crate::pins::Pins;
+use crate::sealing;
+use crate::store;
+use crate::unix_time;
+use data_encoding::BASE64;
+use libp2p::{identity, PeerId};
+use log::{error, info};
+use peer2peer::protocol::KeyRotation;
+use serde::{Deserialize, Serialize};
+
+pub const ROTATIONS_PATH: &str = "./rotations.json";
+// peers offline longer than this have to be told the new peer id by hand
+const ANNOUNCE_FOR: u64 = 90 * 24 * 60 * 60;
+
+fn statement(old: &str, new: &str, at: u64) -> Vec<u8> {
+    format!("peer2peer key rotation\n{}\n{}\n{}", old, new, at).into_bytes()
+}
+
+// the old key vouches for the new one and the new one agrees, so nobody can
+// point a friend's trust at a key they don't hold
+pub fn sign(old: &identity::Keypair, new: &identity::Keypair) -> KeyRotation {
+    let old_id = PeerId::from(old.public()).to_string();
+    let new_id = PeerId::from(new.public()).to_string();
+    let at = unix_time();
+    let statement = statement(&old_id, &new_id, at);
+    let sign = |keys: &identity::Keypair| {
+        BASE64.encode(&keys.sign(&statement).expect("ed25519 signing can't fail"))
+    };
+    KeyRotation {
+        old_signature: sign(old),
+        new_signature: sign(new),
+        old: old_id,
+        new: new_id,
+        at,
+    }
+}
+
+pub fn verify(rotation: &KeyRotation) -> Option<(PeerId, PeerId)> {
+    let old: PeerId = rotation.old.parse().ok()?;
+    let new: PeerId = rotation.new.parse().ok()?;
+    let statement = statement(&rotation.old, &rotation.new, rotation.at);
+    for (peer, signature) in [(&old, &rotation.old_signature), (&new, &rotation.new_signature)] {
+        let signature = BASE64.decode(signature.as_bytes()).ok()?;
+        if !sealing::public_key(peer)?.verify(&statement, &signature) {
+            return None;
+        }
+    }
+    Some((old, new))
+}
+
+// moves the old peer id's group memberships, pinned name and bookmarks over
+// to the new one. unknown peers are left alone, and so are rotations seen before
+pub fn follow(old: &PeerId, new: &PeerId) {
+    let (old_id, new_id) = (old.to_string(), new.to_string());
+    let mut groups = Groups::load();
+    let moved = groups.replace_member(&old_id, &new_id);
+    let repinned = Pins::load().replace_peer(&old_id, &new_id);
+    let rebookmarked = Bookmarks::load().replace_peer(&old_id, &new_id);
+    if moved == 0 && !repinned && !rebookmarked {
+        return;
+    }
+    if moved > 0 {
+        if let Err(e) = groups.save() {
+            error!("error saving groups: {}", e);
+            return;
+        }
+    }
+    info!("{} moved to {}, updated {} groups", old, new, moved);
+    activity::record(Activity::KeyRotated {
+        old: old.to_string(),
+        new: new.to_string(),
+    });
+}
+
+// our own rotations, announced again whenever a peer subscribes so friends
+// that were offline at the time still follow
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Rotations {
+    own: Vec<KeyRotation>,
+}
+
+impl Rotations {
+    pub fn load() -> Self {
+        store::load(ROTATIONS_PATH)
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        store::save(ROTATIONS_PATH, self)
+    }
+
+    pub fn push(&mut self, rotation: KeyRotation) {
+        self.own.push(rotation);
+    }
+
+    pub fn recent(&self) -> impl Iterator<Item = &KeyRotation> {
+        let now = unix_time();
+        self.own.iter().filter(move |r| r.at + ANNOUNCE_FOR > now)
+    }
+}
@@ -0,0 +1,194 @@
+use crate::protocol::{Book, Library};
+use data_encoding::HEXLOWER;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+// version of the library file this build writes. bump it together with a new
+// entry in MIGRATIONS whenever the stored format changes
+pub const LIBRARY_SCHEMA: u64 = 12;
+
+// MIGRATIONS[n] turns a version n + 1 file into version n + 2
+const MIGRATIONS: &[fn(Value) -> Value] = &[
+    v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7, v7_to_v8, v8_to_v9, v9_to_v10,
+    v10_to_v11, v11_to_v12,
+];
+
+#[derive(Serialize, Deserialize)]
+struct StoredLibrary<B> {
+    schema: u64,
+    books: B,
+    // the last batch of the node's history the file holds, the file being a
+    // snapshot of the history
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    history: Option<u64>,
+}
+
+// version 1 was the bare array of books
+fn v1_to_v2(value: Value) -> Value {
+    json!({ "schema": 2, "books": value })
+}
+
+// version 3 added shared_until, which older versions would drop
+fn v2_to_v3(mut value: Value) -> Value {
+    value["schema"] = json!(3);
+    value
+}
+
+// version 4 added series and volume
+fn v3_to_v4(mut value: Value) -> Value {
+    value["schema"] = json!(4);
+    value
+}
+
+// version 5 added rating
+fn v4_to_v5(mut value: Value) -> Value {
+    value["schema"] = json!(5);
+    value
+}
+
+// version 6 added location, condition and copies
+fn v5_to_v6(mut value: Value) -> Value {
+    value["schema"] = json!(6);
+    value
+}
+
+// version 7 added year and isbn
+fn v6_to_v7(mut value: Value) -> Value {
+    value["schema"] = json!(7);
+    value
+}
+
+// version 8 added tags, status and read_at
+fn v7_to_v8(mut value: Value) -> Value {
+    value["schema"] = json!(8);
+    value
+}
+
+// version 9 added cid
+fn v8_to_v9(mut value: Value) -> Value {
+    value["schema"] = json!(9);
+    value
+}
+
+// version 10 added file
+fn v9_to_v10(mut value: Value) -> Value {
+    value["schema"] = json!(10);
+    value
+}
+
+// version 11 added uid, see parse for the books without one
+fn v10_to_v11(mut value: Value) -> Value {
+    value["schema"] = json!(11);
+    value
+}
+
+// version 12 added review
+fn v11_to_v12(mut value: Value) -> Value {
+    value["schema"] = json!(12);
+    value
+}
+
+// 128 random bits, for a book added on this device
+pub fn new_uid() -> String {
+    HEXLOWER.encode(&rand::random::<[u8; 16]>())
+}
+
+// for a book added before books had uids. derived from what the book was
+// then, so devices that synced it get the same one
+pub fn legacy_uid(book: &Book) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("peer2peer book\n{}\n{}\n{}", book.id, book.title, book.author));
+    HEXLOWER.encode(&hasher.finalize()[..16])
+}
+
+fn version(value: &Value) -> Result<u64> {
+    if value.is_array() {
+        return Ok(1);
+    }
+    value
+        .get("schema")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| "library has no schema version".into())
+}
+
+// reads any known version, and refuses files from newer builds rather than
+// dropping fields this one doesn't know about
+pub fn parse(content: &[u8]) -> Result<Library> {
+    let mut value: Value = serde_json::from_slice(content)?;
+    let mut version = version(&value)?;
+    if version == 0 {
+        return Err("library has schema 0, versions start at 1".into());
+    }
+    if version > LIBRARY_SCHEMA {
+        return Err(format!(
+            "library was written by a newer version (schema {}, this one knows up to {})",
+            version, LIBRARY_SCHEMA
+        )
+        .into());
+    }
+    while version < LIBRARY_SCHEMA {
+        value = MIGRATIONS[version as usize - 1](value);
+        version += 1;
+    }
+    let mut stored: StoredLibrary<Library> = serde_json::from_value(value)?;
+    // books from before uids get one derived from what they are, the same
+    // on every device that synced them
+    for book in stored.books.iter_mut().filter(|b| b.uid.is_empty()) {
+        book.uid = legacy_uid(book);
+    }
+    Ok(stored.books)
+}
+
+pub fn to_json(library: &Library) -> Result<String> {
+    to_snapshot(library, None)
+}
+
+// the library as of the given batch of the history
+pub fn to_snapshot(library: &Library, history: Option<u64>) -> Result<String> {
+    let stored = StoredLibrary {
+        schema: LIBRARY_SCHEMA,
+        books: library,
+        history,
+    };
+    Ok(serde_json::to_string(&stored)?)
+}
+
+// the batch of the history a current file was written at, None for a file
+// written before the library was kept as a snapshot
+pub fn history_of(content: &[u8]) -> Option<u64> {
+    let value: Value = serde_json::from_slice(content).ok()?;
+    match version(&value) {
+        Ok(LIBRARY_SCHEMA) => value.get("history")?.as_u64(),
+        _ => None,
+    }
+}
+
+// run once at startup. the old file is kept next to the new one in case
+// anything went wrong. a fresh data directory gets an empty library
+pub fn migrate(path: &str) -> Result<()> {
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            std::fs::write(path, to_json(&Vec::new())?)?;
+            return Ok(());
+        }
+        Err(_) => return Ok(()),
+    };
+    let found = version(&serde_json::from_slice(&content)?)?;
+    let library = parse(&content)?;
+    if found == LIBRARY_SCHEMA {
+        return Ok(());
+    }
+    let backup = format!("{}.v{}", path, found);
+    std::fs::write(&backup, &content)?;
+    std::fs::write(path, to_json(&library)?)?;
+    info!(
+        "migrated {} from schema {} to {}, the old file is in {}",
+        path, found, LIBRARY_SCHEMA, backup
+    );
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// a message we can't make sense of costs far more than a well formed one earns
+const INVALID_PENALTY: f64 = -10.0;
+const VALID_REWARD: f64 = 0.5;
+const MAX_SCORE: f64 = 20.0;
+// more messages than this per window counts as flooding, every extra one is penalized
+const FLOOD_WINDOW: Duration = Duration::from_secs(10);
+const FLOOD_LIMIT: u32 = 30;
+const FLOOD_PENALTY: f64 = -2.0;
+// scores drift back to zero so a bad spell is forgiven after a while
+const DECAY_EVERY: Duration = Duration::from_secs(60);
+const DECAY: f64 = 0.9;
+// peers below this are disconnected and their messages dropped until they recover
+const GRAYLIST_THRESHOLD: f64 = -50.0;
+
+#[derive(Debug)]
+pub struct PeerScore {
+    pub score: f64,
+    pub invalid: u32,
+    pub flooded: u32,
+    window_start: Instant,
+    in_window: u32,
+}
+
+impl PeerScore {
+    fn new() -> Self {
+        PeerScore {
+            score: 0.0,
+            invalid: 0,
+            flooded: 0,
+            window_start: Instant::now(),
+            in_window: 0,
+        }
+    }
+}
+
+// floodsub has no peer scoring of its own, so misbehaving message sources
+// are tracked here. scores are kept by originating peer, which is the only
+// sender floodsub tells us about
+pub struct PeerScores {
+    peers: HashMap<PeerId, PeerScore>,
+    last_decay: Instant,
+}
+
+impl PeerScores {
+    pub fn new() -> Self {
+        PeerScores {
+            peers: HashMap::new(),
+            last_decay: Instant::now(),
+        }
+    }
+
+    // counts an incoming message against the flood limit, true when it's the
+    // one that went over it
+    pub fn received(&mut self, peer: &PeerId) -> bool {
+        let entry = self.peers.entry(*peer).or_insert_with(PeerScore::new);
+        if entry.window_start.elapsed() >= FLOOD_WINDOW {
+            entry.window_start = Instant::now();
+            entry.in_window = 0;
+        }
+        entry.in_window += 1;
+        if entry.in_window > FLOOD_LIMIT {
+            entry.flooded += 1;
+            entry.score += FLOOD_PENALTY;
+        }
+        entry.in_window == FLOOD_LIMIT + 1
+    }
+
+    // flooding messages earn nothing even when they are well formed
+    pub fn valid(&mut self, peer: &PeerId) {
+        if let Some(entry) = self.peers.get_mut(peer) {
+            if entry.in_window <= FLOOD_LIMIT {
+                entry.score = (entry.score + VALID_REWARD).min(MAX_SCORE);
+            }
+        }
+    }
+
+    pub fn invalid(&mut self, peer: &PeerId) {
+        let entry = self.peers.entry(*peer).or_insert_with(PeerScore::new);
+        entry.invalid += 1;
+        entry.score += INVALID_PENALTY;
+    }
+
+    pub fn graylisted(&self, peer: &PeerId) -> bool {
+        matches!(self.peers.get(peer), Some(entry) if entry.score < GRAYLIST_THRESHOLD)
+    }
+
+    pub fn decay(&mut self) {
+        if self.last_decay.elapsed() < DECAY_EVERY {
+            return;
+        }
+        self.last_decay = Instant::now();
+        for entry in self.peers.values_mut() {
+            entry.score *= DECAY;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&PeerId, &PeerScore)> {
+        self.peers.iter()
+    }
+}
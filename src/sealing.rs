@@ -0,0 +1,127 @@
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use curve25519_dalek::{
+    constants::X25519_BASEPOINT, edwards::CompressedEdwardsY, montgomery::MontgomeryPoint,
+    scalar::Scalar,
+};
+use data_encoding::BASE64;
+use libp2p::{identity, PeerId};
+use peer2peer::protocol::{decode, encode, Message, SealedMessage};
+use sha2::{Digest, Sha256, Sha512};
+
+// sealed boxes addressed to a peer id. ed25519 peer ids contain the public key,
+// which converts to an x25519 key, so nothing has to be exchanged beforehand.
+// every box gets a fresh ephemeral key and is laid out as ephemeral key || ciphertext
+
+// None for peer ids that don't embed an ed25519 key
+pub fn public_key(peer: &PeerId) -> Option<identity::ed25519::PublicKey> {
+    let multihash = peer.as_ref();
+    // identity multihash, the digest is the protobuf encoded key itself
+    if multihash.code() != 0 {
+        return None;
+    }
+    match identity::PublicKey::from_protobuf_encoding(multihash.digest()) {
+        Ok(identity::PublicKey::Ed25519(key)) => Some(key),
+        _ => None,
+    }
+}
+
+fn clamp(mut bytes: [u8; 32]) -> Scalar {
+    bytes[0] &= 248;
+    bytes[31] &= 127;
+    bytes[31] |= 64;
+    Scalar::from_bits(bytes)
+}
+
+fn box_key(
+    shared: &MontgomeryPoint,
+    ephemeral: &MontgomeryPoint,
+    recipient: &MontgomeryPoint,
+) -> Key {
+    let mut hasher = Sha256::new();
+    hasher.update(shared.as_bytes());
+    hasher.update(ephemeral.as_bytes());
+    hasher.update(recipient.as_bytes());
+    Key::clone_from_slice(&hasher.finalize())
+}
+
+pub fn seal(to: &PeerId, plaintext: &[u8]) -> Option<Vec<u8>> {
+    let recipient = CompressedEdwardsY(public_key(to)?.encode())
+        .decompress()?
+        .to_montgomery();
+    let secret = clamp(rand::random());
+    let ephemeral = X25519_BASEPOINT * secret;
+    let key = box_key(&(recipient * secret), &ephemeral, &recipient);
+    // the key is never reused, so a fixed nonce is fine
+    let ciphertext = ChaCha20Poly1305::new(&key)
+        .encrypt(Nonce::from_slice(&[0; 12]), plaintext)
+        .ok()?;
+    let mut sealed = ephemeral.as_bytes().to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Some(sealed)
+}
+
+pub fn open(keys: &identity::Keypair, sealed: &[u8]) -> Option<Vec<u8>> {
+    let keypair = match keys {
+        identity::Keypair::Ed25519(keypair) => keypair,
+        _ => return None,
+    };
+    if sealed.len() < 32 {
+        return None;
+    }
+    let (ephemeral, ciphertext) = sealed.split_at(32);
+    let mut ephemeral_bytes = [0u8; 32];
+    ephemeral_bytes.copy_from_slice(ephemeral);
+    let ephemeral = MontgomeryPoint(ephemeral_bytes);
+
+    // the same scalar ed25519 signs with, derived from the seed
+    let mut scalar = [0u8; 32];
+    scalar.copy_from_slice(&Sha512::digest(keypair.secret().as_ref())[..32]);
+    let secret = clamp(scalar);
+    let recipient = X25519_BASEPOINT * secret;
+
+    let key = box_key(&(ephemeral * secret), &ephemeral, &recipient);
+    ChaCha20Poly1305::new(&key)
+        .decrypt(Nonce::from_slice(&[0; 12]), ciphertext)
+        .ok()
+}
+
+// the signature covers the recipient too, so a relay can't redirect a message
+fn signed_part(message: &SealedMessage) -> Vec<u8> {
+    format!("{}\n{}", message.to, message.sealed).into_bytes()
+}
+
+pub fn seal_message(
+    keys: &identity::Keypair,
+    to: &PeerId,
+    message: &Message,
+) -> Option<SealedMessage> {
+    let mut sealed = SealedMessage {
+        from: PeerId::from(keys.public()).to_string(),
+        to: to.to_string(),
+        sealed: BASE64.encode(&seal(to, &encode(message))?),
+        signature: String::new(),
+    };
+    sealed.signature = BASE64.encode(&keys.sign(&signed_part(&sealed)).ok()?);
+    Some(sealed)
+}
+
+// anyone can check who sealed a message, only the recipient can read it
+pub fn verify(message: &SealedMessage) -> bool {
+    let key = match message.from.parse().ok().as_ref().and_then(public_key) {
+        Some(key) => key,
+        None => return false,
+    };
+    match BASE64.decode(message.signature.as_bytes()) {
+        Ok(signature) => key.verify(&signed_part(message), &signature),
+        Err(_) => false,
+    }
+}
+
+pub fn open_message(keys: &identity::Keypair, message: &SealedMessage) -> Option<Message> {
+    if !verify(message) {
+        return None;
+    }
+    let sealed = BASE64.decode(message.sealed.as_bytes()).ok()?;
+    decode(&open(keys, &sealed)?).ok()
+}
@@ -0,0 +1,70 @@
+use peer2peer::protocol::{Book, Library};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+// a volume of one of our series we don't have, and who offers it
+pub struct Missing {
+    pub series: String,
+    pub volume: u32,
+    // peer ids and their copy's title, empty when nobody we heard from has it
+    pub offered_by: Vec<(String, String)>,
+}
+
+// series are told apart by name, ignoring case and surrounding spaces
+fn key(series: &str) -> String {
+    series.trim().to_lowercase()
+}
+
+fn volume_of(book: &Book) -> Option<(String, u32)> {
+    match (&book.series, book.volume) {
+        (Some(series), Some(volume)) => Some((key(series), volume)),
+        _ => None,
+    }
+}
+
+// gaps in every series we hold at least one volume of. a series runs up to
+// the highest volume we or any peer know of, so a missing last volume only
+// shows up once someone offers it
+pub fn missing_volumes(local: &Library, catalogs: &HashMap<String, Library>) -> Vec<Missing> {
+    // key -> (name as we wrote it, volumes we hold)
+    let mut held: BTreeMap<String, (String, BTreeSet<u32>)> = BTreeMap::new();
+    for book in local.iter().filter(|b| b.trashed.is_none()) {
+        if let (Some((key, volume)), Some(name)) = (volume_of(book), &book.series) {
+            let entry = held.entry(key).or_insert_with(|| (name.trim().to_owned(), BTreeSet::new()));
+            entry.1.insert(volume);
+        }
+    }
+    // sorted so the report doesn't change order from run to run
+    let mut peers: Vec<(&String, &Library)> = catalogs.iter().collect();
+    peers.sort_by_key(|(peer, _)| peer.as_str());
+
+    let mut missing = Vec::new();
+    for (key, (name, volumes)) in held {
+        let offered: Vec<(u32, &String, &Book)> = peers
+            .iter()
+            .flat_map(|(peer, catalog)| catalog.iter().map(move |book| (*peer, book)))
+            .filter_map(|(peer, book)| match volume_of(book) {
+                Some((k, volume)) if k == key => Some((volume, peer, book)),
+                _ => None,
+            })
+            .collect();
+        let last = offered
+            .iter()
+            .map(|(volume, _, _)| *volume)
+            .chain(volumes.iter().copied())
+            .max()
+            .unwrap_or_default();
+        for volume in (1..=last).filter(|v| !volumes.contains(v)) {
+            let offered_by = offered
+                .iter()
+                .filter(|(v, _, _)| *v == volume)
+                .map(|(_, peer, book)| (peer.to_string(), book.title.clone()))
+                .collect();
+            missing.push(Missing {
+                series: name.clone(),
+                volume,
+                offered_by,
+            });
+        }
+    }
+    missing
+}
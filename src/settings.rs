@@ -0,0 +1,63 @@
+// which settings differ between two readings of a config file. a setting is
+// named by its section and key, e.g. "power.mode", or by its key alone at the
+// top level, and compared as toml values, so a section added to the config
+// later is covered without being listed anywhere
+use std::collections::{BTreeMap, BTreeSet};
+use toml::Value;
+
+// every setting whose value differs, missing on one side counts as differing
+pub fn changed(old: &Value, new: &Value) -> Vec<String> {
+    let old = flatten(old);
+    let new = flatten(new);
+    let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    names
+        .into_iter()
+        .filter(|name| old.get(*name) != new.get(*name))
+        .cloned()
+        .collect()
+}
+
+// the changed settings that aren't in `live`, the ones applied while running.
+// a live name covers a whole section, e.g. "policy", or one key, e.g.
+// "power.mode". a section with no live keys is named once as a whole, e.g.
+// "api", the others by the keys that changed, e.g. "power.idle_minutes"
+pub fn needing_restart(old: &Value, new: &Value, live: &[&str]) -> Vec<String> {
+    let mut names = BTreeSet::new();
+    for name in changed(old, new) {
+        if live.iter().any(|l| covers(l, &name)) {
+            continue;
+        }
+        let section = name.split('.').next().unwrap_or(&name);
+        if live.iter().any(|l| l.starts_with(&format!("{}.", section))) {
+            names.insert(name.clone());
+        } else {
+            names.insert(section.to_owned());
+        }
+    }
+    names.into_iter().collect()
+}
+
+fn covers(live: &str, name: &str) -> bool {
+    name == live || name.starts_with(&format!("{}.", live))
+}
+
+// one level deep: a section's keys are settings, tables below them are
+// compared whole
+fn flatten(value: &Value) -> BTreeMap<String, &Value> {
+    let mut settings = BTreeMap::new();
+    if let Some(table) = value.as_table() {
+        for (section, value) in table {
+            match value.as_table() {
+                Some(keys) => {
+                    for (key, value) in keys {
+                        settings.insert(format!("{}.{}", section, key), value);
+                    }
+                }
+                None => {
+                    settings.insert(section.clone(), value);
+                }
+            }
+        }
+    }
+    settings
+}
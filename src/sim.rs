@@ -0,0 +1,211 @@
+// a deterministic stand-in for the network: virtual nodes publish messages
+// that reach every other node after a simulated latency, or get lost, all on
+// a virtual clock driven by a seeded random generator. the same seed always
+// gives the same run, so protocol changes can be tested reproducibly
+use crate::protocol::{decode, encode, Message};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// what a virtual node sees of the world while handling an event
+pub struct Context {
+    id: usize,
+    now: u64,
+    outgoing: Vec<Message>,
+}
+
+impl Context {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    // virtual milliseconds since the simulation started
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    // like floodsub: every other node gets a copy, each with its own latency and luck
+    pub fn publish(&mut self, message: Message) {
+        self.outgoing.push(message);
+    }
+}
+
+pub trait VirtualNode {
+    fn receive(&mut self, ctx: &mut Context, from: usize, message: Message);
+
+    // called on every node each tick, for retries and timeouts
+    fn tick(&mut self, _ctx: &mut Context) {}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Conditions {
+    // one way delay in virtual milliseconds, picked uniformly in between
+    pub min_latency: u64,
+    pub max_latency: u64,
+    // chance from 0.0 to 1.0 that a copy never arrives
+    pub loss: f64,
+    pub tick_every: u64,
+}
+
+impl Default for Conditions {
+    fn default() -> Self {
+        Conditions {
+            min_latency: 10,
+            max_latency: 50,
+            loss: 0.0,
+            tick_every: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub lost: u64,
+}
+
+// a copy in flight. seq breaks ties between copies due at the same time
+type Delivery = Reverse<(u64, u64, usize, usize, Vec<u8>)>;
+
+pub struct Simulation<N> {
+    nodes: Vec<N>,
+    conditions: Conditions,
+    rng: SplitMix64,
+    now: u64,
+    seq: u64,
+    next_tick: u64,
+    in_flight: BinaryHeap<Delivery>,
+    stats: Stats,
+}
+
+impl<N: VirtualNode> Simulation<N> {
+    pub fn new(seed: u64, conditions: Conditions) -> Self {
+        Simulation {
+            nodes: Vec::new(),
+            conditions,
+            rng: SplitMix64(seed),
+            now: 0,
+            seq: 0,
+            next_tick: conditions.tick_every,
+            in_flight: BinaryHeap::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn add_node(&mut self, node: N) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    pub fn node(&self, id: usize) -> &N {
+        &self.nodes[id]
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    // lets a test drive a node as if a command was typed on it
+    pub fn act(&mut self, id: usize, action: impl FnOnce(&mut N, &mut Context)) {
+        let mut ctx = self.context(id);
+        action(&mut self.nodes[id], &mut ctx);
+        self.send(ctx);
+    }
+
+    // handles everything due up to `until`, then leaves the clock there
+    pub fn run_until(&mut self, until: u64) {
+        loop {
+            let next_delivery = self.in_flight.peek().map(|Reverse(d)| d.0);
+            let tick_first = match next_delivery {
+                Some(at) => self.next_tick <= at,
+                None => true,
+            };
+            let next = if tick_first {
+                self.next_tick
+            } else {
+                next_delivery.expect("checked above")
+            };
+            if next > until {
+                break;
+            }
+            self.now = next;
+            if tick_first {
+                self.next_tick += self.conditions.tick_every;
+                for id in 0..self.nodes.len() {
+                    self.act(id, |node, ctx| node.tick(ctx));
+                }
+            } else {
+                self.deliver();
+            }
+        }
+        self.now = until;
+    }
+
+    pub fn run_for(&mut self, duration: u64) {
+        self.run_until(self.now + duration);
+    }
+
+    fn deliver(&mut self) {
+        let Reverse((_, _, to, from, data)) = self.in_flight.pop().expect("a delivery is due");
+        // through the real encoding, so the wire format is part of every run
+        let message = decode(&data).expect("simulated nodes only send valid messages");
+        self.stats.delivered += 1;
+        let mut ctx = self.context(to);
+        self.nodes[to].receive(&mut ctx, from, message);
+        self.send(ctx);
+    }
+
+    fn context(&self, id: usize) -> Context {
+        Context {
+            id,
+            now: self.now,
+            outgoing: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, ctx: Context) {
+        for message in ctx.outgoing {
+            let data = encode(&message);
+            for to in (0..self.nodes.len()).filter(|&to| to != ctx.id) {
+                self.stats.sent += 1;
+                if self.rng.chance(self.conditions.loss) {
+                    self.stats.lost += 1;
+                    continue;
+                }
+                let spread = self.conditions.max_latency.saturating_sub(self.conditions.min_latency);
+                let latency = self.conditions.min_latency + self.rng.below(spread + 1);
+                self.seq += 1;
+                self.in_flight
+                    .push(Reverse((self.now + latency, self.seq, to, ctx.id, data.clone())));
+            }
+        }
+    }
+}
+
+// small and good enough for picking latencies, and it keeps this library
+// free of dependencies beyond serde
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        // 53 random bits make a uniform float in [0, 1)
+        let sample = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        sample < probability
+    }
+}
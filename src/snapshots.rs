@@ -0,0 +1,71 @@
+use crate::Result;
+use peer2peer::protocol::Library;
+use peer2peer::schema;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+pub const SNAPSHOTS_DIR: &str = "./snapshots";
+const MAX_NAME: usize = 64;
+
+// a copy of the library under a name, to go back to after a bad import
+pub struct Snapshot {
+    pub name: String,
+    // unix time
+    pub created: u64,
+    pub books: usize,
+}
+
+// letters, digits, "-" and "_", so a name is also a file name
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn path(name: &str) -> PathBuf {
+    PathBuf::from(SNAPSHOTS_DIR).join(format!("{}.json", name))
+}
+
+// a name is taken once, a snapshot is never overwritten
+pub fn create(name: &str, library: &Library) -> Result<()> {
+    if !valid_name(name) {
+        return Err(format!("a name is up to {} letters, digits, - and _", MAX_NAME).into());
+    }
+    if path(name).exists() {
+        return Err(format!("there is a snapshot named {} already", name).into());
+    }
+    std::fs::create_dir_all(SNAPSHOTS_DIR)?;
+    std::fs::write(path(name), schema::to_json(library)?)?;
+    Ok(())
+}
+
+pub fn load(name: &str) -> Result<Library> {
+    if !valid_name(name) || !path(name).exists() {
+        return Err(format!("no snapshot named {}", name).into());
+    }
+    schema::parse(&std::fs::read(path(name))?)
+}
+
+// oldest first. files that don't read as a library are left out
+pub fn list() -> Vec<Snapshot> {
+    let entries = match std::fs::read_dir(SNAPSHOTS_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut snapshots: Vec<Snapshot> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?.strip_suffix(".json")?.to_owned();
+            let created = path.metadata().ok()?.modified().ok()?;
+            let created = created.duration_since(UNIX_EPOCH).ok()?.as_secs();
+            let books = load(&name).ok()?.len();
+            Some(Snapshot {
+                name,
+                created,
+                books,
+            })
+        })
+        .collect();
+    snapshots.sort_by_key(|s| s.created);
+    snapshots
+}
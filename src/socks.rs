@@ -0,0 +1,184 @@
+use data_encoding::BASE32;
+use libp2p::core::transport::{ListenerEvent, TransportError};
+use libp2p::futures::{
+    future::{self, BoxFuture},
+    io::{AsyncRead, AsyncWrite},
+    ready, stream, FutureExt,
+};
+use libp2p::{multiaddr::Protocol, Multiaddr, Transport};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+
+// dials every tcp address through a socks5 proxy such as tor. hostnames are
+// handed to the proxy unresolved so lookups don't leak past it.
+// listening is left to the plain tcp transport it is combined with
+#[derive(Debug, Clone, Copy)]
+pub struct Socks5Transport {
+    proxy: SocketAddr,
+}
+
+impl Socks5Transport {
+    pub fn new(proxy: SocketAddr) -> Self {
+        Socks5Transport { proxy }
+    }
+}
+
+enum Target {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+// /ip4, /ip6 and /dns* followed by /tcp, or /onion3. a trailing /p2p is fine
+fn target(addr: &Multiaddr) -> Option<Target> {
+    let mut parts = addr.iter();
+    let target = match (parts.next()?, parts.next()) {
+        (Protocol::Ip4(ip), Some(Protocol::Tcp(port))) => {
+            Target::Ip(SocketAddr::new(ip.into(), port))
+        }
+        (Protocol::Ip6(ip), Some(Protocol::Tcp(port))) => {
+            Target::Ip(SocketAddr::new(ip.into(), port))
+        }
+        (Protocol::Dns(host), Some(Protocol::Tcp(port)))
+        | (Protocol::Dns4(host), Some(Protocol::Tcp(port)))
+        | (Protocol::Dns6(host), Some(Protocol::Tcp(port))) => {
+            Target::Domain(host.into_owned(), port)
+        }
+        (Protocol::Onion3(onion), next) => {
+            let host = format!("{}.onion", BASE32.encode(onion.hash()).to_lowercase());
+            let target = Target::Domain(host, onion.port());
+            return match next {
+                None | Some(Protocol::P2p(_)) => Some(target),
+                _ => None,
+            };
+        }
+        _ => return None,
+    };
+    match parts.next() {
+        None | Some(Protocol::P2p(_)) => Some(target),
+        _ => None,
+    }
+}
+
+async fn connect(proxy: SocketAddr, target: Target) -> io::Result<ProxiedStream> {
+    let mut stream = TcpStream::connect(proxy).await?;
+
+    // version 5, one auth method: none
+    stream.write_all(&[5, 1, 0]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [5, 0] {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "socks5 proxy wants authentication",
+        ));
+    }
+
+    let mut req = vec![5, 1, 0];
+    let port = match target {
+        Target::Ip(SocketAddr::V4(addr)) => {
+            req.push(1);
+            req.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Target::Ip(SocketAddr::V6(addr)) => {
+            req.push(4);
+            req.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Target::Domain(host, port) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "hostname too long"))?;
+            req.push(3);
+            req.push(len);
+            req.extend_from_slice(host.as_bytes());
+            port
+        }
+    };
+    req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&req).await?;
+
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    if head[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("socks5 proxy could not connect, reply code {}", head[1]),
+        ));
+    }
+    // skip the bound address the proxy reports back
+    let bound_len = match head[3] {
+        1 => 4,
+        4 => 16,
+        3 => stream.read_u8().await? as usize,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad socks5 reply")),
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(ProxiedStream(stream))
+}
+
+impl Transport for Socks5Transport {
+    type Output = ProxiedStream;
+    type Error = io::Error;
+    type Listener =
+        stream::Pending<Result<ListenerEvent<Self::ListenerUpgrade, io::Error>, io::Error>>;
+    type ListenerUpgrade = future::Pending<Result<ProxiedStream, io::Error>>;
+    type Dial = BoxFuture<'static, Result<ProxiedStream, io::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<io::Error>> {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<io::Error>> {
+        match target(&addr) {
+            Some(target) => Ok(connect(self.proxy, target).boxed()),
+            None => Err(TransportError::MultiaddrNotSupported(addr)),
+        }
+    }
+
+    fn dial_as_listener(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<io::Error>> {
+        self.dial(addr)
+    }
+
+    fn address_translation(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+        None
+    }
+}
+
+// libp2p expects futures' io traits, tokio's stream implements its own
+pub struct ProxiedStream(TcpStream);
+
+impl AsyncRead for ProxiedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(tokio::io::AsyncRead::poll_read(Pin::new(&mut self.0), cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+impl AsyncWrite for ProxiedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.0), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_flush(Pin::new(&mut self.0), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_shutdown(Pin::new(&mut self.0), cx)
+    }
+}
@@ -0,0 +1,64 @@
+use log::error;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+// the node's small json files, e.g. peers.json or ledger.json, each read and
+// written whole. a missing file is an empty store, an unreadable one is moved
+// aside to <path>.corrupt for a person to look at before the next save
+// replaces it
+pub fn load<T: DeserializeOwned + Default>(path: &str) -> T {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(_) => return T::default(),
+    };
+    match serde_json::from_slice(&content) {
+        Ok(value) => value,
+        Err(e) => {
+            let aside = format!("{}.corrupt", path);
+            match fs::rename(path, &aside) {
+                Ok(()) => error!("unable to read {}, moved it to {}: {}", path, aside, e),
+                Err(moving) => error!("unable to read {}: {}, nor move it: {}", path, e, moving),
+            }
+            T::default()
+        }
+    }
+}
+
+pub fn save<T: Serialize>(path: &str, value: &T) -> io::Result<()> {
+    write(path, serde_json::to_vec(value)?)
+}
+
+// stores holding secrets, e.g. a network's pre-shared key or link tokens
+pub fn save_private<T: Serialize>(path: &str, value: &T) -> io::Result<()> {
+    write_private(path, serde_json::to_vec(value)?)
+}
+
+// written beside the file and renamed over it, so a crash leaves either the
+// old content or the new one, never half of it
+pub fn write(path: &str, content: impl AsRef<[u8]>) -> io::Result<()> {
+    replace(path, content.as_ref(), None)
+}
+
+// for files holding keys: only the user the node runs as may read them
+pub fn write_private(path: &str, content: impl AsRef<[u8]>) -> io::Result<()> {
+    replace(path, content.as_ref(), Some(0o600))
+}
+
+fn replace(path: &str, content: &[u8], mode: Option<u32>) -> io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    if let Some(mode) = mode {
+        options.mode(mode);
+    }
+    let mut file = options.open(&tmp)?;
+    // the mode only applies to a new file, not to one a crash left behind
+    if let Some(mode) = mode {
+        file.set_permissions(Permissions::from_mode(mode))?;
+    }
+    file.write_all(content)?;
+    file.sync_data()?;
+    fs::rename(&tmp, path)
+}
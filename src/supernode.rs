@@ -0,0 +1,161 @@
+use crate::store;
+use crate::unix_time;
+use log::{error, info};
+use peer2peer::protocol::{Library, Relayed};
+use peer2peer::query::Query;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+pub const AGGREGATE_PATH: &str = "./aggregate.json";
+// how often everyone is asked for their catalog again
+const REFRESH: Duration = Duration::from_secs(30 * 60);
+// a catalog not confirmed for this long is dropped, its peer may be gone
+const STALE: u64 = 7 * 24 * 60 * 60;
+// a peer that just subscribed may not know about us yet, its answer would go
+// nowhere
+const ASK_AFTER: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Held {
+    fetched_at: u64,
+    books: Library,
+}
+
+// the public catalogs of peers that let supernodes keep them, so searches
+// still find their books while they are offline. kept across restarts
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Supernode {
+    catalogs: BTreeMap<String, Held>,
+    #[serde(skip)]
+    refreshed: Option<Instant>,
+    // peers that showed up, to ask for their catalog soon
+    #[serde(skip)]
+    joined: HashMap<String, Instant>,
+}
+
+impl Supernode {
+    pub fn load() -> Self {
+        store::load(AGGREGATE_PATH)
+    }
+
+    fn save(&self) {
+        if let Err(e) = store::save(AGGREGATE_PATH, self) {
+            error!("unable to save aggregate: {}", e);
+        }
+    }
+
+    // true when it's time to ask for every catalog again. peers are asked
+    // when they show up, so not right after startup
+    pub fn refresh_due(&mut self) -> bool {
+        match self.refreshed {
+            Some(at) if at.elapsed() < REFRESH => false,
+            Some(_) => {
+                self.refreshed = Some(Instant::now());
+                true
+            }
+            None => {
+                self.refreshed = Some(Instant::now());
+                false
+            }
+        }
+    }
+
+    // a peer subscribes once per connection, it's asked once
+    pub fn joined(&mut self, peer: &str) {
+        self.joined.entry(peer.to_owned()).or_insert_with(Instant::now);
+    }
+
+    // peers to ask for their catalog now
+    pub fn take_joined(&mut self) -> Vec<String> {
+        let due: Vec<String> = self
+            .joined
+            .iter()
+            .filter(|(_, at)| at.elapsed() >= ASK_AFTER)
+            .map(|(peer, _)| peer.clone())
+            .collect();
+        for peer in &due {
+            self.joined.remove(peer);
+        }
+        due
+    }
+
+    // a whole catalog from a peer. one sent without consent, or with it
+    // taken back, replaces nothing and drops what we kept
+    pub fn received(&mut self, peer: &str, rehost: bool, books: &Library) {
+        if rehost {
+            let held = Held {
+                fetched_at: unix_time(),
+                books: books.clone(),
+            };
+            if self.catalogs.insert(peer.to_owned(), held).is_none() {
+                info!("keeping {}'s catalog of {} books for searches", peer, books.len());
+            }
+        } else if self.catalogs.remove(peer).is_some() {
+            info!("{} no longer lets supernodes keep its catalog, dropped it", peer);
+        } else {
+            return;
+        }
+        self.save();
+    }
+
+    pub fn forget(&mut self, peer: &str) -> bool {
+        if self.catalogs.remove(peer).is_none() {
+            return false;
+        }
+        self.save();
+        true
+    }
+
+    // matches from the catalogs of peers `skip` doesn't rule out, those
+    // online answer for themselves
+    pub fn matches(&self, query: &Query, skip: impl Fn(&str) -> bool) -> Vec<Relayed> {
+        self.catalogs
+            .iter()
+            .filter(|(peer, _)| !skip(peer))
+            .filter_map(|(peer, held)| {
+                let data: Library = held.books.iter().filter(|b| query.matches(b)).cloned().collect();
+                if data.is_empty() {
+                    return None;
+                }
+                Some(Relayed {
+                    peer: peer.clone(),
+                    fetched_at: held.fetched_at,
+                    data,
+                })
+            })
+            .collect()
+    }
+
+    pub fn expire(&mut self) {
+        let cutoff = unix_time().saturating_sub(STALE);
+        let before = self.catalogs.len();
+        self.catalogs.retain(|_, held| held.fetched_at >= cutoff);
+        if self.catalogs.len() != before {
+            info!("dropped {} catalogs not confirmed in a week", before - self.catalogs.len());
+            self.save();
+        }
+    }
+
+    pub fn peers(&self) -> usize {
+        self.catalogs.len()
+    }
+
+    pub fn books(&self) -> usize {
+        self.catalogs.values().map(|held| held.books.len()).sum()
+    }
+}
+
+// takes the last relayed book off a response that's too large, false when
+// nothing relayed is left
+pub fn trim(relayed: &mut Vec<Relayed>) -> bool {
+    let last = match relayed.last_mut() {
+        Some(last) => last,
+        None => return false,
+    };
+    last.data.pop();
+    if last.data.is_empty() {
+        relayed.pop();
+    }
+    true
+}
@@ -0,0 +1,168 @@
+use crate::conflicts::Base;
+use crate::deletions::Deletions;
+use crate::store;
+use crate::unix_time;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use data_encoding::BASE64;
+use libp2p::PeerId;
+use log::error;
+use peer2peer::protocol::{Book, Library, SyncMessage};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+pub const SYNC_STATE_PATH: &str = "./sync.json";
+const CHECK_EVERY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+struct Synced {
+    // edits after this were made apart, see conflicts::merge
+    synced_at: u64,
+    // the highest revision either side had then
+    #[serde(default)]
+    revision: u64,
+}
+
+// where we left off with each device. merging with one doesn't move where we
+// are with the others, their edits since are still made apart from ours
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncState {
+    #[serde(default)]
+    devices: BTreeMap<String, Synced>,
+    // from before it was kept per device, for devices not merged with since
+    #[serde(flatten)]
+    earlier: Synced,
+}
+
+// what goes to our other devices. older versions sent the bare library
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Replica {
+    pub books: Library,
+    #[serde(default)]
+    pub deleted: Deletions,
+}
+
+// replicates the whole library, private books included, between nodes of the
+// same owner. devices pair by sharing a secret, anything that decrypts with the
+// key derived from it came from one of our own devices
+pub struct DeviceSync {
+    key: Key,
+    // peers that sent us a library we could open
+    pub devices: BTreeSet<PeerId>,
+    last_sent: Option<String>,
+    // set when a peer that may be one of our devices shows up
+    resend: bool,
+    next_check: Instant,
+    state: SyncState,
+    // the highest revision in our library when it was last looked at
+    latest: u64,
+}
+
+impl DeviceSync {
+    pub fn new(secret: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"peer2peer device sync\n");
+        hasher.update(secret.as_bytes());
+        DeviceSync {
+            key: Key::clone_from_slice(&hasher.finalize()),
+            devices: BTreeSet::new(),
+            last_sent: None,
+            resend: true,
+            next_check: Instant::now(),
+            state: store::load(SYNC_STATE_PATH),
+            latest: 0,
+        }
+    }
+
+    // where we left off when we last merged with the device, and from now
+    // on it's here
+    pub fn start_merge(&mut self, device: &PeerId, remote: &Replica) -> Base {
+        let device = device.to_string();
+        let last = self.state.devices.get(&device).copied().unwrap_or(self.state.earlier);
+        let base = Base {
+            at: last.synced_at,
+            revision: last.revision,
+        };
+        let theirs = remote.books.iter().filter_map(|b| b.revision).max().unwrap_or(0);
+        let now = Synced {
+            synced_at: unix_time(),
+            revision: self.latest.max(theirs),
+        };
+        self.state.devices.insert(device, now);
+        if let Err(e) = store::save(SYNC_STATE_PATH, &self.state) {
+            error!("unable to save sync state: {}", e);
+        }
+        base
+    }
+
+    pub fn seal(&self, replica: &Replica) -> SyncMessage {
+        let nonce: [u8; 12] = rand::random();
+        let plaintext = serde_json::to_vec(replica).expect("libraries always serialize");
+        let ciphertext = ChaCha20Poly1305::new(&self.key)
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .expect("unable to encrypt library");
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+        SyncMessage {
+            data: BASE64.encode(&data),
+        }
+    }
+
+    // None when it was sealed with another owner's secret
+    pub fn open(&self, message: &SyncMessage) -> Option<Replica> {
+        let data = BASE64.decode(message.data.as_bytes()).ok()?;
+        if data.len() < 12 {
+            return None;
+        }
+        let (nonce, ciphertext) = data.split_at(12);
+        let plaintext = ChaCha20Poly1305::new(&self.key)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()?;
+        match serde_json::from_slice(&plaintext) {
+            Ok(replica) => Some(replica),
+            Err(_) => serde_json::from_slice(&plaintext).ok().map(|books| Replica {
+                books,
+                deleted: Deletions::default(),
+            }),
+        }
+    }
+
+    pub fn peer_appeared(&mut self) {
+        self.resend = true;
+    }
+
+    pub fn check_due(&mut self) -> bool {
+        if Instant::now() < self.next_check {
+            return false;
+        }
+        self.next_check = Instant::now() + CHECK_EVERY;
+        true
+    }
+
+    // whether this state still has to go out: it changed since the last
+    // send, or a device may have come back and missed it
+    pub fn should_send(&mut self, replica: &Replica, force: bool) -> bool {
+        self.latest = replica.books.iter().filter_map(|b| b.revision).max().unwrap_or(0);
+        let state = fingerprint(replica);
+        let send = force || self.resend || self.last_sent.as_ref() != Some(&state);
+        if send {
+            self.last_sent = Some(state);
+            self.resend = false;
+        }
+        send
+    }
+}
+
+// the same on devices holding the same books, whatever they number them
+pub fn fingerprint(replica: &Replica) -> String {
+    let mut books: Vec<Book> = replica
+        .books
+        .iter()
+        .map(|b| Book { id: 0, ..b.clone() })
+        .collect();
+    books.sort_by(|a, b| a.uid.cmp(&b.uid));
+    let deleted: Vec<_> = replica.deleted.iter().collect();
+    serde_json::to_string(&(books, deleted)).expect("libraries always serialize")
+}
@@ -0,0 +1,102 @@
+use log::{debug, info};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+// the first socket systemd passes, see sd_listen_fds(3)
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+// the api socket, when systemd listens for us and starts the node on the
+// first connection. the variables are cleared so nothing we spawn takes it
+#[cfg(unix)]
+pub fn listener() -> Option<std::net::TcpListener> {
+    use std::os::unix::io::FromRawFd;
+
+    let pid = std::env::var("LISTEN_PID").ok()?;
+    let fds: i32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    if pid.parse() != Ok(std::process::id()) || fds < 1 {
+        return None;
+    }
+    if fds > 1 {
+        info!("systemd passed {} sockets, only the first is used", fds);
+    }
+    // safe as systemd hands the fd over to us alone
+    let socket = unsafe { socket2::Socket::from_raw_fd(LISTEN_FDS_START) };
+    info!("api socket passed by systemd");
+    Some(socket.into())
+}
+
+#[cfg(not(unix))]
+pub fn listener() -> Option<std::net::TcpListener> {
+    None
+}
+
+// tells systemd startup is done, for Type=notify units. sent once, when the
+// swarm listens for peers
+pub fn ready() {
+    if !READY.swap(true, Ordering::Relaxed) {
+        notify("READY=1");
+    }
+}
+
+// WatchdogSec= in the unit: systemd restarts the node when the event loop
+// stops getting here
+pub struct Watchdog {
+    interval: Option<Duration>,
+    last: Instant,
+}
+
+impl Watchdog {
+    pub fn from_env() -> Self {
+        let usec = std::env::var("WATCHDOG_USEC").ok().and_then(|v| v.parse().ok());
+        let ours = match std::env::var("WATCHDOG_PID") {
+            Ok(pid) => pid.parse() == Ok(std::process::id()),
+            Err(_) => true,
+        };
+        Watchdog {
+            // twice as often as required, so one late tick isn't fatal
+            interval: usec.filter(|_| ours).map(|usec| Duration::from_micros(usec) / 2),
+            last: Instant::now(),
+        }
+    }
+
+    pub fn pet(&mut self) {
+        match self.interval {
+            Some(interval) if self.last.elapsed() >= interval => {
+                self.last = Instant::now();
+                notify("WATCHDOG=1");
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn notify(state: &str) {
+    use socket2::{Domain, SockAddr, Socket, Type};
+
+    let path = match std::env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    // "@" stands for linux's abstract namespace, which starts with a nul
+    let path = match path.strip_prefix('@') {
+        Some(name) => format!("\0{}", name),
+        None => path,
+    };
+    let sent = SockAddr::unix(&path).and_then(|addr| {
+        let socket = Socket::new(Domain::UNIX, Type::DGRAM, None)?;
+        socket.send_to(state.as_bytes(), &addr)
+    });
+    if let Err(e) = sent {
+        debug!("unable to notify systemd of {}: {}", state, e);
+    }
+}
+
+#[cfg(not(unix))]
+fn notify(_state: &str) {}
@@ -0,0 +1,138 @@
+use crate::config::CONFIG;
+use crate::notify;
+use libp2p::PeerId;
+use log::{error, info};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+const FORMAT: &str = "peer2peer telemetry";
+const VERSION: u32 = 1;
+// a node restarted every few minutes shouldn't report every few minutes
+const FIRST_AFTER: Duration = Duration::from_secs(60 * 60);
+const EVERY: Duration = Duration::from_secs(24 * 60 * 60);
+// distinct versions in a report, the rest are counted as OTHER
+const MAX_VERSIONS: usize = 32;
+const OTHER: &str = "other";
+// peers counted in a report, more are left out of it
+const MAX_PEERS: usize = 10_000;
+
+// everything a report holds. no peer ids, names, addresses or books, only
+// counts and version numbers
+#[derive(Debug, Serialize)]
+pub struct Report {
+    format: &'static str,
+    version: u32,
+    pub agent: &'static str,
+    // library nodes this node identified since the last report
+    pub peers_seen: usize,
+    // their versions and how many ran each
+    pub versions: BTreeMap<String, usize>,
+}
+
+// opted into by setting an endpoint, nothing is collected otherwise
+pub struct Telemetry {
+    started: Instant,
+    sent: Option<Instant>,
+    // kept only until the next report, to count each peer once
+    seen: HashMap<PeerId, String>,
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        Telemetry {
+            started: Instant::now(),
+            sent: None,
+            seen: HashMap::new(),
+        }
+    }
+
+    pub fn saw(&mut self, peer: PeerId, agent_version: &str) {
+        if !enabled() {
+            return;
+        }
+        let version = match version_of(agent_version) {
+            Some(version) => version,
+            None => return,
+        };
+        if self.seen.len() >= MAX_PEERS && !self.seen.contains_key(&peer) {
+            return;
+        }
+        let known = self.seen.values().any(|v| *v == version);
+        let distinct = self.seen.values().collect::<HashSet<_>>().len();
+        let version = match known || distinct < MAX_VERSIONS {
+            true => version,
+            false => OTHER.to_owned(),
+        };
+        self.seen.insert(peer, version);
+    }
+
+    pub fn report(&self) -> Report {
+        let mut versions = BTreeMap::new();
+        for version in self.seen.values() {
+            *versions.entry(version.clone()).or_default() += 1;
+        }
+        Report {
+            format: FORMAT,
+            version: VERSION,
+            agent: env!("CARGO_PKG_VERSION"),
+            peers_seen: self.seen.len(),
+            versions,
+        }
+    }
+
+    // the report to send now, if one is due. what was counted for it is
+    // forgotten, sent or not
+    pub fn due(&mut self) -> Option<Report> {
+        if !enabled() {
+            return None;
+        }
+        let due = match self.sent {
+            Some(at) => at.elapsed() >= EVERY,
+            None => self.started.elapsed() >= FIRST_AFTER,
+        };
+        if !due {
+            return None;
+        }
+        self.sent = Some(Instant::now());
+        let report = self.report();
+        self.seen.clear();
+        Some(report)
+    }
+}
+
+pub fn enabled() -> bool {
+    CONFIG.telemetry.endpoint.is_some()
+}
+
+pub fn send(report: Report) {
+    let endpoint = match CONFIG.telemetry.endpoint {
+        Some(ref endpoint) => endpoint,
+        None => return,
+    };
+    let body = match serde_json::to_string(&report) {
+        Ok(body) => body,
+        Err(_) => return,
+    };
+    // the event loop must not wait on the internet
+    tokio::task::spawn_blocking(move || match notify::request("POST", endpoint, None, &body) {
+        Ok(()) => info!("sent anonymous statistics to {}", endpoint),
+        Err(e) => error!("unable to send statistics to {}: {}", endpoint, e),
+    });
+}
+
+// "peer2peer/0.1.0 alice (chat,nack)" runs 0.1.0. the agent version is
+// whatever the peer says, so only plain major.minor.patch numbers are taken,
+// anything else could carry a name into the report
+fn version_of(agent_version: &str) -> Option<String> {
+    let rest = agent_version.strip_prefix("peer2peer/")?;
+    let version = rest.split(|c: char| c.is_whitespace() || c == '(').next()?;
+    let parts: Vec<&str> = version.split('.').collect();
+    let numeric = |part: &&str| {
+        !part.is_empty() && part.len() <= 5 && part.bytes().all(|b| b.is_ascii_digit())
+    };
+    match parts.len() == 3 && parts.iter().all(numeric) {
+        true => Some(version.to_owned()),
+        false => None,
+    }
+}
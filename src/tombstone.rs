@@ -0,0 +1,47 @@
+use crate::clock::MAX_SKEW;
+use crate::sealing;
+use crate::unix_time;
+use data_encoding::BASE64;
+use libp2p::{identity, PeerId};
+use peer2peer::protocol::Tombstone;
+
+// catalogs are only cached while a node runs, so a late tombstone has
+// nothing left to correct and is more likely a replay
+const FRESH_FOR: u64 = 10 * 60;
+
+fn statement(from: &str, ids: &[usize], at: u64) -> Vec<u8> {
+    let ids: Vec<String> = ids.iter().map(usize::to_string).collect();
+    format!("peer2peer tombstone\n{}\n{}\n{}", from, ids.join(","), at).into_bytes()
+}
+
+pub fn sign(keys: &identity::Keypair, ids: Vec<usize>) -> Tombstone {
+    let from = PeerId::from(keys.public()).to_string();
+    let at = unix_time();
+    let signature = keys
+        .sign(&statement(&from, &ids, at))
+        .expect("ed25519 signing can't fail");
+    Tombstone {
+        from,
+        ids,
+        at,
+        signature: BASE64.encode(&signature),
+    }
+}
+
+// the peer whose books are gone, if it really signed this recently. anyone
+// can pass a tombstone on, so the floodsub source doesn't matter
+pub fn verify(tombstone: &Tombstone) -> Option<PeerId> {
+    let now = unix_time();
+    // one dated ahead would stay fresh for as long as its clock is off
+    if tombstone.at + FRESH_FOR < now || tombstone.at > now + MAX_SKEW {
+        return None;
+    }
+    let from: PeerId = tombstone.from.parse().ok()?;
+    let signature = BASE64.decode(tombstone.signature.as_bytes()).ok()?;
+    let statement = statement(&tombstone.from, &tombstone.ids, tombstone.at);
+    if sealing::public_key(&from)?.verify(&statement, &signature) {
+        Some(from)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,257 @@
+use crate::config::CONFIG;
+use crate::Result;
+use data_encoding::HEXLOWER;
+use log::{debug, error};
+use once_cell::sync::OnceCell;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+    time,
+};
+
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+const TIMEOUT: Duration = Duration::from_secs(5);
+// answers to a broadcast trickle in, the request's own span ends with the last
+// one that came in this long after it was sent
+const REQUEST_WINDOW: Duration = Duration::from_secs(30);
+
+// otlp span kinds
+const SERVER: u8 = 2;
+const CLIENT: u8 = 3;
+
+static SPANS: OnceCell<mpsc::UnboundedSender<Value>> = OnceCell::new();
+
+fn nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+}
+
+// where a span sits in a trace, passed along as a w3c traceparent, e.g.
+// "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Context {
+    trace: String,
+    span: String,
+}
+
+impl Context {
+    fn new(trace: String) -> Self {
+        Context {
+            trace,
+            span: HEXLOWER.encode(&rand::random::<[u8; 8]>()),
+        }
+    }
+
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.split('-');
+        let (version, trace, span) = (parts.next()?, parts.next()?, parts.next()?);
+        let hex = |s: &str, len: usize| s.len() == len && HEXLOWER.decode(s.as_bytes()).is_ok();
+        if version != "00" || !hex(trace, 32) || !hex(span, 16) {
+            return None;
+        }
+        Some(Context {
+            trace: trace.to_owned(),
+            span: span.to_owned(),
+        })
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace, self.span)
+    }
+}
+
+pub struct Span {
+    name: String,
+    kind: u8,
+    context: Context,
+    parent: Option<String>,
+    start: u128,
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl Span {
+    // our side of a request that came with a trace, None when we don't export
+    // spans or the sender doesn't
+    pub fn serve(name: &str, traceparent: Option<&str>, peer: &str) -> Option<Span> {
+        SPANS.get()?;
+        let parent = Context::parse(traceparent?)?;
+        Some(Span {
+            name: name.to_owned(),
+            kind: SERVER,
+            context: Context::new(parent.trace),
+            parent: Some(parent.span),
+            start: nanos(),
+            attributes: vec![("peer", peer.to_owned())],
+        })
+    }
+
+    pub fn traceparent(&self) -> String {
+        self.context.traceparent()
+    }
+
+    pub fn end(self) {
+        self.end_at(nanos());
+    }
+
+    fn end_at(self, end: u128) {
+        let sender = match SPANS.get() {
+            Some(sender) => sender,
+            None => return,
+        };
+        let attributes: Vec<Value> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+            .collect();
+        let mut span = json!({
+            "traceId": self.context.trace,
+            "spanId": self.context.span,
+            "name": self.name,
+            "kind": self.kind,
+            "startTimeUnixNano": self.start.to_string(),
+            "endTimeUnixNano": end.to_string(),
+            "attributes": attributes,
+        });
+        if let Some(parent) = self.parent {
+            span["parentSpanId"] = json!(parent);
+        }
+        let _ = sender.send(span);
+    }
+}
+
+struct Request {
+    span: Span,
+    last: u128,
+}
+
+// requests we sent with a trace, waiting for their answers
+#[derive(Default)]
+pub struct Requests {
+    pending: HashMap<String, Request>,
+}
+
+impl Requests {
+    // the traceparent to send with a new request, None when we don't export
+    pub fn start(&mut self, name: &str) -> Option<String> {
+        SPANS.get()?;
+        let span = Span {
+            name: name.to_owned(),
+            kind: CLIENT,
+            context: Context::new(HEXLOWER.encode(&rand::random::<[u8; 16]>())),
+            parent: None,
+            start: nanos(),
+            attributes: Vec::new(),
+        };
+        let traceparent = span.traceparent();
+        let start = span.start;
+        self.pending.insert(span.context.trace.clone(), Request { span, last: start });
+        Some(traceparent)
+    }
+
+    // an answer to one of ours: its round trip becomes a span of the request
+    pub fn answered(&mut self, traceparent: Option<&str>, name: &str, peer: &str) {
+        let context = match traceparent.and_then(Context::parse) {
+            Some(context) => context,
+            None => return,
+        };
+        let request = match self.pending.get_mut(&context.trace) {
+            Some(request) => request,
+            None => return,
+        };
+        let now = nanos();
+        request.last = now;
+        Span {
+            name: name.to_owned(),
+            kind: CLIENT,
+            context: Context::new(context.trace),
+            parent: Some(request.span.context.span.clone()),
+            start: request.span.start,
+            attributes: vec![("peer", peer.to_owned())],
+        }
+        .end_at(now);
+    }
+
+    // ends the requests no more answers are expected for
+    pub fn expire(&mut self) {
+        let cutoff = nanos().saturating_sub(REQUEST_WINDOW.as_nanos());
+        let expired: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, request)| request.span.start < cutoff)
+            .map(|(trace, _)| trace.clone())
+            .collect();
+        for trace in expired {
+            if let Some(request) = self.pending.remove(&trace) {
+                let last = request.last;
+                request.span.end_at(last);
+            }
+        }
+    }
+}
+
+// sends finished spans to an otlp collector, e.g. jaeger, in batches
+pub fn start(endpoint: String) {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    if SPANS.set(sender).is_err() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = time::interval(EXPORT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut spans = Vec::new();
+            while let Ok(span) = receiver.try_recv() {
+                spans.push(span);
+            }
+            if spans.is_empty() {
+                continue;
+            }
+            match export(&endpoint, spans).await {
+                Ok(count) => debug!("exported {} spans", count),
+                Err(e) => error!("unable to export spans to {}: {}", endpoint, e),
+            }
+        }
+    });
+}
+
+// otlp over http with a json body, which collectors take on port 4318
+async fn export(endpoint: &str, spans: Vec<Value>) -> Result<usize> {
+    let count = spans.len();
+    let service = CONFIG.name.clone().unwrap_or_else(|| "peer2peer".to_owned());
+    let body = json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{ "key": "service.name", "value": { "stringValue": service } }]
+            },
+            "scopeSpans": [{ "scope": { "name": "peer2peer" }, "spans": spans }]
+        }]
+    })
+    .to_string();
+    let rest = endpoint
+        .strip_prefix("http://")
+        .ok_or("only http collectors are supported")?;
+    let host = rest.split('/').next().unwrap_or(rest);
+    let req = format!(
+        "POST /v1/traces HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\n\r\n{}",
+        host,
+        body.len(),
+        body
+    );
+    let mut stream = time::timeout(TIMEOUT, TcpStream::connect(host)).await??;
+    stream.write_all(req.as_bytes()).await?;
+    let mut res = Vec::new();
+    time::timeout(TIMEOUT, stream.read_to_end(&mut res)).await??;
+    let res = String::from_utf8_lossy(&res);
+    let status = res.lines().next().unwrap_or_default();
+    if !matches!(status.split_whitespace().nth(1), Some(code) if code.starts_with('2')) {
+        return Err(format!("collector answered: {}", status).into());
+    }
+    Ok(count)
+}
@@ -0,0 +1,72 @@
+use crate::store;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub const TRAFFIC_PATH: &str = "./traffic.json";
+const SAVE_EVERY: Duration = Duration::from_secs(60);
+const LOW_POWER_SAVE_EVERY: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Traffic {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+}
+
+// message traffic per peer, kept across restarts. sent counts what floodsub
+// put on the wire to that peer, which for a broadcast is every connected peer
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TrafficStats {
+    peers: HashMap<String, Traffic>,
+    #[serde(skip)]
+    last_save: Option<Instant>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl TrafficStats {
+    pub fn load() -> Self {
+        store::load(TRAFFIC_PATH)
+    }
+
+    // written at most once a minute, a crash loses at most that much. every
+    // ten minutes in low power mode
+    pub fn save_if_due(&mut self, low_power: bool) {
+        let every = if low_power { LOW_POWER_SAVE_EVERY } else { SAVE_EVERY };
+        if !self.dirty || matches!(self.last_save, Some(at) if at.elapsed() < every) {
+            return;
+        }
+        self.last_save = Some(Instant::now());
+        self.dirty = false;
+        if let Err(e) = store::save(TRAFFIC_PATH, self) {
+            error!("unable to save traffic stats: {}", e);
+        }
+    }
+
+    pub fn sent(&mut self, peer: &str, bytes: usize) {
+        let traffic = self.peers.entry(peer.to_owned()).or_default();
+        traffic.bytes_sent += bytes as u64;
+        traffic.messages_sent += 1;
+        self.dirty = true;
+    }
+
+    pub fn received(&mut self, peer: &str, bytes: usize) {
+        let traffic = self.peers.entry(peer.to_owned()).or_default();
+        traffic.bytes_received += bytes as u64;
+        traffic.messages_received += 1;
+        self.dirty = true;
+    }
+
+    pub fn forget(&mut self, peer: &str) -> bool {
+        let known = self.peers.remove(peer).is_some();
+        self.dirty |= known;
+        known
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Traffic)> {
+        self.peers.iter()
+    }
+}
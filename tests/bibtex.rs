@@ -0,0 +1,95 @@
+use peer2peer::bibtex::{export, parse};
+use peer2peer::protocol::Book;
+
+fn book(title: &str, author: &str) -> Book {
+    Book {
+        id: 1,
+        title: title.to_owned(),
+        author: author.to_owned(),
+        publisher: "Harper & Row".to_owned(),
+        public: true,
+        year: Some(1974),
+        isbn: Some("0-06-012563-2".to_owned()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn exports_books_with_unique_keys() {
+    let books = [
+        book("The Dispossessed", "Ursula K. Le Guin"),
+        book("The Dispossessed", "Ursula K. Le Guin"),
+    ];
+    let bib = export(&books);
+    let first = r#"@book{guin1974dispossessed,
+  title = {The Dispossessed},
+  author = {Ursula K. Le Guin},
+  publisher = {Harper \& Row},
+  year = {1974},
+  isbn = {0-06-012563-2},
+}
+"#;
+    assert!(bib.starts_with(first), "{}", bib);
+    assert!(bib.contains("@book{guin1974dispossessed2,"));
+}
+
+#[test]
+fn export_reads_back() {
+    let mut original = book("Sets {and} 100% of #things_", "Ursula K. Le Guin");
+    original.series = Some("Hainish Cycle".to_owned());
+    original.volume = Some(6);
+    let entries = parse(&export(&[original.clone()])).unwrap();
+    let imported = entries[0].book().unwrap();
+    assert_eq!(imported.title, original.title);
+    assert_eq!(imported.author, original.author);
+    assert_eq!(imported.publisher, original.publisher);
+    assert_eq!(imported.year, Some(1974));
+    assert_eq!(imported.isbn, original.isbn);
+    assert_eq!(imported.series, original.series);
+    assert_eq!(imported.volume, Some(6));
+    assert!(!imported.public);
+}
+
+#[test]
+fn imports_what_citation_managers_write() {
+    let bib = r#"
+% exported by a citation manager
+@comment{ignored @book{not, title = {a book}} }
+@string{hr = "Harper {\&} Row"}
+@Book{leguin_dispossessed_1974,
+    Title = {The {Dispossessed}: An {Ambiguous} {Utopia}},
+    author = "Le Guin, Ursula K. and Tolkien, J. R. R.",
+    publisher = hr # " (US)",
+    date = {1974-05},
+    ISBN = {978-0-06-051275-4},
+}
+@article(camus,
+    title = {L'{\'E}tranger et la chute du {\c c}a},
+    author = {Albert Camus},
+    journal = {Les Temps Modernes},
+    year = 1956
+)
+@misc{untitled, author = {Nobody}}
+"#;
+    let entries = parse(bib).unwrap();
+    assert_eq!(entries.len(), 3);
+    let dispossessed = entries[0].book().unwrap();
+    assert_eq!(dispossessed.title, "The Dispossessed: An Ambiguous Utopia");
+    assert_eq!(dispossessed.author, "Ursula K. Le Guin and J. R. R. Tolkien");
+    assert_eq!(dispossessed.publisher, "Harper & Row (US)");
+    assert_eq!(dispossessed.year, Some(1974));
+    assert_eq!(dispossessed.isbn.as_deref(), Some("978-0-06-051275-4"));
+    let camus = entries[1].book().unwrap();
+    assert_eq!(camus.title, "L'Étranger et la chute du ça");
+    assert_eq!(camus.publisher, "Les Temps Modernes");
+    assert_eq!(camus.year, Some(1956));
+    assert!(entries[2].book().is_none());
+}
+
+#[test]
+fn rejects_broken_files_with_a_line() {
+    let err = parse("@book{a,\n  title = {unclosed,\n}").unwrap_err();
+    assert!(err.starts_with("line "), "{}", err);
+    assert!(parse("@book{a, title}").is_err());
+    assert!(parse("no entries here").unwrap().is_empty());
+}
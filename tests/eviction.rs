@@ -0,0 +1,39 @@
+use peer2peer::eviction::{to_evict, Eviction, Held};
+
+fn held(id: u64, bytes: u64, used: u64, favorite: bool) -> Held {
+    Held {
+        id,
+        bytes,
+        used,
+        favorite,
+    }
+}
+
+#[test]
+fn nothing_goes_while_it_fits() {
+    let held = [held(1, 40, 10, false), held(2, 40, 20, false)];
+    assert_eq!(to_evict(&held, 20, 100, Eviction::Lru), Some(vec![]));
+    assert_eq!(to_evict(&[], 100, 100, Eviction::Lru), Some(vec![]));
+}
+
+#[test]
+fn least_recently_used_goes_first() {
+    let held = [held(1, 40, 30, false), held(2, 40, 10, false), held(3, 20, 20, false)];
+    assert_eq!(to_evict(&held, 30, 100, Eviction::Lru), Some(vec![2]));
+    assert_eq!(to_evict(&held, 50, 100, Eviction::Lru), Some(vec![2, 3]));
+}
+
+#[test]
+fn favorites_stay_when_kept() {
+    let held = [held(1, 40, 10, true), held(2, 40, 20, false)];
+    assert_eq!(to_evict(&held, 50, 100, Eviction::Lru), Some(vec![1]));
+    assert_eq!(to_evict(&held, 50, 100, Eviction::KeepFavorites), Some(vec![2]));
+    assert_eq!(to_evict(&held, 70, 100, Eviction::KeepFavorites), None);
+}
+
+#[test]
+fn larger_than_the_cap_never_fits() {
+    let held = [held(1, 40, 10, false)];
+    assert_eq!(to_evict(&held, 101, 100, Eviction::Lru), None);
+    assert_eq!(to_evict(&held, u64::MAX, 100, Eviction::Lru), None);
+}
@@ -0,0 +1,53 @@
+use peer2peer::formats::{check, claimed, detect};
+use peer2peer::protocol::FileFormat;
+
+fn epub() -> Vec<u8> {
+    let mut head = b"PK\x03\x04".to_vec();
+    head.resize(30, 0);
+    head.extend_from_slice(b"mimetypeapplication/epub+zip");
+    head.extend_from_slice(b"PK\x03\x04");
+    head
+}
+
+fn mobi() -> Vec<u8> {
+    let mut head = b"Dune".to_vec();
+    head.resize(60, 0);
+    head.extend_from_slice(b"BOOKMOBI");
+    head
+}
+
+#[test]
+fn formats_are_detected_by_magic_bytes() {
+    assert_eq!(detect(b"%PDF-1.7\n%\xe2\xe3"), Some(FileFormat::Pdf));
+    assert_eq!(detect(&epub()), Some(FileFormat::Epub));
+    assert_eq!(detect(&mobi()), Some(FileFormat::Mobi));
+    // any other zip, e.g. a cbz comic, isn't an epub
+    let mut zip = b"PK\x03\x04".to_vec();
+    zip.resize(70, b'x');
+    assert_eq!(detect(&zip), None);
+    assert_eq!(detect(b""), None);
+}
+
+#[test]
+fn extensions_claim_formats() {
+    assert_eq!(claimed("/books/Dune.EPUB"), Some(FileFormat::Epub));
+    assert_eq!(claimed("dune.azw3"), Some(FileFormat::Mobi));
+    assert_eq!(claimed("notes.txt"), None);
+    assert_eq!(claimed("README"), None);
+}
+
+#[test]
+fn mismatches_are_rejected() {
+    assert_eq!(check("dune.epub", &epub()), Ok(Some(FileFormat::Epub)));
+    assert_eq!(
+        check("dune.epub", b"%PDF-1.4"),
+        Err("dune.epub is named as EPUB but is PDF".to_owned())
+    );
+    assert_eq!(
+        check("dune.pdf", b"<html>"),
+        Err("dune.pdf isn't a valid PDF file".to_owned())
+    );
+    // unnamed formats pass, with what they turn out to be
+    assert_eq!(check("notes.txt", b"plain"), Ok(None));
+    assert_eq!(check("dune", &mobi()), Ok(Some(FileFormat::Mobi)));
+}
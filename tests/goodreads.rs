@@ -0,0 +1,58 @@
+use peer2peer::goodreads::parse;
+use peer2peer::protocol::ReadingStatus;
+
+const GOODREADS: &str = r#"Book Id,Title,Author,Author l-f,Additional Authors,ISBN,ISBN13,My Rating,Average Rating,Publisher,Binding,Number of Pages,Year Published,Original Publication Year,Date Read,Date Added,Bookshelves,Bookshelves with positions,Exclusive Shelf,My Review,Spoiler,Private Notes,Read Count,Owned Copies
+5,"The Fall",Albert Camus,"Camus, Albert",Justin O'Brien,"=""0679720227""","=""9780679720225""",4,3.99,Vintage,Paperback,147,1991,1956,2019/03/14,2019/02/01,"philosophy, read","philosophy (#1), read (#40)",read,"Short, and
+bitter.",,,1,0
+9,Dune,Frank Herbert,"Herbert, Frank",,"=""""","=""""",0,4.27,Ace,Paperback,658,2005,1965,,2020/01/05,"sf, to-read","sf (#2), to-read (#7)",to-read,,,,0,0
+"#;
+
+const STORYGRAPH: &str = "\u{feff}Title,Authors,Contributors,ISBN/UID,Format,Read Status,Date Added,\
+Last Date Read,Dates Read,Read Count,Moods,Pace,Character- or Plot-Driven?,Star Rating,Review,Tags,Owned?\r\n\
+Piranesi,Susanna Clarke,,9781635575996,paperback,did-not-finish,2021/05/01,,,0,,,,3.75,,\"fantasy, book club\",No\r\n";
+
+#[test]
+fn goodreads_shelves_become_tags_and_status() {
+    let books = parse(GOODREADS).unwrap();
+    assert_eq!(books.len(), 2);
+    let fall = &books[0];
+    assert_eq!(fall.title, "The Fall");
+    assert_eq!(fall.author, "Albert Camus, Justin O'Brien");
+    assert_eq!(fall.publisher, "Vintage");
+    assert_eq!(fall.isbn.as_deref(), Some("9780679720225"));
+    assert_eq!(fall.year, Some(1956));
+    assert_eq!(fall.rating, Some(4));
+    assert_eq!(fall.tags, vec!["philosophy".to_owned()]);
+    assert_eq!(fall.status, Some(ReadingStatus::Read));
+    assert_eq!(fall.read_at, Some(1_552_521_600));
+    assert!(!fall.public);
+}
+
+#[test]
+fn goodreads_leaves_out_what_is_unknown() {
+    let dune = &parse(GOODREADS).unwrap()[1];
+    assert_eq!(dune.isbn, None);
+    assert_eq!(dune.rating, None);
+    assert_eq!(dune.read_at, None);
+    assert_eq!(dune.tags, vec!["sf".to_owned()]);
+    assert_eq!(dune.status, Some(ReadingStatus::ToRead));
+}
+
+#[test]
+fn storygraph_rounds_stars_and_keeps_tags() {
+    let books = parse(STORYGRAPH).unwrap();
+    assert_eq!(books.len(), 1);
+    let piranesi = &books[0];
+    assert_eq!(piranesi.author, "Susanna Clarke");
+    assert_eq!(piranesi.isbn.as_deref(), Some("9781635575996"));
+    assert_eq!(piranesi.rating, Some(4));
+    assert_eq!(piranesi.tags, vec!["fantasy".to_owned(), "book club".to_owned()]);
+    assert_eq!(piranesi.status, Some(ReadingStatus::Abandoned));
+}
+
+#[test]
+fn other_csv_files_are_refused() {
+    assert!(parse("name,email\nann,ann@example.org\n").is_err());
+    assert!(parse("Title,Author\n\"The Fall,Camus\n").unwrap_err().contains("line 2"));
+    assert!(parse("").unwrap().is_empty());
+}
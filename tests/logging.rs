@@ -0,0 +1,73 @@
+use log::Level;
+use peer2peer::logging::{Entry, FileSink, Sink};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let name = format!("peer2peer-logging-{}-{}", name, std::process::id());
+    let dir = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn entry(message: &str) -> Entry {
+    Entry {
+        time: 1_700_000_000_000,
+        level: Level::Info,
+        target: "peer2peer".to_owned(),
+        message: message.to_owned(),
+    }
+}
+
+fn lines(path: &Path) -> Vec<String> {
+    std::fs::read_to_string(path).unwrap().lines().map(str::to_owned).collect()
+}
+
+#[test]
+fn entries_are_json_lines() {
+    let dir = temp_dir("lines");
+    let sink = FileSink::open(dir.join("node.log"), None, 3).unwrap();
+    sink.write(&entry("hello \"peers\""));
+    sink.write(&entry("second"));
+    sink.flush();
+    let lines = lines(&dir.join("node.log"));
+    assert_eq!(
+        lines[0],
+        r#"{"level":"INFO","message":"hello \"peers\"","target":"peer2peer","time":1700000000000}"#
+    );
+    assert_eq!(lines.len(), 2);
+}
+
+#[test]
+fn rotates_past_max_bytes_and_keeps_a_few() {
+    let dir = temp_dir("rotate");
+    let path = dir.join("node.log");
+    let rotated = Arc::new(Mutex::new(Vec::new()));
+    let seen = rotated.clone();
+    let sink = FileSink::open(&path, Some(1), 2)
+        .unwrap()
+        .on_rotate(move |p| seen.lock().unwrap().push(p.to_owned()));
+    for message in ["one", "two", "three"] {
+        sink.write(&entry(message));
+    }
+    // every line fills a file, the oldest one fell off the end
+    assert!(lines(&path).is_empty());
+    assert!(lines(&dir.join("node.log.1"))[0].contains("three"));
+    assert!(lines(&dir.join("node.log.2"))[0].contains("two"));
+    assert!(!dir.join("node.log.3").exists());
+    assert_eq!(*rotated.lock().unwrap(), vec![dir.join("node.log.1"); 3]);
+}
+
+#[test]
+fn reopen_follows_a_moved_file() {
+    let dir = temp_dir("reopen");
+    let path = dir.join("node.log");
+    let sink = FileSink::open(&path, None, 0).unwrap();
+    sink.write(&entry("before"));
+    std::fs::rename(&path, dir.join("moved.log")).unwrap();
+    sink.reopen().unwrap();
+    sink.write(&entry("after"));
+    assert!(lines(&dir.join("moved.log"))[0].contains("before"));
+    assert!(lines(&path)[0].contains("after"));
+}
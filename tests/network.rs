@@ -0,0 +1,430 @@
+// several nodes in one process, connected over libp2p's memory transport.
+// they speak the same floodsub topic, book protocol and messages as the node
+// binary and answer requests with its responders, so the catalog exchange is
+// exercised over real connections without touching the network or the disk
+use libp2p::{
+    core::{transport::MemoryTransport, upgrade},
+    floodsub::{Floodsub, FloodsubEvent, FloodsubMessage, Topic},
+    futures::{future::poll_fn, StreamExt},
+    identity, mplex,
+    noise::{Keypair, NoiseConfig, X25519Spec},
+    request_response::{RequestResponseEvent, RequestResponseMessage},
+    swarm::{Swarm, SwarmEvent},
+    Multiaddr, NetworkBehaviour, PeerId, Transport,
+};
+use peer2peer::details::{self, Details};
+use peer2peer::protocol::{
+    decode, encode, Book, BookDetail, BookRequest, Library, ListMode, ListRequest, Message,
+    Visibility,
+};
+use peer2peer::query::Query;
+use peer2peer::respond::{self, Requester, Shelf};
+use std::collections::{HashMap, HashSet};
+use std::task::Poll;
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+
+const TOPIC: &str = "library";
+const DEADLINE: Duration = Duration::from_secs(10);
+const NOW: u64 = 1_700_000_000;
+
+#[derive(NetworkBehaviour)]
+#[behaviour(out_event = "Event")]
+struct Behaviour {
+    floodsub: Floodsub,
+    details: Details,
+}
+
+#[derive(Debug)]
+enum Event {
+    Floodsub(FloodsubEvent),
+    // boxed, it carries a whole book
+    Details(Box<RequestResponseEvent<BookRequest, BookDetail>>),
+}
+
+impl From<FloodsubEvent> for Event {
+    fn from(event: FloodsubEvent) -> Self {
+        Event::Floodsub(event)
+    }
+}
+
+impl From<RequestResponseEvent<BookRequest, BookDetail>> for Event {
+    fn from(event: RequestResponseEvent<BookRequest, BookDetail>) -> Self {
+        Event::Details(Box::new(event))
+    }
+}
+
+struct Node {
+    peer: PeerId,
+    addr: Multiaddr,
+    swarm: Swarm<Behaviour>,
+    library: Library,
+    // our groups and their members
+    groups: HashMap<String, HashSet<String>>,
+    // peers seen subscribing to the topic
+    subscribed: HashSet<PeerId>,
+    // catalogs received in answer to our requests
+    catalogs: HashMap<PeerId, Library>,
+    // answers to book requests
+    details: HashMap<PeerId, Option<Book>>,
+}
+
+impl Node {
+    fn new(library: Library) -> Self {
+        let keys = identity::Keypair::generate_ed25519();
+        let peer = PeerId::from(keys.public());
+        let auth_keys = Keypair::<X25519Spec>::new()
+            .into_authentic(&keys)
+            .expect("unable to create auth keys");
+        let transport = MemoryTransport
+            .upgrade(upgrade::Version::V1)
+            .authenticate(NoiseConfig::xx(auth_keys).into_authenticated())
+            .multiplex(mplex::MplexConfig::new())
+            .boxed();
+        let mut floodsub = Floodsub::new(peer);
+        floodsub.subscribe(Topic::new(TOPIC));
+        let behaviour = Behaviour {
+            floodsub,
+            details: details::behaviour(),
+        };
+        let mut swarm = Swarm::new(transport, behaviour, peer);
+        let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>().max(1))
+            .parse()
+            .expect("valid memory address");
+        swarm.listen_on(addr.clone()).expect("unable to listen");
+        Node {
+            peer,
+            addr,
+            swarm,
+            library,
+            groups: HashMap::new(),
+            subscribed: HashSet::new(),
+            catalogs: HashMap::new(),
+            details: HashMap::new(),
+        }
+    }
+
+    fn publish(&mut self, message: &Message) {
+        self.swarm
+            .behaviour_mut()
+            .floodsub
+            .publish(Topic::new(TOPIC), encode(message));
+    }
+
+    // what "ls books all" sends
+    fn ls_books_all(&mut self) {
+        self.search(None);
+    }
+
+    // what "ls books all <query>" sends
+    fn search(&mut self, query: Option<&str>) {
+        self.publish(&Message::ListRequest(ListRequest {
+            mode: ListMode::ALL,
+            query: query.map(str::to_owned),
+            summary: None,
+            trace: None,
+        }));
+    }
+
+    // what "show book <peer id> <id>" sends
+    fn show_book(&mut self, peer: &PeerId, id: usize) {
+        let req = BookRequest {
+            peer: peer.to_string(),
+            id,
+            trace: None,
+        };
+        self.swarm.behaviour_mut().details.send_request(peer, req);
+    }
+
+    // answers with the node's own responder. test nodes can't seal, so
+    // whatever would go out sealed is left out
+    fn answer<T>(&self, source: &PeerId, responder: impl FnOnce(Shelf, &Requester) -> T) -> T {
+        let peer = source.to_string();
+        let in_group = |group: &str| self.groups.get(group).is_some_and(|m| m.contains(&peer));
+        let requester = Requester {
+            peer: peer.clone(),
+            sealable: false,
+            friend: self.groups.values().any(|members| members.contains(&peer)),
+            in_group: &in_group,
+        };
+        let shelf = Shelf {
+            books: self.library.clone(),
+            visibility: &Visibility {
+                public: Vec::new(),
+                friends: Vec::new(),
+            },
+            now: NOW,
+            rehost: false,
+            lent_out: &|_| 0,
+        };
+        responder(shelf, &requester)
+    }
+
+    // what "ls books <peer id>" sends
+    fn ls_books(&mut self, peer: &PeerId) {
+        self.publish(&Message::ListRequest(ListRequest {
+            mode: ListMode::One(peer.to_string()),
+            query: None,
+            summary: None,
+            trace: None,
+        }));
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Floodsub(FloodsubEvent::Subscribed { peer_id, .. }) => {
+                self.subscribed.insert(peer_id);
+            }
+            Event::Floodsub(FloodsubEvent::Message(msg)) => self.handle_message(msg),
+            Event::Floodsub(FloodsubEvent::Unsubscribed { .. }) => (),
+            Event::Details(event) => self.handle_details(*event),
+        }
+    }
+
+    fn handle_details(&mut self, event: RequestResponseEvent<BookRequest, BookDetail>) {
+        let (peer, message) = match event {
+            RequestResponseEvent::Message { peer, message } => (peer, message),
+            RequestResponseEvent::ResponseSent { .. } => return,
+            event => panic!("book protocol failed: {:?}", event),
+        };
+        match message {
+            RequestResponseMessage::Request { request, channel, .. } => {
+                assert_eq!(request.peer, self.peer.to_string());
+                let book = self.answer(&peer, |shelf, requester| {
+                    respond::book(shelf, requester, request.id)
+                });
+                let detail = BookDetail {
+                    receiver: peer.to_string(),
+                    id: request.id,
+                    book,
+                    file: None,
+                    trace: None,
+                };
+                let sent = self.swarm.behaviour_mut().details.send_response(channel, detail);
+                sent.expect("requester still waiting");
+            }
+            RequestResponseMessage::Response { response, .. } => {
+                self.details.insert(peer, response.book);
+            }
+        }
+    }
+
+    fn handle_message(&mut self, msg: FloodsubMessage) {
+        let us = self.peer.to_string();
+        match decode(&msg.data).expect("peers only send valid messages") {
+            Message::ListRequest(req) if respond::addressed_to(&req, &us) => {
+                let query = req
+                    .query
+                    .as_deref()
+                    .map(|q| Query::parse(q).expect("valid query"));
+                let catalog = self.answer(&msg.source, |shelf, requester| {
+                    respond::catalog(shelf, requester, query.as_ref(), req.summary)
+                });
+                assert!(!catalog.sealed);
+                self.publish(&Message::ListResponse(catalog.response));
+            }
+            Message::ListResponse(res) if res.receiver == us => {
+                self.catalogs.insert(msg.source, res.data);
+            }
+            _ => (),
+        }
+    }
+}
+
+struct Network {
+    nodes: Vec<Node>,
+}
+
+impl Network {
+    // every node dials every other one and waits until all are on the topic
+    async fn new(libraries: Vec<Library>) -> Self {
+        let mut nodes: Vec<Node> = libraries.into_iter().map(Node::new).collect();
+        let peers: Vec<(PeerId, Multiaddr)> =
+            nodes.iter().map(|n| (n.peer, n.addr.clone())).collect();
+        for (i, node) in nodes.iter_mut().enumerate() {
+            for (peer, addr) in &peers[..i] {
+                node.swarm.dial(addr.clone()).expect("unable to dial");
+                node.swarm.behaviour_mut().floodsub.add_node_to_partial_view(*peer);
+            }
+            for (peer, _) in &peers[i + 1..] {
+                node.swarm.behaviour_mut().floodsub.add_node_to_partial_view(*peer);
+            }
+        }
+        let mut network = Network { nodes };
+        let everyone = peers.len() - 1;
+        network
+            .run_until(|net| net.nodes.iter().all(|n| n.subscribed.len() == everyone))
+            .await;
+        network
+    }
+
+    fn peer(&self, i: usize) -> PeerId {
+        self.nodes[i].peer
+    }
+
+    // drives every swarm until one of them has something to report
+    async fn step(&mut self) {
+        let events = poll_fn(|cx| {
+            let mut events = Vec::new();
+            for (i, node) in self.nodes.iter_mut().enumerate() {
+                while let Poll::Ready(Some(event)) = node.swarm.poll_next_unpin(cx) {
+                    events.push((i, event));
+                }
+            }
+            if events.is_empty() {
+                Poll::Pending
+            } else {
+                Poll::Ready(events)
+            }
+        })
+        .await;
+        for (i, event) in events {
+            if let SwarmEvent::Behaviour(event) = event {
+                self.nodes[i].handle(event);
+            }
+        }
+    }
+
+    async fn run_until(&mut self, done: impl Fn(&Network) -> bool) {
+        let deadline = Instant::now() + DEADLINE;
+        while !done(self) {
+            if timeout_at(deadline, self.step()).await.is_err() {
+                panic!("network didn't get there within {:?}", DEADLINE);
+            }
+        }
+    }
+
+    // for checking that something does not happen
+    async fn run_for(&mut self, duration: Duration) {
+        let until = Instant::now() + duration;
+        while timeout_at(until, self.step()).await.is_ok() {}
+    }
+}
+
+fn book(id: usize, title: &str, public: bool) -> Book {
+    Book {
+        id,
+        title: title.to_owned(),
+        author: "Anonymous".to_owned(),
+        publisher: "Nobody".to_owned(),
+        public,
+        ..Default::default()
+    }
+}
+
+fn titles(library: &Library) -> Vec<&str> {
+    library.iter().map(|b| b.title.as_str()).collect()
+}
+
+#[tokio::test]
+async fn request_for_all_reaches_every_peer() {
+    let mut net = Network::new(vec![
+        vec![],
+        vec![book(1, "Dune", true), book(2, "Diary", false)],
+        vec![book(1, "Emma", true)],
+    ])
+    .await;
+    net.nodes[0].ls_books_all();
+    net.run_until(|net| net.nodes[0].catalogs.len() == 2).await;
+
+    let catalogs = &net.nodes[0].catalogs;
+    assert_eq!(titles(&catalogs[&net.peer(1)]), ["Dune"]);
+    assert_eq!(titles(&catalogs[&net.peer(2)]), ["Emma"]);
+    // answers are addressed to the requester only
+    assert!(net.nodes[1].catalogs.is_empty());
+    assert!(net.nodes[2].catalogs.is_empty());
+}
+
+#[tokio::test]
+async fn request_for_one_is_answered_by_that_peer_only() {
+    let mut net = Network::new(vec![
+        vec![],
+        vec![book(1, "Dune", true)],
+        vec![book(1, "Emma", true)],
+    ])
+    .await;
+    let wanted = net.peer(2);
+    net.nodes[0].ls_books(&wanted);
+    net.run_until(|net| !net.nodes[0].catalogs.is_empty()).await;
+    net.run_for(Duration::from_millis(200)).await;
+
+    let catalogs = &net.nodes[0].catalogs;
+    assert_eq!(catalogs.len(), 1);
+    assert_eq!(titles(&catalogs[&wanted]), ["Emma"]);
+}
+
+#[tokio::test]
+async fn private_and_trashed_books_stay_home() {
+    let mut trashed = book(2, "Old Notes", true);
+    trashed.trashed = Some(1700000000);
+    let mut net = Network::new(vec![
+        vec![],
+        vec![book(1, "Diary", false), trashed, book(3, "Dune", true)],
+    ])
+    .await;
+    net.nodes[0].ls_books_all();
+    net.run_until(|net| !net.nodes[0].catalogs.is_empty()).await;
+
+    assert_eq!(titles(&net.nodes[0].catalogs[&net.peer(1)]), ["Dune"]);
+}
+
+#[tokio::test]
+async fn queries_are_run_by_the_responder() {
+    let mut net = Network::new(vec![
+        vec![],
+        vec![book(1, "Dune", true), book(2, "Emma", true)],
+    ])
+    .await;
+    net.nodes[0].search(Some("title:dune"));
+    net.run_until(|net| !net.nodes[0].catalogs.is_empty()).await;
+
+    assert_eq!(titles(&net.nodes[0].catalogs[&net.peer(1)]), ["Dune"]);
+}
+
+#[tokio::test]
+async fn group_books_stay_out_of_an_unsealed_catalog() {
+    let mut secret = book(2, "Club Notes", true);
+    secret.visible_to = Some("club".to_owned());
+    let mut net = Network::new(vec![vec![], vec![book(1, "Dune", true), secret]]).await;
+    let member = net.peer(0).to_string();
+    net.nodes[1]
+        .groups
+        .insert("club".to_owned(), [member].into());
+    net.nodes[0].ls_books_all();
+    net.run_until(|net| !net.nodes[0].catalogs.is_empty()).await;
+
+    // a member, but everyone on the topic could read it
+    assert_eq!(titles(&net.nodes[0].catalogs[&net.peer(1)]), ["Dune"]);
+}
+
+#[tokio::test]
+async fn a_book_is_shown_only_while_shared() {
+    let mut ended = book(2, "Emma", true);
+    ended.shared_until = Some(NOW - 1);
+    let mut net = Network::new(vec![vec![], vec![book(1, "Dune", true), ended]]).await;
+    let owner = net.peer(1);
+    net.nodes[0].show_book(&owner, 1);
+    net.run_until(|net| !net.nodes[0].details.is_empty()).await;
+    let shown = net.nodes[0].details.remove(&owner).unwrap();
+    assert_eq!(shown.map(|b| b.title), Some("Dune".to_owned()));
+
+    net.nodes[0].show_book(&owner, 2);
+    net.run_until(|net| !net.nodes[0].details.is_empty()).await;
+    assert!(net.nodes[0].details[&owner].is_none());
+}
+
+#[tokio::test]
+async fn a_review_comes_with_the_book_but_not_the_catalog() {
+    let mut dune = book(1, "Dune", true);
+    dune.review = Some("Spice.".to_owned());
+    let mut net = Network::new(vec![vec![], vec![dune]]).await;
+    let owner = net.peer(1);
+    net.nodes[0].ls_books(&owner);
+    net.run_until(|net| !net.nodes[0].catalogs.is_empty()).await;
+    assert_eq!(net.nodes[0].catalogs[&owner][0].review, None);
+
+    net.nodes[0].show_book(&owner, 1);
+    net.run_until(|net| !net.nodes[0].details.is_empty()).await;
+    let shown = net.nodes[0].details.remove(&owner).unwrap();
+    assert_eq!(shown.and_then(|b| b.review), Some("Spice.".to_owned()));
+}
@@ -0,0 +1,882 @@
+use peer2peer::protocol::{
+    advertised_agent_version, agent_version, catalog_for, decode, encode, named_agent_version,
+    parse_advert, parse_capabilities, parse_name, public_catalog, valid_title, Ack, Advert,
+    Availability, Book, BookDetail, BookRequest, ChatMessage, ClubBook, ClubState, Condition,
+    Deposit, FileFormat, Forget, KeyRotation, ListMode, ListRequest, ListResponse, LoanEvent,
+    LoanRecord, Message, Milestone, Nack, NackReason, Presence, ReadingStatus, Relayed,
+    SealedMessage, Summary, SummaryMode, SyncMessage, Tombstone, Visibility, MAX_BOOKS, MAX_DEPTH,
+    MAX_MESSAGE_SIZE, MAX_SUMMARY_ENTRIES, MAX_TITLE,
+};
+
+fn book() -> Book {
+    Book {
+        id: 1,
+        title: "Dune".to_owned(),
+        author: "Frank Herbert".to_owned(),
+        publisher: "Chilton".to_owned(),
+        public: true,
+        ..Default::default()
+    }
+}
+
+fn sealed() -> SealedMessage {
+    SealedMessage {
+        from: "12D3KooWSender".to_owned(),
+        to: "12D3KooWPeer".to_owned(),
+        sealed: "c2VhbGVk".to_owned(),
+        signature: "c2ln".to_owned(),
+    }
+}
+
+fn rotation() -> KeyRotation {
+    KeyRotation {
+        old: "12D3KooWOld".to_owned(),
+        new: "12D3KooWNew".to_owned(),
+        at: 1700000000,
+        old_signature: "b2xk".to_owned(),
+        new_signature: "bmV3".to_owned(),
+    }
+}
+
+fn encoded(message: Message) -> String {
+    String::from_utf8(encode(&message)).unwrap()
+}
+
+// the exact bytes v2 nodes put on the wire. changing any of these breaks older peers
+
+#[test]
+fn v2_list_request_all_is_pinned() {
+    let req = ListRequest {
+        mode: ListMode::ALL,
+        query: None,
+        summary: None,
+        trace: None,
+    };
+    assert_eq!(
+        encoded(Message::ListRequest(req)),
+        r#"{"v":2,"type":"list_request","mode":"ALL"}"#
+    );
+}
+
+#[test]
+fn v2_list_request_one_is_pinned() {
+    let req = ListRequest {
+        mode: ListMode::One("12D3KooWPeer".to_owned()),
+        query: None,
+        summary: None,
+        trace: None,
+    };
+    assert_eq!(
+        encoded(Message::ListRequest(req)),
+        r#"{"v":2,"type":"list_request","mode":{"One":"12D3KooWPeer"}}"#
+    );
+}
+
+#[test]
+fn v2_list_request_with_query_is_pinned() {
+    let req = ListRequest {
+        mode: ListMode::ALL,
+        query: Some("author:le_guin".to_owned()),
+        summary: None,
+        trace: None,
+    };
+    assert_eq!(
+        encoded(Message::ListRequest(req)),
+        r#"{"v":2,"type":"list_request","mode":"ALL","query":"author:le_guin"}"#
+    );
+}
+
+#[test]
+fn v2_list_response_is_pinned() {
+    let res = ListResponse {
+        mode: ListMode::ALL,
+        query: None,
+        summary: None,
+        data: vec![book()],
+        receiver: "12D3KooWPeer".to_owned(),
+        trace: None,
+        rehost: None,
+        relayed: Vec::new(),
+        version: None,
+    };
+    assert_eq!(
+        encoded(Message::ListResponse(res)),
+        r#"{"v":2,"type":"list_response","mode":"ALL","data":[{"id":1,"title":"Dune","author":"Frank Herbert","publisher":"Chilton","public":true}],"receiver":"12D3KooWPeer"}"#
+    );
+}
+
+#[test]
+fn v2_relayed_search_response_is_pinned() {
+    let res = ListResponse {
+        mode: ListMode::ALL,
+        query: Some("dune".to_owned()),
+        summary: None,
+        data: Vec::new(),
+        receiver: "12D3KooWPeer".to_owned(),
+        trace: None,
+        rehost: Some(true),
+        relayed: vec![Relayed {
+            peer: "12D3KooWAway".to_owned(),
+            fetched_at: 1_700_000_000,
+            data: vec![book()],
+        }],
+        version: None,
+    };
+    assert_eq!(
+        encoded(Message::ListResponse(res)),
+        r#"{"v":2,"type":"list_response","mode":"ALL","data":[],"receiver":"12D3KooWPeer","query":"dune","rehost":true,"relayed":[{"peer":"12D3KooWAway","fetched_at":1700000000,"data":[{"id":1,"title":"Dune","author":"Frank Herbert","publisher":"Chilton","public":true}]}]}"#
+    );
+}
+
+#[test]
+fn v2_catalog_version_is_pinned() {
+    let res = ListResponse {
+        mode: ListMode::ALL,
+        query: None,
+        summary: None,
+        data: Vec::new(),
+        receiver: "12D3KooWPeer".to_owned(),
+        trace: None,
+        rehost: None,
+        relayed: Vec::new(),
+        version: Some(1_700_000_000_123),
+    };
+    assert_eq!(
+        encoded(Message::ListResponse(res)),
+        r#"{"v":2,"type":"list_response","mode":"ALL","data":[],"receiver":"12D3KooWPeer","version":1700000000123}"#
+    );
+    // older peers don't send one
+    let old = r#"{"v":2,"type":"list_response","mode":"ALL","data":[],"receiver":"12D3KooWPeer"}"#;
+    match decode(old.as_bytes()).unwrap() {
+        Message::ListResponse(res) => assert_eq!(res.version, None),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn v2_traced_list_request_is_pinned() {
+    let req = ListRequest {
+        mode: ListMode::ALL,
+        query: None,
+        summary: None,
+        trace: Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_owned()),
+    };
+    assert_eq!(
+        encoded(Message::ListRequest(req)),
+        r#"{"v":2,"type":"list_request","mode":"ALL","trace":"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"}"#
+    );
+}
+
+#[test]
+fn v2_summary_request_is_pinned() {
+    let req = ListRequest {
+        mode: ListMode::ALL,
+        query: None,
+        summary: Some(SummaryMode::ByAuthor),
+        trace: None,
+    };
+    assert_eq!(
+        encoded(Message::ListRequest(req)),
+        r#"{"v":2,"type":"list_request","mode":"ALL","summary":"by_author"}"#
+    );
+}
+
+#[test]
+fn v2_summary_response_is_pinned() {
+    let res = ListResponse {
+        mode: ListMode::ALL,
+        query: None,
+        summary: Some(Summary::of(&[book(), book()], SummaryMode::ByAuthor)),
+        data: vec![],
+        receiver: "12D3KooWPeer".to_owned(),
+        trace: None,
+        rehost: None,
+        relayed: Vec::new(),
+        version: None,
+    };
+    assert_eq!(
+        encoded(Message::ListResponse(res)),
+        r#"{"v":2,"type":"list_response","mode":"ALL","data":[],"receiver":"12D3KooWPeer","summary":{"mode":"by_author","total":2,"counts":{"Frank Herbert":2}}}"#
+    );
+}
+
+#[test]
+fn v2_chat_is_pinned() {
+    let chat = ChatMessage {
+        text: "hello".to_owned(),
+        to: None,
+        id: None,
+    };
+    assert_eq!(
+        encoded(Message::Chat(chat)),
+        r#"{"v":2,"type":"chat","text":"hello","to":null}"#
+    );
+}
+
+// messages as v1 nodes sent them, without version or tag
+
+#[test]
+fn decodes_v1_list_request() {
+    match decode(br#"{"mode":"ALL"}"#).unwrap() {
+        Message::ListRequest(req) => assert!(matches!(req.mode, ListMode::ALL)),
+        other => panic!("decoded as {:?}", other),
+    }
+    match decode(br#"{"mode":{"One":"12D3KooWPeer"}}"#).unwrap() {
+        Message::ListRequest(req) => {
+            assert!(matches!(req.mode, ListMode::One(ref p) if p == "12D3KooWPeer"))
+        }
+        other => panic!("decoded as {:?}", other),
+    }
+}
+
+#[test]
+fn decodes_v1_list_response() {
+    let v1 = br#"{"mode":"ALL","data":[{"id":1,"title":"Dune","author":"Frank Herbert","publisher":"Chilton","public":true}],"receiver":"12D3KooWPeer"}"#;
+    match decode(v1).unwrap() {
+        Message::ListResponse(res) => {
+            assert_eq!(res.receiver, "12D3KooWPeer");
+            assert_eq!(res.data.len(), 1);
+            assert_eq!(res.data[0].title, "Dune");
+        }
+        other => panic!("decoded as {:?}", other),
+    }
+}
+
+#[test]
+fn decodes_v1_chat() {
+    match decode(br#"{"text":"hello","to":"12D3KooWPeer"}"#).unwrap() {
+        Message::Chat(chat) => {
+            assert_eq!(chat.text, "hello");
+            assert_eq!(chat.to.as_deref(), Some("12D3KooWPeer"));
+        }
+        other => panic!("decoded as {:?}", other),
+    }
+}
+
+#[test]
+fn rejects_garbage() {
+    assert!(decode(b"not json").is_err());
+    assert!(decode(br#"{"unrelated":true}"#).is_err());
+    assert!(decode(br#"{"v":2,"type":"unknown_kind"}"#).is_err());
+}
+
+#[test]
+fn rejects_oversized_messages() {
+    let text = "a".repeat(MAX_MESSAGE_SIZE);
+    let chat = encode(&Message::Chat(ChatMessage {
+        text,
+        to: None,
+        id: None,
+    }));
+    assert!(decode(&chat).is_err());
+}
+
+#[test]
+fn rejects_deep_nesting() {
+    let deep = format!("{}{}", "[".repeat(MAX_DEPTH + 1), "]".repeat(MAX_DEPTH + 1));
+    assert!(decode(deep.as_bytes()).is_err());
+    // brackets inside strings don't count
+    let chat = encode(&Message::Chat(ChatMessage {
+        text: format!("\\\"{}", "[".repeat(100)),
+        to: None,
+        id: None,
+    }));
+    assert!(decode(&chat).is_ok());
+}
+
+#[test]
+fn rejects_absurd_book_counts() {
+    let res = encode(&Message::ListResponse(ListResponse {
+        mode: ListMode::ALL,
+        query: None,
+        summary: None,
+        data: vec![book(); MAX_BOOKS + 1],
+        receiver: "12D3KooWPeer".to_owned(),
+        trace: None,
+        rehost: None,
+        relayed: Vec::new(),
+        version: None,
+    }));
+    assert!(res.len() <= MAX_MESSAGE_SIZE);
+    assert!(decode(&res).is_err());
+}
+
+#[test]
+fn counts_relayed_books_towards_the_limit() {
+    let relayed = |peer: &str| Relayed {
+        peer: peer.to_owned(),
+        fetched_at: 0,
+        data: vec![book(); MAX_BOOKS / 2 + 1],
+    };
+    let res = encode(&Message::ListResponse(ListResponse {
+        mode: ListMode::ALL,
+        query: Some("dune".to_owned()),
+        summary: None,
+        data: Vec::new(),
+        receiver: "12D3KooWPeer".to_owned(),
+        trace: None,
+        rehost: None,
+        relayed: vec![relayed("12D3KooWOne"), relayed("12D3KooWTwo")],
+        version: None,
+    }));
+    assert!(res.len() <= MAX_MESSAGE_SIZE);
+    assert!(decode(&res).is_err());
+}
+
+// what the fuzz target does, over every truncation and a byte flipped at
+// every position of a real message: errors are fine, panics aren't
+#[test]
+fn survives_mangled_messages() {
+    let res = encode(&Message::ListResponse(ListResponse {
+        mode: ListMode::One("12D3KooWPeer".to_owned()),
+        query: None,
+        summary: None,
+        data: vec![book()],
+        receiver: "12D3KooWPeer".to_owned(),
+        trace: None,
+        rehost: None,
+        relayed: Vec::new(),
+        version: None,
+    }));
+    for end in 0..res.len() {
+        let _ = decode(&res[..end]);
+    }
+    for i in 0..res.len() {
+        for flip in [0x01, 0x20, 0x80, 0xff] {
+            let mut mangled = res.clone();
+            mangled[i] ^= flip;
+            let _ = decode(&mangled);
+        }
+    }
+}
+
+// v1 nodes parse by field shape, so every v2 message must still fit the v1 struct
+
+#[test]
+fn v1_nodes_read_v2_messages() {
+    let req = encode(&Message::ListRequest(ListRequest {
+        mode: ListMode::ALL,
+        query: None,
+        summary: None,
+        trace: None,
+    }));
+    assert!(serde_json::from_slice::<ListRequest>(&req).is_ok());
+
+    let res = encode(&Message::ListResponse(ListResponse {
+        mode: ListMode::ALL,
+        query: None,
+        summary: None,
+        data: vec![book()],
+        receiver: "12D3KooWPeer".to_owned(),
+        trace: None,
+        rehost: None,
+        relayed: Vec::new(),
+        version: None,
+    }));
+    assert!(serde_json::from_slice::<ListResponse>(&res).is_ok());
+
+    let chat = encode(&Message::Chat(ChatMessage {
+        text: "hello".to_owned(),
+        to: None,
+        id: None,
+    }));
+    assert!(serde_json::from_slice::<ChatMessage>(&chat).is_ok());
+}
+
+#[test]
+fn round_trips_every_message() {
+    let messages = vec![
+        Message::ListRequest(ListRequest {
+            mode: ListMode::One("12D3KooWPeer".to_owned()),
+            query: None,
+            summary: None,
+            trace: None,
+        }),
+        Message::ListResponse(ListResponse {
+            mode: ListMode::ALL,
+            query: None,
+            summary: None,
+            data: vec![book(), book()],
+            receiver: "12D3KooWPeer".to_owned(),
+            trace: None,
+            rehost: None,
+            relayed: Vec::new(),
+            version: None,
+        }),
+        Message::Chat(ChatMessage {
+            text: "hi there".to_owned(),
+            to: Some("12D3KooWPeer".to_owned()),
+            id: Some(7),
+        }),
+        Message::Deposit(Deposit {
+            relay: "12D3KooWRelay".to_owned(),
+            message: sealed(),
+        }),
+        Message::Sealed(sealed()),
+        Message::Sync(SyncMessage {
+            data: "c3luYw==".to_owned(),
+        }),
+        Message::Rotation(rotation()),
+        Message::Tombstone(Tombstone {
+            from: "12D3KooWPeer".to_owned(),
+            ids: vec![1, 2],
+            at: 1700000000,
+            signature: "c2ln".to_owned(),
+        }),
+        Message::Presence(Presence {
+            availability: Availability::Away,
+            status: Some("reading".to_owned()),
+        }),
+        Message::Nack(Nack {
+            receiver: "12D3KooWPeer".to_owned(),
+            reason: NackReason::TooLarge,
+            retry_after: None,
+        }),
+        Message::Ack(Ack {
+            receiver: "12D3KooWPeer".to_owned(),
+            id: 7,
+            signature: None,
+        }),
+        Message::BookRequest(BookRequest {
+            peer: "12D3KooWPeer".to_owned(),
+            id: 1,
+            trace: None,
+        }),
+        Message::BookDetail(Box::new(BookDetail {
+            receiver: "12D3KooWPeer".to_owned(),
+            id: 1,
+            book: Some(book()),
+            file: None,
+            trace: None,
+        })),
+    ];
+    for message in messages {
+        let bytes = encode(&message);
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(encode(&decoded), bytes);
+    }
+}
+
+#[test]
+fn v2_sealed_is_pinned() {
+    assert_eq!(
+        encoded(Message::Sealed(sealed())),
+        r#"{"v":2,"type":"sealed","from":"12D3KooWSender","to":"12D3KooWPeer","sealed":"c2VhbGVk","signature":"c2ln"}"#
+    );
+}
+
+#[test]
+fn v2_rotation_is_pinned() {
+    assert_eq!(
+        encoded(Message::Rotation(rotation())),
+        r#"{"v":2,"type":"rotation","old":"12D3KooWOld","new":"12D3KooWNew","at":1700000000,"old_signature":"b2xk","new_signature":"bmV3"}"#
+    );
+}
+
+#[test]
+fn v2_tombstone_is_pinned() {
+    let tombstone = Tombstone {
+        from: "12D3KooWPeer".to_owned(),
+        ids: vec![1, 2],
+        at: 1700000000,
+        signature: "c2ln".to_owned(),
+    };
+    assert_eq!(
+        encoded(Message::Tombstone(tombstone)),
+        r#"{"v":2,"type":"tombstone","from":"12D3KooWPeer","ids":[1,2],"at":1700000000,"signature":"c2ln"}"#
+    );
+}
+
+#[test]
+fn v2_forget_is_pinned() {
+    let forget = Forget {
+        from: "12D3KooWPeer".to_owned(),
+        at: 1700000000,
+        signature: "c2ln".to_owned(),
+    };
+    assert_eq!(
+        encoded(Message::Forget(forget)),
+        r#"{"v":2,"type":"forget","from":"12D3KooWPeer","at":1700000000,"signature":"c2ln"}"#
+    );
+}
+
+#[test]
+fn v2_presence_is_pinned() {
+    let presence = Presence {
+        availability: Availability::DoNotDisturb,
+        status: None,
+    };
+    assert_eq!(
+        encoded(Message::Presence(presence)),
+        r#"{"v":2,"type":"presence","availability":"do_not_disturb"}"#
+    );
+}
+
+#[test]
+fn v2_nack_is_pinned() {
+    let nack = Nack {
+        receiver: "12D3KooWPeer".to_owned(),
+        reason: NackReason::RateLimited,
+        retry_after: Some(600),
+    };
+    assert_eq!(
+        encoded(Message::Nack(nack)),
+        r#"{"v":2,"type":"nack","receiver":"12D3KooWPeer","reason":"rate_limited","retry_after":600}"#
+    );
+}
+
+#[test]
+fn v2_ack_is_pinned() {
+    let ack = Ack {
+        receiver: "12D3KooWPeer".to_owned(),
+        id: 7,
+        signature: None,
+    };
+    assert_eq!(
+        encoded(Message::Ack(ack)),
+        r#"{"v":2,"type":"ack","receiver":"12D3KooWPeer","id":7}"#
+    );
+}
+
+#[test]
+fn v2_book_request_is_pinned() {
+    let req = BookRequest {
+        peer: "12D3KooWPeer".to_owned(),
+        id: 1,
+        trace: None,
+    };
+    assert_eq!(
+        encoded(Message::BookRequest(req)),
+        r#"{"v":2,"type":"book_request","peer":"12D3KooWPeer","id":1}"#
+    );
+}
+
+#[test]
+fn v2_book_detail_is_pinned() {
+    let detail = BookDetail {
+        receiver: "12D3KooWPeer".to_owned(),
+        id: 1,
+        book: Some(book()),
+        file: None,
+        trace: None,
+    };
+    assert_eq!(
+        encoded(Message::BookDetail(Box::new(detail))),
+        r#"{"v":2,"type":"book_detail","receiver":"12D3KooWPeer","id":1,"book":{"id":1,"title":"Dune","author":"Frank Herbert","publisher":"Chilton","public":true}}"#
+    );
+}
+
+#[test]
+fn v2_club_is_pinned() {
+    let state = ClubState {
+        club: "dune-readers".to_owned(),
+        book: Some(ClubBook {
+            title: "Dune".to_owned(),
+            author: "Frank Herbert".to_owned(),
+        }),
+        schedule: vec![Milestone {
+            by: 1700000000,
+            reading: "book one".to_owned(),
+        }],
+        updated: 1690000000,
+    };
+    assert_eq!(
+        encoded(Message::Club(state)),
+        r#"{"v":2,"type":"club","club":"dune-readers","book":{"title":"Dune","author":"Frank Herbert"},"schedule":[{"by":1700000000,"reading":"book one"}],"updated":1690000000}"#
+    );
+    let empty = ClubState {
+        club: "dune-readers".to_owned(),
+        ..ClubState::default()
+    };
+    assert_eq!(
+        encoded(Message::Club(empty)),
+        r#"{"v":2,"type":"club","club":"dune-readers","updated":0}"#
+    );
+}
+
+#[test]
+fn v2_loan_is_pinned() {
+    let record = LoanRecord {
+        id: 7,
+        event: LoanEvent::Lent,
+        lender: "12D3KooWLender".to_owned(),
+        borrower: "12D3KooWBorrower".to_owned(),
+        book: 1,
+        title: "Dune".to_owned(),
+        at: 1700000000,
+        due: Some(1701209600),
+        lender_signature: Some("bGVuZGVy".to_owned()),
+        borrower_signature: None,
+    };
+    let extended = LoanRecord {
+        event: LoanEvent::Extended,
+        ..record.clone()
+    };
+    assert!(encoded(Message::Loan(extended)).contains(r#""event":"extended""#));
+    assert_eq!(
+        encoded(Message::Loan(record)),
+        r#"{"v":2,"type":"loan","id":7,"event":"lent","lender":"12D3KooWLender","borrower":"12D3KooWBorrower","book":1,"title":"Dune","at":1700000000,"due":1701209600,"lender_signature":"bGVuZGVy"}"#
+    );
+}
+
+#[test]
+fn club_book_matches_copies() {
+    let book = ClubBook {
+        title: "dune".to_owned(),
+        author: " Frank Herbert".to_owned(),
+    };
+    assert_eq!(book.key(), self::book().key());
+}
+
+#[test]
+fn announces_sealed_capability() {
+    let caps = parse_capabilities(&agent_version("0.1.0")).unwrap();
+    assert!(caps.contains("sealed"));
+}
+
+#[test]
+fn named_agent_keeps_capabilities() {
+    let agent = named_agent_version("0.1.0", "alice");
+    assert_eq!(
+        agent,
+        "peer2peer/0.1.0 alice (chat,channels,sealed,nack,ack,details,loans,sealed-catalogs)"
+    );
+    assert_eq!(parse_name(&agent).as_deref(), Some("alice"));
+    assert_eq!(
+        parse_capabilities(&agent),
+        parse_capabilities(&agent_version("0.1.0"))
+    );
+    assert_eq!(parse_name(&agent_version("0.1.0")), None);
+    assert_eq!(parse_name("peer2peer/0.1.0 a/b (chat)"), None);
+}
+
+#[test]
+fn advert_is_pinned() {
+    let advert = Advert {
+        title: Some("Alice's SciFi (Shelf)".to_owned()),
+        books: Some(412),
+    };
+    let agent = advertised_agent_version("peer2peer/0.1.0 alice (chat,nack)", &advert);
+    assert_eq!(
+        agent,
+        "peer2peer/0.1.0 alice title=Alice%27s%20SciFi%20%28Shelf%29 books=412 (chat,nack)"
+    );
+    assert_eq!(parse_advert(&agent), advert);
+    // what older nodes read stays the same
+    assert_eq!(parse_name(&agent), Some("alice".to_owned()));
+    assert_eq!(
+        parse_capabilities(&agent),
+        parse_capabilities("peer2peer/0.1.0 alice (chat,nack)")
+    );
+    let unnamed = advertised_agent_version("peer2peer/0.1.0 (chat)", &advert);
+    assert_eq!(parse_name(&unnamed), None);
+    assert_eq!(parse_advert(&unnamed), advert);
+    assert_eq!(parse_advert(&agent_version("0.1.0")), Advert::default());
+}
+
+#[test]
+fn advert_titles_are_printable() {
+    assert_eq!(parse_advert("peer2peer/0.1.0 title=a%1Bb (chat)").title, None);
+    assert_eq!(parse_advert("peer2peer/0.1.0 title=%FF (chat)").title, None);
+    assert_eq!(parse_advert("peer2peer/0.1.0 title=%2 (chat)").title, None);
+    assert_eq!(parse_advert("peer2peer/0.1.0 books=many (chat)").books, None);
+    assert!(!valid_title(&"x".repeat(MAX_TITLE + 1)));
+}
+
+#[test]
+fn public_catalog_hides_private_details() {
+    let private = Book {
+        id: 2,
+        public: false,
+        ..book()
+    };
+    let edited = Book {
+        visible_to: Some("family".to_owned()),
+        modified: Some(1_700_000_000),
+        revision: Some(7),
+        shared_until: Some(1_700_000_000),
+        ..book()
+    };
+    let trashed = Book {
+        id: 3,
+        trashed: Some(1_700_000_000),
+        ..book()
+    };
+    let catalog = public_catalog(vec![private, edited, trashed], |_| true);
+    assert_eq!(catalog.len(), 1);
+    assert_eq!(catalog[0].visible_to, None);
+    assert_eq!(catalog[0].modified, None);
+    assert_eq!(catalog[0].revision, None);
+    assert_eq!(catalog[0].shared_until, None);
+}
+
+#[test]
+fn book_revision_is_pinned() {
+    let edited = Book {
+        modified: Some(1_700_000_000),
+        revision: Some(42),
+        ..book()
+    };
+    let json = serde_json::to_value(&edited).unwrap();
+    assert_eq!(json["modified"], 1_700_000_000);
+    assert_eq!(json["revision"], 42);
+    // libraries of devices that don't count revisions yet still load
+    assert!(serde_json::to_value(book()).unwrap().get("revision").is_none());
+}
+
+#[test]
+fn summary_lists_only_the_largest_counts() {
+    let library: Vec<Book> = (0..MAX_SUMMARY_ENTRIES + 5)
+        .flat_map(|i| {
+            let mut b = book();
+            b.author = format!("Author {:02}", i);
+            // the first five authors wrote two books each
+            let copies = if i < 5 { 2 } else { 1 };
+            vec![b; copies]
+        })
+        .collect();
+    let summary = Summary::of(&library, SummaryMode::ByAuthor);
+    assert_eq!(summary.total, MAX_SUMMARY_ENTRIES + 10);
+    assert_eq!(summary.counts.len(), MAX_SUMMARY_ENTRIES);
+    assert_eq!(summary.counts["Author 00"], 2);
+    assert!(!summary.counts.contains_key(&format!("Author {:02}", MAX_SUMMARY_ENTRIES + 4)));
+
+    let count = Summary::of(&library, SummaryMode::Count);
+    assert_eq!(count.total, MAX_SUMMARY_ENTRIES + 10);
+    assert!(count.counts.is_empty());
+}
+
+#[test]
+fn public_catalog_keeps_series() {
+    let mut volume = book();
+    volume.series = Some("Dune Chronicles".to_owned());
+    volume.volume = Some(1);
+    let shared = public_catalog(vec![volume], |_| false);
+    assert_eq!(shared[0].series.as_deref(), Some("Dune Chronicles"));
+    assert_eq!(shared[0].volume, Some(1));
+    assert!(encoded(Message::ListResponse(ListResponse {
+        mode: ListMode::ALL,
+        query: None,
+        summary: None,
+        data: shared,
+        receiver: "12D3KooWPeer".to_owned(),
+        trace: None,
+        rehost: None,
+        relayed: Vec::new(),
+        version: None,
+    }))
+    .contains(r#""series":"Dune Chronicles","volume":1"#));
+}
+
+#[test]
+fn public_catalog_hides_shelf_location() {
+    let mut paper = book();
+    paper.location = Some("hallway, second shelf".to_owned());
+    paper.condition = Some(Condition::Fair);
+    paper.copies = Some(2);
+    let shared = public_catalog(vec![paper], |_| false);
+    assert_eq!(shared[0].location, None);
+    assert_eq!(shared[0].condition, Some(Condition::Fair));
+    assert_eq!(shared[0].copies, Some(2));
+    let json = serde_json::to_string(&shared[0]).unwrap();
+    assert!(json.ends_with(r#""condition":"fair","copies":2}"#));
+}
+
+#[test]
+fn public_catalog_shares_cid_but_not_path() {
+    let mut attached = book();
+    attached.cid = Some("bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi".to_owned());
+    attached.file = Some("/home/ann/books/dune.epub".to_owned());
+    attached.status = Some(ReadingStatus::Reading);
+    let shared = public_catalog(vec![attached.clone()], |_| false);
+    assert_eq!(shared[0].cid, attached.cid);
+    assert_eq!(shared[0].file, None);
+    assert_eq!(shared[0].status, None);
+    let json = serde_json::to_string(&book()).unwrap();
+    assert!(!json.contains("cid"));
+}
+
+#[test]
+fn book_file_format_is_pinned() {
+    let mut attached = book();
+    attached.cid = Some("bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi".to_owned());
+    attached.format = Some(FileFormat::Epub);
+    let json = serde_json::to_string(&attached).unwrap();
+    assert!(json.ends_with(r#""cid":"bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi","format":"epub"}"#));
+    // hiding the cid hides what kind of file it is too
+    let visibility = Visibility {
+        public: vec!["cid".to_owned()],
+        friends: Vec::new(),
+    };
+    let shared = catalog_for(vec![attached], |_| false, false, &visibility);
+    assert_eq!(shared[0].cid, None);
+    assert_eq!(shared[0].format, None);
+}
+
+#[test]
+fn catalog_hides_fields_by_audience() {
+    let mut full = book();
+    full.tags = vec!["sci-fi".to_owned()];
+    full.rating = Some(5);
+    full.status = Some(ReadingStatus::Reading);
+    let visibility = Visibility {
+        public: vec!["publisher".to_owned(), "tags".to_owned(), "status".to_owned()],
+        friends: Vec::new(),
+    };
+    let strangers = catalog_for(vec![full.clone()], |_| false, false, &visibility);
+    assert_eq!(strangers[0].publisher, "");
+    assert!(strangers[0].tags.is_empty());
+    assert_eq!(strangers[0].status, None);
+    assert_eq!(strangers[0].rating, Some(5));
+    let friends = catalog_for(vec![full.clone()], |_| false, true, &visibility);
+    assert_eq!(friends[0].publisher, "Chilton");
+    assert_eq!(friends[0].tags, vec!["sci-fi".to_owned()]);
+    assert_eq!(friends[0].status, Some(ReadingStatus::Reading));
+    // a book's own lists replace the configured ones, and stay with us
+    full.hidden = Some(Visibility {
+        public: Vec::new(),
+        friends: vec!["rating".to_owned()],
+    });
+    let own = catalog_for(vec![full.clone()], |_| false, true, &visibility);
+    assert_eq!(own[0].rating, None);
+    assert_eq!(own[0].hidden, None);
+    let own = catalog_for(vec![full], |_| false, false, &visibility);
+    assert_eq!(own[0].publisher, "Chilton");
+    assert_eq!(own[0].rating, Some(5));
+}
+
+#[test]
+fn a_review_can_be_hidden() {
+    let mut reviewed = book();
+    reviewed.review = Some("Spice.".to_owned());
+    let visibility = Visibility {
+        public: vec!["review".to_owned()],
+        friends: Vec::new(),
+    };
+    let strangers = catalog_for(vec![reviewed.clone()], |_| false, false, &visibility);
+    assert_eq!(strangers[0].review, None);
+    let friends = catalog_for(vec![reviewed], |_| false, true, &visibility);
+    assert_eq!(friends[0].review, Some("Spice.".to_owned()));
+}
+
+#[test]
+fn visibility_keeps_reading_status_private_by_default() {
+    let default = Visibility::default();
+    assert_eq!(default.hidden(false), ["status".to_owned()]);
+    assert!(default.same_for_friends());
+    let visibility: Visibility = serde_json::from_str(r#"{"public":["tags"]}"#).unwrap();
+    assert_eq!(visibility.friends, default.friends);
+    assert!(!visibility.same_for_friends());
+}
+
+#[test]
+fn copies_match_by_title_and_author() {
+    let mut copy = book();
+    copy.id = 9;
+    copy.title = "  dune ".to_owned();
+    copy.author = "FRANK  HERBERT".to_owned();
+    copy.rating = Some(5);
+    assert_eq!(copy.key(), book().key());
+    copy.author = "Brian Herbert".to_owned();
+    assert_ne!(copy.key(), book().key());
+}
@@ -0,0 +1,93 @@
+use peer2peer::protocol::Book;
+use peer2peer::query::{Query, MAX_PATTERN_LEN, MAX_TERMS};
+
+fn book(title: &str, author: &str, publisher: &str) -> Book {
+    Book {
+        id: 1,
+        title: title.to_owned(),
+        author: author.to_owned(),
+        publisher: publisher.to_owned(),
+        public: true,
+        ..Default::default()
+    }
+}
+
+fn dispossessed() -> Book {
+    book("The Dispossessed", "Ursula K. Le Guin", "Harper & Row")
+}
+
+fn matches(query: &str, book: &Book) -> bool {
+    Query::parse(query).expect("valid query").matches(book)
+}
+
+#[test]
+fn fields_and_bare_words() {
+    let book = dispossessed();
+    assert!(matches("author:le_guin", &book));
+    assert!(matches("AUTHOR:LE_GUIN title:dispossessed", &book));
+    assert!(matches("harper", &book));
+    assert!(!matches("author:le_guin title:earthsea", &book));
+    assert!(!matches("publisher:guin", &book));
+}
+
+#[test]
+fn patterns() {
+    assert!(matches("title:/colou?r/", &book("Colors", "", "")));
+    let book = dispossessed();
+    assert!(matches("title:/disposs.*/", &book));
+    assert!(matches("title:/^the\\sd[a-z]+d$/", &book));
+    assert!(matches("author:/k\\.?\\sle/", &book));
+    assert!(matches("/&/", &book));
+    assert!(!matches("title:/^disposs/", &book));
+    assert!(!matches("title:/[^a-z ]/", &book));
+}
+
+#[test]
+fn bare_pattern_may_contain_a_colon() {
+    assert!(matches("/dune:/", &book("Dune: Messiah", "", "")));
+}
+
+#[test]
+fn rejects_what_it_does_not_support() {
+    for query in [
+        "",
+        "year:1974",
+        "title:",
+        "title://",
+        "title:/*a/",
+        "title:/[z-a]/",
+        "title:/[abc/",
+        "title:/\\p/",
+    ] {
+        assert!(Query::parse(query).is_err(), "{} should be refused", query);
+    }
+}
+
+#[test]
+fn groups_alternation_and_counted_repeats() {
+    let book = dispossessed();
+    assert!(matches("title:/(earthsea|dispossessed)$/", &book));
+    assert!(matches("title:/s{2}/", &book));
+    assert!(!matches("title:/s{3}/", &book));
+}
+
+#[test]
+fn refuses_patterns_that_compile_too_large() {
+    assert!(Query::parse("title:/(\\w{100}){100}/").is_err());
+}
+
+#[test]
+fn limits_size_and_terms() {
+    let long = format!("title:/{}/", "a".repeat(MAX_PATTERN_LEN + 1));
+    assert!(Query::parse(&long).is_err());
+    let many = ["dune"; MAX_TERMS + 1].join(" ");
+    assert!(Query::parse(&many).is_err());
+    assert!(Query::parse(&["dune"; MAX_TERMS].join(" ")).is_ok());
+}
+
+#[test]
+fn patterns_that_backtrack_badly_stay_fast() {
+    let query = Query::parse("title:/a*a*a*a*a*a*a*a*a*a*b/").unwrap();
+    let title = "a".repeat(10_000);
+    assert!(!query.matches(&book(&title, "", "")));
+}
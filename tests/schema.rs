@@ -0,0 +1,85 @@
+use peer2peer::schema::{history_of, migrate, parse, to_json, to_snapshot, LIBRARY_SCHEMA};
+use std::path::PathBuf;
+
+const V1: &str =
+    r#"[{"id":0,"title":"Dune","author":"Frank Herbert","publisher":"Ace","public":true}]"#;
+
+fn temp_file(name: &str) -> PathBuf {
+    let name = format!("peer2peer-schema-{}-{}.json", name, std::process::id());
+    let path = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn version_one_reads_as_current() {
+    let library = parse(V1.as_bytes()).unwrap();
+    assert_eq!(library.len(), 1);
+    assert_eq!(library[0].title, "Dune");
+    assert!(!library[0].uid.is_empty());
+    // the same book read twice gets the same uid
+    assert_eq!(parse(V1.as_bytes()).unwrap()[0].uid, library[0].uid);
+}
+
+#[test]
+fn current_version_round_trips() {
+    let library = parse(V1.as_bytes()).unwrap();
+    let json = to_json(&library).unwrap();
+    assert!(json.starts_with(&format!(r#"{{"schema":{},"#, LIBRARY_SCHEMA)));
+    let again = parse(json.as_bytes()).unwrap();
+    assert_eq!(again[0].uid, library[0].uid);
+}
+
+#[test]
+fn version_zero_and_future_versions_are_refused() {
+    assert!(parse(br#"{"schema":0,"books":[]}"#).is_err());
+    let future = format!(r#"{{"schema":{},"books":[]}}"#, LIBRARY_SCHEMA + 1);
+    assert!(parse(future.as_bytes()).is_err());
+    assert!(parse(br#"{"books":[]}"#).is_err());
+}
+
+#[test]
+fn migrate_keeps_the_old_file() {
+    let path = temp_file("migrate");
+    std::fs::write(&path, V1).unwrap();
+    let name = path.to_str().unwrap();
+    migrate(name).unwrap();
+    let migrated = std::fs::read(&path).unwrap();
+    assert_eq!(parse(&migrated).unwrap()[0].title, "Dune");
+    let backup = format!("{}.v1", name);
+    assert_eq!(std::fs::read_to_string(&backup).unwrap(), V1);
+    // already current, nothing to do
+    migrate(name).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&backup);
+}
+
+#[test]
+fn migrate_refuses_what_it_cant_read() {
+    let path = temp_file("refuse");
+    std::fs::write(&path, r#"{"schema":0,"books":[]}"#).unwrap();
+    assert!(migrate(path.to_str().unwrap()).is_err());
+    let future = format!(r#"{{"schema":{},"books":[]}}"#, LIBRARY_SCHEMA + 1);
+    std::fs::write(&path, future).unwrap();
+    assert!(migrate(path.to_str().unwrap()).is_err());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn migrate_starts_an_empty_library() {
+    let path = temp_file("fresh");
+    migrate(path.to_str().unwrap()).unwrap();
+    assert!(parse(&std::fs::read(&path).unwrap()).unwrap().is_empty());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn snapshots_name_their_batch_of_the_history() {
+    let library = parse(V1.as_bytes()).unwrap();
+    let json = to_snapshot(&library, Some(7)).unwrap();
+    assert_eq!(history_of(json.as_bytes()), Some(7));
+    assert_eq!(parse(json.as_bytes()).unwrap()[0].uid, library[0].uid);
+    // plain files and older versions hold no place in the history
+    assert_eq!(history_of(to_json(&library).unwrap().as_bytes()), None);
+    assert_eq!(history_of(V1.as_bytes()), None);
+}
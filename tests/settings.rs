@@ -0,0 +1,73 @@
+use peer2peer::settings::{changed, needing_restart};
+use toml::Value;
+
+const LIVE: &[&str] = &["log_level", "policy", "power.mode", "channels"];
+
+fn config(text: &str) -> Value {
+    text.parse().unwrap()
+}
+
+const BASE: &str = r#"
+log_level = "info"
+name = "alice"
+channels = ["sf"]
+
+[policy]
+auto_accept = false
+
+[power]
+mode = "normal"
+idle_minutes = 10
+
+[api]
+listen = "127.0.0.1:8080"
+"#;
+
+#[test]
+fn nothing_changes_between_equal_configs() {
+    assert!(changed(&config(BASE), &config(BASE)).is_empty());
+    assert!(needing_restart(&config(BASE), &config(BASE), LIVE).is_empty());
+}
+
+#[test]
+fn settings_are_named_by_section_and_key() {
+    let new = BASE.replace("idle_minutes = 10", "idle_minutes = 20").replace("alice", "bob");
+    assert_eq!(changed(&config(BASE), &config(&new)), vec!["name", "power.idle_minutes"]);
+}
+
+#[test]
+fn live_settings_need_no_restart() {
+    let new = BASE
+        .replace("\"info\"", "\"debug\"")
+        .replace("auto_accept = false", "auto_accept = true")
+        .replace("\"normal\"", "\"saver\"")
+        .replace("[\"sf\"]", "[\"sf\", \"poetry\"]");
+    assert_eq!(changed(&config(BASE), &config(&new)).len(), 4);
+    assert!(needing_restart(&config(BASE), &config(&new), LIVE).is_empty());
+}
+
+#[test]
+fn a_partly_live_section_is_named_by_key() {
+    let new = BASE.replace("idle_minutes = 10", "idle_minutes = 20");
+    assert_eq!(needing_restart(&config(BASE), &config(&new), LIVE), vec!["power.idle_minutes"]);
+}
+
+#[test]
+fn other_sections_are_named_once() {
+    let new = BASE.replace("127.0.0.1:8080", "127.0.0.1:9090") + "token = \"secret\"\n";
+    assert_eq!(needing_restart(&config(BASE), &config(&new), LIVE), vec!["api"]);
+}
+
+#[test]
+fn sections_nobody_listed_still_need_a_restart() {
+    let new = format!("{}\n[mqtt]\nbroker = \"localhost\"\n", BASE);
+    assert_eq!(needing_restart(&config(BASE), &config(&new), LIVE), vec!["mqtt"]);
+    assert_eq!(needing_restart(&config(&new), &config(BASE), LIVE), vec!["mqtt"]);
+}
+
+#[test]
+fn nested_tables_compare_whole() {
+    let old = format!("{}\n[notify.matrix]\nroom = \"a\"\n", BASE);
+    let new = format!("{}\n[notify.matrix]\nroom = \"b\"\n", BASE);
+    assert_eq!(changed(&config(&old), &config(&new)), vec!["notify.matrix"]);
+}
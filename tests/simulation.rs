@@ -0,0 +1,134 @@
+#![cfg(feature = "simulation")]
+// run with: cargo test --features simulation
+use peer2peer::protocol::{
+    public_catalog, Book, Library, ListMode, ListRequest, ListResponse, Message,
+};
+use peer2peer::sim::{Conditions, Context, Simulation, VirtualNode};
+use std::collections::BTreeMap;
+
+// answers list requests like the node binary, and keeps asking everyone
+// who hasn't answered yet on every tick
+struct Peer {
+    library: Library,
+    // peers to collect catalogs from, when this one is the requester
+    waiting_for: usize,
+    catalogs: BTreeMap<usize, Library>,
+    // virtual time each catalog first arrived
+    arrived: BTreeMap<usize, u64>,
+}
+
+impl Peer {
+    fn new(library: Library) -> Self {
+        Peer {
+            library,
+            waiting_for: 0,
+            catalogs: BTreeMap::new(),
+            arrived: BTreeMap::new(),
+        }
+    }
+
+    fn ls_books_all(&mut self, ctx: &mut Context, peers: usize) {
+        self.waiting_for = peers;
+        ctx.publish(Message::ListRequest(ListRequest {
+            mode: ListMode::ALL,
+            query: None,
+            summary: None,
+            trace: None,
+        }));
+    }
+}
+
+impl VirtualNode for Peer {
+    fn receive(&mut self, ctx: &mut Context, from: usize, message: Message) {
+        match message {
+            Message::ListRequest(_) => ctx.publish(Message::ListResponse(ListResponse {
+                mode: ListMode::ALL,
+                query: None,
+                summary: None,
+                data: public_catalog(self.library.clone(), |_| false),
+                receiver: from.to_string(),
+                trace: None,
+                rehost: None,
+                relayed: Vec::new(),
+                version: None,
+            })),
+            Message::ListResponse(res) if res.receiver == ctx.id().to_string() => {
+                self.arrived.entry(from).or_insert_with(|| ctx.now());
+                self.catalogs.insert(from, res.data);
+            }
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self, ctx: &mut Context) {
+        if self.catalogs.len() < self.waiting_for {
+            ctx.publish(Message::ListRequest(ListRequest {
+                mode: ListMode::ALL,
+                query: None,
+                summary: None,
+                trace: None,
+            }));
+        }
+    }
+}
+
+fn book(id: usize, title: &str) -> Book {
+    Book {
+        id,
+        title: title.to_owned(),
+        author: "Anonymous".to_owned(),
+        publisher: "Nobody".to_owned(),
+        public: true,
+        ..Default::default()
+    }
+}
+
+// node 0 asks everyone else for their catalog
+fn run(seed: u64, conditions: Conditions, duration: u64) -> Simulation<Peer> {
+    let mut sim = Simulation::new(seed, conditions);
+    sim.add_node(Peer::new(vec![]));
+    for title in ["Dune", "Emma", "Ulysses", "Beloved"] {
+        sim.add_node(Peer::new(vec![book(1, title)]));
+    }
+    sim.act(0, |peer, ctx| peer.ls_books_all(ctx, 4));
+    sim.run_for(duration);
+    sim
+}
+
+#[test]
+fn answers_take_two_latencies() {
+    let conditions = Conditions {
+        min_latency: 100,
+        max_latency: 100,
+        ..Conditions::default()
+    };
+    let sim = run(1, conditions, 500);
+    let requester = sim.node(0);
+    assert_eq!(requester.catalogs.len(), 4);
+    assert!(requester.arrived.values().all(|&at| at == 200));
+    assert_eq!(sim.stats().lost, 0);
+}
+
+#[test]
+fn retries_get_through_packet_loss() {
+    let conditions = Conditions {
+        loss: 0.5,
+        ..Conditions::default()
+    };
+    let sim = run(7, conditions, 60_000);
+    assert_eq!(sim.node(0).catalogs.len(), 4);
+    assert_eq!(sim.node(0).catalogs[&2][0].title, "Emma");
+    assert!(sim.stats().lost > 0);
+}
+
+#[test]
+fn same_seed_same_run() {
+    let conditions = Conditions {
+        loss: 0.3,
+        ..Conditions::default()
+    };
+    let first = run(42, conditions, 30_000);
+    let second = run(42, conditions, 30_000);
+    assert_eq!(first.stats(), second.stats());
+    assert_eq!(first.node(0).arrived, second.node(0).arrived);
+}